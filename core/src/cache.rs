@@ -0,0 +1,269 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::Metadata,
+    hash::Hash,
+    io,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    sync::{Mutex, RwLock},
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::Context;
+
+/// How long a file must not have been modified before its content is cached
+///
+/// Filesystems with coarse timestamps may not change the modification time when a file is written twice in quick
+/// succession so recently modified files are always re-read.
+const SETTLE_TIME: Duration = Duration::from_secs(1);
+
+/// An in-memory cache of secret file contents
+///
+/// Every lookup compares the cached state of the file with its current metadata so that rotated secrets (replaced
+/// files as well as files that were modified in place) are picked up immediately without having to read the file.
+/// Cached contents are locked into RAM if the memory lock limit allows it and overwritten once they are replaced or
+/// dropped.
+#[derive(Default)]
+pub struct FileCache {
+    files: Mutex<HashMap<PathBuf, CachedFile>>,
+}
+
+struct CachedFile {
+    fingerprint: Fingerprint,
+    content: LockedBytes,
+}
+
+/// The parts of a files metadata that change whenever the file is replaced or its content is modified
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct Fingerprint {
+    dev: u64,
+    ino: u64,
+    size: u64,
+    mtime: (i64, i64),
+    ctime: (i64, i64),
+}
+
+impl FileCache {
+    /// The cached content of the file at `path` if it has not changed since it was cached
+    ///
+    /// `metadata` must be the current metadata of the file.
+    pub fn get(&self, path: &Path, metadata: &Metadata) -> Option<Vec<u8>> {
+        let mut files = self.files.lock().unwrap();
+        let cached = files.get(path)?;
+        if cached.fingerprint == Fingerprint::of(metadata) {
+            tracing::trace!("Using cached content of secret file {}", path.display());
+            return Some(cached.content.as_bytes().to_vec());
+        }
+        tracing::debug!("Secret file {} changed since it was cached", path.display());
+        files.remove(path);
+        None
+    }
+
+    /// Remember `content` as the content of the file at `path` whose metadata is `metadata`
+    pub fn insert(&self, path: &Path, metadata: &Metadata, content: &[u8]) {
+        let settled = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+            .is_some_and(|age| age >= SETTLE_TIME);
+        if !settled {
+            return;
+        }
+        self.files.lock().unwrap().insert(
+            path.to_path_buf(),
+            CachedFile {
+                fingerprint: Fingerprint::of(metadata),
+                content: LockedBytes::new_locked_if_allowed(content),
+            },
+        );
+    }
+
+    /// Forget the cached content of the file at `path`
+    pub fn invalidate(&self, path: &Path) {
+        self.files.lock().unwrap().remove(path);
+    }
+}
+
+impl fmt::Debug for FileCache {
+    // the cached content is secret so only the paths are printed
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set()
+            .entries(self.files.lock().unwrap().keys())
+            .finish()
+    }
+}
+
+impl Fingerprint {
+    fn of(metadata: &Metadata) -> Self {
+        Self {
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+            size: metadata.size(),
+            mtime: (metadata.mtime(), metadata.mtime_nsec()),
+            ctime: (metadata.ctime(), metadata.ctime_nsec()),
+        }
+    }
+}
+
+/// An in-memory cache of values which expire a fixed time after they have been inserted
+pub struct TtlCache<K, V> {
+    ttl: Duration,
+    values: Mutex<HashMap<K, (Instant, V)>>,
+}
+
+impl<K: Eq + Hash, V: Clone> TtlCache<K, V> {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            values: Mutex::default(),
+        }
+    }
+
+    /// The value stored for `key` if it has not expired yet
+    pub fn get(&self, key: &K) -> Option<V> {
+        let mut values = self.values.lock().unwrap();
+        let (inserted, value) = values.get(key)?;
+        if inserted.elapsed() < self.ttl {
+            return Some(value.clone());
+        }
+        values.remove(key);
+        None
+    }
+
+    /// Store `value` for `key`, replacing any previous value, and drop all expired values
+    pub fn insert(&self, key: K, value: V) {
+        let mut values = self.values.lock().unwrap();
+        values.retain(|_, (inserted, _)| inserted.elapsed() < self.ttl);
+        values.insert(key, (Instant::now(), value));
+    }
+
+    /// Drop all stored values
+    pub fn clear(&self) {
+        self.values.lock().unwrap().clear();
+    }
+}
+
+impl<K, V> fmt::Debug for TtlCache<K, V> {
+    // the cached values are secret so only the number of values is printed
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TtlCache")
+            .field("ttl", &self.ttl)
+            .field("len", &self.values.lock().unwrap().len())
+            .finish()
+    }
+}
+
+/// File contents that were read once when the config was loaded and are used instead of the files afterwards
+#[derive(Default)]
+pub struct PreloadStore {
+    files: RwLock<HashMap<PathBuf, LockedBytes>>,
+}
+
+impl PreloadStore {
+    /// The preloaded content of the file at `path` if it has been preloaded
+    pub fn get(&self, path: &Path) -> Option<Vec<u8>> {
+        self.files
+            .read()
+            .unwrap()
+            .get(path)
+            .map(|content| content.as_bytes().to_vec())
+    }
+
+    /// Store `content` as the content of the file at `path`, locking it into RAM if `pin` is set
+    pub fn insert(&self, path: &Path, content: &[u8], pin: bool) -> anyhow::Result<()> {
+        let content = LockedBytes::new(content, pin)
+            .with_context(|| format!("Could not pin content of {} in memory", path.display()))?;
+        self.files
+            .write()
+            .unwrap()
+            .insert(path.to_path_buf(), content);
+        Ok(())
+    }
+
+    /// Whether the file at `path` has been preloaded
+    pub fn contains(&self, path: &Path) -> bool {
+        self.files.read().unwrap().contains_key(path)
+    }
+}
+
+impl fmt::Debug for PreloadStore {
+    // the preloaded content is secret so only the paths are printed
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set()
+            .entries(self.files.read().unwrap().keys())
+            .finish()
+    }
+}
+
+/// Lock all current and future memory of the process into RAM so that no secret can ever be written to swap
+///
+/// Pages are only locked once they are used so that reserved but untouched memory, e.g. thread stacks, does not
+/// occupy RAM.
+pub fn lock_all_memory() -> io::Result<()> {
+    // SAFETY: mlockall has no memory safety requirements
+    if unsafe { libc::mlockall(libc::MCL_CURRENT | libc::MCL_FUTURE | libc::MCL_ONFAULT) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// A heap buffer which is optionally locked into RAM and overwritten with zeros when it is dropped
+pub struct LockedBytes {
+    bytes: Box<[u8]>,
+    locked: bool,
+}
+
+impl LockedBytes {
+    pub fn new(content: &[u8], lock: bool) -> io::Result<Self> {
+        let mut result = Self {
+            bytes: content.into(),
+            locked: false,
+        };
+        if lock {
+            result.lock()?;
+        }
+        Ok(result)
+    }
+
+    /// Copy `content` into a buffer which is locked into RAM unless the memory lock limit of the process prevents it
+    pub fn new_locked_if_allowed(content: &[u8]) -> Self {
+        let mut result = Self {
+            bytes: content.into(),
+            locked: false,
+        };
+        if let Err(e) = result.lock() {
+            tracing::trace!("Could not lock secret into RAM: {e}");
+        }
+        result
+    }
+
+    fn lock(&mut self) -> io::Result<()> {
+        if self.bytes.is_empty() {
+            return Ok(());
+        }
+        // SAFETY: the range covers exactly the allocation owned by the box which is never moved or resized
+        if unsafe { libc::mlock(self.bytes.as_ptr().cast(), self.bytes.len()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        self.locked = true;
+        Ok(())
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl Drop for LockedBytes {
+    fn drop(&mut self) {
+        for byte in self.bytes.iter_mut() {
+            // SAFETY: the pointer comes from a valid mutable reference; volatile keeps the write from being elided
+            unsafe { std::ptr::write_volatile(byte, 0) };
+        }
+        if self.locked {
+            // SAFETY: the range is the one that was locked in LockedBytes::new
+            unsafe { libc::munlock(self.bytes.as_ptr().cast(), self.bytes.len()) };
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use anyhow::Context;
+use base64::prelude::*;
+
+use crate::mapping::SecretValue;
+
+/// Verify that `value` is well-formed key material if `key` is known to hold a key in the setting `setting_name`
+///
+/// Settings and keys which are not known to this function are accepted as-is.
+pub fn validate_key_material(
+    setting_name: &str,
+    key: &str,
+    value: &SecretValue,
+) -> anyhow::Result<()> {
+    if setting_name == "wireguard" && is_wireguard_key(key) {
+        let SecretValue::String(value) = value else {
+            anyhow::bail!("WireGuard key {setting_name}.{key} must be returned as a string");
+        };
+        for value in wireguard_key_values(key, value) {
+            validate_wireguard_key(value).with_context(|| {
+                format!("Value of {setting_name}.{key} is not a valid WireGuard key")
+            })?;
+        }
+    }
+    if setting_name == "macsec" && is_macsec_key(key) {
+        let SecretValue::String(value) = value else {
+            anyhow::bail!("MACsec key {setting_name}.{key} must be returned as a string");
+        };
+        validate_macsec_key(key, value)
+            .with_context(|| format!("Value of {setting_name}.{key} is not a valid MACsec key"))?;
+    }
+    Ok(())
+}
+
+/// Whether `key` of the macsec setting holds hex encoded MKA key material
+fn is_macsec_key(key: &str) -> bool {
+    key == "mka-cak" || key == "mka-ckn"
+}
+
+/// Verify that `value` is a valid MKA connectivity association key (`mka-cak`) or key name (`mka-ckn`)
+///
+/// The CAK must be 32 or 64 hex characters (128 or 256 bit) long while the CKN must consist of 2 to 64 hex characters
+/// and have an even length.
+fn validate_macsec_key(key: &str, value: &str) -> anyhow::Result<()> {
+    if !value.chars().all(|c| c.is_ascii_hexdigit()) {
+        anyhow::bail!("Key contains non-hex characters");
+    }
+    match key {
+        "mka-cak" if !matches!(value.len(), 32 | 64) => {
+            anyhow::bail!("Key has {} hex characters instead of 32 or 64", value.len())
+        }
+        "mka-ckn" if !(2..=64).contains(&value.len()) || !value.len().is_multiple_of(2) => {
+            anyhow::bail!(
+                "Key name has {} hex characters instead of an even number between 2 and 64",
+                value.len()
+            )
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Whether `key` of the wireguard setting holds base64 encoded key material
+fn is_wireguard_key(key: &str) -> bool {
+    key == "private-key" || key == "preshared-key" || key.ends_with(".preshared-key")
+}
+
+/// The individual keys contained in the value of the wireguard `key`
+///
+/// This is the value itself unless `key` populates multiple peers at once in which case the value consists of
+/// `<public-key> <value>` lines.
+fn wireguard_key_values<'a>(key: &str, value: &'a str) -> Vec<&'a str> {
+    if key.starts_with("peers.*.") {
+        value
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| line.split_once(char::is_whitespace))
+            .map(|(_, value)| value.trim())
+            .collect()
+    } else {
+        vec![value]
+    }
+}
+
+/// Verify that `value` is the base64 encoding of exactly 32 bytes as is required for all WireGuard keys
+fn validate_wireguard_key(value: &str) -> anyhow::Result<()> {
+    let bytes = BASE64_STANDARD
+        .decode(value)
+        .context("Key is not valid base64")?;
+    if bytes.len() != 32 {
+        anyhow::bail!("Key decodes to {} bytes instead of 32", bytes.len());
+    }
+    Ok(())
+}
+
+/// Check `value` against setting-specific constraints and return a human readable description of every violation
+///
+/// These checks mirror the constraints which NetworkManager enforces itself so that problems can be reported with
+/// actionable messages before NetworkManager rejects the value.
+pub fn plausibility_warnings(setting_name: &str, key: &str, value: &SecretValue) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let SecretValue::String(value) = value else {
+        return warnings;
+    };
+
+    if value.is_empty() {
+        warnings.push(format!("{setting_name}.{key} is empty"));
+        return warnings;
+    }
+    if value.ends_with('\n') {
+        warnings.push(format!("{setting_name}.{key} ends with a newline which is probably unintended; consider setting trim = \"newline\""));
+    }
+
+    match (setting_name, key) {
+        ("802-11-wireless-security", "psk") => {
+            let is_passphrase = (8..=63).contains(&value.len());
+            let is_raw_key = value.len() == 64 && value.chars().all(|c| c.is_ascii_hexdigit());
+            if !is_passphrase && !is_raw_key {
+                warnings.push(format!("{setting_name}.{key} must either be a passphrase of 8 to 63 characters or 64 hex characters but has length {}", value.len()));
+            }
+        }
+        ("802-11-wireless-security", "wep-key0" | "wep-key1" | "wep-key2" | "wep-key3") => {
+            let is_ascii_key = matches!(value.len(), 5 | 13);
+            let is_hex_key =
+                matches!(value.len(), 10 | 26) && value.chars().all(|c| c.is_ascii_hexdigit());
+            if !is_ascii_key && !is_hex_key {
+                warnings.push(format!("{setting_name}.{key} must either be 5 or 13 ASCII characters or 10 or 26 hex characters but has length {}", value.len()));
+            }
+        }
+        ("gsm", "pin" | "puk") if !value.chars().all(|c| c.is_ascii_digit()) => {
+            warnings.push(format!(
+                "{setting_name}.{key} should only consist of digits"
+            ));
+        }
+        ("wireguard", key) if is_wireguard_key(key) => {
+            if let Some(e) = wireguard_key_values(key, value)
+                .into_iter()
+                .find_map(|value| validate_wireguard_key(value).err())
+            {
+                warnings.push(format!(
+                    "{setting_name}.{key} is not a valid WireGuard key: {e}"
+                ));
+            }
+        }
+        ("macsec", key) if is_macsec_key(key) => {
+            if let Err(e) = validate_macsec_key(key, value) {
+                warnings.push(format!(
+                    "{setting_name}.{key} is not a valid MACsec key: {e}"
+                ));
+            }
+        }
+        _ => {}
+    }
+
+    warnings
+}
@@ -0,0 +1,291 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::Context;
+use zbus::zvariant::{OwnedValue, Value};
+
+use crate::mapping::{SecretRequest, SecretValue};
+
+/// The properties of a single setting (`a{sv}`)
+pub type PropMap = HashMap<String, OwnedValue>;
+
+/// Encoded values keyed by their possibly dotted key name
+type EncodedSecrets = Vec<(String, Value<'static>)>;
+
+/// Encode the secret values of a single setting into the structure that NetworkManager expects for that setting
+///
+/// After setting-specific encoding, dotted keys like `subdict.key` are turned into nested maps (`a{sv}`).
+pub fn encode_secrets(
+    request: &SecretRequest,
+    secrets: &[(String, SecretValue)],
+) -> anyhow::Result<PropMap> {
+    let encoded = match request.setting_name.as_str() {
+        "wireguard" => encode_wireguard_secrets(request, secrets)?,
+        "vpn" => encode_vpn_secrets(secrets)?,
+        "802-1x" => encode_8021x_secrets(secrets),
+        _ => encode_generic_secrets(secrets),
+    };
+    nest_keys(encoded)
+}
+
+/// Encode secrets as a map of keys to values
+fn encode_generic_secrets(secrets: &[(String, SecretValue)]) -> EncodedSecrets {
+    secrets
+        .iter()
+        .map(|(key, value)| (key.to_owned(), secret_to_value(value)))
+        .collect()
+}
+
+/// A level of nested values that is built from dotted keys
+enum NestedValue {
+    Value(Value<'static>),
+    Map(BTreeMap<String, NestedValue>),
+}
+
+/// Turn dotted keys like `subdict.key` into nested maps
+fn nest_keys(encoded: EncodedSecrets) -> anyhow::Result<PropMap> {
+    let mut root = BTreeMap::<String, NestedValue>::new();
+    for (key, value) in encoded {
+        let mut parts = key.split('.').collect::<Vec<_>>();
+        let last = parts.pop().expect("split always returns at least one part");
+        let mut level = &mut root;
+        for part in parts {
+            level = match level
+                .entry(part.to_owned())
+                .or_insert_with(|| NestedValue::Map(BTreeMap::new()))
+            {
+                NestedValue::Map(map) => map,
+                NestedValue::Value(_) => {
+                    anyhow::bail!("Key {key} conflicts with the value configured for {part}")
+                }
+            };
+        }
+        if let Some(NestedValue::Map(_)) = level.insert(last.to_owned(), NestedValue::Value(value))
+        {
+            anyhow::bail!("Key {key} conflicts with nested values configured below it");
+        }
+    }
+    into_map(root)
+        .into_iter()
+        .map(|(key, value)| {
+            let value = value
+                .try_into_owned()
+                .with_context(|| format!("Could not encode value of {key}"))?;
+            Ok((key, value))
+        })
+        .collect()
+}
+
+fn into_map(level: BTreeMap<String, NestedValue>) -> HashMap<String, Value<'static>> {
+    level
+        .into_iter()
+        .map(|(key, value)| {
+            let value = match value {
+                NestedValue::Value(value) => value,
+                NestedValue::Map(map) => Value::from(into_map(map)),
+            };
+            (key, value)
+        })
+        .collect()
+}
+
+/// Encode secrets of the wireguard setting
+///
+/// Keys of the form `peers.<peer>.<property>` are collected into the `peers` list (`aa{sv}`) with one entry per peer
+/// while all other keys are encoded as-is.
+/// `<peer>` is either the public key of the peer or its index in the connection profile.
+/// If `<peer>` is `*`, the value is read as lines of `<public-key> <value>` pairs and populates `<property>` of every
+/// listed peer.
+fn encode_wireguard_secrets(
+    request: &SecretRequest,
+    secrets: &[(String, SecretValue)],
+) -> anyhow::Result<EncodedSecrets> {
+    let mut result = EncodedSecrets::new();
+    let mut peers = BTreeMap::<String, HashMap<String, Value<'static>>>::new();
+    let mut insert_peer_value = |public_key: &str, property: &str, value: Value<'static>| {
+        peers
+            .entry(public_key.to_owned())
+            .or_insert_with(|| {
+                HashMap::from([(
+                    "public-key".to_string(),
+                    Value::from(public_key.to_string()),
+                )])
+            })
+            .insert(property.to_owned(), value);
+    };
+
+    for (key, value) in secrets {
+        match key
+            .strip_prefix("peers.")
+            .and_then(|peer_key| peer_key.rsplit_once('.'))
+        {
+            Some(("*", property)) => {
+                let SecretValue::String(value) = value else {
+                    anyhow::bail!(
+                        "Value of wireguard.{key} must be a string of <public-key> <value> lines"
+                    );
+                };
+                for line in value.lines().map(str::trim) {
+                    if line.is_empty() || line.starts_with('#') {
+                        continue;
+                    }
+                    let (public_key, peer_value) =
+                        line.split_once(char::is_whitespace).with_context(|| {
+                            format!(
+                                "Line of wireguard.{key} is not of the form <public-key> <value>"
+                            )
+                        })?;
+                    insert_peer_value(
+                        public_key,
+                        property,
+                        Value::from(peer_value.trim().to_owned()),
+                    );
+                }
+            }
+            Some((peer, property)) => {
+                let public_key = match peer.parse::<usize>() {
+                    Ok(index) => request.wireguard_peers.get(index).with_context(|| {
+                        format!("wireguard.{key} references peer {index} but the connection only has {} peers", request.wireguard_peers.len())
+                    })?,
+                    Err(_) => peer,
+                };
+                insert_peer_value(public_key, property, secret_to_value(value));
+            }
+            None => result.push((key.to_owned(), secret_to_value(value))),
+        }
+    }
+
+    if !peers.is_empty() {
+        result.push((
+            "peers".to_string(),
+            Value::from(peers.into_values().collect::<Vec<_>>()),
+        ));
+    }
+    Ok(result)
+}
+
+/// Encode secrets of the vpn setting
+///
+/// VPN plugins expect their secrets inside the `secrets` string map (`a{ss}`) so keys of the form `secrets.<name>` are
+/// collected into it while all other keys are encoded as-is.
+fn encode_vpn_secrets(secrets: &[(String, SecretValue)]) -> anyhow::Result<EncodedSecrets> {
+    let mut result = EncodedSecrets::new();
+    let mut vpn_secrets = HashMap::<String, String>::new();
+
+    for (key, value) in secrets {
+        match key.strip_prefix("secrets.") {
+            Some(name) => {
+                let SecretValue::String(value) = value else {
+                    anyhow::bail!("VPN secret vpn.{key} must be a string");
+                };
+                vpn_secrets.insert(name.to_owned(), value.to_owned());
+            }
+            None => result.push((key.to_owned(), secret_to_value(value))),
+        }
+    }
+
+    if !vpn_secrets.is_empty() {
+        result.push(("secrets".to_string(), Value::from(vpn_secrets)));
+    }
+    Ok(result)
+}
+
+/// Keys of the 802-1x setting which NetworkManager expects as byte arrays (`ay`)
+const IEEE_8021X_BYTE_ARRAY_KEYS: &[&str] = &[
+    "password-raw",
+    "ca-cert",
+    "client-cert",
+    "private-key",
+    "phase2-ca-cert",
+    "phase2-client-cert",
+    "phase2-private-key",
+];
+
+/// Encode secrets of the 802-1x setting
+///
+/// Raw passwords as well as certificate and key blobs are expected as byte arrays by NetworkManager so string values
+/// of those keys are converted accordingly.
+/// All other keys like `password`, `private-key-password` or `pin` are encoded as-is.
+fn encode_8021x_secrets(secrets: &[(String, SecretValue)]) -> EncodedSecrets {
+    secrets
+        .iter()
+        .map(|(key, value)| {
+            let value = match value {
+                SecretValue::String(value)
+                    if IEEE_8021X_BYTE_ARRAY_KEYS.contains(&key.as_str()) =>
+                {
+                    Value::from(value.as_bytes().to_vec())
+                }
+                value => secret_to_value(value),
+            };
+            (key.to_owned(), value)
+        })
+        .collect()
+}
+
+/// Convert a secret value into the D-Bus value that is sent to NetworkManager
+fn secret_to_value(value: &SecretValue) -> Value<'static> {
+    match value {
+        SecretValue::String(value) => Value::from(value.to_owned()),
+        SecretValue::U32(value) => Value::from(*value),
+        SecretValue::Bool(value) => Value::from(*value),
+        SecretValue::StringList(value) => Value::from(value.to_owned()),
+        SecretValue::Bytes(value) => Value::from(value.to_owned()),
+    }
+}
+
+/// Extract the secret value of a possibly dotted `key` from a setting that NetworkManager sent
+///
+/// This is the inverse of [`encode_secrets`] for plain and nested keys (e.g. `secrets.<name>` of VPN settings).
+/// Returns `None` if the setting does not contain the key or if its value has an unsupported type.
+pub fn decode_secret(setting: &PropMap, key: &str) -> Option<SecretValue> {
+    let mut parts = key.split('.');
+    let mut value: &Value = setting.get(parts.next()?)?;
+    for part in parts {
+        let Value::Dict(dict) = unwrap_variant(value) else {
+            return None;
+        };
+        value = dict
+            .iter()
+            .find(|(key, _)| matches!(key, Value::Str(key) if key.as_str() == part))?
+            .1;
+    }
+    variant_to_secret(unwrap_variant(value))
+}
+
+/// Look through a variant to the value it contains
+pub fn unwrap_variant<'a>(value: &'a Value<'a>) -> &'a Value<'a> {
+    match value {
+        Value::Value(inner) => inner,
+        _ => value,
+    }
+}
+
+/// Convert a D-Bus value that NetworkManager sent into a secret value
+fn variant_to_secret(value: &Value) -> Option<SecretValue> {
+    match value {
+        Value::Str(value) => Some(SecretValue::String(value.to_string())),
+        Value::U32(value) => Some(SecretValue::U32(*value)),
+        Value::Bool(value) => Some(SecretValue::Bool(*value)),
+        Value::Array(array) if *array.element_signature() == "y" => Some(SecretValue::Bytes(
+            array
+                .inner()
+                .iter()
+                .map(|byte| match byte {
+                    Value::U8(byte) => Some(*byte),
+                    _ => None,
+                })
+                .collect::<Option<_>>()?,
+        )),
+        Value::Array(array) if *array.element_signature() == "s" => Some(SecretValue::StringList(
+            array
+                .inner()
+                .iter()
+                .map(|item| match item {
+                    Value::Str(item) => Some(item.to_string()),
+                    _ => None,
+                })
+                .collect::<Option<_>>()?,
+        )),
+        _ => None,
+    }
+}
@@ -0,0 +1,109 @@
+use std::{
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::atomic::AtomicBool,
+};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::mapping::run_command_with_input;
+
+/// How the backing files of an entry are encrypted at rest
+///
+/// Files are decrypted after they were read and verified, and values that are saved into writable entries are
+/// encrypted before they are written, so that plaintext never ends up on disk. Both is done by running the respective
+/// tool with the content on stdin.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum Encryption {
+    /// Files are encrypted with [age](https://age-encryption.org)
+    Age {
+        /// Identity file with which files are decrypted
+        identity: PathBuf,
+        /// Recipients to which saved values are encrypted
+        #[serde(default)]
+        recipients: Vec<String>,
+    },
+    /// Files are credentials encrypted with `systemd-creds encrypt`
+    SystemdCreds {
+        /// Name which is embedded into the credentials, by default the file name of the backing file
+        name: Option<String>,
+    },
+}
+
+impl Encryption {
+    /// Whether values can be encrypted for writing them back
+    pub(crate) fn can_encrypt(&self) -> bool {
+        match self {
+            Encryption::Age { recipients, .. } => !recipients.is_empty(),
+            Encryption::SystemdCreds { .. } => true,
+        }
+    }
+
+    /// Decrypt the `content` of the backing file at `file`
+    pub fn decrypt(&self, file: &Path, content: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let command = match self {
+            Encryption::Age { identity, .. } => {
+                let mut command = Command::new("age");
+                command.arg("--decrypt").arg("--identity").arg(identity);
+                command
+            }
+            Encryption::SystemdCreds { .. } => {
+                let mut command = Command::new("systemd-creds");
+                command
+                    .arg("decrypt")
+                    .arg(format!("--name={}", self.credential_name(file)?))
+                    .args(["-", "-"]);
+                command
+            }
+        };
+        self.run(command, content)
+            .with_context(|| format!("Could not decrypt secret file at {}", file.display()))
+    }
+
+    /// Encrypt `content` so that it can be written into the backing file at `file`
+    pub fn encrypt(&self, file: &Path, content: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let command = match self {
+            Encryption::Age { recipients, .. } => {
+                let mut command = Command::new("age");
+                command.arg("--encrypt");
+                for recipient in recipients {
+                    command.arg("--recipient").arg(recipient);
+                }
+                command
+            }
+            Encryption::SystemdCreds { .. } => {
+                let mut command = Command::new("systemd-creds");
+                command
+                    .arg("encrypt")
+                    .arg(format!("--name={}", self.credential_name(file)?))
+                    .args(["-", "-"]);
+                command
+            }
+        };
+        self.run(command, content)
+            .with_context(|| format!("Could not encrypt secret for file {}", file.display()))
+    }
+
+    /// The name embedded into the systemd credential stored at `file`
+    fn credential_name(&self, file: &Path) -> anyhow::Result<String> {
+        if let Encryption::SystemdCreds { name: Some(name) } = self {
+            return Ok(name.clone());
+        }
+        file.file_name()
+            .and_then(|name| name.to_str())
+            .map(ToOwned::to_owned)
+            .with_context(|| format!("Secret file path {} has no UTF-8 file name", file.display()))
+    }
+
+    fn run(&self, mut command: Command, content: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let program = match self {
+            Encryption::Age { .. } => "age",
+            Encryption::SystemdCreds { .. } => "systemd-creds",
+        };
+        command.stdout(Stdio::piped()).stderr(Stdio::inherit());
+        // decrypting and encrypting is quick and thus not worth to be interrupted by canceled requests
+        run_command_with_input(command, Some(content), program, &AtomicBool::new(false))
+    }
+}
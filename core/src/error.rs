@@ -0,0 +1,118 @@
+use std::{fmt, io};
+
+/// Stable classification of failures which is surfaced to automation in logs, D-Bus error replies and exit codes
+///
+/// The string and numeric codes of existing variants never change.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum ErrorCode {
+    /// Any failure that is not classified more specifically
+    Internal,
+    /// A config file or entry could not be loaded or failed validation
+    ConfigInvalid,
+    /// A backing file does not exist
+    FileMissing,
+    /// A backing file exists but could not be accessed
+    FileInaccessible,
+    /// The signature of a backing file is missing or invalid
+    SignatureInvalid,
+    /// A command, plugin or other source failed to provide a value
+    SourceFailed,
+    /// The D-Bus daemon could not be reached
+    BusUnavailable,
+    /// NetworkManager is not running or does not answer
+    NmUnreachable,
+    /// The caller or the user on whose behalf it calls is not authorized
+    NotAuthorized,
+    /// No secrets are configured for the request
+    NoSecrets,
+    /// The request was canceled
+    Canceled,
+    /// The caller exceeded the configured rate limit
+    RateLimited,
+    /// The request sent by NetworkManager could not be interpreted
+    InvalidRequest,
+    /// The value of an entry is past its `not_after` deadline
+    Expired,
+}
+
+/// A failure together with the code that classifies it
+///
+/// It is meant to be attached to errors like any other context, e.g.
+/// `.context(Error::new(ErrorCode::ConfigInvalid, "Could not load config"))`, so that the message is unchanged.
+#[derive(Debug, thiserror::Error)]
+#[error("{message}")]
+pub struct Error {
+    pub code: ErrorCode,
+    message: String,
+}
+
+impl Error {
+    pub fn new(code: ErrorCode, message: impl fmt::Display) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl ErrorCode {
+    /// The string code, e.g. `config-invalid`
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::Internal => "internal",
+            ErrorCode::ConfigInvalid => "config-invalid",
+            ErrorCode::FileMissing => "file-missing",
+            ErrorCode::FileInaccessible => "file-inaccessible",
+            ErrorCode::SignatureInvalid => "signature-invalid",
+            ErrorCode::SourceFailed => "source-failed",
+            ErrorCode::BusUnavailable => "bus-unavailable",
+            ErrorCode::NmUnreachable => "nm-unreachable",
+            ErrorCode::NotAuthorized => "not-authorized",
+            ErrorCode::NoSecrets => "no-secrets",
+            ErrorCode::Canceled => "canceled",
+            ErrorCode::RateLimited => "rate-limited",
+            ErrorCode::InvalidRequest => "invalid-request",
+            ErrorCode::Expired => "expired",
+        }
+    }
+
+    /// The numeric code which is also used as exit code of the CLI
+    ///
+    /// 2 is left out since it is the exit code of usage errors.
+    pub fn number(self) -> u8 {
+        match self {
+            ErrorCode::Internal => 1,
+            ErrorCode::ConfigInvalid => 3,
+            ErrorCode::FileMissing => 4,
+            ErrorCode::FileInaccessible => 5,
+            ErrorCode::SignatureInvalid => 6,
+            ErrorCode::SourceFailed => 7,
+            ErrorCode::BusUnavailable => 8,
+            ErrorCode::NmUnreachable => 9,
+            ErrorCode::NotAuthorized => 10,
+            ErrorCode::NoSecrets => 11,
+            ErrorCode::Canceled => 12,
+            ErrorCode::RateLimited => 13,
+            ErrorCode::InvalidRequest => 14,
+            ErrorCode::Expired => 15,
+        }
+    }
+
+    /// Classify `error` by the outermost [`Error`] in its chain or the kind of an I/O error it was caused by
+    pub fn of(error: &anyhow::Error) -> Self {
+        if let Some(error) = error.downcast_ref::<Error>() {
+            return error.code;
+        }
+        match error.downcast_ref::<io::Error>().map(io::Error::kind) {
+            Some(io::ErrorKind::NotFound) => ErrorCode::FileMissing,
+            Some(io::ErrorKind::PermissionDenied) => ErrorCode::FileInaccessible,
+            _ => ErrorCode::Internal,
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
@@ -0,0 +1,149 @@
+use std::{
+    process::{Command, Stdio},
+    sync::{atomic::AtomicBool, Arc},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::mapping::run_command_with_input;
+
+/// How long an event hook may run if no other timeout is configured
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Something that happened in the agent to which event hooks can react
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Event {
+    /// Secrets were returned to NetworkManager
+    SecretsServed,
+    /// A request could not be served for another reason than the agent having no secrets for it
+    RequestFailed,
+    /// The registration with NetworkManager was lost, either because NetworkManager dropped it or because the
+    /// connection to the D-Bus daemon was lost
+    RegistrationLost,
+    /// The config was reloaded successfully
+    ConfigReloaded,
+    /// Reloading the config failed so that the previous config stays active
+    ReloadFailed,
+}
+
+impl Event {
+    /// The name of the event as used in configs and payloads, e.g. `secrets-served`
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Event::SecretsServed => "secrets-served",
+            Event::RequestFailed => "request-failed",
+            Event::RegistrationLost => "registration-lost",
+            Event::ConfigReloaded => "config-reloaded",
+            Event::ReloadFailed => "reload-failed",
+        }
+    }
+}
+
+/// A command or webhook which is notified about events with a JSON payload
+///
+/// The payload is an object with the `event` name, the Unix `timestamp` in seconds and the details of the event. It
+/// never contains secret values.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EventHook {
+    /// Events on which the hook is fired or all events if empty
+    #[serde(default)]
+    events: Vec<Event>,
+    /// Command and arguments which receive the payload on stdin
+    command: Option<Vec<String>>,
+    /// URL to which the payload is POSTed with `curl`
+    url: Option<String>,
+    /// Number of seconds after which the command or request is aborted
+    timeout: Option<u64>,
+}
+
+impl EventHook {
+    /// Check that the hook is either a command or a webhook
+    pub(crate) fn check(&self) -> anyhow::Result<()> {
+        match (&self.command, &self.url) {
+            (Some(command), None) if !command.is_empty() => Ok(()),
+            (Some(_), None) => anyhow::bail!("The command of an event hook must not be empty"),
+            (None, Some(url)) if url.starts_with("https://") || url.starts_with("http://") => {
+                Ok(())
+            }
+            (None, Some(url)) => anyhow::bail!("Event hook URL {url:?} is not an http(s) URL"),
+            _ => anyhow::bail!("An event hook must specify exactly one of command or url"),
+        }
+    }
+
+    /// Whether the hook is fired on `event`
+    pub fn handles(&self, event: Event) -> bool {
+        self.events.is_empty() || self.events.contains(&event)
+    }
+
+    /// Run the hook for `event` with `details` merged into its payload in the background
+    ///
+    /// Failures are only logged since hooks must never delay or break serving secrets.
+    pub fn fire(&self, event: Event, details: &serde_json::Value) {
+        let mut payload = serde_json::json!({
+            "event": event,
+            "timestamp": SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        });
+        if let (Some(payload), Some(details)) = (payload.as_object_mut(), details.as_object()) {
+            payload.extend(details.clone());
+        }
+        let hook = self.clone();
+        thread::spawn(move || {
+            if let Err(e) = hook.run(event, &payload) {
+                tracing::warn!("Event hook for {} failed: {e:#}", event.as_str());
+            }
+        });
+    }
+
+    fn run(&self, event: Event, payload: &serde_json::Value) -> anyhow::Result<()> {
+        let (mut command, name) = match (&self.command, &self.url) {
+            (Some(command), _) => {
+                let (program, args) = command
+                    .split_first()
+                    .expect("empty event hook commands are rejected when loading the config");
+                let mut result = Command::new(program);
+                result.args(args).env("NM_EVENT", event.as_str());
+                (result, format!("event hook {program}"))
+            }
+            (None, Some(url)) => {
+                let mut result = Command::new("curl");
+                result
+                    .args([
+                        "--fail",
+                        "--silent",
+                        "--show-error",
+                        "--output",
+                        "/dev/null",
+                    ])
+                    .args(["--header", "Content-Type: application/json"])
+                    .args(["--data-binary", "@-"])
+                    .arg(url);
+                (result, format!("webhook {url}"))
+            }
+            (None, None) => {
+                unreachable!("event hooks without a target are rejected when loading the config")
+            }
+        };
+        command.stdout(Stdio::null()).stderr(Stdio::inherit());
+        let timeout = self.timeout.map_or(DEFAULT_TIMEOUT, Duration::from_secs);
+        // the hook is aborted like a canceled request once its timeout has passed
+        let expired = Arc::new(AtomicBool::new(false));
+        thread::spawn({
+            let expired = expired.clone();
+            move || {
+                thread::sleep(timeout);
+                expired.store(true, std::sync::atomic::Ordering::Relaxed);
+            }
+        });
+        let input = serde_json::to_vec(payload)?;
+        run_command_with_input(command, Some(&input), &name, &expired)?;
+        tracing::debug!("Ran {name} for {}", event.as_str());
+        Ok(())
+    }
+}
@@ -0,0 +1,82 @@
+use std::{
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use serde::Deserialize;
+use toml::value::{Datetime, Offset};
+
+/// A point in time after which the value of an entry must not be served anymore, e.g. its rotation deadline
+///
+/// It is written as RFC 3339 timestamp with time zone like `2025-12-31T00:00:00Z`.
+#[derive(Clone, Eq, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Expiry {
+    /// The timestamp as it was configured
+    text: String,
+    at: SystemTime,
+}
+
+impl Expiry {
+    /// Whether the expiry lies before `now`
+    pub fn is_past(&self, now: SystemTime) -> bool {
+        self.at < now
+    }
+}
+
+impl TryFrom<String> for Expiry {
+    type Error = anyhow::Error;
+
+    fn try_from(text: String) -> anyhow::Result<Self> {
+        let datetime: Datetime = text
+            .parse()
+            .with_context(|| format!("{text:?} is not an RFC 3339 timestamp"))?;
+        let (Some(date), Some(time), Some(offset)) =
+            (datetime.date, datetime.time, datetime.offset)
+        else {
+            anyhow::bail!(
+                "{text:?} must specify a date, time and time zone like 2025-12-31T00:00:00Z"
+            );
+        };
+        let offset_minutes = match offset {
+            Offset::Z => 0,
+            Offset::Custom { minutes } => i64::from(minutes),
+        };
+        let seconds = days_since_epoch(i64::from(date.year), date.month.into(), date.day.into())
+            * 86400
+            + i64::from(time.hour) * 3600
+            + i64::from(time.minute) * 60
+            + i64::from(time.second)
+            - offset_minutes * 60;
+        let seconds = u64::try_from(seconds)
+            .ok()
+            .with_context(|| format!("{text:?} lies before 1970"))?;
+        let at = UNIX_EPOCH + Duration::new(seconds, time.nanosecond);
+        Ok(Self { text, at })
+    }
+}
+
+impl fmt::Debug for Expiry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.text, f)
+    }
+}
+
+impl fmt::Display for Expiry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+/// Number of days between 1970-01-01 and the given date of the proleptic Gregorian calendar
+///
+/// See [Howard Hinnant's algorithms](https://howardhinnant.github.io/date_algorithms.html#days_from_civil).
+fn days_since_epoch(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
@@ -0,0 +1,266 @@
+use std::{fmt, path::Path};
+
+use crate::mapping::{MappingEntry, SecretRequest, SecretValue};
+
+/// Maximum number of operations a single call into the hook may perform before it is aborted
+#[cfg(feature = "scripting")]
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+/// A Rhai script which can adjust which entries match a request and transform the values they provide
+///
+/// The script may define `fn matches(request, entry)` which decides whether an entry matches and
+/// `fn transform(request, entry, value)` which returns the value that is served instead of the one read by the entry.
+/// Both are optional.
+pub struct Hook {
+    #[cfg(feature = "scripting")]
+    engine: rhai::Engine,
+    #[cfg(feature = "scripting")]
+    ast: rhai::AST,
+    has_matches: bool,
+    has_transform: bool,
+}
+
+impl fmt::Debug for Hook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hook")
+            .field("has_matches", &self.has_matches)
+            .field("has_transform", &self.has_transform)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(not(feature = "scripting"))]
+impl Hook {
+    pub fn from_file(_path: &Path) -> anyhow::Result<Self> {
+        anyhow::bail!(
+            "Hooks are not supported because the agent was built without the scripting feature"
+        )
+    }
+
+    pub fn matches(
+        &self,
+        _index: usize,
+        _entry: &MappingEntry,
+        _request: &SecretRequest,
+        matched: bool,
+    ) -> bool {
+        matched
+    }
+
+    pub fn transform(
+        &self,
+        _index: usize,
+        _entry: &MappingEntry,
+        _request: &SecretRequest,
+        value: SecretValue,
+    ) -> anyhow::Result<SecretValue> {
+        Ok(value)
+    }
+}
+
+#[cfg(feature = "scripting")]
+impl Hook {
+    /// Compile the script at `path`
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        use std::io::Read;
+
+        use anyhow::Context;
+
+        let mut script = String::new();
+        crate::reader::open(path, 0)
+            .context("Could not open hook script")?
+            .read_to_string(&mut script)
+            .context("Could not read hook script")?;
+        let mut engine = rhai::Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.register_fn("local_time", local_time);
+        let ast = engine
+            .compile(&script)
+            .map_err(|e| anyhow::anyhow!("Could not compile hook script: {e}"))?;
+        let defines = |name: &str, params: usize| {
+            ast.iter_functions()
+                .any(|function| function.name == name && function.params.len() == params)
+        };
+        let has_matches = defines("matches", 2);
+        let has_transform = defines("transform", 3);
+        if !has_matches && !has_transform {
+            tracing::warn!(
+                "Hook script {} defines neither matches(request, entry) nor transform(request, entry, value)",
+                path.display()
+            );
+        }
+        Ok(Self {
+            engine,
+            ast,
+            has_matches,
+            has_transform,
+        })
+    }
+
+    /// Whether entry number `index` matches `request` given that its match settings evaluate to `matched`
+    ///
+    /// Failures of the script are logged and cause the entry not to match.
+    pub fn matches(
+        &self,
+        index: usize,
+        entry: &MappingEntry,
+        request: &SecretRequest,
+        matched: bool,
+    ) -> bool {
+        if !self.has_matches {
+            return matched;
+        }
+        let mut entry = entry_map(index, entry);
+        entry.insert("matched".into(), matched.into());
+        match self.engine.call_fn::<bool>(
+            &mut rhai::Scope::new(),
+            &self.ast,
+            "matches",
+            (request_map(request), entry),
+        ) {
+            Ok(result) => {
+                if result != matched {
+                    tracing::debug!(
+                        index,
+                        matched,
+                        result,
+                        "Hook overrode whether entry matches"
+                    );
+                }
+                result
+            }
+            Err(e) => {
+                tracing::error!("Hook failed to decide whether config entry {index} matches: {e}");
+                false
+            }
+        }
+    }
+
+    /// The value that entry number `index` serves to `request` instead of `value`
+    pub fn transform(
+        &self,
+        index: usize,
+        entry: &MappingEntry,
+        request: &SecretRequest,
+        value: SecretValue,
+    ) -> anyhow::Result<SecretValue> {
+        if !self.has_transform {
+            return Ok(value);
+        }
+        let result = self
+            .engine
+            .call_fn::<rhai::Dynamic>(
+                &mut rhai::Scope::new(),
+                &self.ast,
+                "transform",
+                (
+                    request_map(request),
+                    entry_map(index, entry),
+                    to_dynamic(value),
+                ),
+            )
+            .map_err(|e| {
+                anyhow::anyhow!("Hook failed to transform value of {}: {e}", entry.key())
+            })?;
+        from_dynamic(result).ok_or_else(|| {
+            anyhow::anyhow!(
+                "Hook transformed the value of {} into an unsupported type",
+                entry.key()
+            )
+        })
+    }
+}
+
+/// Information about `request` as it is passed to the hook
+#[cfg(feature = "scripting")]
+fn request_map(request: &SecretRequest) -> rhai::Map {
+    let ssid = match &request.ssid {
+        Some(ssid) => String::from_utf8_lossy(ssid).into_owned().into(),
+        None => rhai::Dynamic::UNIT,
+    };
+    rhai::Map::from_iter([
+        ("connection_id".into(), request.conn_id.clone().into()),
+        ("connection_uuid".into(), request.conn_uuid.clone().into()),
+        ("connection_type".into(), request.conn_type.clone().into()),
+        ("iface".into(), request.iface_name.clone().into()),
+        ("setting_name".into(), request.setting_name.clone().into()),
+        ("ssid".into(), ssid),
+        ("hints".into(), request.hints.clone().into()),
+        ("request_new".into(), request.request_new.into()),
+        ("user_requested".into(), request.user_requested.into()),
+    ])
+}
+
+/// Information about the entry number `index` as it is passed to the hook
+#[cfg(feature = "scripting")]
+fn entry_map(index: usize, entry: &MappingEntry) -> rhai::Map {
+    let mut map = rhai::Map::from_iter([
+        ("index".into(), (index as rhai::INT).into()),
+        (
+            "id".into(),
+            entry
+                .id()
+                .map_or(rhai::Dynamic::UNIT, |id| id.to_owned().into()),
+        ),
+        ("key".into(), entry.key().to_owned().into()),
+        ("source".into(), entry.source_type().into()),
+    ]);
+    for (name, value) in entry.match_criteria() {
+        map.insert(name.into(), value.to_owned().into());
+    }
+    map
+}
+
+#[cfg(feature = "scripting")]
+fn to_dynamic(value: SecretValue) -> rhai::Dynamic {
+    match value {
+        SecretValue::String(value) => value.into(),
+        SecretValue::U32(value) => rhai::INT::from(value).into(),
+        SecretValue::Bool(value) => value.into(),
+        SecretValue::StringList(value) => value.into(),
+        SecretValue::Bytes(value) => rhai::Blob::from(value).into(),
+    }
+}
+
+#[cfg(feature = "scripting")]
+fn from_dynamic(value: rhai::Dynamic) -> Option<SecretValue> {
+    if value.is_string() {
+        return value.into_string().ok().map(SecretValue::String);
+    }
+    if value.is_blob() {
+        return value.try_cast::<rhai::Blob>().map(SecretValue::Bytes);
+    }
+    if let Some(value) = value.clone().try_cast::<rhai::INT>() {
+        return u32::try_from(value).ok().map(SecretValue::U32);
+    }
+    if let Some(value) = value.clone().try_cast::<bool>() {
+        return Some(SecretValue::Bool(value));
+    }
+    value
+        .try_cast::<rhai::Array>()?
+        .into_iter()
+        .map(|item| item.into_string().ok())
+        .collect::<Option<Vec<_>>>()
+        .map(SecretValue::StringList)
+}
+
+/// The current local time as a map that is available to hooks as `local_time()`
+#[cfg(feature = "scripting")]
+fn local_time() -> rhai::Map {
+    // SAFETY: time accepts a null pointer in which case it only returns the time
+    let now = unsafe { libc::time(std::ptr::null_mut()) };
+    // SAFETY: an all-zero tm is valid and localtime_r only writes into it
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    // SAFETY: both pointers are valid for the duration of the call
+    unsafe { libc::localtime_r(&now, &mut tm) };
+    rhai::Map::from_iter([
+        ("year".into(), rhai::INT::from(tm.tm_year + 1900).into()),
+        ("month".into(), rhai::INT::from(tm.tm_mon + 1).into()),
+        ("day".into(), rhai::INT::from(tm.tm_mday).into()),
+        ("hour".into(), rhai::INT::from(tm.tm_hour).into()),
+        ("minute".into(), rhai::INT::from(tm.tm_min).into()),
+        ("second".into(), rhai::INT::from(tm.tm_sec).into()),
+        // 0 is sunday
+        ("weekday".into(), rhai::INT::from(tm.tm_wday).into()),
+    ])
+}
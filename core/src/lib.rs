@@ -0,0 +1,56 @@
+//! Matching, configuration and encoding of NetworkManager secrets
+//!
+//! This crate contains everything that nm-file-secret-agent does to answer a secret request apart from talking to
+//! NetworkManager over D-Bus. It can be used to build other agents or tools that share its config format:
+//!
+//! - [`mapping`] loads config files into a [`MappingConfig`](mapping::MappingConfig) and resolves the values of all
+//!   entries that match a [`SecretRequest`](mapping::SecretRequest).
+//! - [`encoder`] turns resolved values into the nested settings map that NetworkManager expects and back.
+//! - [`source::SecretSource`] allows entries to read their values from sources other than files.
+//!
+//! ```no_run
+//! use std::{path::PathBuf, sync::atomic::AtomicBool};
+//!
+//! use nm_secret_agent_core::{encoder, mapping::{MappingConfig, SecretRequest, TagFilter, ValidationMode}};
+//!
+//! let config = MappingConfig::load(
+//!     &[PathBuf::from("config.toml")],
+//!     None,
+//!     &[],
+//!     &TagFilter::default(),
+//!     ValidationMode::Strict,
+//! )?;
+//! let request = SecretRequest {
+//!     conn_uuid: "d249c3ba-8e0a-4d35-9b6c-2a6f1e1d2c3b".to_owned(),
+//!     setting_name: "wireguard".to_owned(),
+//!     ..Default::default()
+//! };
+//! let secrets = config.get_secrets(&request, false, &AtomicBool::new(false))?;
+//! let settings = encoder::encode_secrets(&request, &secrets)?;
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! Secret files may be opened through a privilege-separated helper process, see [`reader`].
+
+pub mod cache;
+pub mod checks;
+pub mod encoder;
+pub mod encryption;
+pub mod error;
+pub mod events;
+pub mod expiry;
+pub mod hook;
+pub mod mapping;
+pub mod placeholder;
+pub mod plugin;
+pub mod privileges;
+pub mod prompt;
+pub mod reader;
+pub mod redact;
+pub mod remote;
+pub mod signature;
+pub mod source;
+pub mod template;
+pub mod totp;
+pub mod transform;
+pub mod wasm;
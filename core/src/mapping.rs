@@ -0,0 +1,2325 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    fs::{self, File},
+    io::{Read, Write},
+    os::{
+        fd::AsRawFd,
+        unix::fs::{MetadataExt, OpenOptionsExt, PermissionsExt},
+    },
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use uuid::Uuid;
+
+use crate::{
+    cache::{FileCache, PreloadStore, TtlCache},
+    checks,
+    encryption::Encryption,
+    error::{Error, ErrorCode},
+    events::{Event, EventHook},
+    expiry::Expiry,
+    hook::Hook,
+    placeholder,
+    plugin::{self, PluginSource},
+    prompt::Prompt,
+    reader::{self, AllowedPath},
+    redact, remote,
+    signature::SignatureKey,
+    source::SecretSource,
+    template::Template,
+    totp::TotpSource,
+    transform::{self, Encoding, Transform, TrimMode},
+};
+
+/// Maximum size of secret files in bytes if no other limit is configured
+const DEFAULT_MAX_SIZE: u64 = 1024 * 1024;
+
+/// Maximum number of entries whose values are read concurrently while answering a single request
+const MAX_PARALLEL_READS: usize = 8;
+
+/// Counter which makes the names of temporary files unique among concurrent saves of this process
+static TMP_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Config file that is loaded if no config is given explicitly
+pub const DEFAULT_CONFIG_FILE: &str = "/etc/nm-file-secret-agent/config.toml";
+
+/// Directory whose `*.toml` files are loaded in lexical order after [`DEFAULT_CONFIG_FILE`] if no config is given
+/// explicitly
+pub const DEFAULT_CONFIG_DIR: &str = "/etc/nm-file-secret-agent/conf.d";
+
+/// Directory with one subdirectory of override files per hostname which is used if no config is given explicitly
+pub const DEFAULT_OVERRIDES_DIR: &str = "/etc/nm-file-secret-agent/overrides";
+
+/// Short names that can be used instead of the full keys of match settings in entries given on the command line
+const CLI_ENTRY_ALIASES: &[(&str, &str)] = &[
+    ("setting", "match_setting"),
+    ("uuid", "match_uuid"),
+    ("type", "match_type"),
+    ("iface", "match_iface"),
+];
+
+/// Keys of entries whose values are always strings even if they look like other TOML values, e.g. numeric ids
+const CLI_ENTRY_STRING_KEYS: &[&str] = &[
+    "id",
+    "match_id",
+    "match_uuid",
+    "match_type",
+    "match_iface",
+    "match_setting",
+    "match_vpn_message",
+    "key",
+    "file",
+    "template",
+    "not_after",
+];
+
+/// How strictly the configuration is checked before the agent starts serving requests
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum ValidationMode {
+    /// Any problem with the configuration is fatal
+    #[default]
+    Strict,
+    /// Problems with the configuration are logged as warnings but otherwise ignored
+    Warn,
+    /// The configuration is not validated at all
+    Off,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MappingConfig {
+    /// Values that are applied to all entries of the same config file unless overridden by the entry itself
+    #[serde(default)]
+    defaults: EntryDefaults,
+    #[serde(rename = "entry", default, deserialize_with = "deserialize_entries")]
+    entries: Vec<MappingEntry>,
+    /// Entries which are only used while their profile is selected, by profile name
+    #[serde(default)]
+    profiles: HashMap<String, Profile>,
+    /// Source that is asked for secrets when no entry provides any
+    fallback: Option<Fallback>,
+    /// Command that asks the user for secrets when neither an entry nor the fallback provides any
+    prompt: Option<Prompt>,
+    /// Script which can adjust matches and transform values
+    hook: Option<PathBuf>,
+    /// The compiled `hook`
+    #[serde(skip)]
+    script: Option<Hook>,
+    /// Commands and webhooks which are notified about events of the agent
+    #[serde(default, rename = "event_hook")]
+    event_hooks: Vec<EventHook>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct EntryDefaults {
+    match_id: Option<String>,
+    match_uuid: Option<String>,
+    match_type: Option<String>,
+    match_iface: Option<String>,
+    match_setting: Option<String>,
+    match_vpn_message: Option<String>,
+    trim: Option<TrimMode>,
+    encoding: Option<Encoding>,
+    validate_value: Option<bool>,
+    check_plausibility: Option<bool>,
+    max_size: Option<u64>,
+    transform: Option<Vec<Transform>>,
+    on_request_new: Option<RequestNewPolicy>,
+    renew_command: Option<Vec<String>>,
+    strict_hints: Option<bool>,
+    honor_secret_flags: Option<bool>,
+    cache: Option<bool>,
+    cache_ttl: Option<u64>,
+    preload: Option<bool>,
+    pin: Option<bool>,
+    unsafe_permissions: Option<PermissionPolicy>,
+    follow_symlinks: Option<bool>,
+    allowed_dirs: Option<Vec<PathBuf>>,
+    signature_key: Option<PathBuf>,
+    encryption: Option<Encryption>,
+    polkit_action: Option<String>,
+    tags: Option<Vec<String>>,
+}
+
+/// Entries of a config file which are only used while the profile is selected
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Profile {
+    #[serde(rename = "entry", default, deserialize_with = "deserialize_entries")]
+    entries: Vec<MappingEntry>,
+}
+
+/// Which entries are kept when a config is loaded based on their `tags`
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct TagFilter {
+    /// Only entries with at least one of these tags are kept unless this is empty
+    pub only: Vec<String>,
+    /// Entries with any of these tags are dropped, even if they also have one of the `only` tags
+    pub exclude: Vec<String>,
+}
+
+impl TagFilter {
+    /// Whether an entry with `tags` is kept
+    pub fn keeps(&self, tags: &[String]) -> bool {
+        (self.only.is_empty() || tags.iter().any(|tag| self.only.contains(tag)))
+            && !tags.iter().any(|tag| self.exclude.contains(tag))
+    }
+}
+
+/// A derivation that computes the returned secret value from the configured one
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Derivation {
+    /// Compute the 64 hex character WPA pre-shared key from a passphrase and the SSID of the connection
+    WpaPsk,
+}
+
+/// How an entry handles requests in which NetworkManager asks for new secrets because it considers the existing ones
+/// invalid
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RequestNewPolicy {
+    /// The entry does not provide any secrets to such requests
+    #[default]
+    NoSecrets,
+    /// The backing files are read again since the secret may have been rotated in the meantime
+    Reread,
+    /// The configured `renew_command` is run before the backing files are read again
+    Command,
+}
+
+/// How an entry treats backing files which other users can read or modify
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PermissionPolicy {
+    /// The permissions of backing files are not checked
+    Ignore,
+    /// Unsafe permissions are logged as a warning but the file is read anyway
+    #[default]
+    Warn,
+    /// Files with unsafe permissions are not read
+    Refuse,
+}
+
+/// Another secret source to which requests are forwarded if no entry provides secrets for them
+#[derive(Debug, Clone, Deserialize)]
+pub struct Fallback {
+    /// Command and arguments which print the secrets of a request as a JSON object on stdout
+    command: Vec<String>,
+}
+
+/// The D-Bus type as which a secret value is returned to NetworkManager
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DbusType {
+    /// A plain string (`s`)
+    #[default]
+    String,
+    /// An unsigned 32-bit integer (`u`)
+    U32,
+    /// A boolean (`b`) which is read from `true`/`false`, `yes`/`no` or `1`/`0`
+    Bool,
+    /// A list of strings (`as`) which is read with one item per line
+    #[serde(rename = "as")]
+    StringList,
+    /// A byte array (`ay`) containing the raw value which does not need to be valid UTF-8
+    #[serde(rename = "ay")]
+    Bytes,
+}
+
+/// Information about a connection for which NetworkManager requests secrets
+///
+/// It contains no secret values, so it can be serialized to record requests for later replay.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SecretRequest {
+    pub conn_id: String,
+    pub conn_uuid: String,
+    pub conn_type: String,
+    pub iface_name: String,
+    pub setting_name: String,
+    /// SSID of the connection if it is a wifi connection
+    pub ssid: Option<Vec<u8>>,
+    /// Whether NetworkManager considers previously provided secrets invalid and asks for new ones
+    pub request_new: bool,
+    /// Whether the request was initiated by a user action instead of automatically by NetworkManager
+    pub user_requested: bool,
+    /// Whether NetworkManager allows asking the user for secrets
+    pub allow_interaction: bool,
+    /// Keys which NetworkManager hinted at being required
+    pub hints: Vec<String>,
+    /// Keys whose secret flags in the connection profile indicate that they are not provided by agents
+    pub not_agent_owned: Vec<String>,
+    /// Public keys of all peers in the order in which they are configured if it is a wireguard connection
+    pub wireguard_peers: Vec<String>,
+}
+
+impl SecretRequest {
+    /// Whether NetworkManager hinted at `key` being required
+    pub fn is_hinted(&self, key: &str) -> bool {
+        self.key_hints().any(|hint| key_matches_hint(key, hint))
+    }
+
+    /// All hints that name required keys
+    ///
+    /// VPN plugins additionally pass information like the message that should be shown to the user as `x-vpn-<name>:<value>`
+    /// hints which are not included here.
+    pub fn key_hints(&self) -> impl Iterator<Item = &str> {
+        self.hints
+            .iter()
+            .map(String::as_str)
+            .filter(|hint| !hint.starts_with("x-vpn-"))
+    }
+
+    /// The message that a VPN plugin would show to the user when asking for secrets
+    pub fn vpn_message(&self) -> Option<&str> {
+        self.hints
+            .iter()
+            .find_map(|hint| hint.strip_prefix("x-vpn-message:"))
+    }
+}
+
+/// Whether the configured `key` provides the value that NetworkManager asks for with `hint`
+///
+/// VPN secrets are configured as `secrets.<name>` but hinted at only by their name so both forms are accepted.
+pub fn key_matches_hint(key: &str, hint: &str) -> bool {
+    key == hint || key.strip_prefix("secrets.") == Some(hint)
+}
+
+/// A secret value that is ready to be returned to NetworkManager
+///
+/// Its `Debug` implementation never prints the value itself so that it cannot accidentally end up in log messages.
+#[derive(Clone, Eq, PartialEq)]
+pub enum SecretValue {
+    String(String),
+    U32(u32),
+    Bool(bool),
+    StringList(Vec<String>),
+    Bytes(Vec<u8>),
+}
+
+impl fmt::Debug for SecretValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let variant = match self {
+            SecretValue::String(_) => "String",
+            SecretValue::U32(_) => "U32",
+            SecretValue::Bool(_) => "Bool",
+            SecretValue::StringList(_) => "StringList",
+            SecretValue::Bytes(_) => "Bytes",
+        };
+        write!(f, "{variant}(<redacted>)")
+    }
+}
+
+impl SecretValue {
+    /// Serialize this value in the form in which [`DbusType::parse`] reads it from files
+    fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            SecretValue::String(value) => value.as_bytes().to_vec(),
+            SecretValue::U32(value) => value.to_string().into_bytes(),
+            SecretValue::Bool(value) => value.to_string().into_bytes(),
+            SecretValue::StringList(value) => value.join("\n").into_bytes(),
+            SecretValue::Bytes(value) => value.clone(),
+        }
+    }
+
+    /// Convert a JSON value into the secret value it represents
+    ///
+    /// Returns `None` for values that have no equivalent, like objects or negative numbers.
+    fn from_json(value: serde_json::Value) -> Option<Self> {
+        match value {
+            serde_json::Value::String(value) => Some(SecretValue::String(value)),
+            serde_json::Value::Number(value) => Some(SecretValue::U32(
+                value.as_u64().and_then(|i| u32::try_from(i).ok())?,
+            )),
+            serde_json::Value::Bool(value) => Some(SecretValue::Bool(value)),
+            serde_json::Value::Array(items) => Some(SecretValue::StringList(
+                items
+                    .into_iter()
+                    .map(|item| match item {
+                        serde_json::Value::String(item) => Some(item),
+                        _ => None,
+                    })
+                    .collect::<Option<_>>()?,
+            )),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MappingEntry {
+    /// Identifier by which entries of later config files can override this one
+    id: Option<String>,
+    match_id: Option<String>,
+    match_uuid: Option<String>,
+    match_type: Option<String>,
+    match_iface: Option<String>,
+    match_setting: Option<String>,
+    /// Text which must be contained in the `x-vpn-message` hint that a VPN plugin sends along with its request
+    match_vpn_message: Option<String>,
+    trim: Option<TrimMode>,
+    encoding: Option<Encoding>,
+    /// Transformations that are applied to the content of backing files after trimming and decoding
+    transform: Option<Vec<Transform>>,
+    derive: Option<Derivation>,
+    validate_value: Option<bool>,
+    check_plausibility: Option<bool>,
+    /// Maximum size of backing files in bytes
+    max_size: Option<u64>,
+    /// How requests for new secrets are answered
+    on_request_new: Option<RequestNewPolicy>,
+    /// Command that is run to renew the secret before it is re-read if `on_request_new` is `command`
+    renew_command: Option<Vec<String>>,
+    /// Only provide a value if NetworkManager did not send hints or if the key is among them
+    strict_hints: Option<bool>,
+    /// Do not provide a value if the connection profile marks the key as not being agent-owned
+    honor_secret_flags: Option<bool>,
+    /// Write values back into the backing file when NetworkManager asks the agent to save secrets
+    writable: Option<bool>,
+    /// Keep the content of backing files in memory for as long as the files do not change
+    cache: Option<bool>,
+    /// Number of seconds for which a resolved value is reused for further requests of the same connection and setting
+    cache_ttl: Option<u64>,
+    /// Read the backing files once when the config is loaded and never access them again afterwards
+    preload: Option<bool>,
+    /// Lock the preloaded content into RAM so that it cannot be swapped out
+    pin: Option<bool>,
+    /// What happens when a backing file is owned by another user or readable or writable by everyone
+    unsafe_permissions: Option<PermissionPolicy>,
+    /// Open backing files even if the last component of their path is a symlink
+    follow_symlinks: Option<bool>,
+    /// Directories inside which the canonical paths of all backing files must be located
+    allowed_dirs: Option<Vec<PathBuf>>,
+    /// Public key with which a minisign or signify signature of every backing file must be verifiable
+    signature_key: Option<PathBuf>,
+    /// How the backing files are encrypted at rest
+    encryption: Option<Encryption>,
+    /// Polkit action for which the user must be authorized before the value is served for a user-requested activation
+    polkit_action: Option<String>,
+    /// Deadline after which the value is refused because it should have been rotated
+    not_after: Option<Expiry>,
+    /// Labels by which entries are selected at startup, e.g. per machine role
+    tags: Option<Vec<String>>,
+    #[serde(default)]
+    dbus_type: DbusType,
+    key: String,
+    file: Option<PathBuf>,
+    template: Option<Template>,
+    totp: Option<TotpSource>,
+    /// Executable of an out-of-process plugin which provides the value
+    plugin: Option<PathBuf>,
+    /// Directory with one file per wireguard peer which is named after the peer's public key and contains its value
+    peers_dir: Option<PathBuf>,
+    /// Configuration that is passed to the plugin during the handshake
+    plugin_config: Option<toml::Table>,
+    /// Number of seconds within which the plugin must answer the handshake and each request
+    plugin_timeout: Option<u64>,
+    #[serde(skip)]
+    file_cache: FileCache,
+    /// Resolved values by connection uuid and setting name if `cache_ttl` is set
+    #[serde(skip)]
+    value_cache: Option<TtlCache<(String, String), SecretValue>>,
+    /// Contents of the backing files if `preload` is set
+    #[serde(skip)]
+    preloaded: PreloadStore,
+    #[serde(skip)]
+    stats: EntryStats,
+    /// The key loaded from `signature_key`
+    #[serde(skip)]
+    verifier: Option<SignatureKey>,
+    /// The started `plugin` or a source that was attached programmatically with [`MappingConfig::add_entry`]
+    #[serde(skip)]
+    source: Option<Arc<dyn SecretSource>>,
+    /// Name under which the value is looked up in the `KEY=VALUE` lines of `file` if the entry was declared with `keys`
+    #[serde(skip)]
+    file_key: Option<String>,
+    /// Whether `file` contains placeholders which are expanded for every request
+    #[serde(skip)]
+    file_placeholders: bool,
+    /// Backing files whose byte order mark or CRLF line endings have already been warned about
+    #[serde(skip)]
+    normalized_files: Mutex<HashSet<PathBuf>>,
+}
+
+/// Usage counters of an entry since the config was loaded
+#[derive(Debug, Default)]
+pub struct EntryStats {
+    /// Number of requests that the entry matched
+    pub matched: AtomicU64,
+    /// Number of requests for which the entry provided a value
+    pub served: AtomicU64,
+    /// Number of times reading the value of the entry failed
+    pub read_failures: AtomicU64,
+    /// Unix timestamp in seconds of the last time the entry provided a value or 0 if it never did
+    pub last_used: AtomicU64,
+}
+
+impl EntryStats {
+    /// Count a value that the entry provided just now
+    fn record_served(&self) {
+        self.served.fetch_add(1, Ordering::Relaxed);
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        self.last_used.store(now.as_secs(), Ordering::Relaxed);
+    }
+}
+
+impl MappingConfig {
+    /// Read and merge the mapping configurations from all files located at `paths`
+    ///
+    /// Entries are merged in order so that entries of later files replace earlier entries which have the same `id`.
+    /// All other entries are appended.
+    pub fn from_files(paths: &[PathBuf]) -> anyhow::Result<Self> {
+        let mut result = Self {
+            defaults: EntryDefaults::default(),
+            entries: Vec::new(),
+            profiles: HashMap::new(),
+            fallback: None,
+            prompt: None,
+            hook: None,
+            script: None,
+            event_hooks: Vec::new(),
+        };
+        for path in paths {
+            let config = Self::from_file(path).with_context(|| {
+                Error::new(
+                    ErrorCode::ConfigInvalid,
+                    format!("Could not load config file {}", path.display()),
+                )
+            })?;
+            result.merge(config);
+        }
+        Ok(result)
+    }
+
+    /// Read and merge the config files at `paths` followed by the entries of `profile` and the `cli_entries`, drop the
+    /// entries that `tags` does not keep, validate the result according to `mode` and preload secrets
+    ///
+    /// Entries are dropped before validation so that they may refer to files which only exist on other machines.
+    pub fn load(
+        paths: &[PathBuf],
+        profile: Option<&str>,
+        cli_entries: &[String],
+        tags: &TagFilter,
+        mode: ValidationMode,
+    ) -> anyhow::Result<Self> {
+        let config = Self::read(paths, profile, cli_entries, tags)?;
+        config.validate(mode).context(Error::new(
+            ErrorCode::ConfigInvalid,
+            "Config validation failed",
+        ))?;
+        config.preload().context("Could not preload secrets")?;
+        Ok(config)
+    }
+
+    /// Read and merge the configuration like [`Self::load`] but without validating it or preloading secrets
+    pub fn read(
+        paths: &[PathBuf],
+        profile: Option<&str>,
+        cli_entries: &[String],
+        tags: &TagFilter,
+    ) -> anyhow::Result<Self> {
+        let mut config = Self::from_files(paths)?;
+        config.select_profile(profile)?;
+        if !cli_entries.is_empty() {
+            config.merge(Self::from_cli_entries(cli_entries).context(Error::new(
+                ErrorCode::ConfigInvalid,
+                "Could not load entries given on the command line",
+            ))?);
+        }
+        let count = config.entries.len();
+        config.entries.retain(|entry| tags.keeps(entry.tags()));
+        if config.entries.len() < count {
+            tracing::info!(
+                "Skipping {} entries because of their tags",
+                count - config.entries.len()
+            );
+        }
+        Ok(config)
+    }
+
+    /// Merge the entries of the profile named `name` like those of a later config file and drop all other profiles
+    fn select_profile(&mut self, name: Option<&str>) -> anyhow::Result<()> {
+        let mut profiles = std::mem::take(&mut self.profiles);
+        let Some(name) = name else {
+            return Ok(());
+        };
+        let Some(profile) = profiles.remove(name) else {
+            anyhow::bail!(Error::new(
+                ErrorCode::ConfigInvalid,
+                format!("Profile {name} is not defined in any config file"),
+            ));
+        };
+        tracing::info!(
+            "Using profile {name} with {} entries",
+            profile.entries.len()
+        );
+        merge_entries(&mut self.entries, profile.entries);
+        Ok(())
+    }
+
+    /// Read the backing files of all entries which are configured to be preloaded
+    ///
+    /// This is done independently of the validation mode since preloaded entries could never be served otherwise.
+    fn preload(&self) -> anyhow::Result<()> {
+        let mut count = 0;
+        for (i, entry) in self
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| entry.preload.unwrap_or(false))
+        {
+            for file in entry.files() {
+                let content = entry.read_file_content(file, true).with_context(|| {
+                    format!("Could not preload file backing secret of config entry {i}")
+                })?;
+                entry
+                    .preloaded
+                    .insert(file, &content, entry.pin.unwrap_or(false))?;
+                count += 1;
+            }
+        }
+        if count > 0 {
+            tracing::info!("Preloaded {count} secret files");
+        }
+        Ok(())
+    }
+
+    /// Merge the entries of `other` into this config
+    ///
+    /// A fallback or hook configured in `other` replaces the one of this config while event hooks are appended.
+    /// Entries, also those of profiles with the same name, are merged with [`merge_entries`].
+    fn merge(&mut self, other: Self) {
+        self.event_hooks.extend(other.event_hooks);
+        for (name, profile) in other.profiles {
+            let entries = &mut self.profiles.entry(name).or_default().entries;
+            merge_entries(entries, profile.entries);
+        }
+        if other.fallback.is_some() {
+            self.fallback = other.fallback;
+        }
+        if other.prompt.is_some() {
+            self.prompt = other.prompt;
+        }
+        if other.hook.is_some() {
+            self.hook = other.hook;
+            self.script = other.script;
+        }
+        merge_entries(&mut self.entries, other.entries);
+    }
+
+    /// Read a mapping configuration from the file located at `path`
+    ///
+    /// If `path` is an `http://` or `https://` URL, the config is fetched with [`remote::fetch`] instead and relative
+    /// paths in it are resolved against the working directory.
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let (buf, base_dir) = match path.to_str().filter(|_| remote::is_url(path)) {
+            Some(url) => (remote::fetch(url)?, Path::new("")),
+            None => {
+                let mut buf = String::new();
+                reader::open(path, 0)
+                    .context("Could not open config file")?
+                    .read_to_string(&mut buf)
+                    .context("Could not read file content")?;
+                (buf, path.parent().unwrap_or(Path::new("")))
+            }
+        };
+        let config: Self = toml::from_str(&buf)
+            .context("Could not parse config file as required TOML data-structure")?;
+        config.finish(base_dir)
+    }
+
+    /// Build a mapping configuration from entries given on the command line
+    ///
+    /// Each entry is a comma-separated list of `key=value` pairs using the same keys as config files, e.g.
+    /// `match_uuid=<uuid>,setting=wireguard,key=private-key,file=/run/key`. Values which are valid TOML (booleans,
+    /// integers, quoted strings and arrays) are used as such and anything else is taken as a literal string.
+    /// Relative paths are resolved against the working directory.
+    pub fn from_cli_entries(specs: &[String]) -> anyhow::Result<Self> {
+        let entries = specs
+            .iter()
+            .enumerate()
+            .map(|(i, spec)| {
+                parse_cli_entry(spec)
+                    .map(toml::Value::Table)
+                    .with_context(|| format!("Could not parse entry {i} given on the command line"))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let mut table = toml::Table::new();
+        table.insert("entry".to_owned(), toml::Value::Array(entries));
+        let config: Self = toml::Value::Table(table)
+            .try_into()
+            .context("Entries given on the command line are invalid")?;
+        config.finish(Path::new(""))
+    }
+
+    /// Check the entries of a freshly parsed config, resolve their paths relative to `base_dir` and fill in defaults
+    fn finish(mut self, base_dir: &Path) -> anyhow::Result<Self> {
+        if self
+            .fallback
+            .as_ref()
+            .is_some_and(|fallback| fallback.command.is_empty())
+        {
+            anyhow::bail!("The fallback command must not be empty");
+        }
+        if let Some(prompt) = &mut self.prompt {
+            prompt.finish()?;
+        }
+        for (i, hook) in self.event_hooks.iter().enumerate() {
+            hook.check()
+                .with_context(|| format!("Event hook {i} is invalid"))?;
+        }
+        if let Some(hook) = &mut self.hook {
+            *hook = resolve_path(hook, base_dir);
+            self.script = Some(
+                Hook::from_file(hook)
+                    .with_context(|| format!("Could not load hook {}", hook.display()))?,
+            );
+        }
+
+        Self::finish_entries(&mut self.entries, &self.defaults, base_dir)?;
+        for (name, profile) in &mut self.profiles {
+            Self::finish_entries(&mut profile.entries, &self.defaults, base_dir)
+                .with_context(|| format!("Profile {name} is invalid"))?;
+        }
+        Ok(self)
+    }
+
+    /// Check freshly parsed `entries`, resolve their paths relative to `base_dir` and fill in unset values from
+    /// `defaults`
+    fn finish_entries(
+        entries: &mut [MappingEntry],
+        defaults: &EntryDefaults,
+        base_dir: &Path,
+    ) -> anyhow::Result<()> {
+        for (i, entry) in entries.iter_mut().enumerate() {
+            let source_count = [
+                entry.file.is_some(),
+                entry.template.is_some(),
+                entry.totp.is_some(),
+                entry.plugin.is_some(),
+                entry.peers_dir.is_some(),
+                entry.source.is_some(),
+            ]
+            .into_iter()
+            .filter(|&is_set| is_set)
+            .count();
+            if source_count != 1 {
+                anyhow::bail!(
+                    "Config entry {i} must specify exactly one of file, template, totp, plugin or peers_dir"
+                );
+            }
+            entry.apply_defaults(defaults);
+            entry.expand_host_variables().with_context(|| {
+                format!("Could not expand the host variables of config entry {i}")
+            })?;
+            if let Some(file) = &mut entry.file {
+                *file = resolve_path(file, base_dir);
+                entry.file_placeholders = placeholder::check(file)
+                    .with_context(|| format!("Config entry {i} has an invalid file path"))?;
+                if entry.file_placeholders && entry.preload.unwrap_or(false) {
+                    anyhow::bail!(
+                        "Config entry {i} uses placeholders in its file path which cannot be preloaded"
+                    );
+                }
+            }
+            if let Some(template) = &mut entry.template {
+                template.map_files(|file| resolve_path(file, base_dir));
+            }
+            if let Some(totp) = &mut entry.totp {
+                totp.secret_file = resolve_path(&totp.secret_file, base_dir);
+                totp.check()
+                    .with_context(|| format!("Config entry {i} has an invalid totp source"))?;
+            }
+            if let Some(dir) = &mut entry.peers_dir {
+                *dir = resolve_path(dir, base_dir);
+                if !entry.key.starts_with("peers.*.") {
+                    anyhow::bail!(
+                        "Config entry {i} reads peers_dir but its key is not of the form peers.*.<property>"
+                    );
+                }
+                if entry.preload.unwrap_or(false) {
+                    anyhow::bail!("Config entry {i} reads peers_dir which cannot be preloaded");
+                }
+            }
+            if let Some(plugin) = &mut entry.plugin {
+                *plugin = resolve_path(plugin, base_dir);
+                let config = serde_json::to_value(entry.plugin_config.take().unwrap_or_default())
+                    .context("Could not convert plugin_config to JSON")?;
+                let timeout = entry
+                    .plugin_timeout
+                    .map_or(plugin::DEFAULT_TIMEOUT, Duration::from_secs);
+                entry.source = Some(Arc::new(PluginSource::new(
+                    plugin.clone(),
+                    entry.key.clone(),
+                    config,
+                    timeout,
+                )));
+            }
+            for transform in entry.transform.iter_mut().flatten() {
+                if let Transform::Wasm(module) = transform {
+                    let path = resolve_path(module.path(), base_dir);
+                    module.load(path).with_context(|| {
+                        format!("Could not load the WASM transformation of config entry {i}")
+                    })?;
+                }
+            }
+            for dir in entry.allowed_dirs.iter_mut().flatten() {
+                *dir = resolve_path(dir, base_dir);
+            }
+            if let Some(path) = &mut entry.signature_key {
+                *path = resolve_path(path, base_dir);
+                entry.verifier = Some(SignatureKey::from_file(path).with_context(|| {
+                    format!("Could not load the signature key of config entry {i}")
+                })?);
+            }
+            entry.value_cache = entry
+                .cache_ttl
+                .filter(|&ttl| ttl > 0)
+                .map(|ttl| TtlCache::new(Duration::from_secs(ttl)));
+            if entry.on_request_new == Some(RequestNewPolicy::Command)
+                && entry.renew_command.as_ref().is_none_or(Vec::is_empty)
+            {
+                anyhow::bail!(
+                    "Config entry {i} uses on_request_new = \"command\" but has no renew_command"
+                );
+            }
+            if let Some(not_after) = entry
+                .not_after
+                .as_ref()
+                .filter(|i| i.is_past(SystemTime::now()))
+            {
+                tracing::warn!(
+                    "Config entry {i} expired at {not_after} so its value is refused until it is rotated and not_after is updated"
+                );
+            }
+            if entry.pin.unwrap_or(false) && !entry.preload.unwrap_or(false) {
+                anyhow::bail!("Config entry {i} sets pin but is not preloaded");
+            }
+            if entry.preload.unwrap_or(false)
+                && entry.on_request_new.unwrap_or_default() != RequestNewPolicy::NoSecrets
+            {
+                anyhow::bail!(
+                    "Config entry {i} is preloaded and therefore cannot read its files again on requests for new secrets"
+                );
+            }
+            if entry.file_key.is_some() && entry.file.is_none() {
+                anyhow::bail!(
+                    "Config entry {i} declares keys but only files can provide multiple keys"
+                );
+            }
+            if entry.writable.unwrap_or(false) {
+                if entry.file_key.is_some() {
+                    anyhow::bail!(
+                        "Config entry {i} is writable but reads one of multiple keys from its file"
+                    );
+                }
+                if entry.file.is_none() {
+                    anyhow::bail!("Config entry {i} is writable but does not read from a file");
+                }
+                if entry.derive.is_some() || entry.transform.as_ref().is_some_and(|i| !i.is_empty())
+                {
+                    anyhow::bail!("Config entry {i} is writable but uses a transform or derive which cannot be reversed");
+                }
+                if entry.signature_key.is_some() {
+                    anyhow::bail!("Config entry {i} is writable but requires signatures which saved values would lack");
+                }
+                if entry
+                    .encryption
+                    .as_ref()
+                    .is_some_and(|encryption| !encryption.can_encrypt())
+                {
+                    anyhow::bail!("Config entry {i} is writable but its age encryption has no recipients to encrypt saved values to");
+                }
+            }
+            if entry.encryption.is_some() && entry.files().is_empty() && entry.peers_dir.is_none() {
+                anyhow::bail!("Config entry {i} sets encryption but does not read from files");
+            }
+        }
+        Ok(())
+    }
+
+    /// Add an entry that reads its value from `source`
+    ///
+    /// The entry is described by comma-separated `key=value` pairs like the entries of [`Self::from_cli_entries`] but
+    /// must not specify a `file`, `template` or `totp`. It is merged like an entry of a later config file.
+    pub fn add_entry(&mut self, spec: &str, source: Arc<dyn SecretSource>) -> anyhow::Result<()> {
+        let mut entry: MappingEntry = toml::Value::Table(parse_cli_entry(spec)?)
+            .try_into()
+            .context("Entry is invalid")?;
+        entry.source = Some(source);
+        let config = Self {
+            defaults: EntryDefaults::default(),
+            entries: vec![entry],
+            profiles: HashMap::new(),
+            fallback: None,
+            prompt: None,
+            hook: None,
+            script: None,
+            event_hooks: Vec::new(),
+        }
+        .finish(Path::new(""))?;
+        self.merge(config);
+        Ok(())
+    }
+
+    /// Validate that all configured secrets can be read and that match settings look plausible
+    ///
+    /// Depending on `mode`, problems are either treated as fatal, only emitted as warnings or not checked at all.
+    pub fn validate(&self, mode: ValidationMode) -> anyhow::Result<()> {
+        if mode == ValidationMode::Off {
+            tracing::debug!("Skipping config validation because it is disabled");
+            return Ok(());
+        }
+
+        let report = |problem: anyhow::Error| -> anyhow::Result<()> {
+            match mode {
+                ValidationMode::Strict => Err(problem),
+                _ => {
+                    tracing::warn!("{problem:#}");
+                    Ok(())
+                }
+            }
+        };
+
+        for (i, entry) in self.entries.iter().enumerate() {
+            // try to open the backing files
+            for file in entry.files() {
+                if entry.is_expanded(file) {
+                    tracing::debug!(
+                        "Not checking file {} of config entry {i} since its placeholders are only expanded for requests",
+                        file.display()
+                    );
+                    continue;
+                }
+                if let Err(e) = entry.open_file(file) {
+                    report(e.context(format!(
+                        "Could not open file backing secret of config entry {i}"
+                    )))?;
+                }
+            }
+
+            if let Some(dir) = &entry.peers_dir {
+                if let Err(e) = fs::read_dir(dir) {
+                    report(anyhow::Error::new(e).context(format!(
+                        "Could not list peer directory {} of config entry {i}",
+                        dir.display()
+                    )))?;
+                }
+            }
+
+            if let Some(plugin) = &entry.plugin {
+                match fs::metadata(plugin) {
+                    Ok(metadata) if metadata.is_file() && metadata.mode() & 0o111 != 0 => {}
+                    Ok(_) => report(anyhow::anyhow!(
+                        "Plugin {} of config entry {i} is not an executable file",
+                        plugin.display()
+                    ))?,
+                    Err(e) => report(anyhow::Error::new(e).context(format!(
+                        "Could not find plugin {} of config entry {i}",
+                        plugin.display()
+                    )))?,
+                }
+            }
+
+            // check that match_uuid looks like a uuid
+            if let Some(match_uuid) = &entry.match_uuid {
+                if Uuid::parse_str(match_uuid).is_err() {
+                    report(anyhow::anyhow!("match_uuid value {match_uuid} of config entry {i} is not a valid uuid and will prevent the entry from matching anything"))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Read the values of all entries that match `request`
+    ///
+    /// The values of multiple entries are read concurrently to keep the latency low on slow storage.
+    /// Resolution is aborted as soon as `cancelled` is set, killing renew commands that are still running. With
+    /// `dry_run`, renew commands are only logged instead of run.
+    pub fn get_secrets(
+        &self,
+        request: &SecretRequest,
+        dry_run: bool,
+        cancelled: &AtomicBool,
+    ) -> anyhow::Result<Vec<(String, SecretValue)>> {
+        let entries = self
+            .matching_entries(request)
+            .inspect(|(_, entry)| {
+                entry.stats.matched.fetch_add(1, Ordering::Relaxed);
+            })
+            .filter(|(_, entry)| match entry.skip_reason(request) {
+                None => true,
+                Some(reason) => {
+                    tracing::debug!(?entry, "Skipping matching secret entry because {reason}");
+                    false
+                }
+            })
+            .collect::<Vec<_>>();
+        parallel_map(&entries, MAX_PARALLEL_READS, |&(index, entry)| {
+            tracing::debug!(?entry, "Found matching secret entry");
+            if cancelled.load(Ordering::Relaxed) {
+                anyhow::bail!(Error::new(
+                    ErrorCode::Canceled,
+                    "Resolving secrets was canceled"
+                ));
+            }
+            if request.request_new && entry.on_request_new == Some(RequestNewPolicy::Command) {
+                match dry_run {
+                    true => tracing::info!(
+                        "Dry run: not running the renew command of config entry {index} for {}.{}",
+                        request.setting_name,
+                        entry.key
+                    ),
+                    false => entry.run_renew_command(request, cancelled)?,
+                }
+            }
+            let value = entry
+                .read(request)
+                .and_then(|value| match &self.script {
+                    None => Ok(value),
+                    Some(hook) => {
+                        redact::register(&value);
+                        hook.transform(index, entry, request, value)
+                    }
+                })
+                .inspect_err(|_| {
+                    entry.stats.read_failures.fetch_add(1, Ordering::Relaxed);
+                })?;
+            redact::register(&value);
+            entry.stats.record_served();
+            Ok((entry.key.to_owned(), value))
+        })
+        .into_iter()
+        .collect::<Result<Vec<_>>>()
+    }
+
+    /// Ask the configured fallback source for the secrets of a request for which no entry provides any
+    ///
+    /// The fallback command receives information about the request via environment variables and prints a JSON object
+    /// mapping keys to values (strings, unsigned integers, booleans or lists of strings).
+    /// Returns `None` if no fallback is configured.
+    pub fn get_fallback_secrets(
+        &self,
+        request: &SecretRequest,
+        cancelled: &AtomicBool,
+    ) -> anyhow::Result<Option<Vec<(String, SecretValue)>>> {
+        let Some((program, args)) = self
+            .fallback
+            .as_ref()
+            .and_then(|fallback| fallback.command.split_first())
+        else {
+            return Ok(None);
+        };
+        tracing::info!(
+            "Asking fallback command {program} for secrets of {}",
+            request.setting_name
+        );
+        let mut command = Command::new(program);
+        command
+            .args(args)
+            .env("NM_CONNECTION_ID", &request.conn_id)
+            .env("NM_CONNECTION_UUID", &request.conn_uuid)
+            .env("NM_CONNECTION_TYPE", &request.conn_type)
+            .env("NM_SETTING_NAME", &request.setting_name)
+            .env("NM_HINTS", request.hints.join("\n"))
+            .env(
+                "NM_REQUEST_NEW",
+                if request.request_new { "1" } else { "0" },
+            )
+            .stdout(Stdio::piped());
+        let output = run_command(command, &format!("fallback command {program}"), cancelled)?;
+
+        let secrets: serde_json::Map<String, serde_json::Value> = serde_json::from_slice(&output)
+            .with_context(|| {
+            format!("Output of fallback command {program} is not a JSON object")
+        })?;
+        secrets
+            .into_iter()
+            .map(|(key, value)| {
+                let value = SecretValue::from_json(value).with_context(|| {
+                    format!("Fallback command {program} returned an unsupported value for {key}")
+                })?;
+                redact::register(&value);
+                Ok((key, value))
+            })
+            .collect::<Result<_>>()
+            .map(Some)
+    }
+
+    /// Ask the user for the secrets of a request for which neither an entry nor the fallback provides any
+    ///
+    /// Returns `None` if no prompt is configured or NetworkManager does not allow interaction for `request`. With
+    /// `dry_run`, the keys that would be asked for are only logged and `None` is returned as well.
+    pub fn get_prompted_secrets(
+        &self,
+        request: &SecretRequest,
+        dry_run: bool,
+        cancelled: &AtomicBool,
+    ) -> anyhow::Result<Option<Vec<(String, SecretValue)>>> {
+        match &self.prompt {
+            Some(_) if request.allow_interaction && dry_run => {
+                let names = Prompt::keys(request)
+                    .iter()
+                    .map(|key| format!("{}.{key}", request.setting_name))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                tracing::info!("Dry run: not prompting for [{names}]");
+                Ok(None)
+            }
+            Some(prompt) if request.allow_interaction => prompt.ask(request, cancelled).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// All configured entries
+    pub fn entries(&self) -> &[MappingEntry] {
+        &self.entries
+    }
+
+    /// All files and directories below which files are read for this configuration, e.g. by the reader helper
+    ///
+    /// Files with placeholders are represented by their closest directory without any. Symlinks are only followed
+    /// for entries which allow it and are not writable, since the directories of writable entries can be modified by
+    /// the agent.
+    pub fn readable_paths(&self) -> Vec<AllowedPath> {
+        let mut result = self
+            .hook
+            .iter()
+            .map(|hook| AllowedPath::new(hook, true))
+            .collect::<Vec<_>>();
+        for entry in &self.entries {
+            let follow_symlinks =
+                entry.follow_symlinks.unwrap_or(true) && !entry.writable.unwrap_or(false);
+            let files =
+                entry
+                    .files()
+                    .into_iter()
+                    .filter_map(|file| match entry.is_expanded(file) {
+                        true => file
+                            .ancestors()
+                            .skip(1)
+                            .find(|dir| !dir.to_string_lossy().contains('{')),
+                        false => Some(file),
+                    });
+            let wasm_modules =
+                entry
+                    .transform
+                    .iter()
+                    .flatten()
+                    .filter_map(|transform| match transform {
+                        Transform::Wasm(module) => Some(module.path()),
+                        _ => None,
+                    });
+            result.extend(
+                files
+                    .chain(entry.peers_dir.as_deref())
+                    .chain(entry.allowed_dirs.iter().flatten().map(PathBuf::as_path))
+                    .map(|path| AllowedPath::new(path, follow_symlinks))
+                    .chain(
+                        entry
+                            .signature_key
+                            .iter()
+                            .map(PathBuf::as_path)
+                            .chain(wasm_modules)
+                            .map(|path| AllowedPath::new(path, true)),
+                    ),
+            );
+        }
+        result
+    }
+
+    /// Notify all event hooks which handle `event` in the background
+    ///
+    /// `details` is a JSON object whose fields are added to the payload.
+    pub fn fire_event(&self, event: Event, details: serde_json::Value) {
+        for hook in self.event_hooks.iter().filter(|hook| hook.handles(event)) {
+            hook.fire(event, &details);
+        }
+    }
+
+    /// Command and arguments of the configured fallback source
+    pub fn fallback_command(&self) -> Option<&[String]> {
+        self.fallback
+            .as_ref()
+            .map(|fallback| fallback.command.as_slice())
+    }
+
+    /// Command and arguments of the configured prompt
+    pub fn prompt_command(&self) -> Option<&[String]> {
+        self.prompt.as_ref().map(Prompt::command)
+    }
+
+    /// All entries whose match settings match `request` together with their index
+    ///
+    /// If a hook is configured, it decides whether an entry matches instead.
+    pub fn matching_entries<'a>(
+        &'a self,
+        request: &'a SecretRequest,
+    ) -> impl Iterator<Item = (usize, &'a MappingEntry)> + 'a {
+        self.entries.iter().enumerate().filter(|&(index, entry)| {
+            let matched = entry.matches(request);
+            match &self.script {
+                None => matched,
+                Some(hook) => hook.matches(index, entry, request, matched),
+            }
+        })
+    }
+
+    /// Write the values of all writable entries that match `request` back into their files
+    ///
+    /// `lookup` is called with the key of every such entry and returns the value which NetworkManager wants to save.
+    /// Returns the keys that have been saved.
+    pub fn save_secrets(
+        &self,
+        request: &SecretRequest,
+        lookup: impl Fn(&str) -> Option<SecretValue>,
+    ) -> anyhow::Result<Vec<String>> {
+        let mut saved = Vec::new();
+        for (_, entry) in self
+            .matching_entries(request)
+            .filter(|(_, entry)| entry.writable.unwrap_or(false))
+        {
+            if request.not_agent_owned.contains(&entry.key) {
+                tracing::debug!(
+                    ?entry,
+                    "Not saving secret because the connection profile does not mark its key as agent-owned"
+                );
+                continue;
+            }
+            let Some(value) = lookup(&entry.key) else {
+                tracing::debug!(
+                    ?entry,
+                    "Not saving secret because NetworkManager sent no value for it"
+                );
+                continue;
+            };
+            redact::register(&value);
+            entry
+                .write(&value, request)
+                .with_context(|| format!("Could not save secret value of {}", entry.key))?;
+            saved.push(entry.key.to_owned());
+        }
+        Ok(saved)
+    }
+}
+
+impl MappingEntry {
+    /// Whether all configured match settings of this entry match `request`
+    pub fn matches(&self, request: &SecretRequest) -> bool {
+        self.mismatches(request).is_empty()
+    }
+
+    /// Whether the match settings of this entry which relate to the connection profile match `request`
+    ///
+    /// Unlike [`Self::matches`], this ignores `match_vpn_message` since VPN plugins only send their message along with
+    /// a request, which makes it possible to check entries against profiles.
+    pub fn can_match(&self, request: &SecretRequest) -> bool {
+        self.profile_mismatches(request).is_empty()
+    }
+
+    /// Descriptions of all configured match settings of this entry that do not match `request`
+    pub fn mismatches(&self, request: &SecretRequest) -> Vec<String> {
+        let mut result = self.profile_mismatches(request);
+        if let Some(expected) = &self.match_vpn_message {
+            match request.vpn_message() {
+                None => result.push(format!(
+                    "match_vpn_message {expected:?} but the request has no x-vpn-message hint"
+                )),
+                Some(message) if !message.contains(expected.as_str()) => result.push(format!(
+                    "match_vpn_message {expected:?} is not contained in {message:?}"
+                )),
+                Some(_) => {}
+            }
+        }
+        result
+    }
+
+    /// Descriptions of all match settings that do not match the connection profile and setting of `request`
+    fn profile_mismatches(&self, request: &SecretRequest) -> Vec<String> {
+        [
+            ("match_id", &self.match_id, &request.conn_id),
+            ("match_uuid", &self.match_uuid, &request.conn_uuid),
+            ("match_type", &self.match_type, &request.conn_type),
+            ("match_iface", &self.match_iface, &request.iface_name),
+            ("match_setting", &self.match_setting, &request.setting_name),
+        ]
+        .into_iter()
+        .filter_map(|(name, expected, actual)| {
+            let expected = expected.as_ref()?;
+            (expected != actual).then(|| format!("{name} {expected:?} != {actual:?}"))
+        })
+        .collect()
+    }
+
+    /// Why this entry does not provide its value to `request` even though it matches, if it does not
+    pub fn skip_reason(&self, request: &SecretRequest) -> Option<&'static str> {
+        if self.strict_hints.unwrap_or(false)
+            && request.key_hints().next().is_some()
+            && !request.is_hinted(&self.key)
+        {
+            return Some("its key was not hinted at");
+        }
+        if self.honor_secret_flags.unwrap_or(false) && request.not_agent_owned.contains(&self.key) {
+            return Some("the connection profile does not mark its key as agent-owned");
+        }
+        if request.request_new
+            && self.on_request_new.unwrap_or_default() == RequestNewPolicy::NoSecrets
+        {
+            return Some("it cannot provide new secrets");
+        }
+        None
+    }
+
+    /// The identifier by which later config files can override this entry
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    /// The key for which this entry provides a value
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// All configured match settings of this entry as pairs of config key and value
+    pub fn match_criteria(&self) -> Vec<(&'static str, &str)> {
+        [
+            ("match_id", &self.match_id),
+            ("match_uuid", &self.match_uuid),
+            ("match_type", &self.match_type),
+            ("match_iface", &self.match_iface),
+            ("match_setting", &self.match_setting),
+            ("match_vpn_message", &self.match_vpn_message),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| Some((name, value.as_deref()?)))
+        .collect()
+    }
+
+    /// Polkit action that must authorize user-requested activations
+    pub fn polkit_action(&self) -> Option<&str> {
+        self.polkit_action.as_deref()
+    }
+
+    /// The tags by which this entry can be selected
+    pub fn tags(&self) -> &[String] {
+        self.tags.as_deref().unwrap_or_default()
+    }
+
+    /// Whether values which NetworkManager asks to save are written back into the backing file
+    pub fn is_writable(&self) -> bool {
+        self.writable.unwrap_or(false)
+    }
+
+    /// Whether preloaded content of this entry is locked into RAM
+    pub fn is_pinned(&self) -> bool {
+        self.pin.unwrap_or(false)
+    }
+
+    /// How the backing files of this entry are encrypted at rest
+    pub fn encryption(&self) -> Option<&Encryption> {
+        self.encryption.as_ref()
+    }
+
+    /// Deadline after which the value of this entry is refused
+    pub fn not_after(&self) -> Option<&Expiry> {
+        self.not_after.as_ref()
+    }
+
+    /// Usage counters of this entry since the config was loaded
+    pub fn stats(&self) -> &EntryStats {
+        &self.stats
+    }
+
+    /// The kind of source from which the secret value is read (`file`, `template`, `totp` or that of a
+    /// [`SecretSource`])
+    pub fn source_type(&self) -> &'static str {
+        match (&self.file, &self.template, &self.totp, &self.source) {
+            (Some(_), _, _, _) => "file",
+            (None, Some(_), _, _) => "template",
+            (None, None, Some(_), _) => "totp",
+            (None, None, None, Some(source)) => source.kind(),
+            (None, None, None, None) if self.peers_dir.is_some() => "peers_dir",
+            (None, None, None, None) => {
+                unreachable!("entries without a source are rejected when loading the config")
+            }
+        }
+    }
+
+    /// Check that `file` can be opened for reading without reading its content
+    pub fn check_readable(&self, file: &Path) -> anyhow::Result<()> {
+        if self.is_expanded(file) {
+            anyhow::bail!("Path contains placeholders which are only expanded for requests");
+        }
+        self.open_file(file).map(drop)
+    }
+
+    /// All files from which the secret value of this entry is read
+    pub fn files(&self) -> Vec<&Path> {
+        self.file
+            .iter()
+            .map(PathBuf::as_path)
+            .chain(self.template.iter().flat_map(Template::files))
+            .chain(self.totp.iter().map(|totp| totp.secret_file.as_path()))
+            .collect()
+    }
+
+    /// All files from which the secret value of this entry is read for `request`
+    ///
+    /// Unlike [`Self::files`], placeholders in `file` are expanded. A path whose placeholders cannot be expanded for
+    /// `request` is returned as configured.
+    pub fn files_for(&self, request: &SecretRequest) -> Vec<PathBuf> {
+        self.files()
+            .into_iter()
+            .map(|file| {
+                self.expand_file(file, request)
+                    .unwrap_or_else(|_| file.to_path_buf())
+            })
+            .collect()
+    }
+
+    /// Whether `file` is the `file` of this entry and contains placeholders
+    fn is_expanded(&self, file: &Path) -> bool {
+        self.file_placeholders && self.file.as_deref() == Some(file)
+    }
+
+    /// The path of `file` with its placeholders expanded for `request` if it has any
+    fn expand_file(&self, file: &Path, request: &SecretRequest) -> anyhow::Result<PathBuf> {
+        match self.is_expanded(file) {
+            true => placeholder::expand(file, request, &self.key),
+            false => Ok(file.to_path_buf()),
+        }
+    }
+
+    /// Read the secret value from the configured source and apply configured transformations
+    ///
+    /// If `cache_ttl` is configured, a value resolved for the same connection and setting within the TTL is reused
+    /// unless NetworkManager asks for new secrets.
+    pub fn read(&self, request: &SecretRequest) -> anyhow::Result<SecretValue> {
+        if let Some(not_after) = self
+            .not_after
+            .as_ref()
+            .filter(|i| i.is_past(SystemTime::now()))
+        {
+            tracing::error!(
+                id = self.id,
+                key = self.key,
+                notAfter = %not_after,
+                "Refusing to serve {} since it expired at {not_after} and should have been rotated",
+                self.key
+            );
+            anyhow::bail!(Error::new(
+                ErrorCode::Expired,
+                format!("The value of {} expired at {not_after}", self.key)
+            ));
+        }
+        let Some(value_cache) = &self.value_cache else {
+            return self.read_uncached(request);
+        };
+        let cache_key = (request.conn_uuid.clone(), request.setting_name.clone());
+        if !request.request_new {
+            if let Some(value) = value_cache.get(&cache_key) {
+                tracing::debug!("Reusing cached secret value of {}", self.key);
+                return Ok(value);
+            }
+        }
+        let value = self.read_uncached(request)?;
+        value_cache.insert(cache_key, value.clone());
+        Ok(value)
+    }
+
+    /// Read the secret value like [`Self::read`] but without going through the value cache
+    fn read_uncached(&self, request: &SecretRequest) -> anyhow::Result<SecretValue> {
+        // requests for new secrets always read the files again since the cached content has been rejected
+        let fresh = request.request_new;
+        let secret_value = match (&self.file, &self.template, &self.totp, &self.source) {
+            (Some(file), _, _, _) => self.read_file(&self.expand_file(file, request)?, fresh)?,
+            (None, Some(template), _, _) => template.render(|file| self.read_file(file, fresh))?,
+            (None, None, Some(totp), _) => totp
+                .generate(&self.read_file(&totp.secret_file, fresh)?)
+                .context("Could not generate TOTP code")?
+                .into_bytes(),
+            (None, None, None, Some(source)) => {
+                let value = source.read(request).with_context(|| {
+                    format!("Could not read secret from {} source", source.kind())
+                })?;
+                self.transformations().try_fold(value, |value, transform| {
+                    transform
+                        .apply(value)
+                        .with_context(|| format!("Could not apply transformation {transform:?}"))
+                })?
+            }
+            (None, None, None, None) => match &self.peers_dir {
+                Some(dir) => self.read_peers_dir(dir, request, fresh)?,
+                None => {
+                    unreachable!("entries without a source are rejected when loading the config")
+                }
+            },
+        };
+        let secret_value = match self.derive {
+            None => secret_value,
+            Some(derivation) => derivation
+                .apply(secret_value, request)
+                .with_context(|| format!("Could not derive secret value of {}", self.key))?,
+        };
+        let secret_value = self.dbus_type.parse(secret_value).with_context(|| {
+            format!(
+                "Could not interpret secret value of {} as {:?}",
+                self.key, self.dbus_type
+            )
+        })?;
+
+        if self.validate_value.unwrap_or(false) {
+            checks::validate_key_material(&request.setting_name, &self.key, &secret_value)?;
+        }
+        if self.check_plausibility.unwrap_or(false) {
+            for warning in
+                checks::plausibility_warnings(&request.setting_name, &self.key, &secret_value)
+            {
+                tracing::warn!("Secret value read from config entry may be rejected by NetworkManager: {warning}");
+            }
+        }
+        Ok(secret_value)
+    }
+
+    /// Run the configured renew command and wait for it to finish successfully
+    ///
+    /// Information about the request is passed to the command via environment variables.
+    fn run_renew_command(
+        &self,
+        request: &SecretRequest,
+        cancelled: &AtomicBool,
+    ) -> anyhow::Result<()> {
+        let Some((program, args)) = self.renew_command.as_deref().and_then(<[_]>::split_first)
+        else {
+            unreachable!("entries without renew_command are rejected when loading the config");
+        };
+        tracing::info!(
+            "Running renew command {program} for {}.{}",
+            request.setting_name,
+            self.key
+        );
+        let mut command = Command::new(program);
+        command
+            .args(args)
+            .env("NM_CONNECTION_ID", &request.conn_id)
+            .env("NM_CONNECTION_UUID", &request.conn_uuid)
+            .env("NM_CONNECTION_TYPE", &request.conn_type)
+            .env("NM_SETTING_NAME", &request.setting_name)
+            .env("NM_SECRET_KEY", &self.key);
+        run_command(command, &format!("renew command {program}"), cancelled)?;
+        Ok(())
+    }
+
+    /// Open a secret file of this entry for reading
+    ///
+    /// This makes sure that the file is a regular file (and not e.g. a FIFO or device which could block or produce
+    /// endless data) and that it does not exceed the configured size limit.
+    fn open_file(&self, file: &Path) -> anyhow::Result<File> {
+        // O_NONBLOCK prevents blocking when the path unexpectedly points to a FIFO
+        let mut flags = libc::O_NONBLOCK;
+        if !self.follow_symlinks.unwrap_or(true) {
+            flags |= libc::O_NOFOLLOW;
+        }
+        let handle = reader::open(file, flags).map_err(|e| match e.raw_os_error() {
+            Some(libc::ELOOP) if !self.follow_symlinks.unwrap_or(true) => anyhow::anyhow!(
+                "Secret file at {} is a symlink which is not followed",
+                file.display()
+            ),
+            _ => anyhow::Error::new(e)
+                .context(format!("Could not open secret file at {}", file.display())),
+        })?;
+        if let Some(dirs) = &self.allowed_dirs {
+            check_allowed_dir(&handle, file, dirs)?;
+        }
+        let metadata = handle.metadata().with_context(|| {
+            format!(
+                "Could not query metadata of secret file at {}",
+                file.display()
+            )
+        })?;
+        if !metadata.is_file() {
+            anyhow::bail!("Secret file at {} is not a regular file", file.display());
+        }
+        if let Some(problem) = permission_problem(&metadata) {
+            let message = format!("Secret file at {} {problem}", file.display());
+            match self.unsafe_permissions.unwrap_or_default() {
+                PermissionPolicy::Ignore => {}
+                PermissionPolicy::Warn => tracing::warn!("{message}"),
+                PermissionPolicy::Refuse => anyhow::bail!("{message}; refusing to read it"),
+            }
+        }
+        let max_size = self.max_size.unwrap_or(DEFAULT_MAX_SIZE);
+        if metadata.len() > max_size {
+            anyhow::bail!(
+                "Secret file at {} is {} bytes large which exceeds the limit of {max_size} bytes",
+                file.display(),
+                metadata.len()
+            );
+        }
+        Ok(handle)
+    }
+
+    /// Replace the content of the backing file with `value`
+    ///
+    /// The file is replaced atomically by writing to a temporary file next to it which receives the permissions and
+    /// ownership of the original file before it is renamed over it. With `encryption`, only the encrypted value is
+    /// written. Placeholders in the path are expanded for `request`.
+    fn write(&self, value: &SecretValue, request: &SecretRequest) -> anyhow::Result<()> {
+        let Some(file) = &self.file else {
+            unreachable!("writable entries without a file are rejected when loading the config");
+        };
+        let file = &self.expand_file(file, request)?;
+        let content = match self.encoding.and_then(Encoding::encode_transform) {
+            None => value.to_bytes(),
+            Some(transform) => transform.apply(value.to_bytes())?,
+        };
+        let encrypted = match &self.encryption {
+            Some(encryption) => Some(encryption.encrypt(file, &content)?),
+            None => None,
+        };
+
+        let file_name = file
+            .file_name()
+            .with_context(|| format!("Secret file path {} has no file name", file.display()))?;
+        let mut tmp_name = std::ffi::OsString::from(".");
+        tmp_name.push(file_name);
+        tmp_name.push(format!(
+            ".tmp-{}-{}",
+            std::process::id(),
+            TMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let tmp_path = file.with_file_name(tmp_name);
+
+        let existing = fs::metadata(file).ok();
+        // only a temporary file created by this call may be removed again
+        let mut created = false;
+        let result = (|| {
+            let mut tmp = File::options()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(&tmp_path)
+                .with_context(|| {
+                    format!("Could not create temporary file {}", tmp_path.display())
+                })?;
+            created = true;
+            if let Some(existing) = &existing {
+                std::os::unix::fs::fchown(&tmp, Some(existing.uid()), Some(existing.gid()))
+                    .context("Could not transfer ownership of the secret file")?;
+                tmp.set_permissions(fs::Permissions::from_mode(existing.mode()))
+                    .context("Could not transfer permissions of the secret file")?;
+            }
+            tmp.write_all(encrypted.as_deref().unwrap_or(&content))
+                .and_then(|_| tmp.sync_all())
+                .with_context(|| {
+                    format!("Could not write temporary file {}", tmp_path.display())
+                })?;
+            fs::rename(&tmp_path, file)
+                .with_context(|| format!("Could not replace secret file at {}", file.display()))
+        })();
+        self.file_cache.invalidate(file);
+        if let Some(value_cache) = &self.value_cache {
+            value_cache.clear();
+        }
+        if result.is_err() && created {
+            let _ = fs::remove_file(&tmp_path);
+        }
+        result?;
+        if self.preloaded.contains(file) {
+            self.preloaded
+                .insert(file, &content, self.pin.unwrap_or(false))?;
+        }
+
+        tracing::info!("Saved secret {} to file {}", self.key, file.display());
+        Ok(())
+    }
+
+    /// Read the content of a single secret file and apply the transformation pipeline to it
+    ///
+    /// Unless `fresh` is set, the content is taken from the cache if the file has not changed since it was last read.
+    fn read_file(&self, file: &Path, fresh: bool) -> anyhow::Result<Vec<u8>> {
+        let secret_value = self.read_file_content(file, fresh)?;
+        let secret_value = match &self.file_key {
+            Some(key) => {
+                lookup_key_value(&self.normalize(file, secret_value), key).with_context(|| {
+                    format!(
+                        "Could not look up {key} in secret file at {}",
+                        file.display()
+                    )
+                })?
+            }
+            None => secret_value,
+        };
+        self.transformations()
+            .try_fold(secret_value, |value, transform| match transform {
+                Transform::Normalize => Ok(self.normalize(file, value)),
+                transform => transform
+                    .apply(value)
+                    .with_context(|| format!("Could not apply transformation {transform:?}")),
+            })
+            .with_context(|| {
+                format!(
+                    "Could not transform content of secret file at {}",
+                    file.display()
+                )
+            })
+    }
+
+    /// Apply [`Transform::Normalize`] to the content of `file`, warning only about the first change of each file
+    fn normalize(&self, file: &Path, content: Vec<u8>) -> Vec<u8> {
+        transform::normalize(content, || {
+            self.normalized_files
+                .lock()
+                .unwrap()
+                .insert(file.to_owned())
+        })
+    }
+
+    /// Read the value of every peer in `dir` as `<public-key> <value>` lines like `peers.*.<property>` keys expect
+    ///
+    /// Files are named after the public key of their peer, optionally in the URL-safe base64 alphabet since public
+    /// keys may contain `/`. Hidden files, signatures and peers which the connection profile does not have are ignored.
+    fn read_peers_dir(
+        &self,
+        dir: &Path,
+        request: &SecretRequest,
+        fresh: bool,
+    ) -> anyhow::Result<Vec<u8>> {
+        let mut files = fs::read_dir(dir)
+            .and_then(|entries| entries.collect::<std::io::Result<Vec<_>>>())
+            .with_context(|| format!("Could not list peer directory {}", dir.display()))?;
+        files.sort_by_key(fs::DirEntry::file_name);
+
+        let mut lines = Vec::new();
+        for file in files {
+            let name = file.file_name();
+            let Some(name) = name.to_str() else {
+                tracing::debug!(
+                    "Ignoring peer file {:?} whose name is not UTF-8",
+                    file.path()
+                );
+                continue;
+            };
+            if name.starts_with('.')
+                || name.ends_with(".minisig")
+                || name.ends_with(".sig")
+                || file.file_type().is_ok_and(|file_type| file_type.is_dir())
+            {
+                continue;
+            }
+            let public_key = name.replace('-', "+").replace('_', "/");
+            if !request.wireguard_peers.is_empty() && !request.wireguard_peers.contains(&public_key)
+            {
+                tracing::debug!(
+                    "Ignoring peer file {} since the connection has no such peer",
+                    file.path().display()
+                );
+                continue;
+            }
+            let value = self.read_file(&file.path(), fresh)?;
+            let value = String::from_utf8(value).with_context(|| {
+                format!("Peer file {} is not valid UTF-8", file.path().display())
+            })?;
+            if value.trim().contains('\n') {
+                anyhow::bail!(
+                    "Peer file {} contains more than one line",
+                    file.path().display()
+                );
+            }
+            lines.extend_from_slice(format!("{public_key} {}\n", value.trim()).as_bytes());
+        }
+        Ok(lines)
+    }
+
+    /// Read the raw content of a single secret file, going through the cache if it is enabled
+    ///
+    /// Preloaded files are never read again, not even if `fresh` is set.
+    fn read_file_content(&self, file: &Path, fresh: bool) -> anyhow::Result<Vec<u8>> {
+        if let Some(content) = self.preloaded.get(file) {
+            return Ok(content);
+        }
+        let max_size = self.max_size.unwrap_or(DEFAULT_MAX_SIZE);
+        let handle = self.open_file(file)?;
+        let metadata = self
+            .cache
+            .unwrap_or(true)
+            .then(|| handle.metadata())
+            .transpose()
+            .with_context(|| {
+                format!(
+                    "Could not query metadata of secret file at {}",
+                    file.display()
+                )
+            })?;
+        if let Some(metadata) = metadata.as_ref().filter(|_| !fresh) {
+            if let Some(content) = self.file_cache.get(file, metadata) {
+                return Ok(content);
+            }
+        }
+
+        let mut secret_value = Vec::new();
+        handle
+            .take(max_size + 1)
+            .read_to_end(&mut secret_value)
+            .with_context(|| {
+                format!(
+                    "Could not read file content from secret at {}",
+                    file.display()
+                )
+            })?;
+        if secret_value.len() as u64 > max_size {
+            anyhow::bail!(
+                "Secret file at {} is larger than the limit of {max_size} bytes",
+                file.display()
+            );
+        }
+        if let Some(verifier) = &self.verifier {
+            verifier.verify(file, &secret_value).with_context(|| {
+                Error::new(
+                    ErrorCode::SignatureInvalid,
+                    format!(
+                        "Could not verify the signature of secret file at {}",
+                        file.display()
+                    ),
+                )
+            })?;
+            tracing::debug!("Verified signature of secret file {}", file.display());
+        }
+        // the plaintext is cached so that files are only decrypted again once they changed
+        let secret_value = match &self.encryption {
+            Some(encryption) => encryption.decrypt(file, &secret_value)?,
+            None => secret_value,
+        };
+        tracing::debug!("Successfully read secret from file {}", file.display());
+        if let Some(metadata) = &metadata {
+            self.file_cache.insert(file, metadata, &secret_value);
+        }
+        Ok(secret_value)
+    }
+
+    /// All transformations that are applied to the content of backing files in order
+    fn transformations(&self) -> impl Iterator<Item = Transform> + '_ {
+        self.trim
+            .map(TrimMode::transforms)
+            .into_iter()
+            .flatten()
+            .chain(self.encoding.and_then(Encoding::transform))
+            .chain(self.transform.iter().flatten().cloned())
+    }
+
+    /// Replace `{hostname}` and `{machine-id}` in the paths and match settings of this entry
+    fn expand_host_variables(&mut self) -> anyhow::Result<()> {
+        for value in [
+            &mut self.match_id,
+            &mut self.match_uuid,
+            &mut self.match_type,
+            &mut self.match_iface,
+            &mut self.match_setting,
+            &mut self.match_vpn_message,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *value = placeholder::expand_host(value)?;
+        }
+        for path in [&mut self.file, &mut self.peers_dir, &mut self.signature_key]
+            .into_iter()
+            .flatten()
+            .chain(self.totp.iter_mut().map(|totp| &mut totp.secret_file))
+            .chain(self.allowed_dirs.iter_mut().flatten())
+        {
+            *path = placeholder::expand_host_path(path)?;
+        }
+        if let Some(template) = &mut self.template {
+            template.try_map_files(placeholder::expand_host_path)?;
+        }
+        Ok(())
+    }
+
+    /// Fill all values of this entry that are not set from `defaults`
+    fn apply_defaults(&mut self, defaults: &EntryDefaults) {
+        fn fill<T: Clone>(value: &mut Option<T>, default: &Option<T>) {
+            if value.is_none() {
+                value.clone_from(default);
+            }
+        }
+        fill(&mut self.match_id, &defaults.match_id);
+        fill(&mut self.match_uuid, &defaults.match_uuid);
+        fill(&mut self.match_type, &defaults.match_type);
+        fill(&mut self.match_iface, &defaults.match_iface);
+        fill(&mut self.match_setting, &defaults.match_setting);
+        fill(&mut self.match_vpn_message, &defaults.match_vpn_message);
+        fill(&mut self.trim, &defaults.trim);
+        fill(&mut self.encoding, &defaults.encoding);
+        fill(&mut self.validate_value, &defaults.validate_value);
+        fill(&mut self.check_plausibility, &defaults.check_plausibility);
+        fill(&mut self.max_size, &defaults.max_size);
+        fill(&mut self.transform, &defaults.transform);
+        fill(&mut self.on_request_new, &defaults.on_request_new);
+        fill(&mut self.renew_command, &defaults.renew_command);
+        fill(&mut self.strict_hints, &defaults.strict_hints);
+        fill(&mut self.honor_secret_flags, &defaults.honor_secret_flags);
+        fill(&mut self.cache, &defaults.cache);
+        fill(&mut self.cache_ttl, &defaults.cache_ttl);
+        fill(&mut self.preload, &defaults.preload);
+        fill(&mut self.pin, &defaults.pin);
+        fill(&mut self.unsafe_permissions, &defaults.unsafe_permissions);
+        fill(&mut self.follow_symlinks, &defaults.follow_symlinks);
+        fill(&mut self.allowed_dirs, &defaults.allowed_dirs);
+        fill(&mut self.signature_key, &defaults.signature_key);
+        fill(&mut self.encryption, &defaults.encryption);
+        fill(&mut self.polkit_action, &defaults.polkit_action);
+        fill(&mut self.tags, &defaults.tags);
+    }
+}
+
+impl Derivation {
+    /// Compute the derived value from `value` in the context of `request`
+    fn apply(self, value: Vec<u8>, request: &SecretRequest) -> anyhow::Result<Vec<u8>> {
+        match self {
+            Derivation::WpaPsk => {
+                let ssid = request
+                    .ssid
+                    .as_ref()
+                    .context("Connection has no SSID from which a WPA PSK could be derived")?;
+                if !(8..=63).contains(&value.len()) {
+                    anyhow::bail!("WPA passphrases must be between 8 and 63 characters long");
+                }
+                let mut psk = [0u8; 32];
+                pbkdf2::pbkdf2_hmac::<Sha1>(&value, ssid, 4096, &mut psk);
+                Ok(hex::encode(psk).into_bytes())
+            }
+        }
+    }
+}
+
+impl DbusType {
+    /// Interpret `value` as a value of this type
+    fn parse(self, value: Vec<u8>) -> anyhow::Result<SecretValue> {
+        if self == DbusType::Bytes {
+            return Ok(SecretValue::Bytes(value));
+        }
+        let value = String::from_utf8(value).context("Value is not valid UTF-8")?;
+        match self {
+            DbusType::String => Ok(SecretValue::String(value)),
+            DbusType::U32 => Ok(SecretValue::U32(
+                value
+                    .trim()
+                    .parse()
+                    .context("Value is not an unsigned 32-bit integer")?,
+            )),
+            DbusType::Bool => match value.trim() {
+                "true" | "yes" | "1" => Ok(SecretValue::Bool(true)),
+                "false" | "no" | "0" => Ok(SecretValue::Bool(false)),
+                other => Err(anyhow::anyhow!("Value {other:?} is not a boolean")),
+            },
+            DbusType::StringList => Ok(SecretValue::StringList(
+                value
+                    .lines()
+                    .filter(|line| !line.is_empty())
+                    .map(ToOwned::to_owned)
+                    .collect(),
+            )),
+            DbusType::Bytes => unreachable!("byte arrays are returned before UTF-8 decoding"),
+        }
+    }
+}
+
+/// Run `command` until it has finished successfully and return what it printed to a piped stdout
+///
+/// The command is killed as soon as `cancelled` is set.
+/// `name` describes the command in log and error messages.
+pub(crate) fn run_command(
+    command: Command,
+    name: &str,
+    cancelled: &AtomicBool,
+) -> anyhow::Result<Vec<u8>> {
+    run_command_with_input(command, None, name, cancelled)
+}
+
+/// Run `command` like [`run_command`] but write `input` to its stdin if it is given
+pub(crate) fn run_command_with_input(
+    mut command: Command,
+    input: Option<&[u8]>,
+    name: &str,
+    cancelled: &AtomicBool,
+) -> anyhow::Result<Vec<u8>> {
+    if input.is_some() {
+        command.stdin(Stdio::piped());
+    }
+    let mut child = command
+        .spawn()
+        .with_context(|| Error::new(ErrorCode::SourceFailed, format!("Could not run {name}")))?;
+    // the input is written in the background as well since the command may only read all of it after it printed output
+    let writer = child.stdin.take().zip(input).map(|(mut stdin, input)| {
+        let input = input.to_vec();
+        thread::spawn(move || stdin.write_all(&input))
+    });
+    // read the output in the background so that the command does not block on a full pipe
+    let output = child.stdout.take().map(|mut stdout| {
+        thread::spawn(move || {
+            let mut output = Vec::new();
+            stdout.read_to_end(&mut output).map(|_| output)
+        })
+    });
+
+    // poll the command instead of blocking on it so that it can be killed when the request is canceled
+    let status = loop {
+        if let Some(status) = child
+            .try_wait()
+            .with_context(|| format!("Could not wait for {name}"))?
+        {
+            break status;
+        }
+        if cancelled.load(Ordering::Relaxed) {
+            tracing::info!("Killing {name} because the request was canceled");
+            let _ = child.kill();
+            let _ = child.wait();
+            anyhow::bail!(Error::new(
+                ErrorCode::Canceled,
+                format!("The request was canceled while {name} was running")
+            ));
+        }
+        thread::sleep(Duration::from_millis(50));
+    };
+    if !status.success() {
+        anyhow::bail!(Error::new(
+            ErrorCode::SourceFailed,
+            format!("Running {name} failed with {status}")
+        ));
+    }
+    if let Some(writer) = writer {
+        writer
+            .join()
+            .expect("writing the input of a command does not panic")
+            .with_context(|| format!("Could not write input of {name}"))?;
+    }
+    match output {
+        None => Ok(Vec::new()),
+        Some(reader) => reader
+            .join()
+            .expect("reading the output of a command does not panic")
+            .with_context(|| format!("Could not read output of {name}")),
+    }
+}
+
+/// Apply `f` to all `items` using up to `parallelism` threads and return the results in the order of `items`
+fn parallel_map<T: Sync, R: Send>(
+    items: &[T],
+    parallelism: usize,
+    f: impl Fn(&T) -> R + Sync,
+) -> Vec<R> {
+    let workers = parallelism.min(items.len());
+    if workers <= 1 {
+        return items.iter().map(f).collect();
+    }
+    let next = AtomicUsize::new(0);
+    // worker threads don't inherit the span of the request so it is entered explicitly to keep log messages correlated
+    let span = tracing::Span::current();
+    let mut results = thread::scope(|scope| {
+        let handles = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let _entered = span.enter();
+                    let mut results = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(item) = items.get(i) else {
+                            break results;
+                        };
+                        results.push((i, f(item)));
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("worker threads do not panic"))
+            .collect::<Vec<_>>()
+    });
+    results.sort_by_key(|(i, _)| *i);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// The existing config files at the default locations in the order in which they are merged
+pub fn default_config_paths() -> anyhow::Result<Vec<PathBuf>> {
+    let mut result = Vec::new();
+    if Path::new(DEFAULT_CONFIG_FILE).exists() {
+        result.push(PathBuf::from(DEFAULT_CONFIG_FILE));
+    }
+    result.extend(toml_files(Path::new(DEFAULT_CONFIG_DIR))?);
+    Ok(result)
+}
+
+/// The override files of this host below `dir` in the order in which they are merged
+///
+/// These are the `*.toml` files in the subdirectory named after the hostname. They are merged after the base config so
+/// that their entries replace those with the same `id`.
+pub fn override_paths(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let hostname = placeholder::hostname().context("Could not determine the hostname")?;
+    toml_files(&dir.join(hostname))
+}
+
+/// All `*.toml` files in `dir` in lexical order or none if `dir` does not exist
+fn toml_files(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(anyhow::Error::new(e).context(format!("Could not read {}", dir.display())))
+        }
+    };
+    let mut files = Vec::new();
+    for entry in entries {
+        let path = entry
+            .with_context(|| format!("Could not read {}", dir.display()))?
+            .path();
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "toml")
+            && path.is_file()
+        {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Parse an entry given on the command line as comma-separated `key=value` pairs into a TOML table
+fn parse_cli_entry(spec: &str) -> anyhow::Result<toml::Table> {
+    let mut result = toml::Table::new();
+    for pair in split_cli_entry(spec) {
+        let (key, value) = pair
+            .split_once('=')
+            .with_context(|| format!("{pair:?} is not of the form key=value"))?;
+        let key = CLI_ENTRY_ALIASES
+            .iter()
+            .find(|(alias, _)| *alias == key)
+            .map_or(key, |(_, name)| name);
+        let value = match CLI_ENTRY_STRING_KEYS.contains(&key) {
+            true => toml::Value::String(value.to_owned()),
+            false => toml::from_str::<toml::Table>(&format!("value = {value}"))
+                .ok()
+                .and_then(|mut table| table.remove("value"))
+                .unwrap_or_else(|| toml::Value::String(value.to_owned())),
+        };
+        if result.insert(key.to_owned(), value).is_some() {
+            anyhow::bail!("{key} is given more than once");
+        }
+    }
+    Ok(result)
+}
+
+/// Split `spec` at all commas which are not part of a quoted string or an array
+fn split_cli_entry(spec: &str) -> Vec<&str> {
+    let mut result = Vec::new();
+    let (mut depth, mut quote, mut start) = (0usize, None, 0);
+    for (i, c) in spec.char_indices() {
+        match (c, quote) {
+            ('"' | '\'', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            ('[', None) => depth += 1,
+            (']', None) => depth = depth.saturating_sub(1),
+            (',', None) if depth == 0 => {
+                result.push(&spec[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    result.push(&spec[start..]);
+    result.retain(|pair| !pair.is_empty());
+    result
+}
+
+/// Verify that the file that was opened as `handle` from `path` is located inside one of `dirs`
+///
+/// The check uses the path of the file that was actually opened so that symlinks which are swapped after checking the
+/// path cannot redirect the read.
+fn check_allowed_dir(handle: &File, path: &Path, dirs: &[PathBuf]) -> anyhow::Result<()> {
+    let real_path =
+        fs::read_link(format!("/proc/self/fd/{}", handle.as_raw_fd())).with_context(|| {
+            format!(
+                "Could not determine the canonical path of {}",
+                path.display()
+            )
+        })?;
+    let allowed = dirs.iter().any(|dir| {
+        let dir = fs::canonicalize(dir).unwrap_or_else(|_| dir.clone());
+        real_path.starts_with(dir)
+    });
+    if !allowed {
+        anyhow::bail!(
+            "Secret file at {} resolves to {} which is outside of the allowed directories",
+            path.display(),
+            real_path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Describe why the permissions of a file with `metadata` let other users read or modify it
+///
+/// Files must be owned by root or the user running the agent and must not be readable or writable by everyone.
+/// Group permissions are accepted since secrets are commonly shared with a dedicated group.
+fn permission_problem(metadata: &fs::Metadata) -> Option<String> {
+    // SAFETY: geteuid has no preconditions and cannot fail
+    let euid = unsafe { libc::geteuid() };
+    if metadata.uid() != 0 && metadata.uid() != euid {
+        return Some(format!(
+            "is owned by uid {} instead of root or the agent's user",
+            metadata.uid()
+        ));
+    }
+    let mode = metadata.mode() & 0o777;
+    if mode & 0o004 != 0 {
+        return Some(format!("is readable by everyone (mode {mode:03o})"));
+    }
+    if mode & 0o002 != 0 {
+        return Some(format!("is writable by everyone (mode {mode:03o})"));
+    }
+    None
+}
+
+/// Merge `other` into `entries` as if they were the entries of a later config file
+///
+/// All entries with an id that `other` also uses are replaced by those of `other` at the position of the first one,
+/// since entries declared with `keys` share their id. All other entries of `other` are appended.
+fn merge_entries(entries: &mut Vec<MappingEntry>, other: Vec<MappingEntry>) {
+    let existing_ids = entries
+        .iter()
+        .filter_map(|entry| entry.id.clone())
+        .collect::<HashSet<_>>();
+    let mut overrides = HashMap::<String, Vec<MappingEntry>>::new();
+    let mut appended = Vec::new();
+    for entry in other {
+        match &entry.id {
+            Some(id) if existing_ids.contains(id) => {
+                overrides.entry(id.clone()).or_default().push(entry)
+            }
+            _ => appended.push(entry),
+        }
+    }
+    let mut replaced = HashSet::new();
+    for entry in std::mem::take(entries) {
+        let Some(id) = entry
+            .id
+            .clone()
+            .filter(|id| overrides.contains_key(id) || replaced.contains(id))
+        else {
+            entries.push(entry);
+            continue;
+        };
+        if let Some(overriding) = overrides.remove(&id) {
+            tracing::debug!(id, "Overriding config entry with a later one");
+            entries.extend(overriding);
+            replaced.insert(id);
+        }
+    }
+    entries.extend(appended);
+}
+
+/// Deserialize the `[[entry]]` tables of a config, declaring one entry per key for tables that set `keys`
+///
+/// Such a table is equivalent to one table per key which sets `key` to it and looks up its value in the `KEY=VALUE`
+/// lines of `file`.
+fn deserialize_entries<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Vec<MappingEntry>, D::Error> {
+    use serde::de::Error as _;
+
+    let mut entries = Vec::new();
+    for (i, mut table) in Vec::<toml::Table>::deserialize(deserializer)?
+        .into_iter()
+        .enumerate()
+    {
+        let Some(keys) = table.remove("keys") else {
+            entries.push(
+                toml::Value::Table(table)
+                    .try_into()
+                    .map_err(D::Error::custom)?,
+            );
+            continue;
+        };
+        if table.contains_key("key") {
+            return Err(D::Error::custom(format!(
+                "entry {i} must specify either key or keys"
+            )));
+        }
+        let keys = Vec::<String>::deserialize(keys)
+            .map_err(|e| D::Error::custom(format!("keys of entry {i} are invalid: {e}")))?;
+        if keys.is_empty() {
+            return Err(D::Error::custom(format!(
+                "keys of entry {i} must not be empty"
+            )));
+        }
+        for key in keys {
+            let mut table = table.clone();
+            table.insert("key".to_owned(), toml::Value::String(key.clone()));
+            let mut entry: MappingEntry = toml::Value::Table(table)
+                .try_into()
+                .map_err(D::Error::custom)?;
+            entry.file_key = Some(key);
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+/// Find the value of `key` in `content` consisting of `KEY=VALUE` lines
+///
+/// Whitespace around keys and values is ignored, as are empty lines and lines starting with `#`. If a key is defined
+/// multiple times, the last definition wins. `content` must already be normalized like [`Transform::Normalize`] does.
+fn lookup_key_value(content: &[u8], key: &str) -> anyhow::Result<Vec<u8>> {
+    let mut value = None;
+    for (i, line) in content.split(|&c| c == b'\n').enumerate() {
+        if line.trim_ascii().is_empty() || line.trim_ascii_start().starts_with(b"#") {
+            continue;
+        }
+        let Some(separator) = line.iter().position(|&c| c == b'=') else {
+            anyhow::bail!("Line {} is not of the form KEY=VALUE", i + 1);
+        };
+        if line[..separator].trim_ascii() == key.as_bytes() {
+            value = Some(line[separator + 1..].trim_ascii().to_vec());
+        }
+    }
+    value.with_context(|| format!("The file does not define {key}"))
+}
+
+/// Expand a leading `~` to the current users home directory and make relative paths relative to `base_dir`
+fn resolve_path(path: &Path, base_dir: &Path) -> PathBuf {
+    let path = match path.strip_prefix("~") {
+        Ok(rest) => match std::env::var_os("HOME") {
+            Some(home) => PathBuf::from(home).join(rest),
+            None => {
+                tracing::warn!(
+                    "Cannot expand ~ in path {} because $HOME is not set",
+                    path.display()
+                );
+                path.to_path_buf()
+            }
+        },
+        Err(_) => path.to_path_buf(),
+    };
+
+    if path.is_relative() {
+        base_dir.join(path)
+    } else {
+        path
+    }
+}
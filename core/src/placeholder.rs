@@ -0,0 +1,136 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+use crate::mapping::SecretRequest;
+
+/// Names of the placeholders that can be used in `file` paths
+const NAMES: &[&str] = &["uuid", "id", "iface", "setting", "key"];
+
+/// File from which the machine id is read
+const MACHINE_ID_FILE: &str = "/etc/machine-id";
+
+/// Replace `{hostname}` and `{machine-id}` in `text` by the hostname and machine id of this host
+///
+/// Other text in braces is kept as is, so that it can still be expanded per request or matched literally.
+pub fn expand_host(text: &str) -> anyhow::Result<String> {
+    let mut result = text.to_owned();
+    if result.contains("{hostname}") {
+        result = result.replace("{hostname}", &hostname()?);
+    }
+    if result.contains("{machine-id}") {
+        result = result.replace("{machine-id}", &machine_id()?);
+    }
+    Ok(result)
+}
+
+/// Replace `{hostname}` and `{machine-id}` in `path` like [`expand_host`]
+///
+/// Paths which are not valid UTF-8 are taken literally.
+pub fn expand_host_path(path: &Path) -> anyhow::Result<PathBuf> {
+    match path.to_str() {
+        Some(text) => expand_host(text)
+            .map(PathBuf::from)
+            .with_context(|| format!("Could not expand host variables of {}", path.display())),
+        None => Ok(path.to_path_buf()),
+    }
+}
+
+/// Whether `path` contains `{<name>}` placeholders
+///
+/// Fails if a placeholder is unterminated or uses an unknown name. Paths which are not valid UTF-8 are taken
+/// literally.
+pub fn check(path: &Path) -> anyhow::Result<bool> {
+    let Some(path) = path.to_str() else {
+        return Ok(false);
+    };
+    let mut found = false;
+    substitute(path, |name| {
+        found = true;
+        Ok(format!("{{{name}}}"))
+    })?;
+    Ok(found)
+}
+
+/// Replace the placeholders in `path` by the properties of `request` and the `key` of the entry
+///
+/// `{uuid}`, `{id}`, `{iface}` and `{setting}` are replaced by the connection uuid, connection id, interface name and
+/// setting name of the request and `{key}` by the key of the entry. Values which are empty or could escape the directory, like `..` or anything
+/// containing a `/`, are refused since connection ids are chosen by users.
+pub fn expand(path: &Path, request: &SecretRequest, key: &str) -> anyhow::Result<PathBuf> {
+    let text = path
+        .to_str()
+        .with_context(|| format!("Path {} is not valid UTF-8", path.display()))?;
+    substitute(text, |name| {
+        let value = match name {
+            "uuid" => request.conn_uuid.as_str(),
+            "id" => request.conn_id.as_str(),
+            "iface" => request.iface_name.as_str(),
+            "setting" => request.setting_name.as_str(),
+            "key" => key,
+            _ => unreachable!("unknown placeholders are rejected by substitute"),
+        };
+        if value.is_empty() || value == "." || value == ".." || value.contains(['/', '\0']) {
+            anyhow::bail!("The value {value:?} of placeholder {{{name}}} cannot be used in a path");
+        }
+        Ok(value.to_owned())
+    })
+    .map(PathBuf::from)
+    .with_context(|| format!("Could not expand placeholders of {}", path.display()))
+}
+
+/// Replace every placeholder in `path` by the result of calling `value` with its name
+fn substitute(
+    path: &str,
+    mut value: impl FnMut(&str) -> anyhow::Result<String>,
+) -> anyhow::Result<String> {
+    let mut result = String::with_capacity(path.len());
+    let mut rest = path;
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| anyhow::anyhow!("Unterminated placeholder in path {path:?}"))?;
+        let name = &rest[start + 1..start + end];
+        if !NAMES.contains(&name) {
+            anyhow::bail!(
+                "Unsupported placeholder {{{name}}} in path {path:?}; supported are {}",
+                NAMES
+                    .iter()
+                    .map(|name| format!("{{{name}}}"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+        result.push_str(&value(name)?);
+        rest = &rest[start + end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// The hostname of this machine as reported by `gethostname(2)`
+pub(crate) fn hostname() -> anyhow::Result<String> {
+    let mut buf = [0u8; 256];
+    // SAFETY: the buffer is valid for writes of its full length
+    if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    let len = buf.iter().position(|&c| c == 0).unwrap_or(buf.len());
+    let hostname = std::str::from_utf8(&buf[..len]).context("The hostname is not valid UTF-8")?;
+    if hostname.is_empty() || hostname.contains('/') || hostname.starts_with('.') {
+        anyhow::bail!("The hostname {hostname:?} cannot be used as directory name");
+    }
+    Ok(hostname.to_owned())
+}
+
+/// The id of this machine as stored in `/etc/machine-id`
+fn machine_id() -> anyhow::Result<String> {
+    let content = std::fs::read_to_string(MACHINE_ID_FILE)
+        .with_context(|| format!("Could not read the machine id from {MACHINE_ID_FILE}"))?;
+    let id = content.trim();
+    if id.len() != 32 || !id.bytes().all(|c| c.is_ascii_hexdigit()) {
+        anyhow::bail!("{MACHINE_ID_FILE} does not contain a valid machine id");
+    }
+    Ok(id.to_owned())
+}
@@ -0,0 +1,290 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{self, BufRead, BufReader, Read, Write},
+    path::PathBuf,
+    process::{Child, ChildStdin, Command, Stdio},
+    sync::{mpsc, Mutex, PoisonError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use base64::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    error::{Error, ErrorCode},
+    mapping::SecretRequest,
+    source::SecretSource,
+};
+
+/// Version of the protocol spoken with plugins which they must confirm during the handshake
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// How long a plugin may take to answer the handshake or a request if no other timeout is configured
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Maximum length of a single message that a plugin prints
+const MAX_MESSAGE_LEN: u64 = 1024 * 1024;
+
+/// A source that asks an out-of-process plugin for secret values
+///
+/// The plugin is started when the first value is needed and is kept running to answer further requests. It is killed
+/// and started again for the next request if it does not answer within the timeout or violates the protocol.
+pub struct PluginSource {
+    /// Executable of the plugin
+    path: PathBuf,
+    /// Key of the entry which the plugin provides values for
+    key: String,
+    /// Entry specific configuration which is passed to the plugin during the handshake
+    config: serde_json::Value,
+    timeout: Duration,
+    state: Mutex<PluginState>,
+}
+
+#[derive(Default)]
+struct PluginState {
+    process: Option<PluginProcess>,
+    next_id: u64,
+    /// Values the plugin allowed to be reused by connection uuid and setting name together with their expiry
+    cache: HashMap<(String, String), (Instant, Vec<u8>)>,
+}
+
+/// A running plugin with the channel through which its messages arrive
+struct PluginProcess {
+    child: Child,
+    stdin: ChildStdin,
+    messages: mpsc::Receiver<io::Result<String>>,
+}
+
+/// A message that a plugin prints as a single line of JSON
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum PluginMessage {
+    /// Answer to the handshake confirming the protocol version
+    Hello { version: u32 },
+    /// The value requested by the `get` message with the same `id`
+    Value {
+        id: u64,
+        value: String,
+        /// Whether `value` is base64 encoded binary data instead of text
+        #[serde(default)]
+        base64: bool,
+        /// Number of seconds for which the value may be reused for the same connection and setting
+        ttl: Option<u64>,
+    },
+    /// The value requested by the `get` message with the same `id` could not be provided
+    Error { id: u64, message: String },
+}
+
+impl fmt::Debug for PluginSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // the state is left out since it contains cached secret values
+        f.debug_struct("PluginSource")
+            .field("path", &self.path)
+            .field("key", &self.key)
+            .field("timeout", &self.timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for PluginProcess {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl PluginSource {
+    /// Create a source that asks the plugin at `path` for values of `key`, passing `config` along during the handshake
+    pub fn new(path: PathBuf, key: String, config: serde_json::Value, timeout: Duration) -> Self {
+        Self {
+            path,
+            key,
+            config,
+            timeout,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Start the plugin and perform the handshake
+    fn start(&self) -> anyhow::Result<PluginProcess> {
+        tracing::debug!("Starting plugin {}", self.path.display());
+        let mut child = Command::new(&self.path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Could not start plugin {}", self.path.display()))?;
+        let stdin = child.stdin.take().expect("stdin of the plugin is piped");
+        let mut stdout =
+            BufReader::new(child.stdout.take().expect("stdout of the plugin is piped"));
+        // read messages in the background so that waiting for them can time out
+        let (sender, messages) = mpsc::channel();
+        thread::spawn(move || loop {
+            let mut line = String::new();
+            let message = match stdout.by_ref().take(MAX_MESSAGE_LEN).read_line(&mut line) {
+                Ok(0) => break,
+                Ok(_) if !line.ends_with('\n') => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Message is too long or not terminated by a newline",
+                )),
+                result => result.map(|_| line),
+            };
+            let failed = message.is_err();
+            if sender.send(message).is_err() || failed {
+                break;
+            }
+        });
+
+        let mut process = PluginProcess {
+            child,
+            stdin,
+            messages,
+        };
+        let hello = serde_json::json!({
+            "type": "hello",
+            "version": PROTOCOL_VERSION,
+            "config": self.config,
+        });
+        match self.exchange(&mut process, &hello)? {
+            PluginMessage::Hello {
+                version: PROTOCOL_VERSION,
+            } => Ok(process),
+            PluginMessage::Hello { version } => anyhow::bail!(
+                "Plugin speaks protocol version {version} but version {PROTOCOL_VERSION} is required"
+            ),
+            other => anyhow::bail!("Plugin answered the handshake with {other:?}"),
+        }
+    }
+
+    /// Send `message` to the plugin and wait for the next message it prints
+    fn exchange(
+        &self,
+        process: &mut PluginProcess,
+        message: &serde_json::Value,
+    ) -> anyhow::Result<PluginMessage> {
+        let mut line = message.to_string();
+        line.push('\n');
+        process
+            .stdin
+            .write_all(line.as_bytes())
+            .and_then(|()| process.stdin.flush())
+            .context("Could not send message to plugin")?;
+        let reply = match process.messages.recv_timeout(self.timeout) {
+            Ok(reply) => reply.context("Could not read message of plugin")?,
+            Err(mpsc::RecvTimeoutError::Timeout) => anyhow::bail!(
+                "Plugin did not answer within {} seconds",
+                self.timeout.as_secs_f32()
+            ),
+            Err(mpsc::RecvTimeoutError::Disconnected) => anyhow::bail!("Plugin exited"),
+        };
+        serde_json::from_str(&reply).context("Plugin printed an invalid message")
+    }
+
+    /// Ask the plugin for the value that `request` asks for, starting the plugin first if it is not running
+    fn get(
+        &self,
+        state: &mut PluginState,
+        request: &SecretRequest,
+    ) -> anyhow::Result<PluginMessage> {
+        if state.process.is_none() {
+            state.process = Some(self.start()?);
+        }
+        let process = state.process.as_mut().expect("plugin was just started");
+        state.next_id += 1;
+        let id = state.next_id;
+        let message = serde_json::json!({
+            "type": "get",
+            "id": id,
+            "key": self.key,
+            "request": {
+                "connection_id": request.conn_id,
+                "connection_uuid": request.conn_uuid,
+                "connection_type": request.conn_type,
+                "iface": request.iface_name,
+                "setting_name": request.setting_name,
+                "hints": request.hints,
+                "request_new": request.request_new,
+                "user_requested": request.user_requested,
+            },
+        });
+        let reply = self.exchange(process, &message)?;
+        match reply {
+            PluginMessage::Value { id: reply_id, .. }
+            | PluginMessage::Error { id: reply_id, .. }
+                if reply_id == id =>
+            {
+                Ok(reply)
+            }
+            other => anyhow::bail!("Plugin answered request {id} with {other:?}"),
+        }
+    }
+}
+
+impl SecretSource for PluginSource {
+    fn kind(&self) -> &'static str {
+        "plugin"
+    }
+
+    fn read(&self, request: &SecretRequest) -> anyhow::Result<Vec<u8>> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let cache_key = (request.conn_uuid.clone(), request.setting_name.clone());
+        match state.cache.remove(&cache_key) {
+            Some((expiry, value)) if !request.request_new && expiry > Instant::now() => {
+                tracing::debug!(
+                    "Reusing value of {} which plugin allowed to be cached",
+                    self.key
+                );
+                state.cache.insert(cache_key, (expiry, value.clone()));
+                return Ok(value);
+            }
+            _ => {}
+        }
+
+        let reply = self.get(&mut state, request).inspect_err(|_| {
+            // the plugin is in an unknown state so a fresh one is started for the next request
+            state.process = None;
+        });
+        let reply = reply.with_context(|| {
+            Error::new(
+                ErrorCode::SourceFailed,
+                format!("Plugin {} failed", self.path.display()),
+            )
+        })?;
+        let (value, ttl) = match reply {
+            PluginMessage::Value {
+                value,
+                base64: false,
+                ttl,
+                ..
+            } => (value.into_bytes(), ttl),
+            PluginMessage::Value {
+                value,
+                base64: true,
+                ttl,
+                ..
+            } => (
+                BASE64_STANDARD
+                    .decode(value)
+                    .context("Plugin returned a value that is not valid base64")?,
+                ttl,
+            ),
+            PluginMessage::Error { message, .. } => {
+                anyhow::bail!(Error::new(
+                    ErrorCode::SourceFailed,
+                    format!(
+                        "Plugin {} returned an error: {message}",
+                        self.path.display()
+                    )
+                ))
+            }
+            PluginMessage::Hello { .. } => unreachable!("only replies to the request are returned"),
+        };
+        if let Some(ttl) = ttl.filter(|&ttl| ttl > 0) {
+            let expiry = Instant::now() + Duration::from_secs(ttl);
+            state.cache.insert(cache_key, (expiry, value.clone()));
+        }
+        Ok(value)
+    }
+}
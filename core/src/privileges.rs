@@ -0,0 +1,149 @@
+use std::{
+    ffi::{CStr, CString},
+    io,
+};
+
+use anyhow::Context;
+
+/// Capabilities which the agent can keep after switching to an unprivileged user
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum Capability {
+    /// Read any file regardless of its permissions (`CAP_DAC_READ_SEARCH`)
+    DacReadSearch,
+    /// Read and write any file regardless of its permissions, as needed for writing secrets back (`CAP_DAC_OVERRIDE`)
+    DacOverride,
+}
+
+impl Capability {
+    /// Name of the capability as used by systemd and `capabilities(7)`, e.g. `CAP_DAC_READ_SEARCH`
+    pub fn name(self) -> &'static str {
+        match self {
+            Capability::DacOverride => "CAP_DAC_OVERRIDE",
+            Capability::DacReadSearch => "CAP_DAC_READ_SEARCH",
+        }
+    }
+
+    /// Number of the capability as defined in `linux/capability.h`
+    fn number(self) -> u32 {
+        match self {
+            Capability::DacOverride => 1,
+            Capability::DacReadSearch => 2,
+        }
+    }
+}
+
+/// The unprivileged user to which the agent switches after it has connected to the bus
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RunAs {
+    pub user: String,
+    /// Capabilities which are retained as the only ones of the process
+    pub keep_capabilities: Vec<Capability>,
+}
+
+/// Switch the process to the user and capabilities described by `run_as`
+///
+/// The supplementary groups of the process are replaced by those of the user so that group-readable secret files can
+/// be shared with the agent. This is irreversible; the process cannot regain root afterwards.
+pub fn drop_privileges(run_as: &RunAs) -> anyhow::Result<()> {
+    let (uid, gid) = lookup_user(&run_as.user)?;
+    let name = CString::new(run_as.user.as_str()).context("User name contains a NUL byte")?;
+    let keep_capabilities = !run_as.keep_capabilities.is_empty();
+
+    // SAFETY: none of these calls have memory safety requirements besides `name` being a valid C string
+    unsafe {
+        if keep_capabilities && libc::prctl(libc::PR_SET_KEEPCAPS, 1, 0, 0, 0) != 0 {
+            return Err(io::Error::last_os_error()).context("Could not retain capabilities");
+        }
+        if libc::initgroups(name.as_ptr(), gid) != 0 {
+            return Err(io::Error::last_os_error())
+                .context("Could not set the supplementary groups");
+        }
+        if libc::setresgid(gid, gid, gid) != 0 {
+            return Err(io::Error::last_os_error()).context("Could not change the group id");
+        }
+        if libc::setresuid(uid, uid, uid) != 0 {
+            return Err(io::Error::last_os_error()).context("Could not change the user id");
+        }
+    }
+    // the kernel clears the effective capabilities when the user id changes so the kept ones have to be raised again
+    set_capabilities(&run_as.keep_capabilities).context("Could not set capabilities")?;
+    if keep_capabilities {
+        // SAFETY: prctl has no memory safety requirements
+        unsafe { libc::prctl(libc::PR_SET_KEEPCAPS, 0, 0, 0, 0) };
+    }
+
+    // SAFETY: setuid has no memory safety requirements
+    if uid != 0 && unsafe { libc::setuid(0) } == 0 {
+        anyhow::bail!("Could still regain root privileges after dropping them");
+    }
+    Ok(())
+}
+
+/// Determine the user and primary group id of the user named `user`
+fn lookup_user(user: &str) -> anyhow::Result<(libc::uid_t, libc::gid_t)> {
+    let name = CString::new(user).context("User name contains a NUL byte")?;
+    let mut buffer = vec![0 as libc::c_char; 16 * 1024];
+    // SAFETY: an all-zero passwd is valid since it only consists of integers and pointers
+    let mut passwd = unsafe { std::mem::zeroed::<libc::passwd>() };
+    let mut result = std::ptr::null_mut();
+    // SAFETY: all pointers are valid for the duration of the call and the buffer length matches the buffer
+    let error = unsafe {
+        libc::getpwnam_r(
+            name.as_ptr(),
+            &mut passwd,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    if error != 0 {
+        return Err(io::Error::from_raw_os_error(error))
+            .with_context(|| format!("Could not look up user {user}"));
+    }
+    if result.is_null() {
+        anyhow::bail!("User {user} does not exist");
+    }
+    // SAFETY: getpwnam_r succeeded so pw_name points to a C string inside the buffer
+    let found = unsafe { CStr::from_ptr(passwd.pw_name) };
+    tracing::debug!(
+        "Resolved user {} to uid {} and gid {}",
+        found.to_string_lossy(),
+        passwd.pw_uid,
+        passwd.pw_gid
+    );
+    Ok((passwd.pw_uid, passwd.pw_gid))
+}
+
+/// Replace the permitted and effective capabilities of the process by `capabilities`
+fn set_capabilities(capabilities: &[Capability]) -> io::Result<()> {
+    #[repr(C)]
+    struct Header {
+        version: u32,
+        pid: libc::c_int,
+    }
+    #[repr(C)]
+    #[derive(Default, Clone, Copy)]
+    struct Data {
+        effective: u32,
+        permitted: u32,
+        inheritable: u32,
+    }
+
+    // _LINUX_CAPABILITY_VERSION_3 which uses two data structs to describe 64 capabilities
+    let mut header = Header {
+        version: 0x2008_0522,
+        pid: 0,
+    };
+    let mut data = [Data::default(); 2];
+    for capability in capabilities {
+        let (index, bit) = (capability.number() / 32, capability.number() % 32);
+        data[index as usize].effective |= 1 << bit;
+        data[index as usize].permitted |= 1 << bit;
+    }
+    // SAFETY: header and data have the layout that the kernel expects for version 3
+    if unsafe { libc::syscall(libc::SYS_capset, &mut header, data.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
@@ -0,0 +1,176 @@
+use std::{
+    process::{Command, Stdio},
+    sync::atomic::AtomicBool,
+    time::Duration,
+};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::{
+    cache::TtlCache,
+    mapping::{run_command, SecretRequest, SecretValue},
+    redact,
+};
+
+/// Keys that are asked for if NetworkManager did not hint at any, by setting name
+const DEFAULT_KEYS: &[(&str, &str)] = &[
+    ("802-11-wireless-security", "psk"),
+    ("802-1x", "password"),
+    ("vpn", "secrets.password"),
+    ("gsm", "password"),
+    ("cdma", "password"),
+    ("pppoe", "password"),
+    ("wireguard", "private-key"),
+];
+
+/// Asks a user for the secrets of requests for which neither an entry nor the fallback provides any
+///
+/// This only happens for requests in which NetworkManager allows interaction. Each key is asked for by running
+/// `command` with a message describing the secret as last argument, which by default is `systemd-ask-password`.
+#[derive(Debug, Deserialize)]
+pub struct Prompt {
+    /// Command and arguments which print the answer of the user on stdout
+    #[serde(default = "default_command")]
+    command: Vec<String>,
+    /// Number of seconds for which an answer is reused for further requests of the same connection and setting
+    #[serde(default = "default_cache_ttl")]
+    cache_ttl: u64,
+    /// Answers by connection uuid, setting name and key
+    #[serde(skip)]
+    answers: Option<TtlCache<(String, String, String), SecretValue>>,
+}
+
+fn default_command() -> Vec<String> {
+    vec!["systemd-ask-password".to_owned()]
+}
+
+fn default_cache_ttl() -> u64 {
+    3600
+}
+
+impl Prompt {
+    /// Check the configuration and set up the cache of answers
+    pub(crate) fn finish(&mut self) -> anyhow::Result<()> {
+        if self.command.is_empty() {
+            anyhow::bail!("The prompt command must not be empty");
+        }
+        self.answers =
+            (self.cache_ttl > 0).then(|| TtlCache::new(Duration::from_secs(self.cache_ttl)));
+        Ok(())
+    }
+
+    /// Command and arguments which are run to ask for a key
+    pub fn command(&self) -> &[String] {
+        &self.command
+    }
+
+    /// The keys which are asked for to answer `request`
+    ///
+    /// These are the hinted keys or, without hints, the usual key of the setting if there is one.
+    pub fn keys(request: &SecretRequest) -> Vec<String> {
+        let mut keys = request
+            .key_hints()
+            .map(ToOwned::to_owned)
+            .collect::<Vec<_>>();
+        if keys.is_empty() {
+            keys.extend(
+                DEFAULT_KEYS
+                    .iter()
+                    .find(|(setting_name, _)| *setting_name == request.setting_name)
+                    .map(|(_, key)| (*key).to_owned()),
+            );
+        }
+        keys
+    }
+
+    /// Ask for every key that `request` requires and return the answers
+    ///
+    /// Answers are reused while they are cached unless NetworkManager asks for new secrets. The user is not asked at
+    /// all if it is unknown which keys are required.
+    pub fn ask(
+        &self,
+        request: &SecretRequest,
+        cancelled: &AtomicBool,
+    ) -> anyhow::Result<Vec<(String, SecretValue)>> {
+        let keys = Self::keys(request);
+        if keys.is_empty() {
+            tracing::info!(
+                "Not prompting for secrets of {} since NetworkManager did not hint at any keys",
+                request.setting_name
+            );
+        }
+
+        let mut secrets = Vec::with_capacity(keys.len());
+        for key in keys {
+            let cache_key = (
+                request.conn_uuid.clone(),
+                request.setting_name.clone(),
+                key.clone(),
+            );
+            let cached = self
+                .answers
+                .as_ref()
+                .filter(|_| !request.request_new)
+                .and_then(|answers| answers.get(&cache_key));
+            let value = match cached {
+                Some(value) => {
+                    tracing::debug!("Reusing the answer to the prompt for {key}");
+                    value
+                }
+                None => {
+                    let value = self.ask_key(request, &key, cancelled)?;
+                    if let Some(answers) = &self.answers {
+                        answers.insert(cache_key, value.clone());
+                    }
+                    value
+                }
+            };
+            secrets.push((key, value));
+        }
+        Ok(secrets)
+    }
+
+    /// Run the prompt command for a single key
+    fn ask_key(
+        &self,
+        request: &SecretRequest,
+        key: &str,
+        cancelled: &AtomicBool,
+    ) -> anyhow::Result<SecretValue> {
+        let (program, args) = self
+            .command
+            .split_first()
+            .expect("empty prompt commands are rejected when loading the config");
+        let message = match request.conn_id.is_empty() {
+            true => format!("Secret {}.{key}:", request.setting_name),
+            false => format!(
+                "Secret {}.{key} of connection {}:",
+                request.setting_name, request.conn_id
+            ),
+        };
+        tracing::info!(
+            "Asking for {}.{key} with prompt command {program}",
+            request.setting_name
+        );
+        let mut command = Command::new(program);
+        command
+            .args(args)
+            .arg(message)
+            .env("NM_CONNECTION_ID", &request.conn_id)
+            .env("NM_CONNECTION_UUID", &request.conn_uuid)
+            .env("NM_CONNECTION_TYPE", &request.conn_type)
+            .env("NM_SETTING_NAME", &request.setting_name)
+            .env("NM_KEY", key)
+            .stdout(Stdio::piped());
+        let output = run_command(command, &format!("prompt command {program}"), cancelled)?;
+        let mut answer = String::from_utf8(output)
+            .with_context(|| format!("The answer to the prompt for {key} is not valid UTF-8"))?;
+        if answer.ends_with('\n') {
+            answer.pop();
+        }
+        let value = SecretValue::String(answer);
+        redact::register(&value);
+        Ok(value)
+    }
+}
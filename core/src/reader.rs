@@ -0,0 +1,413 @@
+use std::{
+    ffi::OsStr,
+    fs::{self, File},
+    io, mem,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::{ffi::OsStrExt, fs::OpenOptionsExt},
+    },
+    path::{Component, Path, PathBuf},
+    sync::{Mutex, OnceLock},
+};
+
+use anyhow::Context;
+
+use crate::privileges::{self, Capability, RunAs};
+
+/// Open flags which the agent may pass to the helper; everything else is ignored so files are only ever read
+const ALLOWED_FLAGS: libc::c_int = libc::O_NONBLOCK | libc::O_NOFOLLOW;
+
+/// Suffixes of the signature files which are read next to signed files
+const SIGNATURE_SUFFIXES: &[&str] = &[".minisig", ".sig"];
+
+/// Maximum size of a request consisting of the open flags and a path
+const MAX_REQUEST_LEN: usize = mem::size_of::<libc::c_int>() + libc::PATH_MAX as usize;
+
+/// The agent's end of the socket connected to the reader helper if one was spawned
+static HELPER: OnceLock<Mutex<OwnedFd>> = OnceLock::new();
+
+/// A file or directory below which the reader helper opens files on behalf of the agent
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct AllowedPath {
+    path: PathBuf,
+    follow_symlinks: bool,
+}
+
+impl AllowedPath {
+    /// Allow opening the file at `path` together with its signatures or, if it is a directory, all files below it
+    ///
+    /// Without `follow_symlinks`, files are opened with `O_NOFOLLOW` and must not be reached via symlinked directories
+    /// below `path` either. Relative paths are resolved against the working directory.
+    pub fn new(path: &Path, follow_symlinks: bool) -> Self {
+        Self {
+            path: std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf()),
+            follow_symlinks,
+        }
+    }
+
+    /// Whether `path`, which must be absolute and free of `..` components, is located at or below this path
+    fn contains(&self, path: &Path) -> bool {
+        path.starts_with(&self.path)
+            || SIGNATURE_SUFFIXES.iter().any(|suffix| {
+                path.as_os_str()
+                    .as_bytes()
+                    .strip_suffix(suffix.as_bytes())
+                    .is_some_and(|signed| signed == self.path.as_os_str().as_bytes())
+            })
+    }
+}
+
+/// The paths which the helper opens files below
+///
+/// Paths of the previous configuration stay allowed until the next refresh since the agent keeps using it if loading
+/// the new one fails.
+struct Allowlist<F> {
+    fixed: Vec<AllowedPath>,
+    configured: F,
+    current: Vec<AllowedPath>,
+    previous: Vec<AllowedPath>,
+}
+
+impl<F: Fn() -> anyhow::Result<Vec<AllowedPath>>> Allowlist<F> {
+    /// Determine the configured paths again, keeping the current ones if that fails
+    fn refresh(&mut self) -> io::Result<()> {
+        match (self.configured)() {
+            Ok(paths) => {
+                tracing::debug!("Reader helper allows {} configured paths", paths.len());
+                self.previous = mem::replace(&mut self.current, paths);
+                Ok(())
+            }
+            Err(e) => {
+                tracing::warn!("Reader helper could not determine the configured paths: {e:#}");
+                Err(io::Error::from_raw_os_error(libc::EINVAL))
+            }
+        }
+    }
+
+    fn paths(&self) -> impl Iterator<Item = &AllowedPath> {
+        self.fixed.iter().chain(&self.current).chain(&self.previous)
+    }
+
+    /// Open `path` for reading if it is allowed, ignoring all `flags` but [`ALLOWED_FLAGS`]
+    fn open(&self, path: &Path, flags: libc::c_int) -> io::Result<File> {
+        let denied = || {
+            tracing::warn!(
+                "Reader helper refuses to open {} which is not part of the config",
+                path.display()
+            );
+            io::Error::from_raw_os_error(libc::EACCES)
+        };
+        let path = std::path::absolute(path)?;
+        if path.components().any(|c| c == Component::ParentDir) {
+            return Err(denied());
+        }
+        let allowed = self
+            .paths()
+            .filter(|allowed| allowed.contains(&path))
+            .collect::<Vec<_>>();
+        if allowed.is_empty() {
+            return Err(denied());
+        }
+        let follow_symlinks = allowed.iter().any(|allowed| allowed.follow_symlinks);
+        let mut flags = flags & ALLOWED_FLAGS;
+        if !follow_symlinks {
+            flags |= libc::O_NOFOLLOW;
+        }
+        let file = File::options().read(true).custom_flags(flags).open(&path)?;
+        if !follow_symlinks {
+            // O_NOFOLLOW only covers the last component so the file that was actually opened is checked as well
+            let real_path = fs::read_link(format!("/proc/self/fd/{}", file.as_raw_fd()))?;
+            let inside = allowed.iter().any(|allowed| {
+                let dir = fs::canonicalize(&allowed.path).unwrap_or_else(|_| allowed.path.clone());
+                real_path.starts_with(dir)
+            });
+            if !inside {
+                return Err(denied());
+            }
+        }
+        Ok(file)
+    }
+}
+
+/// Fork a helper process which opens files on behalf of the agent and passes their descriptors back
+///
+/// The helper switches to the user given in `run_as` but keeps `CAP_DAC_READ_SEARCH` so that it can open all files while
+/// the agent itself runs without any capabilities. It only ever opens files for reading and only those at or below the
+/// `fixed` paths and the paths which `configured` returns, which bounds what a bug in the D-Bus facing agent can be
+/// used for. `configured` runs in the helper at startup and whenever the agent calls [`refresh`], so that it is
+/// independent of the agent.
+///
+/// This must be called before any other thread is started since only the calling thread survives the fork.
+pub fn spawn(
+    run_as: &RunAs,
+    fixed: Vec<AllowedPath>,
+    configured: impl Fn() -> anyhow::Result<Vec<AllowedPath>>,
+) -> anyhow::Result<()> {
+    let mut fds = [0; 2];
+    // SAFETY: socketpair only writes two descriptors into the provided array
+    if unsafe {
+        libc::socketpair(
+            libc::AF_UNIX,
+            libc::SOCK_SEQPACKET | libc::SOCK_CLOEXEC,
+            0,
+            fds.as_mut_ptr(),
+        )
+    } != 0
+    {
+        return Err(io::Error::last_os_error())
+            .context("Could not create a socket for the reader helper");
+    }
+    // SAFETY: socketpair succeeded so both descriptors are open and owned by nobody else
+    let (agent, helper) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+
+    // SAFETY: the process is still single-threaded so the child can safely continue to run arbitrary code
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()).context("Could not fork the reader helper"),
+        0 => {
+            drop(agent);
+            let result = setup_helper(run_as).and_then(|()| {
+                let mut allowlist = Allowlist {
+                    fixed,
+                    configured,
+                    current: Vec::new(),
+                    previous: Vec::new(),
+                };
+                // a broken config is reported by the agent which can still read the config files
+                let _ = allowlist.refresh();
+                serve(&helper, &mut allowlist)
+            });
+            if let Err(e) = &result {
+                tracing::error!("Reader helper failed: {e:#}");
+            }
+            std::process::exit(result.is_err() as i32);
+        }
+        pid => {
+            drop(helper);
+            tracing::debug!("Spawned reader helper with pid {pid}");
+            HELPER
+                .set(Mutex::new(agent))
+                .map_err(|_| anyhow::anyhow!("The reader helper has already been spawned"))
+        }
+    }
+}
+
+/// Open the file at `path` for reading with the additional open `flags`, via the reader helper if one was spawned
+pub fn open(path: &Path, flags: libc::c_int) -> io::Result<File> {
+    match HELPER.get() {
+        Some(helper) => request(&helper.lock().unwrap(), path, flags),
+        None => File::options().read(true).custom_flags(flags).open(path),
+    }
+}
+
+/// Make the reader helper determine the paths of the configuration again, e.g. before it is reloaded
+///
+/// Does nothing if no helper was spawned.
+pub fn refresh() -> io::Result<()> {
+    let Some(helper) = HELPER.get() else {
+        return Ok(());
+    };
+    let socket = helper.lock().unwrap();
+    send_request(&socket, Path::new(""), 0)?;
+    match receive_reply(&socket).map_err(helper_error)? {
+        (0, _) => Ok(()),
+        (errno, _) => Err(io::Error::from_raw_os_error(errno)),
+    }
+}
+
+/// Ask the helper connected to `socket` to open `path` and receive the resulting descriptor
+fn request(socket: &OwnedFd, path: &Path, flags: libc::c_int) -> io::Result<File> {
+    send_request(socket, path, flags)?;
+    let (errno, fd) = receive_reply(socket).map_err(helper_error)?;
+    match (errno, fd) {
+        (0, Some(fd)) => Ok(File::from(fd)),
+        (0, None) => Err(helper_error(io::Error::other(
+            "reply contains no file descriptor",
+        ))),
+        (errno, _) => Err(io::Error::from_raw_os_error(errno)),
+    }
+}
+
+/// Describe that the helper could not be reached because of `e`
+fn helper_error(e: io::Error) -> io::Error {
+    io::Error::new(e.kind(), format!("Reader helper is not available: {e}"))
+}
+
+/// Send a request to open `path` with `flags` or, if `path` is empty, to refresh the allowed paths
+fn send_request(socket: &OwnedFd, path: &Path, flags: libc::c_int) -> io::Result<()> {
+    let mut message = flags.to_ne_bytes().to_vec();
+    message.extend_from_slice(path.as_os_str().as_bytes());
+    if message.len() > MAX_REQUEST_LEN {
+        return Err(io::Error::from_raw_os_error(libc::ENAMETOOLONG));
+    }
+    // SAFETY: the buffer is valid for its length
+    if unsafe {
+        libc::send(
+            socket.as_raw_fd(),
+            message.as_ptr().cast(),
+            message.len(),
+            libc::MSG_NOSIGNAL,
+        )
+    } < 0
+    {
+        return Err(helper_error(io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+/// Receive the reply to a request, consisting of an errno value and, on success, a file descriptor
+fn receive_reply(socket: &OwnedFd) -> io::Result<(i32, Option<OwnedFd>)> {
+    let mut errno = [0u8; mem::size_of::<i32>()];
+    let mut iov = libc::iovec {
+        iov_base: errno.as_mut_ptr().cast(),
+        iov_len: errno.len(),
+    };
+    // SAFETY: CMSG_SPACE only computes a size
+    let mut control =
+        vec![0u8; unsafe { libc::CMSG_SPACE(mem::size_of::<RawFd>() as u32) } as usize];
+    // SAFETY: an all-zero msghdr is valid and the relevant fields are filled in below
+    let mut header = unsafe { mem::zeroed::<libc::msghdr>() };
+    header.msg_iov = &mut iov;
+    header.msg_iovlen = 1;
+    header.msg_control = control.as_mut_ptr().cast();
+    header.msg_controllen = control.len() as _;
+
+    // SAFETY: all buffers referenced by the header are valid for their lengths
+    let len = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut header, libc::MSG_CMSG_CLOEXEC) };
+    if len < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if len as usize != errno.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "helper closed the connection",
+        ));
+    }
+
+    let mut fd = None;
+    // SAFETY: the header was filled by recvmsg so the control messages it points to are well-formed
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&header);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                let raw = std::ptr::read_unaligned(libc::CMSG_DATA(cmsg).cast::<RawFd>());
+                fd = Some(OwnedFd::from_raw_fd(raw));
+            }
+            cmsg = libc::CMSG_NXTHDR(&header, cmsg);
+        }
+    }
+    Ok((i32::from_ne_bytes(errno), fd))
+}
+
+/// Restrict the freshly forked helper process to what it needs for opening files
+fn setup_helper(run_as: &RunAs) -> anyhow::Result<()> {
+    // SAFETY: prctl has no memory safety requirements and the name is a NUL terminated string
+    unsafe {
+        // the helper must not outlive the agent, e.g. when the agent is killed
+        if libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL) != 0 {
+            return Err(io::Error::last_os_error())
+                .context("Could not set the parent death signal");
+        }
+        libc::prctl(libc::PR_SET_NAME, c"nm-fsa-reader".as_ptr());
+    }
+    privileges::drop_privileges(&RunAs {
+        user: run_as.user.clone(),
+        keep_capabilities: vec![Capability::DacReadSearch],
+    })?;
+    // SAFETY: prctl has no memory safety requirements
+    if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+        return Err(io::Error::last_os_error()).context("Could not forbid gaining new privileges");
+    }
+    Ok(())
+}
+
+/// Answer requests of the agent on `socket` until it closes the connection
+fn serve<F: Fn() -> anyhow::Result<Vec<AllowedPath>>>(
+    socket: &OwnedFd,
+    allowlist: &mut Allowlist<F>,
+) -> anyhow::Result<()> {
+    let mut buffer = vec![0u8; MAX_REQUEST_LEN];
+    loop {
+        // SAFETY: the buffer is valid for its length
+        let len = unsafe {
+            libc::recv(
+                socket.as_raw_fd(),
+                buffer.as_mut_ptr().cast(),
+                buffer.len(),
+                0,
+            )
+        };
+        match len {
+            0 => return Ok(()),
+            len if len < 0 => {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(e).context("Could not receive a request");
+            }
+            _ => {}
+        }
+        let result = match parse_request(&buffer[..len as usize]) {
+            Ok((path, _)) if path.as_os_str().is_empty() => allowlist.refresh().map(|()| None),
+            Ok((path, flags)) => allowlist.open(path, flags).map(Some),
+            Err(e) => Err(e),
+        };
+        send_reply(socket, result).context("Could not send a reply")?;
+    }
+}
+
+/// Split a request into the path and open flags
+///
+/// An empty path requests to refresh the allowed paths.
+fn parse_request(request: &[u8]) -> io::Result<(&Path, libc::c_int)> {
+    let invalid = || io::Error::from_raw_os_error(libc::EINVAL);
+    let (flags, path) = request
+        .split_first_chunk::<{ mem::size_of::<libc::c_int>() }>()
+        .ok_or_else(invalid)?;
+    if path.contains(&0) {
+        return Err(invalid());
+    }
+    Ok((
+        Path::new(OsStr::from_bytes(path)),
+        libc::c_int::from_ne_bytes(*flags),
+    ))
+}
+
+/// Send the outcome of a request back to the agent, passing the descriptor of an opened file along
+fn send_reply(socket: &OwnedFd, result: io::Result<Option<File>>) -> io::Result<()> {
+    let (errno, file) = match result {
+        Ok(file) => (0, file),
+        Err(e) => (e.raw_os_error().unwrap_or(libc::EIO), None),
+    };
+    let mut errno = errno.to_ne_bytes();
+    let mut iov = libc::iovec {
+        iov_base: errno.as_mut_ptr().cast(),
+        iov_len: errno.len(),
+    };
+    // SAFETY: an all-zero msghdr is valid and the relevant fields are filled in below
+    let mut header = unsafe { mem::zeroed::<libc::msghdr>() };
+    header.msg_iov = &mut iov;
+    header.msg_iovlen = 1;
+    // SAFETY: CMSG_SPACE only computes a size
+    let mut control =
+        vec![0u8; unsafe { libc::CMSG_SPACE(mem::size_of::<RawFd>() as u32) } as usize];
+    if let Some(file) = &file {
+        header.msg_control = control.as_mut_ptr().cast();
+        header.msg_controllen = control.len() as _;
+        // SAFETY: the control buffer has room for exactly one control message carrying a descriptor
+        unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&header);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<RawFd>() as u32) as _;
+            std::ptr::write_unaligned(libc::CMSG_DATA(cmsg).cast::<RawFd>(), file.as_raw_fd());
+        }
+    }
+    // SAFETY: all buffers referenced by the header are valid for their lengths
+    if unsafe { libc::sendmsg(socket.as_raw_fd(), &header, libc::MSG_NOSIGNAL) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // the descriptor has been duplicated into the agent so the helper's copy is closed by dropping the file
+    drop(file);
+    Ok(())
+}
@@ -0,0 +1,158 @@
+use std::{
+    borrow::Cow,
+    fmt::{self, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        RwLock,
+    },
+};
+
+use sha1::{Digest, Sha1};
+use tracing::{field::Field, Event, Level, Subscriber};
+use tracing_subscriber::{field::Visit, layer::Context, Layer};
+
+use crate::{cache::LockedBytes, mapping::SecretValue};
+
+/// Text with which secret values are replaced in log messages
+const REDACTED: &str = "<redacted>";
+
+/// Secrets shorter than this are not redacted because they would match all over unrelated log messages
+const MIN_SECRET_LEN: usize = 4;
+
+/// Maximum number of secret values that are remembered for redaction, dropping the oldest ones first
+const MAX_SECRETS: usize = 1024;
+
+/// All secret values that the agent has handled so far
+static SECRETS: RwLock<Vec<LockedBytes>> = RwLock::new(Vec::new());
+
+/// Whether connection ids are replaced by their hash in log messages
+static HASH_CONNECTION_IDS: AtomicBool = AtomicBool::new(false);
+
+/// Remember `value` so that it is scrubbed from all log messages from now on
+pub fn register(value: &SecretValue) {
+    match value {
+        SecretValue::String(value) => register_bytes(value.as_bytes()),
+        SecretValue::StringList(values) => {
+            for value in values {
+                register_bytes(value.as_bytes());
+            }
+        }
+        SecretValue::Bytes(value) => register_bytes(value),
+        // numbers and booleans are too common to be scrubbed without destroying every log message
+        SecretValue::U32(_) | SecretValue::Bool(_) => {}
+    }
+}
+
+fn register_bytes(value: &[u8]) {
+    // log messages are text so secrets that are not valid UTF-8 can only appear in them as different characters
+    if value.len() < MIN_SECRET_LEN || std::str::from_utf8(value).is_err() {
+        return;
+    }
+    if SECRETS
+        .read()
+        .unwrap()
+        .iter()
+        .any(|secret| secret.as_bytes() == value)
+    {
+        return;
+    }
+    // the copy is not pinned because pinning may fail and redaction must never be the reason for losing a secret
+    let Ok(secret) = LockedBytes::new(value, false) else {
+        return;
+    };
+    let mut secrets = SECRETS.write().unwrap();
+    if secrets.len() >= MAX_SECRETS {
+        secrets.remove(0);
+    }
+    secrets.push(secret);
+}
+
+/// `text` with all registered secret values replaced or `None` if it contains none
+fn scrub(text: &str) -> Option<String> {
+    let secrets = SECRETS.read().unwrap();
+    let mut result = Cow::Borrowed(text);
+    for secret in secrets.iter() {
+        let Ok(secret) = std::str::from_utf8(secret.as_bytes()) else {
+            continue;
+        };
+        // values that are formatted via Debug appear with escaped quotes, backslashes and control characters
+        let escaped = secret.escape_debug().to_string();
+        for form in [secret, escaped.as_str()] {
+            if result.contains(form) {
+                result = Cow::Owned(result.replace(form, REDACTED));
+            }
+        }
+    }
+    match result {
+        Cow::Borrowed(_) => None,
+        Cow::Owned(result) => Some(result),
+    }
+}
+
+/// Replace connection ids by their hash in log messages from now on
+pub fn hash_connection_ids() {
+    HASH_CONNECTION_IDS.store(true, Ordering::Relaxed);
+}
+
+/// The form in which the connection id `id` is logged
+///
+/// This is the id itself unless hashing is enabled in which case it is a truncated SHA-1 hash of it that still allows
+/// correlating log messages of the same connection.
+pub fn connection_id(id: &str) -> Cow<'_, str> {
+    if !HASH_CONNECTION_IDS.load(Ordering::Relaxed) {
+        return Cow::Borrowed(id);
+    }
+    let hash = Sha1::digest(id.as_bytes());
+    Cow::Owned(format!("sha1:{}", hex::encode(&hash[..6])))
+}
+
+/// A layer which suppresses log events that contain registered secret values
+///
+/// Since layers cannot modify events before other layers format them, events containing a secret are disabled for all
+/// layers and a scrubbed copy of them is logged instead. Its fields are flattened into the message so that they are
+/// not indexed separately by structured log targets.
+/// This layer must be the outermost one so that it sees every event before any other layer does.
+pub struct RedactionLayer;
+
+impl<S: Subscriber> Layer<S> for RedactionLayer {
+    fn event_enabled(&self, event: &Event<'_>, _: Context<'_, S>) -> bool {
+        // the scrubbed copies are logged with this module as target and never contain secrets
+        if event.metadata().target() == module_path!() {
+            return true;
+        }
+        let mut text = EventText::default();
+        event.record(&mut text);
+        let Some(text) = scrub(&text.0) else {
+            return true;
+        };
+        let origin = event.metadata().target();
+        match *event.metadata().level() {
+            Level::ERROR => tracing::error!(origin, "{text}"),
+            Level::WARN => tracing::warn!(origin, "{text}"),
+            Level::INFO => tracing::info!(origin, "{text}"),
+            Level::DEBUG => tracing::debug!(origin, "{text}"),
+            Level::TRACE => tracing::trace!(origin, "{text}"),
+        }
+        false
+    }
+}
+
+/// The message and all fields of an event formatted as a single line of text
+#[derive(Default)]
+struct EventText(String);
+
+impl Visit for EventText {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record_debug(field, &format_args!("{value}"));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if !self.0.is_empty() {
+            self.0.push(' ');
+        }
+        let _ = match field.name() {
+            "message" => write!(self.0, "{value:?}"),
+            name => write!(self.0, "{name}={value:?}"),
+        };
+    }
+}
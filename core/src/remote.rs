@@ -0,0 +1,194 @@
+use std::{
+    fs,
+    io::Write,
+    os::unix::fs::{DirBuilderExt, OpenOptionsExt},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{atomic::AtomicBool, OnceLock},
+};
+
+use anyhow::Context;
+use sha1::{Digest, Sha1};
+
+use crate::{mapping::run_command, signature::SignatureKey};
+
+/// Directory in which verified copies of remote configs are kept if no other directory is configured
+pub const DEFAULT_CACHE_DIR: &str = "/var/cache/nm-file-secret-agent";
+
+/// Maximum size of remote configs and their signatures in bytes
+const MAX_SIZE: u64 = 1024 * 1024;
+
+/// Number of seconds within which a single download must finish
+const DOWNLOAD_TIMEOUT: u64 = 30;
+
+/// Suffixes of the detached signatures which are looked up next to a remote config in order
+const SIGNATURE_SUFFIXES: &[&str] = &[".minisig", ".sig"];
+
+/// How configs that are given as `http://` or `https://` URLs are fetched
+#[derive(Debug, Clone)]
+pub struct RemoteOptions {
+    /// Public key with which the detached minisign or signify signature of every remote config must be verifiable
+    pub signature_key: Option<PathBuf>,
+    /// Directory in which the last verified copy of every remote config is kept for offline boots
+    pub cache_dir: PathBuf,
+}
+
+impl Default for RemoteOptions {
+    fn default() -> Self {
+        Self {
+            signature_key: None,
+            cache_dir: PathBuf::from(DEFAULT_CACHE_DIR),
+        }
+    }
+}
+
+static OPTIONS: OnceLock<RemoteOptions> = OnceLock::new();
+
+/// Set the options with which remote configs are fetched
+///
+/// This must be called before the first config is loaded, otherwise the defaults are used.
+pub fn configure(options: RemoteOptions) -> anyhow::Result<()> {
+    OPTIONS
+        .set(options)
+        .map_err(|_| anyhow::anyhow!("Remote configs have already been configured"))
+}
+
+/// Whether `path` is the URL of a remote config instead of a local path
+pub fn is_url(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|path| path.starts_with("https://") || path.starts_with("http://"))
+}
+
+/// Download the config at `url` and verify its detached signature
+///
+/// The signature is downloaded from `<url>.minisig` or, if that fails, from signify's `<url>.sig`. Every verified
+/// config is stored in the cache directory, from which it is taken if downloading or verifying fails later on, e.g.
+/// because the machine boots without network.
+pub fn fetch(url: &str) -> anyhow::Result<String> {
+    let options = OPTIONS.get_or_init(RemoteOptions::default);
+    let key_path = options.signature_key.as_ref().with_context(|| {
+        format!("Remote config {url} cannot be loaded without a key to verify its signature")
+    })?;
+    let key = SignatureKey::from_file(key_path)?;
+    let cached = options.cache_dir.join(format!(
+        "{}.toml",
+        hex::encode(Sha1::digest(url.as_bytes()))
+    ));
+
+    match download_verified(url, &key) {
+        Ok((content, signature)) => {
+            if let Err(e) = store(&options.cache_dir, &cached, &content, &signature) {
+                tracing::warn!(
+                    "Could not cache remote config {url} at {}: {e:#}",
+                    cached.display()
+                );
+            }
+            tracing::debug!("Fetched and verified remote config {url}");
+            Ok(content)
+        }
+        Err(e) => match read_cached(&cached, &key) {
+            Ok(content) => {
+                tracing::warn!(
+                    "Using the cached copy of remote config {url} since it could not be fetched: {e:#}"
+                );
+                Ok(content)
+            }
+            Err(cache_error) => {
+                tracing::debug!("No usable cached copy of remote config {url}: {cache_error:#}");
+                Err(e.context(format!(
+                    "Could not fetch remote config {url} and no verified copy is cached"
+                )))
+            }
+        },
+    }
+}
+
+/// Download the config at `url` together with its signature and verify it with `key`
+fn download_verified(url: &str, key: &SignatureKey) -> anyhow::Result<(String, String)> {
+    let content = download(url)?;
+    let mut errors = Vec::new();
+    let signature = SIGNATURE_SUFFIXES
+        .iter()
+        .find_map(|suffix| {
+            download(&format!("{url}{suffix}"))
+                .inspect_err(|e| errors.push(format!("{e:#}")))
+                .ok()
+        })
+        .with_context(|| format!("Could not download a signature: {}", errors.join("; ")))?;
+    let signature = String::from_utf8(signature).context("The signature is not valid UTF-8")?;
+    key.verify_detached(&content, &signature)
+        .context("Could not verify the signature")?;
+    let content = String::from_utf8(content).context("The config is not valid UTF-8")?;
+    Ok((content, signature))
+}
+
+/// Download `url` with `curl`
+fn download(url: &str) -> anyhow::Result<Vec<u8>> {
+    let mut command = Command::new("curl");
+    command
+        .args(["--fail", "--silent", "--show-error", "--location"])
+        .args(["--proto", "=http,https", "--proto-redir", "=https"])
+        .arg("--max-time")
+        .arg(DOWNLOAD_TIMEOUT.to_string())
+        .arg("--max-filesize")
+        .arg(MAX_SIZE.to_string())
+        .arg(url)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit());
+    // downloads are bounded by their timeout and only happen while loading the config
+    run_command(command, &format!("curl for {url}"), &AtomicBool::new(false))
+}
+
+/// Read the copy of a remote config cached at `path` and verify it again with `key`
+fn read_cached(path: &Path, key: &SignatureKey) -> anyhow::Result<String> {
+    let content =
+        fs::read_to_string(path).with_context(|| format!("Could not read {}", path.display()))?;
+    let signature_path = signature_path(path);
+    let signature = fs::read_to_string(&signature_path)
+        .with_context(|| format!("Could not read {}", signature_path.display()))?;
+    key.verify_detached(content.as_bytes(), &signature)
+        .context("Could not verify the signature of the cached copy")?;
+    Ok(content)
+}
+
+/// Atomically replace the cached copy at `path` and its signature
+fn store(dir: &Path, path: &Path, content: &str, signature: &str) -> anyhow::Result<()> {
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)
+        .with_context(|| format!("Could not create {}", dir.display()))?;
+    // the signature is written first so that a crash in between leaves a copy which fails verification
+    write_atomically(&signature_path(path), signature)?;
+    write_atomically(path, content)
+}
+
+fn write_atomically(path: &Path, content: &str) -> anyhow::Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(format!(".tmp-{}", std::process::id()));
+    let tmp_path = PathBuf::from(tmp_path);
+    let result = fs::File::options()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&tmp_path)
+        .and_then(|mut file| {
+            file.write_all(content.as_bytes())?;
+            file.sync_all()
+        })
+        .and_then(|()| fs::rename(&tmp_path, path))
+        .with_context(|| format!("Could not write {}", path.display()));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+/// The path at which the signature of the cached copy at `path` is stored
+fn signature_path(path: &Path) -> PathBuf {
+    let mut result = path.as_os_str().to_owned();
+    result.push(".sig");
+    PathBuf::from(result)
+}
@@ -0,0 +1,151 @@
+use std::{
+    ffi::OsString,
+    fmt,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use base64::Engine;
+
+use crate::reader;
+
+/// Maximum size of public key and signature files in bytes
+const MAX_FILE_SIZE: u64 = 4096;
+
+/// Algorithm identifier of Ed25519 keys and signatures over the raw content, used by signify and legacy minisign
+const ALGORITHM_ED25519: &[u8; 2] = b"Ed";
+
+/// A minisign or signify public key against which backing files are verified
+///
+/// Both tools use the same public key format so a key works with signatures created by either of them.
+#[derive(Clone)]
+pub struct SignatureKey {
+    minisign: minisign_verify::PublicKey,
+    key_id: [u8; 8],
+    key: ed25519_compact::PublicKey,
+}
+
+impl fmt::Debug for SignatureKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SignatureKey")
+            .field(&hex::encode_upper(self.key_id))
+            .finish()
+    }
+}
+
+impl SignatureKey {
+    /// Read the public key file at `path`, e.g. `minisign.pub` or `signify.pub`
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let content = read_small_file(path)
+            .with_context(|| format!("Could not read public key file {}", path.display()))?;
+        Self::decode(&content)
+            .with_context(|| format!("Could not parse public key file {}", path.display()))
+    }
+
+    fn decode(content: &str) -> anyhow::Result<Self> {
+        let encoded = payload_line(content).context("The file contains no public key")?;
+        let minisign = minisign_verify::PublicKey::from_base64(encoded)
+            .map_err(|e| anyhow::anyhow!("Invalid public key: {e}"))?;
+        let decoded = base64::engine::general_purpose::STANDARD
+            .decode(encoded)
+            .context("Invalid public key encoding")?;
+        let key = ed25519_compact::PublicKey::from_slice(&decoded[10..])
+            .map_err(|e| anyhow::anyhow!("Invalid public key: {e}"))?;
+        Ok(Self {
+            minisign,
+            key_id: decoded[2..10].try_into().unwrap(),
+            key,
+        })
+    }
+
+    /// Verify that `content` read from `file` carries a valid signature made with this key
+    ///
+    /// The signature is read from `<file>.minisig` or, if that does not exist, from signify's `<file>.sig`.
+    pub fn verify(&self, file: &Path, content: &[u8]) -> anyhow::Result<()> {
+        let minisig_path = with_suffix(file, ".minisig");
+        match read_small_file(&minisig_path) {
+            Ok(signature) => return self.verify_minisign(content, &signature),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => {
+                return Err(e).with_context(|| {
+                    format!("Could not read signature file {}", minisig_path.display())
+                })
+            }
+        }
+        let sig_path = with_suffix(file, ".sig");
+        match read_small_file(&sig_path) {
+            Ok(signature) => self.verify_signify(content, &signature),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => anyhow::bail!(
+                "Neither {} nor {} exists",
+                minisig_path.display(),
+                sig_path.display()
+            ),
+            Err(e) => Err(e)
+                .with_context(|| format!("Could not read signature file {}", sig_path.display())),
+        }
+    }
+
+    /// Verify that `content` carries a valid detached minisign or signify `signature` made with this key
+    pub fn verify_detached(&self, content: &[u8], signature: &str) -> anyhow::Result<()> {
+        match minisign_verify::Signature::decode(signature) {
+            Ok(_) => self.verify_minisign(content, signature),
+            Err(_) => self.verify_signify(content, signature),
+        }
+    }
+
+    fn verify_minisign(&self, content: &[u8], signature: &str) -> anyhow::Result<()> {
+        let signature = minisign_verify::Signature::decode(signature)
+            .map_err(|e| anyhow::anyhow!("Invalid minisign signature: {e}"))?;
+        // signatures of older minisign versions are made over the raw content instead of its hash
+        self.minisign
+            .verify(content, &signature, true)
+            .map_err(|e| anyhow::anyhow!("Minisign signature verification failed: {e}"))
+    }
+
+    fn verify_signify(&self, content: &[u8], signature: &str) -> anyhow::Result<()> {
+        let encoded = payload_line(signature).context("The signature file is empty")?;
+        let decoded = base64::engine::general_purpose::STANDARD
+            .decode(encoded)
+            .context("Invalid signify signature encoding")?;
+        if decoded.len() != 2 + 8 + 64 || &decoded[..2] != ALGORITHM_ED25519 {
+            anyhow::bail!("Invalid signify signature");
+        }
+        if decoded[2..10] != self.key_id {
+            anyhow::bail!(
+                "The signature was made with key {} instead of {}",
+                hex::encode_upper(&decoded[2..10]),
+                hex::encode_upper(self.key_id)
+            );
+        }
+        let signature = ed25519_compact::Signature::from_slice(&decoded[10..])
+            .map_err(|e| anyhow::anyhow!("Invalid signify signature: {e}"))?;
+        self.key
+            .verify(content, &signature)
+            .map_err(|e| anyhow::anyhow!("Signify signature verification failed: {e}"))
+    }
+}
+
+/// The base64 encoded line following the `untrusted comment:` line of a key or signature file
+fn payload_line(content: &str) -> Option<&str> {
+    content
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with("untrusted comment:"))
+}
+
+/// `path` with `suffix` appended to its file name
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Read the small text file at `path`, going through the reader helper if one was spawned
+fn read_small_file(path: &Path) -> io::Result<String> {
+    let mut content = String::new();
+    reader::open(path, libc::O_NONBLOCK)?
+        .take(MAX_FILE_SIZE)
+        .read_to_string(&mut content)?;
+    Ok(content)
+}
@@ -0,0 +1,19 @@
+use std::fmt;
+
+use crate::mapping::SecretRequest;
+
+/// A source from which config entries can read secret values besides files, templates and TOTP secrets
+///
+/// Sources are attached to entries with [`MappingConfig::add_entry`](crate::mapping::MappingConfig::add_entry). The
+/// returned bytes are treated like the content of a backing file, i.e. the entry's `trim`, `encoding`, `transform`,
+/// `derive` and `dbus_type` settings are applied to them before the value is served.
+pub trait SecretSource: fmt::Debug + Send + Sync {
+    /// Short name describing the kind of source, e.g. in the output of the `list` subcommand
+    fn kind(&self) -> &'static str;
+
+    /// Read the raw value that `request` asks for
+    ///
+    /// This is called from a dedicated thread and may block. If `request.request_new` is set, NetworkManager has
+    /// rejected the previously provided value and any value the source keeps around should be considered stale.
+    fn read(&self, request: &SecretRequest) -> anyhow::Result<Vec<u8>>;
+}
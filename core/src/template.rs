@@ -0,0 +1,102 @@
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+/// A template from which a secret value is rendered by combining literal text with file contents
+///
+/// Templates are written as strings in which `{{ file('<path>') }}` placeholders are replaced by the content of the
+/// referenced file, e.g. `"{{ file('user') }}:{{ file('pass') }}"`.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Template {
+    parts: Vec<TemplatePart>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum TemplatePart {
+    Literal(String),
+    File(PathBuf),
+}
+
+impl Template {
+    /// All files that are referenced by this template
+    pub fn files(&self) -> impl Iterator<Item = &Path> {
+        self.parts.iter().filter_map(|part| match part {
+            TemplatePart::File(path) => Some(path.as_path()),
+            TemplatePart::Literal(_) => None,
+        })
+    }
+
+    /// Replace all file paths referenced by this template with the result of `f`
+    pub fn map_files(&mut self, mut f: impl FnMut(&Path) -> PathBuf) {
+        for part in self.parts.iter_mut() {
+            if let TemplatePart::File(path) = part {
+                *path = f(path);
+            }
+        }
+    }
+
+    /// Replace all file paths referenced by this template with the result of `f`, stopping at the first error
+    pub fn try_map_files(
+        &mut self,
+        mut f: impl FnMut(&Path) -> anyhow::Result<PathBuf>,
+    ) -> anyhow::Result<()> {
+        for part in self.parts.iter_mut() {
+            if let TemplatePart::File(path) = part {
+                *path = f(path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Render the template by calling `read_file` for every referenced file
+    pub fn render(
+        &self,
+        mut read_file: impl FnMut(&Path) -> anyhow::Result<Vec<u8>>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let mut result = Vec::new();
+        for part in self.parts.iter() {
+            match part {
+                TemplatePart::Literal(text) => result.extend_from_slice(text.as_bytes()),
+                TemplatePart::File(path) => result.extend(read_file(path)?),
+            }
+        }
+        Ok(result)
+    }
+}
+
+impl TryFrom<String> for Template {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let mut parts = Vec::new();
+        let mut rest = value.as_str();
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                parts.push(TemplatePart::Literal(rest[..start].to_owned()));
+            }
+            let end = rest[start..]
+                .find("}}")
+                .ok_or_else(|| anyhow::anyhow!("Unterminated placeholder in template {value:?}"))?;
+            let expression = rest[start + 2..start + end].trim();
+            parts.push(parse_expression(expression).ok_or_else(|| {
+                anyhow::anyhow!("Unsupported placeholder {{{{ {expression} }}}} in template {value:?}; only file('<path>') is supported")
+            })?);
+            rest = &rest[start + end + 2..];
+        }
+        if !rest.is_empty() {
+            parts.push(TemplatePart::Literal(rest.to_owned()));
+        }
+        Ok(Self { parts })
+    }
+}
+
+/// Parse a placeholder expression of the form `file('<path>')` or `file("<path>")`
+fn parse_expression(expression: &str) -> Option<TemplatePart> {
+    let argument = expression.strip_prefix("file(")?.strip_suffix(')')?.trim();
+    let path = argument
+        .strip_prefix('\'')
+        .and_then(|i| i.strip_suffix('\''))
+        .or_else(|| argument.strip_prefix('"').and_then(|i| i.strip_suffix('"')))?;
+    Some(TemplatePart::File(PathBuf::from(path)))
+}
@@ -0,0 +1,92 @@
+use std::{
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha1::Sha1;
+
+/// A source that computes the current time-based one-time password (RFC 6238) from a shared secret
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TotpSource {
+    /// File containing the base32 encoded shared secret
+    pub secret_file: PathBuf,
+    /// Number of digits of the generated code
+    #[serde(default = "default_digits")]
+    pub digits: u32,
+    /// Number of seconds for which a code is valid
+    #[serde(default = "default_period")]
+    pub period: u64,
+}
+
+fn default_digits() -> u32 {
+    6
+}
+
+fn default_period() -> u64 {
+    30
+}
+
+impl TotpSource {
+    /// Check that codes with the configured number of digits and period can be generated
+    pub(crate) fn check(&self) -> anyhow::Result<()> {
+        if !(1..=9).contains(&self.digits) {
+            anyhow::bail!("TOTP codes must have between 1 and 9 digits");
+        }
+        if self.period == 0 {
+            anyhow::bail!("TOTP period must not be 0");
+        }
+        Ok(())
+    }
+
+    /// Compute the code that is valid at the current time from the content of `secret_file`
+    pub fn generate(&self, secret: &[u8]) -> anyhow::Result<String> {
+        self.check()?;
+
+        let key = decode_base32(secret).context("TOTP secret is not valid base32")?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .context("System time is before the unix epoch")?;
+        let counter = now.as_secs() / self.period;
+
+        let mut mac = Hmac::<Sha1>::new_from_slice(&key).context("Invalid TOTP secret")?;
+        mac.update(&counter.to_be_bytes());
+        let digest = mac.finalize().into_bytes();
+
+        // dynamic truncation as described in RFC 4226
+        let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+        let binary = u32::from_be_bytes([
+            digest[offset] & 0x7f,
+            digest[offset + 1],
+            digest[offset + 2],
+            digest[offset + 3],
+        ]);
+        let code = binary % 10u32.pow(self.digits);
+        Ok(format!("{code:0width$}", width = self.digits as usize))
+    }
+}
+
+/// Decode RFC 4648 base32 while ignoring case, whitespace and padding as is common for TOTP secrets
+fn decode_base32(value: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut result = Vec::new();
+    let mut buffer: u64 = 0;
+    let mut bits = 0;
+    for &c in value {
+        let digit = match c.to_ascii_uppercase() {
+            c @ b'A'..=b'Z' => c - b'A',
+            c @ b'2'..=b'7' => c - b'2' + 26,
+            b'=' | b' ' | b'\t' | b'\r' | b'\n' => continue,
+            other => anyhow::bail!("Invalid base32 character {:?}", other as char),
+        };
+        buffer = (buffer << 5) | u64::from(digit);
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            result.push((buffer >> bits) as u8);
+        }
+    }
+    Ok(result)
+}
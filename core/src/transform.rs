@@ -0,0 +1,196 @@
+use anyhow::Context;
+use base64::prelude::*;
+use serde::Deserialize;
+
+use crate::wasm::WasmTransform;
+
+/// How a secret value is trimmed after it has been read from its file
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrimMode {
+    /// The value is used exactly as it is stored in the file
+    #[default]
+    None,
+    /// A single trailing newline is removed after normalizing the line endings
+    Newline,
+    /// All leading and trailing whitespace is removed after normalizing the line endings
+    Whitespace,
+}
+
+/// How the content of a secret file is encoded
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    /// The file contains the secret value as-is
+    #[default]
+    Plain,
+    /// The file contains the base64 encoded secret value
+    Base64,
+    /// The file contains the hex encoded secret value
+    Hex,
+}
+
+/// A single step of the transformation pipeline that is applied to the content of secret files
+///
+/// Transformations are configured as strings, e.g. `"trim"`, `"base64-decode"`, `"jsonpointer:/psk"` or
+/// `"wasm:/path/to/module.wasm"`.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Transform {
+    /// Remove all leading and trailing whitespace
+    Trim,
+    /// Remove a single trailing newline
+    TrimNewline,
+    /// Remove a leading UTF-8 byte order mark and turn CRLF line endings into LF
+    Normalize,
+    /// Decode base64 into the raw bytes
+    Base64Decode,
+    /// Encode raw bytes as base64
+    Base64Encode,
+    /// Decode a hex string into the raw bytes
+    HexDecode,
+    /// Encode raw bytes as a lowercase hex string
+    HexEncode,
+    /// Parse the value as JSON and extract the value at the given JSON pointer (RFC 6901)
+    JsonPointer(String),
+    /// Run a sandboxed WebAssembly module with the value as input
+    Wasm(WasmTransform),
+}
+
+impl TrimMode {
+    /// The transformations that implement this trim mode
+    ///
+    /// Trimming is meant for text files, so apart from `none` the value is normalized first. Otherwise files that were
+    /// edited on Windows or exported by some tools would end up with a carriage return or byte order mark inside the
+    /// secret.
+    pub fn transforms(self) -> Vec<Transform> {
+        match self {
+            TrimMode::None => Vec::new(),
+            TrimMode::Newline => vec![Transform::Normalize, Transform::TrimNewline],
+            TrimMode::Whitespace => vec![Transform::Normalize, Transform::Trim],
+        }
+    }
+}
+
+impl Encoding {
+    /// The transformation that decodes values of this encoding
+    pub fn transform(self) -> Option<Transform> {
+        match self {
+            Encoding::Plain => None,
+            Encoding::Base64 => Some(Transform::Base64Decode),
+            Encoding::Hex => Some(Transform::HexDecode),
+        }
+    }
+
+    /// The transformation that encodes raw values in this encoding
+    pub fn encode_transform(self) -> Option<Transform> {
+        match self {
+            Encoding::Plain => None,
+            Encoding::Base64 => Some(Transform::Base64Encode),
+            Encoding::Hex => Some(Transform::HexEncode),
+        }
+    }
+}
+
+impl Transform {
+    /// Apply this transformation to `value`
+    pub fn apply(&self, value: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        match self {
+            Transform::Trim => Ok(value.trim_ascii().to_vec()),
+            Transform::TrimNewline => match value.strip_suffix(b"\n") {
+                Some(trimmed) => Ok(trimmed.to_vec()),
+                None => Ok(value),
+            },
+            Transform::Normalize => Ok(normalize(value, || true)),
+            Transform::Base64Decode => BASE64_STANDARD
+                .decode(value.trim_ascii())
+                .context("Value is not valid base64"),
+            Transform::Base64Encode => Ok(BASE64_STANDARD.encode(value).into_bytes()),
+            Transform::HexDecode => {
+                hex::decode(value.trim_ascii()).context("Value is not valid hex")
+            }
+            Transform::HexEncode => Ok(hex::encode(value).into_bytes()),
+            Transform::JsonPointer(pointer) => {
+                let json: serde_json::Value =
+                    serde_json::from_slice(&value).context("Value is not valid JSON")?;
+                match json.pointer(pointer) {
+                    None => anyhow::bail!("JSON value does not contain {pointer}"),
+                    Some(serde_json::Value::String(s)) => Ok(s.clone().into_bytes()),
+                    Some(
+                        extracted @ (serde_json::Value::Number(_) | serde_json::Value::Bool(_)),
+                    ) => Ok(extracted.to_string().into_bytes()),
+                    Some(_) => {
+                        anyhow::bail!("JSON value at {pointer} is not a string, number or boolean")
+                    }
+                }
+            }
+            Transform::Wasm(module) => module.apply(value),
+        }
+    }
+}
+
+impl TryFrom<String> for Transform {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        if let Some(pointer) = value.strip_prefix("jsonpointer:") {
+            if !pointer.is_empty() && !pointer.starts_with('/') {
+                anyhow::bail!("JSON pointer {pointer:?} must be empty or start with /");
+            }
+            return Ok(Transform::JsonPointer(pointer.to_owned()));
+        }
+        if let Some(path) = value.strip_prefix("wasm:") {
+            if path.is_empty() {
+                anyhow::bail!("WASM transformations need the path of a module");
+            }
+            return Ok(Transform::Wasm(WasmTransform::new(path.into())));
+        }
+        match value.as_str() {
+            "trim" => Ok(Transform::Trim),
+            "trim-newline" => Ok(Transform::TrimNewline),
+            "normalize" => Ok(Transform::Normalize),
+            "base64-decode" => Ok(Transform::Base64Decode),
+            "base64-encode" => Ok(Transform::Base64Encode),
+            "hex-decode" => Ok(Transform::HexDecode),
+            "hex-encode" => Ok(Transform::HexEncode),
+            other => Err(anyhow::anyhow!("Unknown transformation {other:?}")),
+        }
+    }
+}
+
+/// Remove a leading UTF-8 byte order mark from `value` and replace all CRLF line endings with LF
+///
+/// A warning is logged whenever something was changed since it usually means that the file was not written as
+/// intended. Values that are read again and again only need to be warned about once, so `first` is asked whether
+/// this is the first change of the value's origin and repeats are only logged at debug level.
+pub(crate) fn normalize(value: Vec<u8>, first: impl FnOnce() -> bool) -> Vec<u8> {
+    let (value, bom) = match value.strip_prefix(b"\xef\xbb\xbf") {
+        Some(stripped) => (stripped, true),
+        None => (&value[..], false),
+    };
+    let line_endings = value.windows(2).filter(|pair| pair == b"\r\n").count();
+    if !bom && line_endings == 0 {
+        return value.to_vec();
+    }
+    let first = first();
+    let log = |message: &str| match first {
+        true => tracing::warn!("{message}"),
+        false => tracing::debug!("{message}"),
+    };
+    if bom {
+        log("Removed a UTF-8 byte order mark from the start of a secret value");
+    }
+    if line_endings == 0 {
+        return value.to_vec();
+    }
+    log(&format!(
+        "Replaced {line_endings} CRLF line endings of a secret value with LF"
+    ));
+    let mut normalized = Vec::with_capacity(value.len() - line_endings);
+    for (i, &c) in value.iter().enumerate() {
+        if c != b'\r' || value.get(i + 1) != Some(&b'\n') {
+            normalized.push(c);
+        }
+    }
+    normalized
+}
@@ -0,0 +1,153 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+/// Amount of fuel, roughly the number of executed instructions, that a module may consume for a single value
+#[cfg(feature = "wasm")]
+const MAX_FUEL: u64 = 10_000_000;
+
+/// Maximum size in bytes to which a module may grow its linear memory
+#[cfg(feature = "wasm")]
+const MAX_MEMORY: usize = 16 * 1024 * 1024;
+
+/// A transformation implemented by a sandboxed WebAssembly module
+///
+/// Modules cannot import anything, so they have no access to the host besides the value passed to them. Every value is
+/// transformed by a fresh instance, which is limited in the amount of memory and instructions it can use.
+///
+/// A module must export its `memory` and the functions
+/// - `alloc(len: i32) -> i32` returning a pointer to `len` bytes into which the input is written,
+/// - `transform(ptr: i32, len: i32) -> i32` returning 0 on success,
+/// - `output_ptr() -> i32` and `output_len() -> i32` locating the output, or an error message if `transform` failed.
+#[derive(Clone)]
+pub struct WasmTransform {
+    path: PathBuf,
+    #[cfg(feature = "wasm")]
+    module: Option<(wasmi::Engine, wasmi::Module)>,
+}
+
+impl fmt::Debug for WasmTransform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("WasmTransform").field(&self.path).finish()
+    }
+}
+
+impl PartialEq for WasmTransform {
+    fn eq(&self, other: &Self) -> bool {
+        self.path == other.path
+    }
+}
+
+impl Eq for WasmTransform {}
+
+impl WasmTransform {
+    /// Create a transformation using the module at `path` which must be loaded with [`Self::load`] before it is applied
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            #[cfg(feature = "wasm")]
+            module: None,
+        }
+    }
+
+    /// Path of the module
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(not(feature = "wasm"))]
+impl WasmTransform {
+    pub fn load(&mut self, _path: PathBuf) -> anyhow::Result<()> {
+        anyhow::bail!(
+            "WASM transformations are not supported because the agent was built without the wasm feature"
+        )
+    }
+
+    pub fn apply(&self, _value: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        unreachable!("WASM transformations cannot be loaded without the wasm feature")
+    }
+}
+
+#[cfg(feature = "wasm")]
+impl WasmTransform {
+    /// Compile the module at `path`, which replaces the path given when this transformation was created
+    ///
+    /// The module may be given in either the binary or the text format.
+    pub fn load(&mut self, path: PathBuf) -> anyhow::Result<()> {
+        use std::io::Read;
+
+        use anyhow::Context;
+
+        let mut wasm = Vec::new();
+        crate::reader::open(&path, 0)
+            .context("Could not open WASM module")?
+            .read_to_end(&mut wasm)
+            .context("Could not read WASM module")?;
+        let mut config = wasmi::Config::default();
+        config.consume_fuel(true);
+        let engine = wasmi::Engine::new(&config);
+        let module = wasmi::Module::new(&engine, &wasm).context("Could not compile WASM module")?;
+        if let Some(import) = module.imports().next() {
+            anyhow::bail!(
+                "WASM module imports {}::{} but modules must not import anything",
+                import.module(),
+                import.name()
+            );
+        }
+        self.path = path;
+        self.module = Some((engine, module));
+        Ok(())
+    }
+
+    /// Run the module with `value` as input and return its output
+    pub fn apply(&self, value: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        use anyhow::Context;
+
+        let Some((engine, module)) = &self.module else {
+            unreachable!("WASM modules are loaded together with the config");
+        };
+        let mut store = wasmi::Store::new(
+            engine,
+            wasmi::StoreLimitsBuilder::new()
+                .memory_size(MAX_MEMORY)
+                .build(),
+        );
+        store.limiter(|limits| limits);
+        store.set_fuel(MAX_FUEL)?;
+        let instance = wasmi::Linker::new(engine)
+            .instantiate_and_start(&mut store, module)
+            .context("Could not instantiate WASM module")?;
+        let memory = instance
+            .get_memory(&store, "memory")
+            .context("WASM module does not export its memory")?;
+        let alloc = instance.get_typed_func::<i32, i32>(&store, "alloc")?;
+        let transform = instance.get_typed_func::<(i32, i32), i32>(&store, "transform")?;
+        let output_ptr = instance.get_typed_func::<(), i32>(&store, "output_ptr")?;
+        let output_len = instance.get_typed_func::<(), i32>(&store, "output_len")?;
+
+        let len = i32::try_from(value.len()).context("Value is too large for a WASM module")?;
+        let ptr = alloc.call(&mut store, len)?;
+        memory
+            .write(&mut store, ptr as u32 as usize, &value)
+            .context("WASM module allocated an invalid input buffer")?;
+        let status = transform.call(&mut store, (ptr, len))?;
+        let ptr = output_ptr.call(&mut store, ())?;
+        let len = output_len.call(&mut store, ())?;
+        if len as u32 as usize > memory.data_size(&store) {
+            anyhow::bail!("WASM module returned an output larger than its memory");
+        }
+        let mut output = vec![0; len as u32 as usize];
+        memory
+            .read(&store, ptr as u32 as usize, &mut output)
+            .context("WASM module returned an invalid output buffer")?;
+        if status != 0 {
+            anyhow::bail!(
+                "WASM module failed with status {status}: {}",
+                String::from_utf8_lossy(&output)
+            );
+        }
+        Ok(output)
+    }
+}
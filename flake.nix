@@ -16,9 +16,18 @@
       version = cargoToml.package.version;
       src = ./.;
       cargoLock.lockFile = ./Cargo.lock;
-      LD_LIBRARY_PATH = with pkgs; lib.makeLibraryPath [ dbus ];
-      PKG_CONFIG_PATH = with pkgs; lib.makeSearchPathOutput "dev" "lib/pkgconfig" [ dbus ];
-      nativeBuildInputs = with pkgs; [ pkg-config ];
+      nativeBuildInputs = [ pkgs.installShellFiles ];
+      # the integration tests run the agent against a fake NetworkManager on a private bus
+      nativeCheckInputs = [ pkgs.dbus ];
+      postInstall = ''
+        installShellCompletion --cmd ${cargoToml.package.name} \
+          --bash <($out/bin/${cargoToml.package.name} completions bash) \
+          --fish <($out/bin/${cargoToml.package.name} completions fish) \
+          --zsh <($out/bin/${cargoToml.package.name} completions zsh)
+        mkdir man
+        $out/bin/${cargoToml.package.name} man --out-dir man
+        installManPage man/*
+      '';
       meta = {
         description = cargoToml.package.description;
         mainProgram = cargoToml.package.name;
@@ -30,9 +39,7 @@
     nixosModules.default = import nix/module.nix packages;
 
     devShells.x86_64-linux.default = pkgs.mkShell {
-      LD_LIBRARY_PATH = with pkgs; lib.makeLibraryPath [ dbus ];
-      PKG_CONFIG_PATH = with pkgs; lib.makeSearchPathOutput "dev" "lib/pkgconfig" [ dbus ];
-      packages = with pkgs; [ cargo rustfmt pkg-config pre-commit ];
+      packages = with pkgs; [ cargo rustfmt pre-commit dbus ];
     };
   };
 }
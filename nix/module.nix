@@ -4,17 +4,53 @@ let
   cfg = config.networking.networkmanager.ensureProfiles.secrets;
   toml = pkgs.formats.toml {};
 
-  configFileData = {
+  configFileData = lib.optionalAttrs (cfg.defaults != {}) { defaults = cfg.defaults; }
+  // lib.optionalAttrs (cfg.fallbackCommand != null) { fallback = { command = cfg.fallbackCommand; }; }
+  // lib.optionalAttrs (cfg.prompt.enable) { prompt = { command = cfg.prompt.command; cache_ttl = cfg.prompt.cacheTtl; }; }
+  // lib.optionalAttrs (cfg.hook != null) { hook = pkgs.writeText "hook.rhai" cfg.hook; }
+  // {
     entry = builtins.map
-      (i: {
-        key = i.key;
-        file = i.file;
-        }
+      (i: lib.optionalAttrs (i.key != null) { key = i.key; }
+        // lib.optionalAttrs (i.keys != null) { keys = i.keys; }
+        // lib.optionalAttrs (i.file != null) { file = i.file; }
+        // lib.optionalAttrs (i.template != null) { template = i.template; }
+        // lib.optionalAttrs (i.totp != null) { totp = { secret_file = i.totp.secretFile; digits = i.totp.digits; period = i.totp.period; }; }
+        // lib.optionalAttrs (i.plugin != null) { plugin = i.plugin; }
+        // lib.optionalAttrs (i.peersDir != null) { peers_dir = i.peersDir; }
+        // lib.optionalAttrs (i.pluginConfig != null) { plugin_config = i.pluginConfig; }
+        // lib.optionalAttrs (i.pluginTimeout != null) { plugin_timeout = i.pluginTimeout; }
+        // lib.optionalAttrs (i.id != null) { id = i.id; }
         // lib.optionalAttrs (i.matchId != null) { match_id = i.matchId; }
         // lib.optionalAttrs (i.matchUuid != null) { match_uuid = i.matchUuid; }
         // lib.optionalAttrs (i.matchType != null) { match_type = i.matchType; }
         // lib.optionalAttrs (i.matchIface != null) { match_iface = i.matchIface; }
         // lib.optionalAttrs (i.matchSetting != null) { match_setting = i.matchSetting; }
+        // lib.optionalAttrs (i.matchVpnMessage != null) { match_vpn_message = i.matchVpnMessage; }
+        // lib.optionalAttrs (i.trim != null) { trim = i.trim; }
+        // lib.optionalAttrs (i.encoding != null) { encoding = i.encoding; }
+        // lib.optionalAttrs (i.transform != null) { transform = i.transform; }
+        // lib.optionalAttrs (i.derive != null) { derive = i.derive; }
+        // lib.optionalAttrs (i.validateValue != null) { validate_value = i.validateValue; }
+        // lib.optionalAttrs (i.checkPlausibility != null) { check_plausibility = i.checkPlausibility; }
+        // lib.optionalAttrs (i.maxSize != null) { max_size = i.maxSize; }
+        // lib.optionalAttrs (i.onRequestNew != null) { on_request_new = i.onRequestNew; }
+        // lib.optionalAttrs (i.renewCommand != null) { renew_command = i.renewCommand; }
+        // lib.optionalAttrs (i.strictHints != null) { strict_hints = i.strictHints; }
+        // lib.optionalAttrs (i.honorSecretFlags != null) { honor_secret_flags = i.honorSecretFlags; }
+        // lib.optionalAttrs (i.writable != null) { writable = i.writable; }
+        // lib.optionalAttrs (i.cache != null) { cache = i.cache; }
+        // lib.optionalAttrs (i.cacheTtl != null) { cache_ttl = i.cacheTtl; }
+        // lib.optionalAttrs (i.preload != null) { preload = i.preload; }
+        // lib.optionalAttrs (i.pin != null) { pin = i.pin; }
+        // lib.optionalAttrs (i.unsafePermissions != null) { unsafe_permissions = i.unsafePermissions; }
+        // lib.optionalAttrs (i.followSymlinks != null) { follow_symlinks = i.followSymlinks; }
+        // lib.optionalAttrs (i.allowedDirs != null) { allowed_dirs = i.allowedDirs; }
+        // lib.optionalAttrs (i.signatureKey != null) { signature_key = i.signatureKey; }
+        // lib.optionalAttrs (i.encryption != null) { encryption = i.encryption; }
+        // lib.optionalAttrs (i.polkitAction != null) { polkit_action = i.polkitAction; }
+        // lib.optionalAttrs (i.notAfter != null) { not_after = i.notAfter; }
+        // lib.optionalAttrs (i.tags != null) { tags = i.tags; }
+        // lib.optionalAttrs (i.dbusType != null) { dbus_type = i.dbusType; }
       )
       cfg.entries;
   };
@@ -28,11 +64,109 @@ in {
     package = lib.mkPackageOption flakePkgs."${pkgs.stdenv.system}" "nm-file-secret-agent" {
       pkgsText = "self.packages";
     };
+    vpnHints = lib.mkOption {
+      description = "Whether to register with NetworkManager's VpnHints capability so that VPN plugins pass their hints to the agent";
+      type = lib.types.bool;
+      default = false;
+    };
+    lockMemory = lib.mkOption {
+      description = "Whether all memory of the agent is locked into RAM so that secrets are never written to swap";
+      type = lib.types.bool;
+      default = false;
+    };
+    user = lib.mkOption {
+      description = "user to which the agent switches after connecting to the bus and preloading secrets";
+      type = lib.types.nullOr lib.types.str;
+      default = null;
+    };
+    keepCapabilities = lib.mkOption {
+      description = "capabilities which the agent retains after switching to the configured user";
+      type = lib.types.listOf (lib.types.enum [ "dac-read-search" "dac-override" ]);
+      default = [];
+    };
+    privsep = lib.mkOption {
+      description = "Whether files are opened by a separate helper process so that the agent itself needs no capabilities (requires user to be set)";
+      type = lib.types.bool;
+      default = false;
+    };
+    onlyTags = lib.mkOption {
+      description = "tags of which entries must have at least one to be served; all entries are served if empty";
+      type = lib.types.listOf lib.types.str;
+      default = [];
+    };
+    excludeTags = lib.mkOption {
+      description = "tags of entries which are not served";
+      type = lib.types.listOf lib.types.str;
+      default = [];
+    };
+    rateLimit = lib.mkOption {
+      description = "Maximum number of requests for secrets per connection within a minute";
+      type = lib.types.nullOr lib.types.ints.positive;
+      default = null;
+    };
+    noMatch = lib.mkOption {
+      description = "How requests which no entry provides secrets for are answered: with an empty result or the NoSecrets error";
+      type = lib.types.enum [ "empty" "no-secrets" ];
+      default = "empty";
+    };
+    dryRun = lib.mkOption {
+      description = "Whether requests are only resolved and logged while NetworkManager is told that no secrets are available";
+      type = lib.types.bool;
+      default = false;
+    };
+    recordRequests = lib.mkOption {
+      description = "File to which every request for secrets is appended without secret values so that it can be replayed with the replay subcommand";
+      type = lib.types.nullOr lib.types.str;
+      default = null;
+    };
+    fallbackCommand = lib.mkOption {
+      description = "command and arguments which print the secrets of requests that no entry provides secrets for as a JSON object";
+      type = lib.types.nullOr (lib.types.listOf lib.types.str);
+      default = null;
+    };
+    prompt = {
+      enable = lib.mkEnableOption "asking for secrets that neither an entry nor the fallback provides when NetworkManager allows interaction";
+      command = lib.mkOption {
+        description = "command and arguments which ask for a secret described by the message passed as last argument and print the answer";
+        type = lib.types.listOf lib.types.str;
+        default = [ "${pkgs.systemd}/bin/systemd-ask-password" ];
+        defaultText = lib.literalExpression ''[ "''${pkgs.systemd}/bin/systemd-ask-password" ]'';
+      };
+      cacheTtl = lib.mkOption {
+        description = "Number of seconds for which answers are reused or 0 to always ask again";
+        type = lib.types.ints.unsigned;
+        default = 3600;
+      };
+    };
+    notifications = {
+      enable = lib.mkEnableOption "desktop notifications in graphical user sessions whenever the agent fails to serve a request";
+      repeatAfter = lib.mkOption {
+        description = "Number of seconds during which repeated failures of the same connection and error are not notified again";
+        type = lib.types.ints.unsigned;
+        default = 60;
+      };
+    };
+    hook = lib.mkOption {
+      description = "Rhai script defining matches(request, entry) and/or transform(request, entry, value) to adjust matches and served values";
+      type = lib.types.nullOr lib.types.lines;
+      default = null;
+    };
+    defaults = lib.mkOption {
+      description = "Values applied to all entries unless overridden by the entry itself (using the config files snake_case key names)";
+      default = {};
+      type = toml.type;
+      example = { match_setting = "wireguard"; };
+    };
     entries = lib.mkOption {
       description = "The secrets to provide";
       default = [];
       type = lib.types.listOf (lib.types.submodule {
         options = {
+          id = lib.mkOption {
+            description = "identifier by which entries of later config files can override this one";
+            type = lib.types.nullOr lib.types.str;
+            default = null;
+          };
           matchId = lib.mkOption {
             description = "network manager connection id (displayed as name in GUIs)";
             type = lib.types.nullOr lib.types.str;
@@ -58,13 +192,198 @@ in {
             type = lib.types.nullOr lib.types.str;
             default = null;
           };
+          matchVpnMessage = lib.mkOption {
+            description = "text which must be contained in the x-vpn-message hint of a VPN plugin";
+            type = lib.types.nullOr lib.types.str;
+            default = null;
+          };
+          totp = lib.mkOption {
+            description = "compute a time-based one-time password instead of reading the value from a file";
+            default = null;
+            type = lib.types.nullOr (lib.types.submodule {
+              options = {
+                secretFile = lib.mkOption {
+                  description = "file containing the base32 encoded TOTP secret";
+                  type = lib.types.str;
+                };
+                digits = lib.mkOption {
+                  description = "number of digits of the generated code";
+                  type = lib.types.ints.positive;
+                  default = 6;
+                };
+                period = lib.mkOption {
+                  description = "number of seconds for which a code is valid";
+                  type = lib.types.ints.positive;
+                  default = 30;
+                };
+              };
+            });
+          };
+          trim = lib.mkOption {
+            description = "how the secret value is trimmed after it has been read";
+            type = lib.types.nullOr (lib.types.enum [ "none" "newline" "whitespace" ]);
+            default = null;
+          };
+          encoding = lib.mkOption {
+            description = "how the content of the secret file is encoded";
+            type = lib.types.nullOr (lib.types.enum [ "plain" "base64" "hex" ]);
+            default = null;
+          };
+          transform = lib.mkOption {
+            description = "transformations that are applied in order to the content of backing files";
+            type = lib.types.nullOr (lib.types.listOf lib.types.str);
+            default = null;
+            example = [ "trim" "base64-decode" "jsonpointer:/psk" ];
+          };
+          derive = lib.mkOption {
+            description = "derivation that computes the returned secret value from the configured one";
+            type = lib.types.nullOr (lib.types.enum [ "wpa-psk" ]);
+            default = null;
+          };
+          validateValue = lib.mkOption {
+            description = "whether well-known key material is verified before it is returned";
+            type = lib.types.nullOr lib.types.bool;
+            default = null;
+          };
+          checkPlausibility = lib.mkOption {
+            description = "whether warnings are logged for values that NetworkManager will probably reject";
+            type = lib.types.nullOr lib.types.bool;
+            default = null;
+          };
+          maxSize = lib.mkOption {
+            description = "maximum size of backing files in bytes";
+            type = lib.types.nullOr lib.types.ints.unsigned;
+            default = null;
+          };
+          onRequestNew = lib.mkOption {
+            description = "how requests in which NetworkManager asks for new secrets are handled";
+            type = lib.types.nullOr (lib.types.enum [ "no-secrets" "reread" "command" ]);
+            default = null;
+          };
+          renewCommand = lib.mkOption {
+            description = "command and arguments that renew the secret before it is read again if onRequestNew is command";
+            type = lib.types.nullOr (lib.types.listOf lib.types.str);
+            default = null;
+          };
+          strictHints = lib.mkOption {
+            description = "whether the value is only provided if NetworkManager hinted at the key";
+            type = lib.types.nullOr lib.types.bool;
+            default = null;
+          };
+          honorSecretFlags = lib.mkOption {
+            description = "whether the value is skipped if the connection profile does not mark the key as agent-owned";
+            type = lib.types.nullOr lib.types.bool;
+            default = null;
+          };
+          writable = lib.mkOption {
+            description = "whether values which NetworkManager asks to save are written back into the file";
+            type = lib.types.nullOr lib.types.bool;
+            default = null;
+          };
+          cache = lib.mkOption {
+            description = "whether the content of backing files is cached in memory for as long as the files do not change";
+            type = lib.types.nullOr lib.types.bool;
+            default = null;
+          };
+          cacheTtl = lib.mkOption {
+            description = "number of seconds for which a resolved value is reused without reading its source again";
+            type = lib.types.nullOr lib.types.ints.unsigned;
+            default = null;
+          };
+          preload = lib.mkOption {
+            description = "whether the backing files are read once at startup and never accessed again";
+            type = lib.types.nullOr lib.types.bool;
+            default = null;
+          };
+          pin = lib.mkOption {
+            description = "whether preloaded content is locked into RAM so that it cannot be swapped out";
+            type = lib.types.nullOr lib.types.bool;
+            default = null;
+          };
+          unsafePermissions = lib.mkOption {
+            description = "how backing files which are owned by another user or readable or writable by everyone are treated";
+            type = lib.types.nullOr (lib.types.enum [ "ignore" "warn" "refuse" ]);
+            default = null;
+          };
+          followSymlinks = lib.mkOption {
+            description = "whether backing files are opened even if the last component of their path is a symlink";
+            type = lib.types.nullOr lib.types.bool;
+            default = null;
+          };
+          allowedDirs = lib.mkOption {
+            description = "directories inside which the canonical paths of all backing files must be located";
+            type = lib.types.nullOr (lib.types.listOf lib.types.str);
+            default = null;
+          };
+          signatureKey = lib.mkOption {
+            description = "minisign or signify public key with which a detached signature of every backing file must be verifiable";
+            type = lib.types.nullOr lib.types.str;
+            default = null;
+          };
+          encryption = lib.mkOption {
+            description = "how the backing files are encrypted at rest, e.g. { type = \"age\"; identity = \"/etc/age-key.txt\"; recipients = [ \"age1…\" ]; } or { type = \"systemd-creds\"; }";
+            type = lib.types.nullOr toml.type;
+            default = null;
+          };
+          polkitAction = lib.mkOption {
+            description = "polkit action for which the user of the active session must be authorized before secrets are served for user-requested activations";
+            type = lib.types.nullOr lib.types.str;
+            default = null;
+          };
+          notAfter = lib.mkOption {
+            description = "RFC 3339 timestamp like 2025-12-31T00:00:00Z after which the secret is refused because it should have been rotated";
+            type = lib.types.nullOr lib.types.str;
+            default = null;
+          };
+          tags = lib.mkOption {
+            description = "labels by which the entry is selected via onlyTags and excludeTags";
+            type = lib.types.nullOr (lib.types.listOf lib.types.str);
+            default = null;
+          };
+          dbusType = lib.mkOption {
+            description = "D-Bus type as which the secret value is returned";
+            type = lib.types.nullOr (lib.types.enum [ "string" "u32" "bool" "as" "ay" ]);
+            default = null;
+          };
           key = lib.mkOption {
             description = "key in the setting section for which entry provides a value";
-            type = lib.types.str;
+            type = lib.types.nullOr lib.types.str;
+            default = null;
+          };
+          keys = lib.mkOption {
+            description = "keys which are all read from the KEY=VALUE lines of file; alternative to key";
+            type = lib.types.nullOr (lib.types.listOf lib.types.str);
+            default = null;
           };
           file = lib.mkOption {
             description = "file from which the secret value is read";
-            type = lib.types.str;
+            type = lib.types.nullOr lib.types.str;
+            default = null;
+          };
+          template = lib.mkOption {
+            description = "template combining literal text with {{ file('<path>') }} placeholders from which the secret value is rendered";
+            type = lib.types.nullOr lib.types.str;
+            default = null;
+          };
+          plugin = lib.mkOption {
+            description = "executable of an out-of-process plugin which provides the secret value";
+            type = lib.types.nullOr lib.types.str;
+            default = null;
+          };
+          peersDir = lib.mkOption {
+            description = "directory with one file per wireguard peer which is named after its public key and provides the value of a peers.*.<property> key";
+            type = lib.types.nullOr lib.types.str;
+            default = null;
+          };
+          pluginConfig = lib.mkOption {
+            description = "entry specific configuration which is passed to the plugin during the handshake";
+            type = lib.types.nullOr toml.type;
+            default = null;
+          };
+          pluginTimeout = lib.mkOption {
+            description = "number of seconds within which the plugin must answer the handshake and each request";
+            type = lib.types.nullOr lib.types.ints.positive;
+            default = null;
           };
         };
       });
@@ -79,7 +398,26 @@ in {
       after = [ "NetworkManager.service" ];
       wantedBy = ["multi-user.target"];
       restartTriggers = [ configFile ];
-      script = "${lib.getExe cfg.package} --conf ${configFile}";
+      path = [ pkgs.age config.systemd.package ];
+      script = "exec ${lib.getExe cfg.package} --conf ${configFile}${lib.optionalString cfg.vpnHints " --vpn-hints"}${lib.optionalString cfg.dryRun " --dry-run"}${lib.optionalString cfg.lockMemory " --lock-memory"}${lib.optionalString (cfg.user != null) " --user ${cfg.user}"}${lib.concatMapStrings (c: " --keep-capability ${c}") cfg.keepCapabilities}${lib.optionalString cfg.privsep " --privsep"}${lib.optionalString (cfg.onlyTags != []) " --only-tags ${lib.escapeShellArg (lib.concatStringsSep "," cfg.onlyTags)}"}${lib.optionalString (cfg.excludeTags != []) " --exclude-tags ${lib.escapeShellArg (lib.concatStringsSep "," cfg.excludeTags)}"} --no-match ${cfg.noMatch}${lib.optionalString (cfg.rateLimit != null) " --rate-limit ${toString cfg.rateLimit}"}${lib.optionalString (cfg.recordRequests != null) " --record-requests ${lib.escapeShellArg cfg.recordRequests}"}";
+      serviceConfig = {
+        Type = "notify";
+        ExecReload = "${pkgs.coreutils}/bin/kill -HUP $MAINPID";
+        WatchdogSec = "60s";
+      };
+    };
+
+    systemd.user.services."nm-file-secret-agent-notify" = lib.mkIf cfg.notifications.enable {
+      description = "Desktop notifications about secrets that nm-file-secret-agent could not serve";
+      documentation = [ "https://git.lly.sh/ftsell/nm-file-secret-agent" ];
+      partOf = [ "graphical-session.target" ];
+      after = [ "graphical-session.target" ];
+      wantedBy = [ "graphical-session.target" ];
+      script = "exec ${lib.getExe cfg.package} notify --repeat-after ${toString cfg.notifications.repeatAfter}";
+      serviceConfig = {
+        Restart = "on-failure";
+        RestartSec = "10s";
+      };
     };
   };
 }
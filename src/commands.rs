@@ -0,0 +1,968 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::Write,
+    os::unix::fs::{DirBuilderExt, OpenOptionsExt},
+    path::{Path, PathBuf},
+    sync::atomic::AtomicBool,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Context;
+use clap::{Args, Subcommand};
+use clap_complete::Shell;
+use nm_secret_agent_core::{
+    encoder,
+    error::{Error, ErrorCode},
+    mapping::{MappingConfig, MappingEntry, SecretRequest, SecretValue},
+};
+use tokio::time;
+use zbus::{
+    fdo::DBusProxy,
+    proxy::CacheProperties,
+    zvariant::{OwnedObjectPath, OwnedValue},
+    Connection,
+};
+
+use crate::{
+    dbus_server::{self, FileSecretAgentProxy},
+    keyfile::Keyfile,
+    nm_settings,
+};
+
+/// How long to wait for a peer on the bus to reply when probing whether it is an agent
+const AGENT_PROBE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Arguments of the `resolve` subcommand
+#[derive(Args, Debug, Eq, PartialEq, Hash)]
+pub struct ResolveArgs {
+    /// Id of the simulated connection (displayed as name in GUIs)
+    #[arg(long, default_value = "")]
+    id: String,
+
+    /// Uuid of the simulated connection
+    #[arg(long, default_value = "")]
+    uuid: String,
+
+    /// Type of the simulated connection, e.g. `802-11-wireless` or its alias `wifi`
+    #[arg(long = "type", default_value = "")]
+    conn_type: String,
+
+    /// Interface name of the simulated connection
+    #[arg(long, default_value = "")]
+    iface: String,
+
+    /// Name of the setting section for which secrets are requested
+    #[arg(long)]
+    setting: String,
+
+    /// Key which NetworkManager hints at being required
+    ///
+    /// Can be given multiple times. VPN hints like `x-vpn-message:<text>` are passed on as-is.
+    #[arg(long = "hint", value_name = "KEY")]
+    hints: Vec<String>,
+
+    /// SSID of the simulated wifi connection which is used to derive WPA pre-shared keys
+    #[arg(long)]
+    ssid: Option<String>,
+
+    /// Simulate NetworkManager asking for new secrets because it considers the existing ones invalid
+    #[arg(long)]
+    request_new: bool,
+
+    /// Simulate NetworkManager allowing interaction so that the configured prompt is used
+    #[arg(long)]
+    allow_interaction: bool,
+
+    /// Print the resolved secret values instead of redacting them
+    #[arg(long)]
+    show_secrets: bool,
+}
+
+/// Short connection type names which nmcli also accepts in place of the full type name
+const TYPE_ALIASES: &[(&str, &str)] =
+    &[("wifi", "802-11-wireless"), ("ethernet", "802-3-ethernet")];
+
+impl ResolveArgs {
+    /// The request which these arguments describe
+    fn request(&self) -> SecretRequest {
+        let conn_type = TYPE_ALIASES
+            .iter()
+            .find(|(alias, _)| *alias == self.conn_type)
+            .map_or(self.conn_type.as_str(), |(_, conn_type)| conn_type);
+        SecretRequest {
+            conn_id: self.id.clone(),
+            conn_uuid: self.uuid.clone(),
+            conn_type: conn_type.to_owned(),
+            iface_name: self.iface.clone(),
+            setting_name: self.setting.clone(),
+            ssid: self.ssid.as_ref().map(|ssid| ssid.as_bytes().to_vec()),
+            request_new: self.request_new,
+            allow_interaction: self.allow_interaction || self.request_new,
+            hints: self.hints.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Simulate a request for secrets and print which entries match and which values would be returned
+///
+/// Like for real requests, renew and fallback commands are run if the request requires them.
+pub fn resolve(mapping: &MappingConfig, args: &ResolveArgs) -> anyhow::Result<()> {
+    report_request(mapping, &args.request(), args.show_secrets)
+}
+
+/// Print which entries match `request` and which values would be returned to it
+fn report_request(
+    mapping: &MappingConfig,
+    request: &SecretRequest,
+    show_secrets: bool,
+) -> anyhow::Result<()> {
+    let matching = mapping.matching_entries(request).collect::<Vec<_>>();
+    match matching.is_empty() {
+        true => println!("No entry matches the request"),
+        false => println!("Matching entries:"),
+    }
+    for (index, entry) in matching {
+        let id = entry
+            .id()
+            .map(|id| format!(" (id {id})"))
+            .unwrap_or_default();
+        println!("  #{index}{id} provides {}", entry.key());
+        for file in entry.files_for(request) {
+            println!("    {}: {}", file.display(), file_state(entry, &file));
+        }
+    }
+
+    let cancelled = AtomicBool::new(false);
+    let mut secrets = mapping
+        .get_secrets(request, false, &cancelled)
+        .context("Could not fetch secrets")?;
+    let mut source = "entries";
+    if secrets.is_empty() {
+        if let Some(fallback) = mapping
+            .get_fallback_secrets(request, &cancelled)
+            .context("Could not fetch secrets from the fallback source")?
+        {
+            secrets = fallback;
+            source = "fallback source";
+        }
+    }
+    if secrets.is_empty() {
+        if let Some(prompted) = mapping
+            .get_prompted_secrets(request, false, &cancelled)
+            .context("Could not prompt for secrets")?
+        {
+            secrets = prompted;
+            source = "prompt";
+        }
+    }
+
+    if secrets.is_empty() {
+        println!("No secrets would be returned");
+        return Ok(());
+    }
+    encoder::encode_secrets(request, &secrets).context("Could not encode secrets")?;
+    println!("Returned secrets (from {source}):");
+    for (key, value) in secrets {
+        let value = match show_secrets {
+            true => format_value(&value),
+            false => "<redacted>".to_owned(),
+        };
+        println!("  {}.{key} = {value}", request.setting_name);
+    }
+    Ok(())
+}
+
+/// Arguments of the `replay` subcommand
+#[derive(Args, Debug, Eq, PartialEq, Hash)]
+pub struct ReplayArgs {
+    /// File with one recorded request per line as written by `--record-requests`
+    file: PathBuf,
+
+    /// Print the resolved secret values instead of redacting them
+    #[arg(long)]
+    show_secrets: bool,
+}
+
+/// Resolve every recorded request and print which entries match and which values would be returned
+pub fn replay(mapping: &MappingConfig, args: &ReplayArgs) -> anyhow::Result<()> {
+    let content = fs::read_to_string(&args.file).with_context(|| {
+        format!(
+            "Could not read recorded requests from {}",
+            args.file.display()
+        )
+    })?;
+    let lines = content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty());
+    for (i, (number, line)) in lines.enumerate() {
+        let request: SecretRequest = serde_json::from_str(line).with_context(|| {
+            format!(
+                "Line {} of {} is not a recorded request",
+                number + 1,
+                args.file.display()
+            )
+        })?;
+        if i > 0 {
+            println!();
+        }
+        println!(
+            "Request for {} of connection {:?} ({}):",
+            request.setting_name, request.conn_id, request.conn_uuid
+        );
+        if let Err(e) = report_request(mapping, &request, args.show_secrets) {
+            println!("Resolving the request failed: {e:#}");
+        }
+    }
+    Ok(())
+}
+
+/// Arguments of the `verify-connection` subcommand
+#[derive(Args, Debug, Eq, PartialEq, Hash)]
+pub struct VerifyConnectionArgs {
+    /// Id or uuid of the connection profile
+    connection: String,
+
+    /// Only verify requests for this setting instead of all settings which require secrets or are matched by entries
+    #[arg(long)]
+    setting: Option<String>,
+
+    /// Print the resolved secret values instead of redacting them
+    #[arg(long)]
+    show_secrets: bool,
+}
+
+/// Fetch a connection profile from NetworkManager and report which secrets would be served for it
+///
+/// The requests are built from the profile exactly like for real requests from NetworkManager so that e.g. the SSID,
+/// interface name, wireguard peers and secret flags are taken into account.
+pub async fn verify_connection(
+    conn: &Connection,
+    mapping: &MappingConfig,
+    args: &VerifyConnectionArgs,
+) -> anyhow::Result<()> {
+    let profile = nm_settings::list_profiles(conn)
+        .await?
+        .into_iter()
+        .map(|(_, settings)| settings)
+        .find(|settings| {
+            ["id", "uuid"].into_iter().any(|key| {
+                settings
+                    .get("connection")
+                    .and_then(|connection| connection.get(key))
+                    .and_then(|value| value.downcast_ref::<&str>().ok())
+                    == Some(args.connection.as_str())
+            })
+        })
+        .with_context(|| format!("NetworkManager has no connection {:?}", args.connection))?;
+
+    let setting_names = match &args.setting {
+        Some(setting_name) => vec![setting_name.clone()],
+        None => {
+            let mut names = nm_settings::required_secrets(&profile)
+                .into_iter()
+                .map(|secret| secret.setting_name)
+                .collect::<Vec<_>>();
+            for setting_name in profile.keys() {
+                let request = dbus_server::build_request(&profile, setting_name, &[], 0)?;
+                if mapping.matching_entries(&request).next().is_some() {
+                    names.push(setting_name.clone());
+                }
+            }
+            names.sort();
+            names.dedup();
+            names
+        }
+    };
+    if setting_names.is_empty() {
+        println!("The connection requires no secrets and no entry matches any of its settings");
+    }
+    for (i, setting_name) in setting_names.iter().enumerate() {
+        let request = dbus_server::build_request(&profile, setting_name, &[], 0)?;
+        if i > 0 {
+            println!();
+        }
+        print!(
+            "Request for {setting_name} of {} ({}, interface {:?}",
+            request.conn_id, request.conn_uuid, request.iface_name
+        );
+        if let Some(ssid) = &request.ssid {
+            print!(", SSID {:?}", String::from_utf8_lossy(ssid));
+        }
+        println!("):");
+        report_request(mapping, &request, args.show_secrets)?;
+    }
+    Ok(())
+}
+
+/// Arguments of the `push` subcommand
+#[derive(Args, Debug, Eq, PartialEq, Hash)]
+pub struct PushArgs {
+    /// Id or uuid of a connection profile into which secrets are pushed instead of all profiles
+    ///
+    /// Can be given multiple times.
+    #[arg(long = "connection", value_name = "CONNECTION")]
+    connections: Vec<String>,
+
+    /// Only change the profiles in memory so that the secrets are gone once NetworkManager restarts
+    #[arg(long)]
+    in_memory: bool,
+}
+
+/// Write the secrets which the entries of `mapping` provide directly into the connection profiles of NetworkManager
+///
+/// The secrets of every setting are resolved like for a request of NetworkManager and added to the settings and
+/// already stored secrets of the profile, which then replace the profile via `Update2`. Profiles for which no entry
+/// provides secrets are left untouched.
+pub async fn push(
+    conn: &Connection,
+    mapping: &MappingConfig,
+    args: &PushArgs,
+) -> anyhow::Result<()> {
+    let flags = match args.in_memory {
+        true => nm_settings::Update2Flags::InMemory,
+        false => nm_settings::Update2Flags::ToDisk,
+    };
+    let mut missing = args.connections.iter().collect::<HashSet<_>>();
+    let (mut pushed, mut failed) = (0, 0);
+    let mut first_error = None;
+    for (path, profile) in nm_settings::list_profiles(conn).await? {
+        let (id, uuid) = (
+            nm_settings::profile_id(&profile).to_owned(),
+            nm_settings::profile_uuid(&profile).to_owned(),
+        );
+        if !args.connections.is_empty() {
+            let selected = args
+                .connections
+                .iter()
+                .any(|connection| *connection == id || *connection == uuid);
+            if !selected {
+                continue;
+            }
+            missing.retain(|connection| **connection != id && **connection != uuid);
+        }
+        match push_profile(conn, mapping, &path, profile, flags).await {
+            Ok(0) => tracing::debug!("No entry provides secrets for connection {id}"),
+            Ok(count) => {
+                println!("Pushed {count} secrets into connection {id} ({uuid})");
+                pushed += 1;
+            }
+            Err(e) => {
+                tracing::error!("Could not push secrets into connection {id}: {e:#}");
+                failed += 1;
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    if let Some(connection) = missing.into_iter().next() {
+        anyhow::bail!(Error::new(
+            ErrorCode::InvalidRequest,
+            format!("NetworkManager has no connection {connection:?}"),
+        ));
+    }
+    if let Some(e) = first_error {
+        return Err(e.context(format!("Could not push secrets into {failed} connections")));
+    }
+    if pushed == 0 {
+        println!("No entry provides secrets for any connection");
+    }
+    Ok(())
+}
+
+/// Push the secrets of all settings of the connection profile `profile` at `path` and return how many were pushed
+async fn push_profile(
+    conn: &Connection,
+    mapping: &MappingConfig,
+    path: &OwnedObjectPath,
+    mut profile: dbus_server::NestedSettingsMap,
+    flags: nm_settings::Update2Flags,
+) -> anyhow::Result<usize> {
+    let cancelled = AtomicBool::new(false);
+    let required = nm_settings::required_secrets(&profile);
+    let mut setting_names = profile.keys().cloned().collect::<Vec<_>>();
+    setting_names.sort();
+    let mut resolved = Vec::new();
+    for setting_name in setting_names {
+        let request = dbus_server::build_request(&profile, &setting_name, &[], 0)?;
+        let secrets = mapping.get_secrets(&request, false, &cancelled)?;
+        if secrets.is_empty() {
+            continue;
+        }
+        for (key, _) in &secrets {
+            if let Some(secret) = required.iter().find(|secret| {
+                secret.setting_name == setting_name && secret.key == *key && secret.flags != 0
+            }) {
+                tracing::warn!(
+                    "{setting_name}.{key} of connection {} has the secret flags {} so NetworkManager does not store it in the profile",
+                    request.conn_id,
+                    secret.flags
+                );
+            }
+        }
+        resolved.push((request, secrets));
+    }
+    if resolved.is_empty() {
+        return Ok(0);
+    }
+
+    let proxy = nm_settings::SettingsConnectionProxy::builder(conn)
+        .path(path)?
+        .build()
+        .await?;
+    // the profile replaces all settings including secrets, so those which NetworkManager already stores are kept
+    let setting_names = profile.keys().cloned().collect::<Vec<_>>();
+    for setting_name in setting_names
+        .iter()
+        .filter(|name| nm_settings::has_secrets(name))
+    {
+        match proxy.get_secrets(setting_name).await {
+            Ok(stored) => {
+                for (name, secrets) in stored {
+                    nm_settings::merge_secrets(profile.entry(name).or_default(), secrets)?;
+                }
+            }
+            Err(e) => tracing::debug!("Could not query the stored secrets of {setting_name}: {e}"),
+        }
+    }
+    let mut count = 0;
+    for (request, secrets) in resolved {
+        count += secrets.len();
+        let encoded =
+            encoder::encode_secrets(&request, &secrets).context("Could not encode secrets")?;
+        nm_settings::merge_secrets(profile.entry(request.setting_name).or_default(), encoded)?;
+    }
+    proxy
+        .update2(&profile, flags as u32, HashMap::new())
+        .await
+        .context(Error::new(
+            ErrorCode::NmUnreachable,
+            "Could not update the connection profile",
+        ))?;
+    Ok(count)
+}
+
+/// Query NetworkManager for its connection profiles and report entries which can never match any of them
+///
+/// Fails if there is such an entry so that the check can be used in scripts, e.g. after deploying a new config.
+pub async fn check_connections(conn: &Connection, mapping: &MappingConfig) -> anyhow::Result<()> {
+    let profiles = nm_settings::list_profiles(conn).await?;
+    let unmatchable =
+        nm_settings::unmatchable_entries(mapping, profiles.iter().map(|(_, profile)| profile));
+    if unmatchable.is_empty() {
+        println!(
+            "All {} entries match at least one of the {} connections of NetworkManager",
+            mapping.entries().len(),
+            profiles.len()
+        );
+        return Ok(());
+    }
+    for &index in &unmatchable {
+        let entry = &mapping.entries()[index];
+        let id = entry
+            .id()
+            .map(|id| format!(" (id {id})"))
+            .unwrap_or_default();
+        println!(
+            "#{index}{id} providing {} matches no connection: {}",
+            entry.key(),
+            nm_settings::describe_criteria(entry)
+        );
+    }
+    anyhow::bail!(Error::new(
+        ErrorCode::ConfigInvalid,
+        format!(
+            "{} of {} entries match none of the {} connections of NetworkManager",
+            unmatchable.len(),
+            mapping.entries().len(),
+            profiles.len()
+        )
+    ))
+}
+
+/// Print for every entry whether it matches the request described by `args` or which match settings reject it
+///
+/// Unlike `resolve`, this never reads any secrets or runs commands.
+pub fn explain(mapping: &MappingConfig, args: &ResolveArgs) -> anyhow::Result<()> {
+    let request = args.request();
+    if mapping.entries().is_empty() {
+        println!("No entries are configured");
+    }
+    // a configured hook can override the match settings
+    let matching = mapping
+        .matching_entries(&request)
+        .map(|(index, _)| index)
+        .collect::<HashSet<_>>();
+    for (index, entry) in mapping.entries().iter().enumerate() {
+        let id = entry
+            .id()
+            .map(|id| format!(" (id {id})"))
+            .unwrap_or_default();
+        let mismatches = entry.mismatches(&request);
+        match (
+            matching.contains(&index),
+            mismatches.is_empty(),
+            entry.skip_reason(&request),
+        ) {
+            (true, _, None) => println!("entry {index}{id}: matches and provides {}", entry.key()),
+            (true, _, Some(reason)) => {
+                println!("entry {index}{id}: matches but is skipped because {reason}")
+            }
+            (false, true, _) => println!("entry {index}{id}: rejected by the hook"),
+            (false, false, _) => println!("entry {index}{id}: {}", mismatches.join(", ")),
+        }
+    }
+    Ok(())
+}
+
+/// Arguments of the `list` subcommand
+#[derive(Args, Debug, Eq, PartialEq, Hash)]
+pub struct ListArgs {
+    /// Print the entries as JSON instead of human readable text
+    #[arg(long)]
+    json: bool,
+}
+
+/// Print all entries of the effective configuration after merging config files and applying defaults
+pub fn list(mapping: &MappingConfig, args: &ListArgs) -> anyhow::Result<()> {
+    if args.json {
+        let entries = mapping
+            .entries()
+            .iter()
+            .enumerate()
+            .map(|(index, entry)| {
+                serde_json::json!({
+                    "index": index,
+                    "id": entry.id(),
+                    "key": entry.key(),
+                    "match": entry
+                        .match_criteria()
+                        .into_iter()
+                        .map(|(name, value)| (name.to_owned(), value.into()))
+                        .collect::<serde_json::Map<_, _>>(),
+                    "tags": entry.tags(),
+                    "source": entry.source_type(),
+                    "not_after": entry.not_after().map(ToString::to_string),
+                    "files": entry
+                        .files()
+                        .into_iter()
+                        .map(|file| (file.display().to_string(), file_state(entry, file).into()))
+                        .collect::<serde_json::Map<_, _>>(),
+                })
+            })
+            .collect::<Vec<_>>();
+        let output = serde_json::json!({
+            "entries": entries,
+            "fallback": mapping.fallback_command(),
+        });
+        println!("{output:#}");
+        return Ok(());
+    }
+
+    if mapping.entries().is_empty() {
+        println!("No entries are configured");
+    }
+    for (index, entry) in mapping.entries().iter().enumerate() {
+        let id = entry
+            .id()
+            .map(|id| format!(" (id {id})"))
+            .unwrap_or_default();
+        println!(
+            "#{index}{id} provides {} from {}",
+            entry.key(),
+            entry.source_type()
+        );
+        let criteria = entry.match_criteria();
+        match criteria.is_empty() {
+            true => println!("  matches every request"),
+            false => {
+                for (name, value) in criteria {
+                    println!("  {name} = {value:?}");
+                }
+            }
+        }
+        if !entry.tags().is_empty() {
+            println!("  tagged {}", entry.tags().join(", "));
+        }
+        if let Some(not_after) = entry.not_after() {
+            match not_after.is_past(SystemTime::now()) {
+                true => println!("  expired at {not_after}"),
+                false => println!("  expires at {not_after}"),
+            }
+        }
+        for file in entry.files() {
+            println!("  {}: {}", file.display(), file_state(entry, file));
+        }
+    }
+    if let Some(command) = mapping.fallback_command() {
+        println!("Fallback command: {command:?}");
+    }
+    Ok(())
+}
+
+/// Whether `file` of `entry` is readable or why it is not
+fn file_state(entry: &MappingEntry, file: &Path) -> String {
+    match entry.check_readable(file) {
+        Ok(()) => "readable".to_owned(),
+        Err(e) => format!("{e:#}"),
+    }
+}
+
+/// Arguments of the `generate` subcommand
+#[derive(Args, Debug, Eq, PartialEq, Hash)]
+pub struct GenerateArgs {
+    /// Directory below which the placeholder secret file paths of the generated entries are located
+    #[arg(long, value_name = "DIR", default_value = "/run/secrets/nm")]
+    secrets_dir: PathBuf,
+
+    /// Only generate entries for the connection with this id or uuid
+    ///
+    /// Can be given multiple times. By default, entries are generated for all connections which require secrets.
+    #[arg(long = "connection", value_name = "ID|UUID")]
+    connections: Vec<String>,
+}
+
+/// Query NetworkManager for its connection profiles and print a config skeleton providing all their secrets
+pub async fn generate(conn: &Connection, args: &GenerateArgs) -> anyhow::Result<()> {
+    let profiles = nm_settings::list_profiles(conn).await?;
+
+    println!("# Generated by nm-file-secret-agent generate");
+    for (_, settings) in profiles {
+        let property = |key: &str| {
+            settings
+                .get("connection")
+                .and_then(|connection| connection.get(key))
+                .and_then(|value| value.downcast_ref::<&str>().ok())
+                .unwrap_or_default()
+                .to_owned()
+        };
+        let (id, uuid, conn_type) = (property("id"), property("uuid"), property("type"));
+        if !args.connections.is_empty() && !args.connections.iter().any(|i| *i == id || *i == uuid)
+        {
+            continue;
+        }
+
+        let secrets = nm_settings::required_secrets(&settings);
+        if secrets.is_empty() {
+            tracing::debug!("Connection {id} does not require any secrets");
+            continue;
+        }
+        println!();
+        println!("# {id} ({conn_type})");
+        for secret in secrets {
+            println!();
+            if !secret.is_agent_owned() {
+                println!(
+                    "# {0}.{1}-flags is {2} so NetworkManager only asks agents for this secret if it is not stored in the profile; set it to 1 (agent-owned) to rely on this agent",
+                    secret.setting_name,
+                    secret.key.strip_prefix("secrets.").unwrap_or(&secret.key),
+                    secret.flags,
+                );
+            }
+            let file = args
+                .secrets_dir
+                .join(file_name(&id))
+                .join(file_name(&format!(
+                    "{}.{}",
+                    secret.setting_name, secret.key
+                )));
+            println!("[[entry]]");
+            println!("match_uuid = {}", toml_string(&uuid));
+            println!("match_setting = {}", toml_string(&secret.setting_name));
+            println!("key = {}", toml_string(&secret.key));
+            println!("file = {}", toml_string(&file.display().to_string()));
+        }
+    }
+    Ok(())
+}
+
+/// Arguments of the `import` subcommand
+#[derive(Args, Debug, Eq, PartialEq, Hash)]
+pub struct ImportArgs {
+    #[command(subcommand)]
+    source: ImportSource,
+}
+
+/// Where secrets are imported from
+#[derive(Subcommand, Debug, Eq, PartialEq, Hash)]
+enum ImportSource {
+    /// Extract the secrets of NetworkManager keyfiles (e.g. from /etc/NetworkManager/system-connections)
+    Keyfile(ImportKeyfileArgs),
+}
+
+/// Arguments of the `import keyfile` subcommand
+#[derive(Args, Debug, Eq, PartialEq, Hash)]
+struct ImportKeyfileArgs {
+    /// Keyfiles or directories containing keyfiles to import
+    #[arg(required = true)]
+    paths: Vec<PathBuf>,
+
+    /// Directory into which the extracted secrets are written
+    ///
+    /// Every secret is written to `<DIR>/<connection id>/<setting>.<key>` which must not exist yet.
+    #[arg(long, value_name = "DIR")]
+    target_dir: PathBuf,
+}
+
+/// Import secrets from another store into individual files and print the config entries which provide them
+pub fn import(args: &ImportArgs) -> anyhow::Result<()> {
+    match &args.source {
+        ImportSource::Keyfile(args) => import_keyfiles(args),
+    }
+}
+
+/// Extract all secrets of NetworkManager keyfiles into files below the target directory
+fn import_keyfiles(args: &ImportKeyfileArgs) -> anyhow::Result<()> {
+    let mut paths = Vec::new();
+    for path in &args.paths {
+        match path.is_dir() {
+            true => {
+                let mut files = fs::read_dir(path)
+                    .with_context(|| format!("Could not list directory {}", path.display()))?
+                    .map(|entry| Ok(entry?.path()))
+                    .collect::<std::io::Result<Vec<_>>>()
+                    .with_context(|| format!("Could not list directory {}", path.display()))?;
+                files.retain(|file| file.is_file());
+                files.sort();
+                paths.extend(files);
+            }
+            false => paths.push(path.clone()),
+        }
+    }
+
+    println!("# Imported by nm-file-secret-agent import keyfile");
+    for path in paths {
+        let keyfile = Keyfile::from_file(&path)
+            .with_context(|| format!("Could not import keyfile {}", path.display()))?;
+        if keyfile.secrets.is_empty() {
+            tracing::info!("Keyfile {} does not contain any secrets", path.display());
+            continue;
+        }
+        let dir = args.target_dir.join(file_name(&keyfile.id));
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&dir)
+            .with_context(|| format!("Could not create directory {}", dir.display()))?;
+
+        println!();
+        println!("# {} ({})", keyfile.id, keyfile.conn_type);
+        for secret in &keyfile.secrets {
+            let file = dir.join(file_name(&format!(
+                "{}.{}",
+                secret.setting_name, secret.key
+            )));
+            File::options()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(&file)
+                .and_then(|mut handle| handle.write_all(secret.value.as_bytes()))
+                .with_context(|| format!("Could not write secret file {}", file.display()))?;
+            tracing::info!(
+                "Extracted {}.{} of {} into {}",
+                secret.setting_name,
+                secret.key,
+                keyfile.id,
+                file.display()
+            );
+
+            let (section, key) = &secret.location;
+            let flags_section = match section.as_str() {
+                "vpn-secrets" => "vpn",
+                section => section,
+            };
+            println!();
+            println!(
+                "# remove {key} from [{section}] of {} and set {key}-flags=1 in [{flags_section}] so that NetworkManager asks this agent",
+                path.display()
+            );
+            println!("[[entry]]");
+            println!("match_uuid = {}", toml_string(&keyfile.uuid));
+            println!("match_setting = {}", toml_string(&secret.setting_name));
+            println!("key = {}", toml_string(&secret.key));
+            println!("file = {}", toml_string(&file.display().to_string()));
+        }
+    }
+    Ok(())
+}
+
+/// Arguments of the `stats` subcommand
+#[derive(Args, Debug, Eq, PartialEq, Hash)]
+pub struct StatsArgs {
+    /// Unique bus name of the agent to query instead of searching the bus for it
+    #[arg(long, value_name = "NAME")]
+    agent: Option<String>,
+
+    /// Print the statistics as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+/// Query a running agent for the usage counters of its entries and print them
+pub async fn stats(conn: &Connection, args: &StatsArgs) -> anyhow::Result<()> {
+    let agent = match &args.agent {
+        Some(agent) => agent.clone(),
+        None => find_agent(conn).await?,
+    };
+    let entries = FileSecretAgentProxy::builder(conn)
+        .destination(agent.as_str())?
+        .build()
+        .await?
+        .get_stats()
+        .await
+        .with_context(|| format!("Could not query the statistics of the agent {agent}"))?;
+    let field = |entry: &HashMap<String, OwnedValue>, name: &str| {
+        entry
+            .get(name)
+            .and_then(|value| u64::try_from(value).ok())
+            .unwrap_or_default()
+    };
+
+    if args.json {
+        let entries = entries
+            .iter()
+            .map(|entry| {
+                serde_json::json!({
+                    "index": entry.get("index").and_then(|index| u32::try_from(index).ok()),
+                    "id": entry.get("id").and_then(|id| String::try_from(id.clone()).ok()),
+                    "key": entry.get("key").and_then(|key| String::try_from(key.clone()).ok()),
+                    "matched": field(entry, "matched"),
+                    "served": field(entry, "served"),
+                    "read_failures": field(entry, "read_failures"),
+                    "last_used": Some(field(entry, "last_used")).filter(|&last_used| last_used > 0),
+                })
+            })
+            .collect::<Vec<_>>();
+        println!("{:#}", serde_json::Value::Array(entries));
+        return Ok(());
+    }
+
+    if entries.is_empty() {
+        println!("The agent has no entries configured");
+    }
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    for entry in &entries {
+        let string = |name: &str| {
+            entry
+                .get(name)
+                .and_then(|value| String::try_from(value.clone()).ok())
+        };
+        let index = entry
+            .get("index")
+            .and_then(|index| u32::try_from(index).ok())
+            .unwrap_or_default();
+        let id = string("id")
+            .map(|id| format!(" (id {id})"))
+            .unwrap_or_default();
+        let last_used = match field(entry, "last_used") {
+            0 => "never".to_owned(),
+            last_used => format!("{}s ago", now.saturating_sub(last_used)),
+        };
+        println!(
+            "#{index}{id} provides {}: matched {}, served {}, read failures {}, last used {last_used}",
+            string("key").unwrap_or_default(),
+            field(entry, "matched"),
+            field(entry, "served"),
+            field(entry, "read_failures"),
+        );
+    }
+    Ok(())
+}
+
+/// Search the bus for the single running agent
+async fn find_agent(conn: &Connection) -> anyhow::Result<String> {
+    let own_name = conn.unique_name().map(|name| name.to_string());
+    let names = DBusProxy::new(conn)
+        .await?
+        .list_names()
+        .await
+        .context("Could not list the names on the bus")?;
+    let mut agents = Vec::new();
+    for name in names {
+        if !name.starts_with(':') || Some(name.to_string()) == own_name {
+            continue;
+        }
+        let proxy = FileSecretAgentProxy::builder(conn)
+            .destination(name.to_string())?
+            .cache_properties(CacheProperties::No)
+            .build()
+            .await?;
+        // peers which don't serve the agent interface reply with an error or not at all
+        if let Ok(Ok(_)) = time::timeout(AGENT_PROBE_TIMEOUT, proxy.version()).await {
+            agents.push(name.to_string());
+        }
+    }
+    match agents.as_slice() {
+        [] => anyhow::bail!("No running agent was found on the bus"),
+        [agent] => Ok(agent.clone()),
+        _ => anyhow::bail!(
+            "Multiple agents are running ({}); select one with --agent",
+            agents.join(", ")
+        ),
+    }
+}
+
+/// Arguments of the `completions` subcommand
+#[derive(Args, Debug, Eq, PartialEq, Hash)]
+pub struct CompletionsArgs {
+    /// Shell for which the completion script is generated
+    shell: Shell,
+}
+
+/// Print a shell completion script for the command line interface `cli`
+pub fn completions(mut cli: clap::Command, args: &CompletionsArgs) -> anyhow::Result<()> {
+    let name = cli.get_name().to_owned();
+    clap_complete::generate(args.shell, &mut cli, name, &mut std::io::stdout());
+    Ok(())
+}
+
+/// Arguments of the `man` subcommand
+#[derive(Args, Debug, Eq, PartialEq, Hash)]
+pub struct ManArgs {
+    /// Write man pages for the program and all of its subcommands into this directory instead of printing the one of
+    /// the program
+    #[arg(long, value_name = "DIR")]
+    out_dir: Option<PathBuf>,
+}
+
+/// Render the man page of the command line interface `cli`
+pub fn man(cli: clap::Command, args: &ManArgs) -> anyhow::Result<()> {
+    match &args.out_dir {
+        None => clap_mangen::Man::new(cli)
+            .render(&mut std::io::stdout())
+            .context("Could not render man page"),
+        Some(dir) => clap_mangen::generate_to(cli, dir)
+            .with_context(|| format!("Could not write man pages into {}", dir.display())),
+    }
+}
+
+/// Format `value` as a TOML string literal
+fn toml_string(value: &str) -> String {
+    toml::Value::String(value.to_owned()).to_string()
+}
+
+/// Turn `name` into a file name by replacing all characters that are problematic in paths
+fn file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' | '_' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+/// Format a secret value for printing it to the terminal
+fn format_value(value: &SecretValue) -> String {
+    match value {
+        SecretValue::String(value) => format!("{value:?}"),
+        SecretValue::U32(value) => value.to_string(),
+        SecretValue::Bool(value) => value.to_string(),
+        SecretValue::StringList(value) => format!("{value:?}"),
+        SecretValue::Bytes(value) => format!("0x{}", hex::encode(value)),
+    }
+}
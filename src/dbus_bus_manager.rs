@@ -1,406 +0,0 @@
-// This code was autogenerated with `dbus-codegen-rust -s -g -m None -d org.freedesktop.DBus -p /org/freedesktop/DBus`, see https://github.com/diwic/dbus-rs
-use dbus;
-#[allow(unused_imports)]
-use dbus::arg;
-use dbus::blocking;
-
-pub trait OrgFreedesktopDBus {
-    fn hello(&self) -> Result<String, dbus::Error>;
-    fn request_name(&self, arg0: &str, arg1: u32) -> Result<u32, dbus::Error>;
-    fn release_name(&self, arg0: &str) -> Result<u32, dbus::Error>;
-    fn start_service_by_name(&self, arg0: &str, arg1: u32) -> Result<u32, dbus::Error>;
-    fn update_activation_environment(
-        &self,
-        arg0: ::std::collections::HashMap<&str, &str>,
-    ) -> Result<(), dbus::Error>;
-    fn name_has_owner(&self, arg0: &str) -> Result<bool, dbus::Error>;
-    fn list_names(&self) -> Result<Vec<String>, dbus::Error>;
-    fn list_activatable_names(&self) -> Result<Vec<String>, dbus::Error>;
-    fn add_match(&self, arg0: &str) -> Result<(), dbus::Error>;
-    fn remove_match(&self, arg0: &str) -> Result<(), dbus::Error>;
-    fn get_name_owner(&self, arg0: &str) -> Result<String, dbus::Error>;
-    fn list_queued_owners(&self, arg0: &str) -> Result<Vec<String>, dbus::Error>;
-    fn get_connection_unix_user(&self, arg0: &str) -> Result<u32, dbus::Error>;
-    fn get_connection_unix_process_id(&self, arg0: &str) -> Result<u32, dbus::Error>;
-    fn get_adt_audit_session_data(&self, arg0: &str) -> Result<Vec<u8>, dbus::Error>;
-    fn get_connection_selinux_security_context(&self, arg0: &str) -> Result<Vec<u8>, dbus::Error>;
-    fn reload_config(&self) -> Result<(), dbus::Error>;
-    fn get_id(&self) -> Result<String, dbus::Error>;
-    fn get_connection_credentials(&self, arg0: &str) -> Result<arg::PropMap, dbus::Error>;
-    fn features(&self) -> Result<Vec<String>, dbus::Error>;
-    fn interfaces(&self) -> Result<Vec<String>, dbus::Error>;
-}
-
-#[derive(Debug)]
-pub struct OrgFreedesktopDBusNameOwnerChanged {
-    pub arg0: String,
-    pub arg1: String,
-    pub arg2: String,
-}
-
-impl arg::AppendAll for OrgFreedesktopDBusNameOwnerChanged {
-    fn append(&self, i: &mut arg::IterAppend) {
-        arg::RefArg::append(&self.arg0, i);
-        arg::RefArg::append(&self.arg1, i);
-        arg::RefArg::append(&self.arg2, i);
-    }
-}
-
-impl arg::ReadAll for OrgFreedesktopDBusNameOwnerChanged {
-    fn read(i: &mut arg::Iter) -> Result<Self, arg::TypeMismatchError> {
-        Ok(OrgFreedesktopDBusNameOwnerChanged {
-            arg0: i.read()?,
-            arg1: i.read()?,
-            arg2: i.read()?,
-        })
-    }
-}
-
-impl dbus::message::SignalArgs for OrgFreedesktopDBusNameOwnerChanged {
-    const NAME: &'static str = "NameOwnerChanged";
-    const INTERFACE: &'static str = "org.freedesktop.DBus";
-}
-
-#[derive(Debug)]
-pub struct OrgFreedesktopDBusNameLost {
-    pub arg0: String,
-}
-
-impl arg::AppendAll for OrgFreedesktopDBusNameLost {
-    fn append(&self, i: &mut arg::IterAppend) {
-        arg::RefArg::append(&self.arg0, i);
-    }
-}
-
-impl arg::ReadAll for OrgFreedesktopDBusNameLost {
-    fn read(i: &mut arg::Iter) -> Result<Self, arg::TypeMismatchError> {
-        Ok(OrgFreedesktopDBusNameLost { arg0: i.read()? })
-    }
-}
-
-impl dbus::message::SignalArgs for OrgFreedesktopDBusNameLost {
-    const NAME: &'static str = "NameLost";
-    const INTERFACE: &'static str = "org.freedesktop.DBus";
-}
-
-#[derive(Debug)]
-pub struct OrgFreedesktopDBusNameAcquired {
-    pub arg0: String,
-}
-
-impl arg::AppendAll for OrgFreedesktopDBusNameAcquired {
-    fn append(&self, i: &mut arg::IterAppend) {
-        arg::RefArg::append(&self.arg0, i);
-    }
-}
-
-impl arg::ReadAll for OrgFreedesktopDBusNameAcquired {
-    fn read(i: &mut arg::Iter) -> Result<Self, arg::TypeMismatchError> {
-        Ok(OrgFreedesktopDBusNameAcquired { arg0: i.read()? })
-    }
-}
-
-impl dbus::message::SignalArgs for OrgFreedesktopDBusNameAcquired {
-    const NAME: &'static str = "NameAcquired";
-    const INTERFACE: &'static str = "org.freedesktop.DBus";
-}
-
-#[derive(Debug)]
-pub struct OrgFreedesktopDBusActivatableServicesChanged {}
-
-impl arg::AppendAll for OrgFreedesktopDBusActivatableServicesChanged {
-    fn append(&self, _: &mut arg::IterAppend) {}
-}
-
-impl arg::ReadAll for OrgFreedesktopDBusActivatableServicesChanged {
-    fn read(_: &mut arg::Iter) -> Result<Self, arg::TypeMismatchError> {
-        Ok(OrgFreedesktopDBusActivatableServicesChanged {})
-    }
-}
-
-impl dbus::message::SignalArgs for OrgFreedesktopDBusActivatableServicesChanged {
-    const NAME: &'static str = "ActivatableServicesChanged";
-    const INTERFACE: &'static str = "org.freedesktop.DBus";
-}
-
-impl<'a, T: blocking::BlockingSender, C: ::std::ops::Deref<Target = T>> OrgFreedesktopDBus
-    for blocking::Proxy<'a, C>
-{
-    fn hello(&self) -> Result<String, dbus::Error> {
-        self.method_call("org.freedesktop.DBus", "Hello", ())
-            .and_then(|r: (String,)| Ok(r.0))
-    }
-
-    fn request_name(&self, arg0: &str, arg1: u32) -> Result<u32, dbus::Error> {
-        self.method_call("org.freedesktop.DBus", "RequestName", (arg0, arg1))
-            .and_then(|r: (u32,)| Ok(r.0))
-    }
-
-    fn release_name(&self, arg0: &str) -> Result<u32, dbus::Error> {
-        self.method_call("org.freedesktop.DBus", "ReleaseName", (arg0,))
-            .and_then(|r: (u32,)| Ok(r.0))
-    }
-
-    fn start_service_by_name(&self, arg0: &str, arg1: u32) -> Result<u32, dbus::Error> {
-        self.method_call("org.freedesktop.DBus", "StartServiceByName", (arg0, arg1))
-            .and_then(|r: (u32,)| Ok(r.0))
-    }
-
-    fn update_activation_environment(
-        &self,
-        arg0: ::std::collections::HashMap<&str, &str>,
-    ) -> Result<(), dbus::Error> {
-        self.method_call(
-            "org.freedesktop.DBus",
-            "UpdateActivationEnvironment",
-            (arg0,),
-        )
-    }
-
-    fn name_has_owner(&self, arg0: &str) -> Result<bool, dbus::Error> {
-        self.method_call("org.freedesktop.DBus", "NameHasOwner", (arg0,))
-            .and_then(|r: (bool,)| Ok(r.0))
-    }
-
-    fn list_names(&self) -> Result<Vec<String>, dbus::Error> {
-        self.method_call("org.freedesktop.DBus", "ListNames", ())
-            .and_then(|r: (Vec<String>,)| Ok(r.0))
-    }
-
-    fn list_activatable_names(&self) -> Result<Vec<String>, dbus::Error> {
-        self.method_call("org.freedesktop.DBus", "ListActivatableNames", ())
-            .and_then(|r: (Vec<String>,)| Ok(r.0))
-    }
-
-    fn add_match(&self, arg0: &str) -> Result<(), dbus::Error> {
-        self.method_call("org.freedesktop.DBus", "AddMatch", (arg0,))
-    }
-
-    fn remove_match(&self, arg0: &str) -> Result<(), dbus::Error> {
-        self.method_call("org.freedesktop.DBus", "RemoveMatch", (arg0,))
-    }
-
-    fn get_name_owner(&self, arg0: &str) -> Result<String, dbus::Error> {
-        self.method_call("org.freedesktop.DBus", "GetNameOwner", (arg0,))
-            .and_then(|r: (String,)| Ok(r.0))
-    }
-
-    fn list_queued_owners(&self, arg0: &str) -> Result<Vec<String>, dbus::Error> {
-        self.method_call("org.freedesktop.DBus", "ListQueuedOwners", (arg0,))
-            .and_then(|r: (Vec<String>,)| Ok(r.0))
-    }
-
-    fn get_connection_unix_user(&self, arg0: &str) -> Result<u32, dbus::Error> {
-        self.method_call("org.freedesktop.DBus", "GetConnectionUnixUser", (arg0,))
-            .and_then(|r: (u32,)| Ok(r.0))
-    }
-
-    fn get_connection_unix_process_id(&self, arg0: &str) -> Result<u32, dbus::Error> {
-        self.method_call(
-            "org.freedesktop.DBus",
-            "GetConnectionUnixProcessID",
-            (arg0,),
-        )
-        .and_then(|r: (u32,)| Ok(r.0))
-    }
-
-    fn get_adt_audit_session_data(&self, arg0: &str) -> Result<Vec<u8>, dbus::Error> {
-        self.method_call("org.freedesktop.DBus", "GetAdtAuditSessionData", (arg0,))
-            .and_then(|r: (Vec<u8>,)| Ok(r.0))
-    }
-
-    fn get_connection_selinux_security_context(&self, arg0: &str) -> Result<Vec<u8>, dbus::Error> {
-        self.method_call(
-            "org.freedesktop.DBus",
-            "GetConnectionSELinuxSecurityContext",
-            (arg0,),
-        )
-        .and_then(|r: (Vec<u8>,)| Ok(r.0))
-    }
-
-    fn reload_config(&self) -> Result<(), dbus::Error> {
-        self.method_call("org.freedesktop.DBus", "ReloadConfig", ())
-    }
-
-    fn get_id(&self) -> Result<String, dbus::Error> {
-        self.method_call("org.freedesktop.DBus", "GetId", ())
-            .and_then(|r: (String,)| Ok(r.0))
-    }
-
-    fn get_connection_credentials(&self, arg0: &str) -> Result<arg::PropMap, dbus::Error> {
-        self.method_call("org.freedesktop.DBus", "GetConnectionCredentials", (arg0,))
-            .and_then(|r: (arg::PropMap,)| Ok(r.0))
-    }
-
-    fn features(&self) -> Result<Vec<String>, dbus::Error> {
-        <Self as blocking::stdintf::org_freedesktop_dbus::Properties>::get(
-            self,
-            "org.freedesktop.DBus",
-            "Features",
-        )
-    }
-
-    fn interfaces(&self) -> Result<Vec<String>, dbus::Error> {
-        <Self as blocking::stdintf::org_freedesktop_dbus::Properties>::get(
-            self,
-            "org.freedesktop.DBus",
-            "Interfaces",
-        )
-    }
-}
-
-pub trait OrgFreedesktopDBusProperties {
-    fn get<R0: for<'b> arg::Get<'b> + 'static>(
-        &self,
-        arg0: &str,
-        arg1: &str,
-    ) -> Result<R0, dbus::Error>;
-    fn get_all(&self, arg0: &str) -> Result<arg::PropMap, dbus::Error>;
-    fn set<I2: arg::Arg + arg::Append>(
-        &self,
-        arg0: &str,
-        arg1: &str,
-        arg2: I2,
-    ) -> Result<(), dbus::Error>;
-}
-
-#[derive(Debug)]
-pub struct OrgFreedesktopDBusPropertiesPropertiesChanged {
-    pub interface_name: String,
-    pub changed_properties: arg::PropMap,
-    pub invalidated_properties: Vec<String>,
-}
-
-impl arg::AppendAll for OrgFreedesktopDBusPropertiesPropertiesChanged {
-    fn append(&self, i: &mut arg::IterAppend) {
-        arg::RefArg::append(&self.interface_name, i);
-        arg::RefArg::append(&self.changed_properties, i);
-        arg::RefArg::append(&self.invalidated_properties, i);
-    }
-}
-
-impl arg::ReadAll for OrgFreedesktopDBusPropertiesPropertiesChanged {
-    fn read(i: &mut arg::Iter) -> Result<Self, arg::TypeMismatchError> {
-        Ok(OrgFreedesktopDBusPropertiesPropertiesChanged {
-            interface_name: i.read()?,
-            changed_properties: i.read()?,
-            invalidated_properties: i.read()?,
-        })
-    }
-}
-
-impl dbus::message::SignalArgs for OrgFreedesktopDBusPropertiesPropertiesChanged {
-    const NAME: &'static str = "PropertiesChanged";
-    const INTERFACE: &'static str = "org.freedesktop.DBus.Properties";
-}
-
-impl<'a, T: blocking::BlockingSender, C: ::std::ops::Deref<Target = T>> OrgFreedesktopDBusProperties
-    for blocking::Proxy<'a, C>
-{
-    fn get<R0: for<'b> arg::Get<'b> + 'static>(
-        &self,
-        arg0: &str,
-        arg1: &str,
-    ) -> Result<R0, dbus::Error> {
-        self.method_call("org.freedesktop.DBus.Properties", "Get", (arg0, arg1))
-            .and_then(|r: (arg::Variant<R0>,)| Ok((r.0).0))
-    }
-
-    fn get_all(&self, arg0: &str) -> Result<arg::PropMap, dbus::Error> {
-        self.method_call("org.freedesktop.DBus.Properties", "GetAll", (arg0,))
-            .and_then(|r: (arg::PropMap,)| Ok(r.0))
-    }
-
-    fn set<I2: arg::Arg + arg::Append>(
-        &self,
-        arg0: &str,
-        arg1: &str,
-        arg2: I2,
-    ) -> Result<(), dbus::Error> {
-        self.method_call(
-            "org.freedesktop.DBus.Properties",
-            "Set",
-            (arg0, arg1, arg::Variant(arg2)),
-        )
-    }
-}
-
-pub trait OrgFreedesktopDBusIntrospectable {
-    fn introspect(&self) -> Result<String, dbus::Error>;
-}
-
-impl<'a, T: blocking::BlockingSender, C: ::std::ops::Deref<Target = T>>
-    OrgFreedesktopDBusIntrospectable for blocking::Proxy<'a, C>
-{
-    fn introspect(&self) -> Result<String, dbus::Error> {
-        self.method_call("org.freedesktop.DBus.Introspectable", "Introspect", ())
-            .and_then(|r: (String,)| Ok(r.0))
-    }
-}
-
-pub trait OrgFreedesktopDBusMonitoring {
-    fn become_monitor(&self, arg0: Vec<&str>, arg1: u32) -> Result<(), dbus::Error>;
-}
-
-impl<'a, T: blocking::BlockingSender, C: ::std::ops::Deref<Target = T>> OrgFreedesktopDBusMonitoring
-    for blocking::Proxy<'a, C>
-{
-    fn become_monitor(&self, arg0: Vec<&str>, arg1: u32) -> Result<(), dbus::Error> {
-        self.method_call(
-            "org.freedesktop.DBus.Monitoring",
-            "BecomeMonitor",
-            (arg0, arg1),
-        )
-    }
-}
-
-pub trait OrgFreedesktopDBusDebugStats {
-    fn get_stats(&self) -> Result<arg::PropMap, dbus::Error>;
-    fn get_connection_stats(&self, arg0: &str) -> Result<arg::PropMap, dbus::Error>;
-    fn get_all_match_rules(
-        &self,
-    ) -> Result<::std::collections::HashMap<String, Vec<String>>, dbus::Error>;
-}
-
-impl<'a, T: blocking::BlockingSender, C: ::std::ops::Deref<Target = T>> OrgFreedesktopDBusDebugStats
-    for blocking::Proxy<'a, C>
-{
-    fn get_stats(&self) -> Result<arg::PropMap, dbus::Error> {
-        self.method_call("org.freedesktop.DBus.Debug.Stats", "GetStats", ())
-            .and_then(|r: (arg::PropMap,)| Ok(r.0))
-    }
-
-    fn get_connection_stats(&self, arg0: &str) -> Result<arg::PropMap, dbus::Error> {
-        self.method_call(
-            "org.freedesktop.DBus.Debug.Stats",
-            "GetConnectionStats",
-            (arg0,),
-        )
-        .and_then(|r: (arg::PropMap,)| Ok(r.0))
-    }
-
-    fn get_all_match_rules(
-        &self,
-    ) -> Result<::std::collections::HashMap<String, Vec<String>>, dbus::Error> {
-        self.method_call("org.freedesktop.DBus.Debug.Stats", "GetAllMatchRules", ())
-            .and_then(|r: (::std::collections::HashMap<String, Vec<String>>,)| Ok(r.0))
-    }
-}
-
-pub trait OrgFreedesktopDBusPeer {
-    fn get_machine_id(&self) -> Result<String, dbus::Error>;
-    fn ping(&self) -> Result<(), dbus::Error>;
-}
-
-impl<'a, T: blocking::BlockingSender, C: ::std::ops::Deref<Target = T>> OrgFreedesktopDBusPeer
-    for blocking::Proxy<'a, C>
-{
-    fn get_machine_id(&self) -> Result<String, dbus::Error> {
-        self.method_call("org.freedesktop.DBus.Peer", "GetMachineId", ())
-            .and_then(|r: (String,)| Ok(r.0))
-    }
-
-    fn ping(&self) -> Result<(), dbus::Error> {
-        self.method_call("org.freedesktop.DBus.Peer", "Ping", ())
-    }
-}
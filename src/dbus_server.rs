@@ -1,15 +1,46 @@
-use std::{collections::HashMap, ops::Deref, time::Duration};
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    fmt::Display,
+    fs,
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::Duration,
+};
 
 use anyhow::Context;
-use dbus::{
-    arg::{PropMap, RefArg, Variant},
-    blocking::Connection,
-    MethodErr, Path,
+use clap::ValueEnum;
+use futures_util::StreamExt;
+use nm_secret_agent_core::{
+    encoder::{self, unwrap_variant, PropMap},
+    error::{Error, ErrorCode},
+    events::Event,
+    mapping::{key_matches_hint, MappingConfig, SecretRequest, TagFilter, ValidationMode},
+    privileges::{self, RunAs},
+    reader, redact,
+};
+use tokio::{
+    signal::unix::{signal, Signal, SignalKind},
+    sync::{oneshot, Notify},
+    time::{self, Instant, Interval},
+};
+use tracing::Instrument;
+use zbus::{
+    connection::Builder,
+    fdo::DBusProxy,
+    interface,
+    message::Header,
+    names::{ErrorName, WellKnownName},
+    object_server::SignalEmitter,
+    zvariant::{ObjectPath, OwnedObjectPath, OwnedValue, Value},
+    Connection, DBusError, Message,
 };
-use dbus_crossroads::{Context as DbusContext, Crossroads};
 
-use crate::dbus_bus_manager::OrgFreedesktopDBus;
-use crate::{agent_manager::OrgFreedesktopNetworkManagerAgentManager, mapping::MappingConfig};
+use crate::{agent_manager::AgentManagerProxy, nm_settings, polkit, systemd};
 
 /// Indication of agent capabilities
 ///
@@ -17,7 +48,6 @@ use crate::{agent_manager::OrgFreedesktopNetworkManagerAgentManager, mapping::Ma
 #[repr(u32)]
 enum SecretAgentCapabilities {
     None = 0,
-    #[allow(unused)]
     VpnHints = 1,
 }
 
@@ -25,180 +55,1369 @@ enum SecretAgentCapabilities {
 ///
 /// See [reference](https://networkmanager.dev/docs/api/latest/nm-dbus-types.html#NMSecretAgentGetSecretsFlags)
 #[repr(u32)]
-enum GetSecretsFlags {
+pub enum GetSecretsFlags {
     /// No special behavior; by default no user interaction is allowed and requests for secrets are fulfilled from persistent storage, or if no secrets are available an error is returned.
     #[allow(dead_code)]
     None = 0x0,
     /// allows the request to interact with the user, possibly prompting via UI for secrets if any are required, or if none are found in persistent storage.
-    #[allow(dead_code)]
     AllowInteraction = 0x1,
     /// explicitly prompt for new secrets from the user. This flag signals that NetworkManager thinks any existing secrets are invalid or wrong. This flag implies that interaction is allowed.
     RequestNew = 0x2,
     /// set if the request was initiated by user-requested action via the D-Bus interface, as opposed to automatically initiated by NetworkManager in response to (for example) scan results or carrier changes.
-    #[allow(dead_code)]
     UserRequested = 0x4,
     /// indicates that WPS enrollment is active with PBC method. The agent may suggest that the user pushes a button on the router instead of supplying a PSK.
     WbsPbcActive = 0x8,
 }
 
+/// Flags describing how a secret of a connection profile is stored
+///
+/// These are stored in `<key>-flags` properties next to the secret itself.
+/// See [reference](https://networkmanager.dev/docs/api/latest/nm-dbus-types.html#NMSettingSecretFlags).
+#[repr(u32)]
+pub enum SecretFlags {
+    /// The secret is stored by NetworkManager itself
+    #[allow(dead_code)]
+    None = 0x0,
+    /// A secret agent is responsible for providing and storing the secret
+    AgentOwned = 0x1,
+    /// The secret is not saved but requested from the user every time it is needed
+    #[allow(dead_code)]
+    NotSaved = 0x2,
+    /// The secret is not required
+    NotRequired = 0x4,
+}
+
+/// Errors that a secret agent can return to NetworkManager
+///
+/// NetworkManager treats these differently, e.g. it falls back to other agents on `NoSecrets` but not necessarily on
+/// `Failed`.
+/// See [reference](https://networkmanager.dev/docs/api/latest/nm-dbus-types.html#NMSecretAgentError).
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SecretAgentError {
+    /// Generic failure
+    Failed,
+    /// The caller does not have permission to call the method
+    PermissionDenied,
+    /// The connection that was passed is invalid
+    InvalidConnection,
+    /// The request was canceled by the user
+    #[allow(dead_code)]
+    UserCanceled,
+    /// The request was canceled by the agent itself
+    AgentCanceled,
+    /// The agent has no secrets for the request
+    NoSecrets,
+}
+
+impl SecretAgentError {
+    /// The D-Bus error name by which NetworkManager identifies this error
+    pub fn dbus_name(self) -> &'static str {
+        match self {
+            SecretAgentError::Failed => "org.freedesktop.NetworkManager.SecretAgent.Failed",
+            SecretAgentError::PermissionDenied => {
+                "org.freedesktop.NetworkManager.SecretAgent.PermissionDenied"
+            }
+            SecretAgentError::InvalidConnection => {
+                "org.freedesktop.NetworkManager.SecretAgent.InvalidConnection"
+            }
+            SecretAgentError::UserCanceled => {
+                "org.freedesktop.NetworkManager.SecretAgent.UserCanceled"
+            }
+            SecretAgentError::AgentCanceled => {
+                "org.freedesktop.NetworkManager.SecretAgent.AgentCanceled"
+            }
+            SecretAgentError::NoSecrets => "org.freedesktop.NetworkManager.SecretAgent.NoSecrets",
+        }
+    }
+
+    /// Find the most specific agent error in the chain of `error` or fall back to `Failed`
+    fn from_anyhow(error: &anyhow::Error) -> Self {
+        error
+            .downcast_ref::<SecretAgentError>()
+            .copied()
+            .unwrap_or(SecretAgentError::Failed)
+    }
+
+    /// The error code that classifies this error unless a more specific one is known
+    fn code(self) -> ErrorCode {
+        match self {
+            SecretAgentError::Failed => ErrorCode::Internal,
+            SecretAgentError::PermissionDenied => ErrorCode::NotAuthorized,
+            SecretAgentError::InvalidConnection => ErrorCode::InvalidRequest,
+            SecretAgentError::UserCanceled | SecretAgentError::AgentCanceled => ErrorCode::Canceled,
+            SecretAgentError::NoSecrets => ErrorCode::NoSecrets,
+        }
+    }
+
+    /// Construct an error reply with this error name and the given message
+    fn method_err(self, message: impl Display) -> MethodError {
+        MethodError {
+            error: self,
+            code: self.code(),
+            message: message.to_string(),
+        }
+    }
+}
+
+/// Classify `error` by the explicit code or I/O error in its chain or else by the agent error it carries
+pub fn error_code(error: &anyhow::Error) -> ErrorCode {
+    match (ErrorCode::of(error), SecretAgentError::from_anyhow(error)) {
+        (ErrorCode::Internal, agent_error) => agent_error.code(),
+        (code, _) => code,
+    }
+}
+
+impl Display for SecretAgentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.dbus_name())
+    }
+}
+
+impl std::error::Error for SecretAgentError {}
+
+/// Context of requests which the agent canceled because they took longer than `--request-timeout`
+///
+/// Unlike requests canceled by NetworkManager, these are reported as failures.
+#[derive(Debug)]
+struct RequestTimeout(Duration);
+
+impl Display for RequestTimeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Resolving the request took longer than {}s",
+            self.0.as_secs()
+        )
+    }
+}
+
+/// Options which influence how the agent interacts with NetworkManager
+#[derive(Debug, Clone, Default)]
+pub struct AgentOptions {
+    /// Register with the VpnHints capability so that VPN plugins pass their hints through to the agent
+    pub vpn_hints: bool,
+    /// Paths of the config files from which the mapping was loaded
+    pub config_paths: Vec<PathBuf>,
+    /// Profile whose entries are merged after the common entries of the config files
+    pub profile: Option<String>,
+    /// Entries given on the command line which are merged after those of the config files
+    pub cli_entries: Vec<String>,
+    /// Which entries are kept based on their tags
+    pub tags: TagFilter,
+    /// How strictly the config is validated when it is reloaded, independently of how it was validated at startup
+    pub validation: ValidationMode,
+    /// Address of the bus to connect to instead of the system bus
+    pub bus_address: Option<String>,
+    /// Connect to the session bus for testing without registering with NetworkManager or verifying callers
+    pub session_bus: bool,
+    /// How long to wait for NetworkManager to become available at startup or `None` to wait indefinitely
+    pub wait_for_nm: Option<Duration>,
+    /// Interval in which the registration with NetworkManager is checked and renewed if necessary
+    pub health_check_interval: Option<Duration>,
+    /// How long resolving a single GetSecrets call may take or `None` to wait for it indefinitely
+    pub request_timeout: Option<Duration>,
+    /// User ids from which calls to the agent are accepted in addition to them coming from NetworkManager's bus name
+    pub allowed_uids: Vec<u32>,
+    /// Resolve and log requests as usual but always reply with `NoSecrets` and never write secrets back
+    pub dry_run: bool,
+    /// Maximum number of GetSecrets calls per connection within a minute or `None` for no limit
+    pub rate_limit: Option<u32>,
+    /// How GetSecrets calls which no entry provides secrets for are answered
+    pub no_match: NoMatchReply,
+    /// User to which the agent switches once it has connected to the bus
+    pub run_as: Option<RunAs>,
+    /// File to which every GetSecrets request is appended as a line of JSON
+    pub record_requests: Option<PathBuf>,
+}
+
+/// Replies to GetSecrets calls for which neither an entry nor the fallback provides secrets
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Hash, ValueEnum)]
+pub enum NoMatchReply {
+    /// An empty result, which some NetworkManager versions consider a satisfied request
+    #[default]
+    Empty,
+    /// The `NoSecrets` error so that NetworkManager asks other agents
+    NoSecrets,
+}
+
+/// A secret agent error together with the message that is replied to a method call
+///
+/// The error code is prepended to the message in brackets, e.g. `[file-missing] Could not …`.
+#[derive(Debug)]
+struct MethodError {
+    error: SecretAgentError,
+    code: ErrorCode,
+    message: String,
+}
+
+impl MethodError {
+    /// Construct the error reply for `error` named after the agent error in its chain
+    fn from_anyhow(error: &anyhow::Error) -> Self {
+        MethodError {
+            error: SecretAgentError::from_anyhow(error),
+            code: error_code(error),
+            message: format!("{error:#}"),
+        }
+    }
+}
+
+impl DBusError for MethodError {
+    fn create_reply(&self, call: &Header<'_>) -> zbus::Result<Message> {
+        Message::error(call, self.name())?.build(&(format!("[{}] {}", self.code, self.message),))
+    }
+
+    fn name(&self) -> ErrorName<'_> {
+        ErrorName::from_static_str_unchecked(self.error.dbus_name())
+    }
+
+    fn description(&self) -> Option<&str> {
+        Some(&self.message)
+    }
+}
+
+/// Access control for callers of the secret agent interface
+#[derive(Debug)]
+struct AccessControl {
+    known_nm_names: Mutex<Vec<String>>,
+    allowed_uids: Vec<u32>,
+}
+
 pub type NestedSettingsMap = HashMap<String, PropMap>;
 
+/// Arguments of a GetSecrets call
+type GetSecretsArgs = (NestedSettingsMap, OwnedObjectPath, String, Vec<String>, u32);
+
+/// Result of a GetSecrets call together with the keys of all returned secrets
+type SecretsResult = anyhow::Result<(NestedSettingsMap, Vec<String>)>;
+
+/// The object path under which the secret agent is served
+pub const AGENT_PATH: &str = "/org/freedesktop/NetworkManager/SecretAgent";
+
+/// Timeout for method calls to NetworkManager and the D-Bus daemon
+const METHOD_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Initial delay before retrying to connect or register
+const MIN_BACKOFF: Duration = Duration::from_millis(250);
+
+/// Maximum delay between retries to connect or register
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+/// Period within which the GetSecrets calls of a connection count against the rate limit
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+
+/// A GetSecrets call that is currently being resolved on a separate thread
+#[derive(Debug)]
+struct PendingRequest {
+    connection_path: OwnedObjectPath,
+    setting_name: String,
+    /// Wakes up the waiting method call so that it replies immediately when the request is canceled
+    cancel: oneshot::Sender<()>,
+    /// Set when the request is canceled so that the blocking task aborts slow sources
+    cancelled: Arc<AtomicBool>,
+}
+
+/// The currently active mapping which is replaced as a whole when the config is reloaded
+type SharedMapping = Arc<RwLock<Arc<MappingConfig>>>;
+
+/// Runtime state of the agent which is exposed as D-Bus properties for monitoring
+#[derive(Debug, Default)]
+struct AgentStatus {
+    /// Whether the agent is currently registered with NetworkManager
+    registered: AtomicBool,
+    /// Number of GetSecrets calls that were answered successfully
+    served_requests: AtomicU64,
+    /// Number of calls that were denied because they did not come from NetworkManager
+    denied_requests: AtomicU64,
+    /// Why the last reload of the config failed or `None` if it succeeded or the config was never reloaded
+    reload_error: Mutex<Option<String>>,
+}
+
 #[derive(Debug)]
 struct Server {
-    known_nm_names: Vec<String>,
-    mapping: MappingConfig,
-}
-
-pub fn run(mapping: MappingConfig) -> anyhow::Result<()> {
-    let mut cross = Crossroads::new();
-
-    let iface_token = cross.register("org.freedesktop.NetworkManager.SecretAgent", |b| {
-        // GetSecrets()
-        b.method(
-            "GetSecrets",
-            (
-                "connection",
-                "connection_path",
-                "setting_name",
-                "hints",
-                "flags",
-            ),
-            ("secrets",),
-            move |ctx: &mut DbusContext,
-                  obj: &mut Server,
-                  args: (NestedSettingsMap, Path, String, Vec<String>, u32)| {
-                tracing::debug!("got getSecrets() call");
-                verify_access(ctx, &obj.known_nm_names)?;
-                match get_secret(&mut obj.mapping, args) {
-                    Ok(secrets) => Ok((secrets,)),
-                    Err(e) => {
-                        tracing::error!(error = %e, "Could not execute getSecrets()");
-                        Err(MethodErr::failed(&e))
+    mapping: SharedMapping,
+    options: AgentOptions,
+    status: Arc<AgentStatus>,
+    /// Verification of callers or `None` on the session bus where there is no NetworkManager to verify against
+    access: Option<AccessControl>,
+    pending: Mutex<HashMap<u64, PendingRequest>>,
+    next_request_id: AtomicU64,
+    /// Times of the GetSecrets calls within the rate limit window by connection
+    recent_calls: Mutex<HashMap<String, VecDeque<Instant>>>,
+    /// Notified whenever the mapping was replaced so that the entries are checked against the connections again
+    mapping_reloaded: Notify,
+    /// Held while the config is reloaded so that concurrent reloads cannot replace a newer mapping with an older one
+    reloading: Mutex<()>,
+}
+
+impl Server {
+    /// A snapshot of the currently active mapping
+    fn mapping(&self) -> Arc<MappingConfig> {
+        self.mapping.read().unwrap().clone()
+    }
+
+    /// Load the config again and replace the active mapping with it if it is valid
+    ///
+    /// The new config is loaded, validated and preloaded completely before it replaces the active mapping in a single
+    /// step, so requests are either served from the old or the new config. The previous mapping stays active if the
+    /// new config can not be loaded or does not pass validation.
+    fn reload_config(&self) -> anyhow::Result<()> {
+        let _reloading = self.reloading.lock().unwrap();
+        // the reader helper must allow the files of the new config before it can be validated and preloaded
+        if let Err(e) = reader::refresh() {
+            tracing::warn!("Could not refresh the paths allowed by the reader helper: {e}");
+        }
+        let mapping = MappingConfig::load(
+            &self.options.config_paths,
+            self.options.profile.as_deref(),
+            &self.options.cli_entries,
+            &self.options.tags,
+            self.options.validation,
+        )?;
+        tracing::info!("Reloaded config with {} entries", mapping.entries().len());
+        *self.mapping.write().unwrap() = Arc::new(mapping);
+        self.mapping_reloaded.notify_one();
+        Ok(())
+    }
+
+    /// Verify that a call of the secret agent interface comes from NetworkManager
+    async fn verify_nm_caller(
+        &self,
+        conn: &Connection,
+        header: &Header<'_>,
+    ) -> Result<(), MethodError> {
+        let Some(access) = &self.access else {
+            return Ok(());
+        };
+        access.verify(conn, header).await.inspect_err(|_| {
+            self.status.denied_requests.fetch_add(1, Ordering::Relaxed);
+        })
+    }
+
+    /// Verify that a call of a management method comes from root
+    async fn verify_management_caller(
+        &self,
+        conn: &Connection,
+        header: &Header<'_>,
+    ) -> zbus::fdo::Result<()> {
+        if self.access.is_none() {
+            return Ok(());
+        }
+        verify_root_caller(conn, header).await.inspect_err(|_| {
+            self.status.denied_requests.fetch_add(1, Ordering::Relaxed);
+        })
+    }
+
+    /// Record a GetSecrets call for `connection` and reject it if the connection exceeded the configured rate limit
+    ///
+    /// Rejected calls do not count against the limit so that a connection can be served again once its earlier calls
+    /// have left the window.
+    fn check_rate_limit(&self, connection: &str) -> Result<(), MethodError> {
+        let Some(limit) = self.options.rate_limit else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        let mut recent_calls = self.recent_calls.lock().unwrap();
+        // connections without calls in the window are forgotten so that the map does not grow indefinitely
+        recent_calls.retain(|_, calls| {
+            while calls
+                .front()
+                .is_some_and(|&time| now.duration_since(time) >= RATE_LIMIT_WINDOW)
+            {
+                calls.pop_front();
+            }
+            !calls.is_empty()
+        });
+        let calls = recent_calls.entry(connection.to_owned()).or_default();
+        if calls.len() >= limit as usize {
+            let retry_after = RATE_LIMIT_WINDOW - now.duration_since(calls[0]);
+            tracing::warn!(
+                connectionUuid = connection,
+                "Rejecting GetSecrets() call because the connection exceeded the rate limit"
+            );
+            return Err(MethodError {
+                code: ErrorCode::RateLimited,
+                ..SecretAgentError::Failed.method_err(format!(
+                    "Connection {connection} exceeded the rate limit of {limit} requests per minute; retry in {}s",
+                    retry_after.as_secs() + 1
+                ))
+            });
+        }
+        calls.push_back(now);
+        Ok(())
+    }
+
+    /// Resolve the GetSecrets call with the request id `id` on a separate thread so that slow sources don't hold
+    /// up other requests
+    async fn get_secrets(&self, conn: &Connection, id: u64, args: GetSecretsArgs) -> SecretsResult {
+        self.authorize(conn, &args).await?;
+        let (cancel, canceled) = oneshot::channel();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        self.pending.lock().unwrap().insert(
+            id,
+            PendingRequest {
+                connection_path: args.1.clone(),
+                setting_name: args.2.clone(),
+                cancel,
+                cancelled: cancelled.clone(),
+            },
+        );
+
+        let mapping = self.mapping();
+        let task = {
+            let cancelled = cancelled.clone();
+            let span = tracing::Span::current();
+            let no_match = self.options.no_match;
+            let dry_run = self.options.dry_run;
+            let record_requests = self.options.record_requests.clone();
+            spawn_thread(move || {
+                span.in_scope(|| {
+                    if let Some(path) = &record_requests {
+                        let (connection, _, setting_name, hints, flags) = &args;
+                        // requests which cannot be decoded are not recorded since they could not be replayed anyway
+                        if let Ok(request) = build_request(connection, setting_name, hints, *flags)
+                        {
+                            if let Err(e) = record_request(path, &request) {
+                                tracing::warn!(
+                                    "Could not record request to {}: {e:#}",
+                                    path.display()
+                                );
+                            }
+                        }
                     }
+                    get_secret(&mapping, args, no_match, dry_run, &cancelled)
+                })
+            })
+        };
+        let deadline = async {
+            match self.options.request_timeout {
+                Some(timeout) => time::sleep(timeout).await,
+                None => std::future::pending().await,
+            }
+        };
+        let result = tokio::select! {
+            result = task => result.unwrap_or_else(|e| {
+                Err(e.context("Resolving the request failed unexpectedly"))
+            }),
+            // the blocking task notices the cancellation by itself and its result is discarded
+            _ = canceled => Err(anyhow::Error::new(SecretAgentError::AgentCanceled)
+                .context("The request was canceled by NetworkManager")),
+            _ = deadline => {
+                cancelled.store(true, Ordering::Relaxed);
+                Err(anyhow::Error::new(SecretAgentError::AgentCanceled).context(RequestTimeout(
+                    self.options.request_timeout.unwrap_or_default(),
+                )))
+            }
+        };
+        self.pending.lock().unwrap().remove(&id);
+        result
+    }
+
+    /// Check the polkit actions of all entries that match a user-requested GetSecrets call
+    ///
+    /// Calls whose connection cannot be decoded are passed through since resolving them reports the problem anyway.
+    async fn authorize(&self, conn: &Connection, args: &GetSecretsArgs) -> anyhow::Result<()> {
+        let (connection, _, setting_name, hints, flags) = args;
+        let Ok(request) = build_request(connection, setting_name, hints, *flags) else {
+            return Ok(());
+        };
+        if !request.user_requested {
+            return Ok(());
+        }
+        let mapping = self.mapping();
+        let actions = mapping
+            .matching_entries(&request)
+            .filter_map(|(_, entry)| entry.polkit_action())
+            .collect::<BTreeSet<_>>();
+        for action in actions {
+            let authorized = polkit::check_authorization(conn, action, &request)
+                .await
+                .map_err(|e| {
+                    anyhow::Error::new(SecretAgentError::PermissionDenied)
+                        .context(format!("{e:#}"))
+                        .context(format!("Could not check polkit action {action}"))
+                })?;
+            if !authorized {
+                return Err(
+                    anyhow::Error::new(SecretAgentError::PermissionDenied).context(format!(
+                    "The user of the active session is not authorized for polkit action {action}"
+                )),
+                );
+            }
+            tracing::info!(action, "Authorized user-requested activation via polkit");
+        }
+        Ok(())
+    }
+
+    /// Cancel all pending GetSecrets calls for the given connection and setting
+    fn cancel_get_secrets(&self, connection_path: &ObjectPath<'_>, setting_name: &str) {
+        let canceled = self
+            .pending
+            .lock()
+            .unwrap()
+            .extract_if(|_, request| {
+                request.connection_path.as_ref() == *connection_path
+                    && request.setting_name == setting_name
+            })
+            .map(|(_, request)| request)
+            .collect::<Vec<_>>();
+        let canceled_any = !canceled.is_empty();
+        for request in canceled {
+            request.cancelled.store(true, Ordering::Relaxed);
+            let _ = request.cancel.send(());
+        }
+        if canceled_any {
+            tracing::info!(%connection_path, setting_name, "Canceled pending GetSecrets() call");
+        } else {
+            tracing::debug!(
+                %connection_path,
+                setting_name,
+                "No pending GetSecrets() call matches the canceled one"
+            );
+        }
+    }
+}
+
+/// The interface which NetworkManager calls on secret agents
+struct SecretAgent(Arc<Server>);
+
+#[interface(name = "org.freedesktop.NetworkManager.SecretAgent")]
+impl SecretAgent {
+    #[zbus(out_args("secrets"))]
+    #[allow(clippy::too_many_arguments)]
+    async fn get_secrets(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: Header<'_>,
+        connection: NestedSettingsMap,
+        connection_path: OwnedObjectPath,
+        setting_name: String,
+        hints: Vec<String>,
+        flags: u32,
+    ) -> Result<NestedSettingsMap, MethodError> {
+        // all log messages of the request, including those of the blocking task resolving it, carry its id
+        let request_id = self.0.next_request_id.fetch_add(1, Ordering::Relaxed);
+        let span = tracing::info_span!("request", requestId = request_id);
+        async move {
+            tracing::debug!("got getSecrets() call");
+            self.0.verify_nm_caller(conn, &header).await?;
+            let connection_property = |key: &str| {
+                connection
+                    .get("connection")
+                    .and_then(|connection| connection.get(key))
+                    .and_then(|value| value.downcast_ref::<&str>().ok())
+                    .unwrap_or_default()
+                    .to_owned()
+            };
+            let connection_id = connection_property("id");
+            let connection_uuid = connection_property("uuid");
+            match connection_uuid.is_empty() {
+                true => self.0.check_rate_limit(connection_path.as_str())?,
+                false => self.0.check_rate_limit(&connection_uuid)?,
+            }
+
+            let args = (
+                connection,
+                connection_path,
+                setting_name.clone(),
+                hints,
+                flags,
+            );
+            match self.0.get_secrets(conn, request_id, args).await {
+                Ok((_, keys)) if self.0.options.dry_run => {
+                    let names = keys
+                        .iter()
+                        .map(|key| format!("{setting_name}.{key}"))
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    tracing::info!(
+                        "Dry run: replying NoSecrets instead of returning secret values for [{names}]"
+                    );
+                    Err(SecretAgentError::NoSecrets.method_err("The agent is running in dry-run mode"))
                 }
-            },
+                Ok((secrets, keys)) => {
+                    self.0
+                        .status
+                        .served_requests
+                        .fetch_add(1, Ordering::Relaxed);
+                    if !keys.is_empty() {
+                        let caller = header.sender().map(|i| i.to_string()).unwrap_or_default();
+                        let emitted = match SignalEmitter::new(conn, AGENT_PATH) {
+                            Ok(emitter) => {
+                                AgentApi::secrets_served(
+                                    &emitter,
+                                    &connection_uuid,
+                                    &setting_name,
+                                    &keys,
+                                    &caller,
+                                )
+                                .await
+                            }
+                            Err(e) => Err(e),
+                        };
+                        if let Err(e) = emitted {
+                            tracing::warn!("Could not emit SecretsServed signal: {e}");
+                        }
+                        self.0.mapping().fire_event(
+                            Event::SecretsServed,
+                            serde_json::json!({
+                                "connection_uuid": connection_uuid,
+                                "setting_name": setting_name,
+                                "keys": keys,
+                            }),
+                        );
+                    }
+                    Ok(secrets)
+                }
+                Err(e) => {
+                    let reply = MethodError::from_anyhow(&e);
+                    // having no secrets for a request is expected since NetworkManager asks all agents
+                    if reply.error == SecretAgentError::NoSecrets {
+                        tracing::info!(errorCode = reply.code.as_str(), "Replying NoSecrets: {e:#}");
+                    } else if reply.error != SecretAgentError::AgentCanceled
+                        || e.is::<RequestTimeout>()
+                    {
+                        tracing::error!(
+                            error = format!("{e:#}"),
+                            errorName = reply.error.dbus_name(),
+                            errorCode = reply.code.as_str(),
+                            "Could not execute getSecrets()"
+                        );
+                        let emitted = match SignalEmitter::new(conn, AGENT_PATH) {
+                            Ok(emitter) => {
+                                AgentApi::request_failed(
+                                    &emitter,
+                                    redact::connection_id(&connection_id).as_ref(),
+                                    &connection_uuid,
+                                    &setting_name,
+                                    reply.code.as_str(),
+                                    &reply.message,
+                                )
+                                .await
+                            }
+                            Err(e) => Err(e),
+                        };
+                        if let Err(e) = emitted {
+                            tracing::warn!("Could not emit RequestFailed signal: {e}");
+                        }
+                        self.0.mapping().fire_event(
+                            Event::RequestFailed,
+                            serde_json::json!({
+                                "connection_id": redact::connection_id(&connection_id),
+                                "connection_uuid": connection_uuid,
+                                "setting_name": setting_name,
+                                "error_code": reply.code.as_str(),
+                                "message": reply.message,
+                            }),
+                        );
+                    }
+                    Err(reply)
+                }
+            }
+        }
+        .instrument(span)
+        .await
+    }
+
+    async fn cancel_get_secrets(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: Header<'_>,
+        connection_path: OwnedObjectPath,
+        setting_name: String,
+    ) -> Result<(), MethodError> {
+        tracing::debug!(%connection_path, setting_name, "got CancelGetSecrets() call");
+        self.0.verify_nm_caller(conn, &header).await?;
+        self.0.cancel_get_secrets(&connection_path, &setting_name);
+        Ok(())
+    }
+
+    async fn save_secrets(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: Header<'_>,
+        connection: NestedSettingsMap,
+        connection_path: OwnedObjectPath,
+    ) -> Result<(), MethodError> {
+        tracing::debug!(%connection_path, "got SaveSecrets() call");
+        self.0.verify_nm_caller(conn, &header).await?;
+        if self.0.options.dry_run {
+            tracing::info!("Dry run: not saving secrets");
+            return Ok(());
+        }
+        let mapping = self.0.mapping();
+        let result = spawn_thread(move || save_secrets(&mapping, &connection))
+            .await
+            .unwrap_or_else(Err);
+        result.map_err(|e| {
+            let reply = MethodError::from_anyhow(&e);
+            tracing::error!(
+                error = format!("{e:#}"),
+                errorName = reply.error.dbus_name(),
+                errorCode = reply.code.as_str(),
+                "Could not execute SaveSecrets()"
+            );
+            reply
+        })
+    }
+
+    async fn delete_secrets(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: Header<'_>,
+        #[allow(unused_variables)] connection: NestedSettingsMap,
+        connection_path: OwnedObjectPath,
+    ) -> Result<(), MethodError> {
+        tracing::debug!(%connection_path, "got DeleteSecrets() call");
+        self.0.verify_nm_caller(conn, &header).await?;
+        Ok(())
+    }
+}
+
+/// The interface through which the agent exposes its own status and functionality
+struct AgentApi(Arc<Server>);
+
+#[interface(name = "sh.lly.NmFileSecretAgent")]
+impl AgentApi {
+    #[zbus(property(emits_changed_signal = "const"))]
+    async fn version(&self) -> String {
+        env!("CARGO_PKG_VERSION").to_owned()
+    }
+
+    #[zbus(property(emits_changed_signal = "false"))]
+    async fn registered(&self) -> bool {
+        self.0.status.registered.load(Ordering::Relaxed)
+    }
+
+    #[zbus(property(emits_changed_signal = "false"))]
+    async fn config_paths(&self) -> Vec<String> {
+        self.0
+            .options
+            .config_paths
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect()
+    }
+
+    #[zbus(property(emits_changed_signal = "false"))]
+    async fn entry_count(&self) -> u32 {
+        self.0.mapping().entries().len() as u32
+    }
+
+    #[zbus(property(emits_changed_signal = "false"))]
+    async fn served_requests(&self) -> u64 {
+        self.0.status.served_requests.load(Ordering::Relaxed)
+    }
+
+    #[zbus(property(emits_changed_signal = "false"))]
+    async fn denied_requests(&self) -> u64 {
+        self.0.status.denied_requests.load(Ordering::Relaxed)
+    }
+
+    #[zbus(property(emits_changed_signal = "false"))]
+    async fn last_reload_error(&self) -> String {
+        self.0
+            .status
+            .reload_error
+            .lock()
+            .unwrap()
+            .clone()
+            .unwrap_or_default()
+    }
+
+    async fn reload_config(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> zbus::fdo::Result<()> {
+        tracing::debug!("got ReloadConfig() call");
+        self.0.verify_management_caller(conn, &header).await?;
+        reload_config(&self.0).await.map_err(|e| {
+            let code = error_code(&e);
+            tracing::error!(
+                errorCode = code.as_str(),
+                "Could not reload config; keeping the previous one: {e:#}"
+            );
+            zbus::fdo::Error::Failed(format!("[{code}] {e:#}"))
+        })
+    }
+
+    #[zbus(out_args("entries"))]
+    #[allow(clippy::too_many_arguments)]
+    async fn resolve_test(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: Header<'_>,
+        id: String,
+        uuid: String,
+        r#type: String,
+        iface: String,
+        setting: String,
+    ) -> zbus::fdo::Result<Vec<HashMap<String, Value<'static>>>> {
+        tracing::debug!("got ResolveTest() call");
+        self.0.verify_management_caller(conn, &header).await?;
+        let request = SecretRequest {
+            conn_id: id,
+            conn_uuid: uuid,
+            conn_type: r#type,
+            iface_name: iface,
+            setting_name: setting,
+            ..Default::default()
+        };
+        let mapping = self.0.mapping();
+        spawn_thread(move || resolve_test(&mapping, &request))
+            .await
+            .map_err(|e| zbus::fdo::Error::Failed(format!("{e:#}")))
+    }
+
+    #[zbus(out_args("entries"))]
+    async fn get_stats(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> zbus::fdo::Result<Vec<HashMap<String, Value<'static>>>> {
+        tracing::debug!("got GetStats() call");
+        self.0.verify_management_caller(conn, &header).await?;
+        Ok(entry_stats(&self.0.mapping()))
+    }
+
+    #[zbus(signal)]
+    async fn secrets_served(
+        emitter: &SignalEmitter<'_>,
+        connection_uuid: &str,
+        setting_name: &str,
+        keys: &[String],
+        caller: &str,
+    ) -> zbus::Result<()>;
+
+    /// Emitted when a request for secrets failed for another reason than the agent having no secrets for it
+    #[zbus(signal)]
+    async fn request_failed(
+        emitter: &SignalEmitter<'_>,
+        connection_id: &str,
+        connection_uuid: &str,
+        setting_name: &str,
+        error_code: &str,
+        message: &str,
+    ) -> zbus::Result<()>;
+}
+
+/// Client side of the [`AgentApi`] through which subcommands talk to a running agent
+#[zbus::proxy(
+    interface = "sh.lly.NmFileSecretAgent",
+    default_path = "/org/freedesktop/NetworkManager/SecretAgent"
+)]
+pub trait FileSecretAgent {
+    #[zbus(property)]
+    fn version(&self) -> zbus::Result<String>;
+
+    /// Usage counters of all entries
+    fn get_stats(&self) -> zbus::Result<Vec<HashMap<String, OwnedValue>>>;
+}
+
+/// Serve the secret agent until a fatal error occurs
+///
+/// When the connection to the bus is lost, e.g. because the D-Bus daemon was restarted, the agent reconnects with
+/// exponential backoff and registers with NetworkManager again.
+pub async fn run(mapping: MappingConfig, options: AgentOptions) -> anyhow::Result<()> {
+    let mapping = SharedMapping::new(RwLock::new(Arc::new(mapping)));
+    let status = Arc::new(AgentStatus::default());
+    // the handlers are installed once so that the signals never terminate the agent, not even while it is reconnecting
+    let mut signals = Signals {
+        hangup: signal(SignalKind::hangup()).context("Could not install SIGHUP handler")?,
+        user_defined1: signal(SignalKind::user_defined1())
+            .context("Could not install SIGUSR1 handler")?,
+    };
+    let mut conn = connect(options.bus_address.as_deref(), options.session_bus).await?;
+    if let Some(run_as) = &options.run_as {
+        privileges::drop_privileges(run_as)
+            .with_context(|| format!("Could not switch to user {}", run_as.user))?;
+        tracing::info!("Switched to user {}", run_as.user);
+    }
+    loop {
+        let e = serve(conn, &mapping, &options, &status, &mut signals).await?;
+        let was_registered = status.registered.swap(false, Ordering::Relaxed);
+        tracing::warn!("Lost connection to the D-Bus daemon; reconnecting: {e:#}");
+        if was_registered {
+            mapping.read().unwrap().fire_event(
+                Event::RegistrationLost,
+                serde_json::json!({ "reason": format!("Lost connection to the D-Bus daemon: {e:#}") }),
+            );
+        }
+        systemd::notify("STATUS=Reconnecting to the D-Bus daemon");
+
+        let mut backoff = MIN_BACKOFF;
+        conn = loop {
+            time::sleep(backoff).await;
+            match connect(options.bus_address.as_deref(), options.session_bus).await {
+                Ok(conn) => break conn,
+                Err(e) => tracing::debug!("Could not reconnect to the D-Bus daemon: {e:#}"),
+            }
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        };
+    }
+}
+
+/// Register the agent on `conn` and serve its API until the connection is lost
+///
+/// Errors while setting up the agent are returned as `Err` while losing the connection afterwards is returned as `Ok`
+/// with the error that caused it so that the caller can reconnect.
+async fn serve(
+    conn: Connection,
+    mapping: &SharedMapping,
+    options: &AgentOptions,
+    status: &Arc<AgentStatus>,
+    signals: &mut Signals,
+) -> anyhow::Result<anyhow::Error> {
+    let server = Arc::new(Server {
+        mapping: mapping.clone(),
+        options: options.clone(),
+        status: status.clone(),
+        // there is no NetworkManager on the session bus so there is nothing to verify callers against
+        access: (!options.session_bus).then(|| AccessControl {
+            known_nm_names: Mutex::default(),
+            allowed_uids: options.allowed_uids.clone(),
+        }),
+        pending: Mutex::default(),
+        next_request_id: AtomicU64::new(0),
+        recent_calls: Mutex::default(),
+        mapping_reloaded: Notify::new(),
+        reloading: Mutex::default(),
+    });
+    conn.object_server()
+        .at(AGENT_PATH, SecretAgent(server.clone()))
+        .await
+        .context("Could not serve the secret agent interface")?;
+    conn.object_server()
+        .at(AGENT_PATH, AgentApi(server.clone()))
+        .await
+        .context("Could not serve the agent status interface")?;
+
+    let unique_name = conn
+        .unique_name()
+        .map(|name| name.to_string())
+        .unwrap_or_default();
+    tracing::debug!("Connected to bus as {unique_name}");
+
+    if let Some(access) = &server.access {
+        *access.known_nm_names.lock().unwrap() = wait_for_registration(&conn, options).await?;
+    }
+    status
+        .registered
+        .store(server.access.is_some(), Ordering::Relaxed);
+
+    systemd::notify("READY=1\nSTATUS=Serving secret agent API");
+    match options.session_bus {
+        true => tracing::info!(
+            "Serving D-Bus API on the session bus as {unique_name} without NetworkManager registration"
+        ),
+        false => tracing::info!("Registered with NetworkManager; now serving D-Bus API"),
+    }
+    if server.access.is_some() {
+        tokio::spawn(watch_connections(conn.clone(), server.clone()));
+    }
+    // systemd recommends sending watchdog notifications at half the configured interval
+    let mut watchdog = systemd::watchdog_interval().map(|interval| periodic(interval / 2));
+    let mut health_check = options
+        .health_check_interval
+        .filter(|_| !options.session_bus)
+        .map(periodic);
+    // method calls are dispatched by zbus in the background so this loop only does periodic housekeeping
+    loop {
+        tokio::select! {
+            _ = conn.closed() => {
+                return Ok(anyhow::anyhow!("The connection was closed by the D-Bus daemon"));
+            }
+            _ = tick(&mut health_check) => {
+                if check_registration(&conn, options).await {
+                    server.mapping().fire_event(
+                        Event::RegistrationLost,
+                        serde_json::json!({ "reason": "NetworkManager dropped the registration" }),
+                    );
+                }
+            }
+            _ = tick(&mut watchdog) => systemd::notify("WATCHDOG=1"),
+            _ = signals.hangup.recv() => {
+                tracing::info!("Received SIGHUP; reloading config");
+                systemd::notify_reloading();
+                if let Err(e) = reload_config(&server).await {
+                    tracing::error!(
+                        errorCode = error_code(&e).as_str(),
+                        "Could not reload config; keeping the previous one: {e:#}"
+                    );
+                }
+                systemd::notify("READY=1");
+            }
+            _ = signals.user_defined1.recv() => log_stats(&server.mapping()),
+        }
+    }
+}
+
+/// Handlers of the signals by which the agent is controlled
+struct Signals {
+    /// Reload the config
+    hangup: Signal,
+    /// Log the usage statistics of all entries
+    user_defined1: Signal,
+}
+
+/// Log the usage counters of all entries
+fn log_stats(mapping: &MappingConfig) {
+    tracing::info!(
+        "Usage statistics of {} config entries since the config was loaded:",
+        mapping.entries().len()
+    );
+    for (index, entry) in mapping.entries().iter().enumerate() {
+        let stats = entry.stats();
+        tracing::info!(
+            index,
+            id = entry.id(),
+            key = entry.key(),
+            matched = stats.matched.load(Ordering::Relaxed),
+            served = stats.served.load(Ordering::Relaxed),
+            readFailures = stats.read_failures.load(Ordering::Relaxed),
+            lastUsed = stats.last_used.load(Ordering::Relaxed),
+            "Config entry {index}"
         );
+    }
+}
 
-        // CancelGetSecrets()
-        b.method(
-            "CancelGetSecrets",
-            ("connection_path", "setting_name"),
-            (),
-            move |_ctx: &mut DbusContext,
-                  _obj: &mut Server,
-                  (connection_path, setting_name): (Path, String)| {
-                tracing::debug!(%connection_path, setting_name, "got CancelGetSecrets() call");
-                Ok(())
-            },
+/// Keep track of which entries match any connection profile of NetworkManager until the connection is closed
+///
+/// Entries that match no profile, e.g. because of a typo in their uuid or an outdated id, are logged at startup and
+/// after every reload. Afterwards, NetworkManager's signals are used to log whenever an entry stops matching because
+/// a profile was removed or starts matching because one was added. Nothing is rejected since profiles can be created
+/// at any time.
+async fn watch_connections(conn: Connection, server: Arc<Server>) {
+    if let Err(e) = track_connections(&conn, &server).await {
+        tracing::warn!(
+            "Could not check config entries against the connections of NetworkManager: {e:#}"
         );
+    }
+}
 
-        // SaveSecrets()
-        b.method(
-            "SaveSecrets",
-            ("connection", "connection_path"),
-            (),
-            move |_ctx: &mut DbusContext,
-                  _obj: &mut Server,
-                  (_connection, connection_path): (NestedSettingsMap, Path)| {
-                tracing::debug!(%connection_path, "got SaveSecrets() call");
-                Ok(())
-            },
+async fn track_connections(conn: &Connection, server: &Server) -> anyhow::Result<()> {
+    let settings = nm_settings::SettingsProxy::new(conn).await?;
+    // subscribe before listing the profiles so that no change in between is missed
+    let mut added = settings.receive_new_connection().await?;
+    let mut removed = settings.receive_connection_removed().await?;
+    let mut profiles = nm_settings::list_profiles(conn)
+        .await?
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+    let mut mapping = server.mapping();
+    let mut unmatchable = nm_settings::unmatchable_entries(&mapping, profiles.values());
+    log_unmatchable_entries(&mapping, &unmatchable, profiles.len());
+
+    loop {
+        let change = tokio::select! {
+            signal = added.next() => {
+                let Some(signal) = signal else { return Ok(()) };
+                let path = signal.args()?.connection;
+                let profile = match nm_settings::get_profile(conn, &path).await {
+                    Ok(profile) => profile,
+                    // the profile may already be gone again
+                    Err(e) => {
+                        tracing::debug!("Could not query added connection: {e:#}");
+                        continue;
+                    }
+                };
+                let change = format!("connection {:?} was added", redact::connection_id(nm_settings::profile_id(&profile)));
+                profiles.insert(path, profile);
+                change
+            }
+            signal = removed.next() => {
+                let Some(signal) = signal else { return Ok(()) };
+                let Some(profile) = profiles.remove(&signal.args()?.connection) else {
+                    continue;
+                };
+                format!("connection {:?} was removed", redact::connection_id(nm_settings::profile_id(&profile)))
+            }
+            () = server.mapping_reloaded.notified() => {
+                mapping = server.mapping();
+                unmatchable = nm_settings::unmatchable_entries(&mapping, profiles.values());
+                log_unmatchable_entries(&mapping, &unmatchable, profiles.len());
+                continue;
+            }
+        };
+        tracing::debug!("NetworkManager reported that {change}");
+
+        let previous = std::mem::replace(
+            &mut unmatchable,
+            nm_settings::unmatchable_entries(&mapping, profiles.values()),
         );
+        for &index in unmatchable.difference(&previous) {
+            let entry = &mapping.entries()[index];
+            tracing::warn!(
+                index,
+                id = entry.id(),
+                key = entry.key(),
+                "Config entry {index} no longer matches any connection since {change}"
+            );
+        }
+        for &index in previous.difference(&unmatchable) {
+            let entry = &mapping.entries()[index];
+            tracing::info!(
+                index,
+                id = entry.id(),
+                key = entry.key(),
+                "Config entry {index} matches a connection again since {change}"
+            );
+        }
+    }
+}
 
-        // DeleteSecrets()
-        b.method(
-            "DeleteSecrets",
-            ("connection", "connection_path"),
-            (),
-            move |_ctx: &mut DbusContext,
-                  _obj: &mut Server,
-                  (_connection, connection_path): (NestedSettingsMap, Path)| {
-                tracing::debug!(%connection_path, "got DeleteSecrets() call");
-                Ok(())
-            },
+/// Log a warning for each of the `unmatchable` entries of `mapping`
+fn log_unmatchable_entries(
+    mapping: &MappingConfig,
+    unmatchable: &BTreeSet<usize>,
+    profile_count: usize,
+) {
+    for &index in unmatchable {
+        let entry = &mapping.entries()[index];
+        tracing::warn!(
+            index,
+            id = entry.id(),
+            key = entry.key(),
+            "Config entry {index} does not match any of the {profile_count} connections of NetworkManager: {}",
+            nm_settings::describe_criteria(entry)
         );
-    });
+    }
+    if unmatchable.is_empty() {
+        tracing::debug!(
+            "All config entries match at least one of the {profile_count} connections of NetworkManager"
+        );
+    }
+}
+
+/// Reload the config of `server` on a separate thread
+///
+/// The registration with NetworkManager is not touched and the active config is kept if the new one is invalid. The
+/// outcome is recorded for the `LastReloadError` property and in the status shown by the service manager.
+async fn reload_config(server: &Arc<Server>) -> anyhow::Result<()> {
+    let result = {
+        let server = server.clone();
+        spawn_thread(move || server.reload_config())
+            .await
+            .unwrap_or_else(Err)
+    };
+    let error = result
+        .as_ref()
+        .err()
+        .map(|e| format!("[{}] {e:#}", error_code(e)));
+    let mapping = server.mapping();
+    match &error {
+        None => {
+            systemd::notify("STATUS=Serving secret agent API");
+            mapping.fire_event(
+                Event::ConfigReloaded,
+                serde_json::json!({ "entry_count": mapping.entries().len() }),
+            );
+        }
+        Some(error) => {
+            systemd::notify(&format!(
+                "STATUS=Serving the previous config since reloading failed: {error}"
+            ));
+            mapping.fire_event(Event::ReloadFailed, serde_json::json!({ "message": error }));
+        }
+    }
+    *server.status.reload_error.lock().unwrap() = error;
+    result
+}
 
-    tracing::debug!("Connecting to system bus");
-    let conn = Connection::new_system().context("Could not connect to the system D-Bus daemon")?;
-    tracing::debug!("Connected to bus as {}", conn.unique_name());
+/// Run the blocking function `f` on a new thread spawned from the current one
+///
+/// Unlike the threads of tokio's blocking pool, which may have been spawned before the agent switched users, the new
+/// thread is guaranteed to inherit the capabilities of the thread that serves the agent.
+async fn spawn_thread<T: Send + 'static>(
+    f: impl FnOnce() -> T + Send + 'static,
+) -> anyhow::Result<T> {
+    let (sender, receiver) = oneshot::channel();
+    std::thread::Builder::new()
+        .spawn(move || {
+            let _ = sender.send(f());
+        })
+        .context("Could not spawn a thread")?;
+    receiver.await.context("The thread panicked")
+}
 
-    let known_nm_names = get_nm_names(&conn)?;
-    register_agent(&conn)?;
+/// An interval whose first tick happens after `period` has elapsed instead of immediately
+fn periodic(period: Duration) -> Interval {
+    time::interval_at(Instant::now() + period, period)
+}
 
-    cross.insert(
-        "/org/freedesktop/NetworkManager/SecretAgent",
-        &[iface_token],
-        Server {
-            known_nm_names,
-            mapping,
-        },
-    );
+/// Wait for the next tick of `interval` or forever if there is none
+async fn tick(interval: &mut Option<Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Connect to the bus on which NetworkManager operates
+///
+/// This is the bus at `bus_address` if given or else the session bus if `session_bus` is set and the system bus
+/// otherwise.
+pub async fn connect(bus_address: Option<&str>, session_bus: bool) -> anyhow::Result<Connection> {
+    async fn build(builder: zbus::Result<Builder<'static>>) -> zbus::Result<Connection> {
+        builder?.method_timeout(METHOD_TIMEOUT).build().await
+    }
+    match bus_address {
+        None if session_bus => {
+            tracing::debug!("Connecting to session bus");
+            build(Builder::session()).await.context(Error::new(
+                ErrorCode::BusUnavailable,
+                "Could not connect to the session D-Bus daemon",
+            ))
+        }
+        None => {
+            tracing::debug!("Connecting to system bus");
+            build(Builder::system()).await.context(Error::new(
+                ErrorCode::BusUnavailable,
+                "Could not connect to the system D-Bus daemon",
+            ))
+        }
+        Some(address) => {
+            tracing::debug!("Connecting to bus at {address}");
+            build(Builder::address(address)).await.with_context(|| {
+                Error::new(
+                    ErrorCode::BusUnavailable,
+                    format!("Could not connect to the D-Bus daemon at {address}"),
+                )
+            })
+        }
+    }
+}
 
-    tracing::info!("Registered with NetworkManager; now serving D-Bus API");
-    cross.serve(&conn).context("Could not run D-Bus service")?;
+/// Register with NetworkManager, retrying with exponential backoff while it is not available yet
+///
+/// NetworkManager might not be on the bus yet during early boot so instead of failing immediately, registration is
+/// retried until it succeeds or `options.wait_for_nm` has elapsed.
+/// Returns the bus names of NetworkManager against which callers are verified.
+async fn wait_for_registration(
+    conn: &Connection,
+    options: &AgentOptions,
+) -> anyhow::Result<Vec<String>> {
+    let start = Instant::now();
+    let mut backoff = MIN_BACKOFF;
+    loop {
+        let result = async {
+            let known_nm_names = get_nm_names(conn).await?;
+            register_agent(conn, options).await?;
+            anyhow::Ok(known_nm_names)
+        };
+        let e = match result.await {
+            Ok(known_nm_names) => return Ok(known_nm_names),
+            Err(e) => e,
+        };
 
-    unreachable!();
+        let elapsed = start.elapsed();
+        if options
+            .wait_for_nm
+            .is_some_and(|timeout| elapsed >= timeout)
+        {
+            return Err(e.context(Error::new(
+                ErrorCode::NmUnreachable,
+                format!(
+                    "NetworkManager did not become available within {}s",
+                    elapsed.as_secs()
+                ),
+            )));
+        }
+        if backoff == MIN_BACKOFF {
+            tracing::info!("Waiting for NetworkManager to become available: {e:#}");
+        } else {
+            tracing::debug!("NetworkManager is still not available: {e:#}");
+        }
+        let delay = match options.wait_for_nm {
+            Some(timeout) => backoff.min(timeout.saturating_sub(elapsed)),
+            None => backoff,
+        };
+        time::sleep(delay).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
 }
 
-fn register_agent(conn: &Connection) -> anyhow::Result<()> {
+async fn register_agent(conn: &Connection, options: &AgentOptions) -> anyhow::Result<()> {
     tracing::debug!("Registering secret agent with NetworkManager");
-    let proxy = conn.with_proxy(
-        "org.freedesktop.NetworkManager",
-        "/org/freedesktop/NetworkManager/AgentManager",
-        Duration::from_secs(1),
-    );
-    proxy
-        .register_with_capabilities("nm-file-secret-agent", SecretAgentCapabilities::None as u32)
-        .context("Could not register as secret agent with NetworkManager")?;
+    send_registration(conn, options).await.context(Error::new(
+        ErrorCode::NmUnreachable,
+        "Could not register as secret agent with NetworkManager",
+    ))?;
     Ok(())
 }
 
-fn get_nm_names(conn: &Connection) -> anyhow::Result<Vec<String>> {
+async fn send_registration(conn: &Connection, options: &AgentOptions) -> zbus::Result<()> {
+    let proxy = AgentManagerProxy::new(conn).await?;
+    let capabilities = match options.vpn_hints {
+        true => SecretAgentCapabilities::VpnHints,
+        false => SecretAgentCapabilities::None,
+    };
+    proxy
+        .register_with_capabilities("nm-file-secret-agent", capabilities as u32)
+        .await
+}
+
+/// Verify that NetworkManager still knows about this agent and register again if it does not
+///
+/// NetworkManager refuses to register the same agent twice so a successful registration means that the previous one
+/// had been lost, e.g. after suspend/resume or a glitch of the bus. Returns whether that was the case.
+async fn check_registration(conn: &Connection, options: &AgentOptions) -> bool {
+    tracing::debug!("Checking that the agent is still registered with NetworkManager");
+    match send_registration(conn, options).await {
+        Ok(()) => {
+            tracing::warn!(
+                "NetworkManager had dropped the secret agent registration; registered again"
+            );
+            true
+        }
+        Err(zbus::Error::MethodError(name, _, _))
+            if name.as_str() == "org.freedesktop.NetworkManager.AgentManager.PermissionDenied" =>
+        {
+            tracing::debug!("Secret agent registration is still active");
+            false
+        }
+        Err(e) => {
+            tracing::warn!("Could not check secret agent registration with NetworkManager: {e}");
+            false
+        }
+    }
+}
+
+async fn get_nm_names(conn: &Connection) -> anyhow::Result<Vec<String>> {
     tracing::debug!(
         "Querying DBus bus manager for all names that NetworkManager operates on the bus"
     );
-    let proxy = conn.with_proxy(
-        "org.freedesktop.DBus",
-        "/org/freedesktop/DBus",
-        Duration::from_secs(5),
-    );
-    let name = "org.freedesktop.NetworkManager".to_string();
-    let name_owner = proxy
-        .get_name_owner(&name)
-        .context("Could not query owner of name org.freedesktop.NetworkManager")?;
+    let name = "org.freedesktop.NetworkManager";
+    let name_owner = async {
+        DBusProxy::new(conn)
+            .await?
+            .get_name_owner(WellKnownName::from_static_str_unchecked(name).into())
+            .await
+    }
+    .await
+    .context("Could not query owner of name org.freedesktop.NetworkManager")?;
 
-    Ok(vec![name_owner, name])
+    Ok(vec![name_owner.to_string(), name.to_owned()])
 }
 
+/// Resolve a GetSecrets call and return the result together with the keys of all returned secrets
+///
+/// With `dry_run`, renew and fallback commands as well as prompts are only logged instead of run.
 fn get_secret(
-    mapping: &mut MappingConfig,
-    (connection, _connection_path, setting_name, hints, flags): (
-        NestedSettingsMap,
-        Path,
-        String,
-        Vec<String>,
-        u32,
-    ),
-) -> anyhow::Result<NestedSettingsMap> {
-    let conn_id = &connection["connection"]["id"]
-        .as_str()
-        .context("Connection property connection.id is not a string")?;
-    let conn_uuid = connection["connection"]["uuid"]
-        .as_str()
-        .context("Connection property connection.uuid is not a string")?;
-    let conn_type = connection["connection"]["type"]
-        .as_str()
-        .context("Connection property connection.type is not a string")?;
-    let iface_name = connection["connection"]["interface-name"]
-        .as_str()
-        .context("Connection property connection.interface-name is not a string")?;
-
+    mapping: &MappingConfig,
+    (connection, _connection_path, setting_name, hints, flags): GetSecretsArgs,
+    no_match: NoMatchReply,
+    dry_run: bool,
+    cancelled: &AtomicBool,
+) -> anyhow::Result<(NestedSettingsMap, Vec<String>)> {
+    let request = build_request(&connection, &setting_name, &hints, flags)?;
     tracing::info!(
-        connectionId = conn_id,
-        connectionUuid = conn_uuid,
-        connectionType = conn_type,
-        ifaceName = iface_name,
+        connectionId = %redact::connection_id(&request.conn_id),
+        connectionUuid = request.conn_uuid,
+        connectionType = request.conn_type,
+        ifaceName = request.iface_name,
         settingName = setting_name,
         ?hints,
         ?flags,
@@ -206,36 +1425,62 @@ fn get_secret(
     );
 
     // abort on unsupported flags
-    if (flags & GetSecretsFlags::RequestNew as u32) == GetSecretsFlags::RequestNew as u32 {
-        panic!("NetworkManager requested new credentials which cannot be provided by this agent");
-    }
     if (flags & GetSecretsFlags::WbsPbcActive as u32) == GetSecretsFlags::WbsPbcActive as u32 {
-        panic!("NetworkManager requested a WPA action to be performed which is not supported by this agent");
+        return Err(anyhow::Error::new(SecretAgentError::NoSecrets).context(
+            "NetworkManager requested a WPA action to be performed which is not supported by this agent",
+        ));
     }
 
     // fetch matching secret entries
-    let secrets = mapping
-        .get_secrets(conn_id, conn_uuid, conn_type, iface_name, &setting_name)
+    let mut secrets = mapping
+        .get_secrets(&request, dry_run, cancelled)
         .context("Could not fetch secrets")?;
+    if secrets.is_empty() {
+        let fallback = match (dry_run, mapping.fallback_command()) {
+            (true, Some(command)) => {
+                tracing::info!(
+                    "Dry run: not asking fallback command {} for secrets of {setting_name}",
+                    command.join(" ")
+                );
+                None
+            }
+            _ => mapping
+                .get_fallback_secrets(&request, cancelled)
+                .context("Could not fetch secrets from the fallback source")?,
+        };
+        let has_fallback = fallback.is_some();
+        secrets = fallback.unwrap_or_default();
+        if secrets.is_empty() {
+            if let Some(prompted) = mapping
+                .get_prompted_secrets(&request, dry_run, cancelled)
+                .context("Could not prompt for secrets")?
+            {
+                secrets = prompted;
+            }
+        }
+        if secrets.is_empty() && has_fallback {
+            return Err(anyhow::Error::new(SecretAgentError::NoSecrets).context(
+                "No entry provides secrets for the request and neither does the fallback source",
+            ));
+        }
+    }
+    if request.request_new && secrets.is_empty() {
+        return Err(anyhow::Error::new(SecretAgentError::NoSecrets).context(
+            "NetworkManager requested new credentials but no matching entry is configured to provide them",
+        ));
+    }
 
     if !secrets.is_empty() {
         // encode a result dataset
         let mut result = NestedSettingsMap::new();
-        result.insert(setting_name.clone(), HashMap::new());
-        for (key, secret_value) in secrets.iter() {
-            result
-                .get_mut(&setting_name)
-                .unwrap()
-                .insert(key.to_owned(), Variant(Box::new(secret_value.to_owned())));
-        }
+        result.insert(
+            setting_name.clone(),
+            encoder::encode_secrets(&request, &secrets).context("Could not encode secrets")?,
+        );
 
         // warn if NetworkManager hinted at values that are not provided
-        for hint in hints.iter() {
-            if result[&setting_name]
-                .keys()
-                .find(|&key| key == hint)
-                .is_none()
-            {
+        for hint in request.key_hints() {
+            if !secrets.iter().any(|(key, _)| key_matches_hint(key, hint)) {
                 tracing::warn!("Call from NetworkManager hinted at required key {setting_name}.{hint} and while nm-file-secret-agent has secret entries configured in the {setting_name} section, the key {hint} is missing");
             }
         }
@@ -246,30 +1491,347 @@ fn get_secret(
             .collect::<Vec<_>>()
             .join(", ");
         tracing::info!("returning secrets values for {matched_names}");
-        Ok(result)
+        Ok((result, secrets.into_iter().map(|(key, _)| key).collect()))
+    } else if no_match == NoMatchReply::NoSecrets {
+        Err(anyhow::Error::new(SecretAgentError::NoSecrets)
+            .context("No entries were configured that match the request"))
     } else {
         tracing::info!(
             "no entries were configured that match the request so no secrets are returned"
         );
-        Ok(NestedSettingsMap::default())
+        Ok((NestedSettingsMap::default(), Vec::new()))
     }
 }
 
-/// Verify that NetworkManager was the one who called
-fn verify_access(ctx: &mut DbusContext, known_nm_names: &Vec<String>) -> Result<(), MethodErr> {
-    tracing::debug!("Verifying that it was NetworkManager that called us");
-    let sender = ctx.message().sender();
-    match sender {
-        None => {
-            tracing::debug!("Denying method access for sender without a bus name");
-            Err(MethodErr::failed("Access Denied"))
+/// Extract the information about a request for secrets of `setting_name` from `connection`
+pub fn build_request(
+    connection: &NestedSettingsMap,
+    setting_name: &str,
+    hints: &[String],
+    flags: u32,
+) -> anyhow::Result<SecretRequest> {
+    let property = |key: &str| {
+        connection
+            .get("connection")
+            .and_then(|settings| settings.get(key))
+            .map(|value| {
+                value
+                    .downcast_ref::<&str>()
+                    .ok()
+                    .context(SecretAgentError::InvalidConnection)
+                    .with_context(|| {
+                        format!("Connection property connection.{key} is not a string")
+                    })
+            })
+            .transpose()
+    };
+    let required_property = |key: &str| {
+        property(key)?
+            .context(SecretAgentError::InvalidConnection)
+            .with_context(|| format!("Connection property connection.{key} is missing"))
+    };
+    let conn_id = required_property("id")?;
+    let conn_uuid = required_property("uuid")?;
+    let conn_type = required_property("type")?;
+    // NetworkManager omits the interface name of connections which are not bound to a specific interface
+    let iface_name = property("interface-name")?.unwrap_or_default();
+    let ssid = connection
+        .get("802-11-wireless")
+        .and_then(|wireless| wireless.get("ssid"))
+        .map(|ssid| decode_bytes(ssid))
+        .transpose()
+        .context("Connection property 802-11-wireless.ssid is not a byte array")?;
+    let wireguard_peers = connection
+        .get("wireguard")
+        .and_then(|wireguard| wireguard.get("peers"))
+        .map(|peers| decode_wireguard_peers(peers))
+        .transpose()
+        .context("Connection property wireguard.peers is not a list of peers")?
+        .unwrap_or_default();
+
+    Ok(SecretRequest {
+        conn_id: conn_id.to_owned(),
+        conn_uuid: conn_uuid.to_owned(),
+        conn_type: conn_type.to_owned(),
+        iface_name: iface_name.to_owned(),
+        setting_name: setting_name.to_owned(),
+        ssid,
+        wireguard_peers,
+        request_new: (flags & GetSecretsFlags::RequestNew as u32) != 0,
+        user_requested: (flags & GetSecretsFlags::UserRequested as u32) != 0,
+        // asking for new secrets implies that interaction is allowed
+        allow_interaction: (flags
+            & (GetSecretsFlags::AllowInteraction as u32 | GetSecretsFlags::RequestNew as u32))
+            != 0,
+        hints: hints.to_vec(),
+        not_agent_owned: connection
+            .get(setting_name)
+            .map(|setting| get_not_agent_owned_keys(setting_name, setting))
+            .unwrap_or_default(),
+    })
+}
+
+/// Append `request` as a line of JSON to the file at `path` which is only readable by its owner
+fn record_request(path: &Path, request: &SecretRequest) -> anyhow::Result<()> {
+    let mut line = serde_json::to_string(request)?;
+    line.push('\n');
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(line.as_bytes())?;
+    Ok(())
+}
+
+/// Describe which entries match `request` and whether their files are readable without reading any secret values
+fn resolve_test(
+    mapping: &MappingConfig,
+    request: &SecretRequest,
+) -> Vec<HashMap<String, Value<'static>>> {
+    mapping
+        .matching_entries(request)
+        .map(|(index, entry)| {
+            let files = entry
+                .files_for(request)
+                .into_iter()
+                .map(|file| {
+                    let state = match entry.check_readable(&file) {
+                        Ok(()) => "readable".to_owned(),
+                        Err(e) => format!("{e:#}"),
+                    };
+                    (file.display().to_string(), state)
+                })
+                .collect::<HashMap<_, _>>();
+            let mut result = HashMap::new();
+            result.insert("index".to_owned(), Value::from(index as u32));
+            if let Some(id) = entry.id() {
+                result.insert("id".to_owned(), Value::from(id.to_owned()));
+            }
+            result.insert("key".to_owned(), Value::from(entry.key().to_owned()));
+            result.insert("files".to_owned(), Value::from(files));
+            result
+        })
+        .collect()
+}
+
+/// The usage counters of all entries as returned by the `GetStats` method
+fn entry_stats(mapping: &MappingConfig) -> Vec<HashMap<String, Value<'static>>> {
+    mapping
+        .entries()
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| {
+            let stats = entry.stats();
+            let mut result = HashMap::new();
+            result.insert("index".to_owned(), Value::from(index as u32));
+            if let Some(id) = entry.id() {
+                result.insert("id".to_owned(), Value::from(id.to_owned()));
+            }
+            result.insert("key".to_owned(), Value::from(entry.key().to_owned()));
+            for (name, counter) in [
+                ("matched", &stats.matched),
+                ("served", &stats.served),
+                ("read_failures", &stats.read_failures),
+                ("last_used", &stats.last_used),
+            ] {
+                result.insert(
+                    name.to_owned(),
+                    Value::from(counter.load(Ordering::Relaxed)),
+                );
+            }
+            result
+        })
+        .collect()
+}
+
+/// Write the secrets of `connection` back into the files of writable entries
+fn save_secrets(mapping: &MappingConfig, connection: &NestedSettingsMap) -> anyhow::Result<()> {
+    for (setting_name, setting) in connection.iter() {
+        if setting_name == "connection" {
+            continue;
+        }
+        let request = build_request(connection, setting_name, &[], 0)?;
+        let saved = mapping
+            .save_secrets(&request, |key| encoder::decode_secret(setting, key))
+            .context("Could not save secrets")?;
+        if !saved.is_empty() {
+            let saved_names = saved
+                .iter()
+                .map(|key| format!("{setting_name}.{key}"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            tracing::info!("saved secret values for {saved_names}");
+        }
+    }
+    Ok(())
+}
+
+/// Decode a D-Bus byte array (`ay`) value
+fn decode_bytes(value: &Value) -> anyhow::Result<Vec<u8>> {
+    let Value::Array(array) = unwrap_variant(value) else {
+        anyhow::bail!("Value is not an array");
+    };
+    array
+        .inner()
+        .iter()
+        .map(|byte| match byte {
+            Value::U8(byte) => Ok(*byte),
+            _ => Err(anyhow::anyhow!("Array item is not a byte")),
+        })
+        .collect()
+}
+
+/// Determine all keys of a setting whose `<key>-flags` property indicates that they are not provided by agents
+///
+/// Keys are returned in the form in which they are configured in entries, so VPN secrets whose flags are stored in
+/// `vpn.data` are returned as `secrets.<name>`.
+fn get_not_agent_owned_keys(setting_name: &str, setting: &PropMap) -> Vec<String> {
+    let is_agent_owned = |flags: u32| flags & SecretFlags::AgentOwned as u32 != 0;
+
+    let mut result = setting
+        .iter()
+        .filter_map(|(key, value)| {
+            Some((
+                key.strip_suffix("-flags")?,
+                value.downcast_ref::<u32>().ok()?,
+            ))
+        })
+        .filter(|(_, flags)| !is_agent_owned(*flags))
+        .map(|(key, _)| key.to_owned())
+        .collect::<Vec<_>>();
+
+    // vpn plugins store their secret flags as strings inside the data dictionary
+    if setting_name == "vpn" {
+        if let Some(Value::Dict(data)) = setting.get("data").map(|data| unwrap_variant(data)) {
+            for (key, value) in data.iter() {
+                let (Value::Str(key), Value::Str(value)) = (key, unwrap_variant(value)) else {
+                    continue;
+                };
+                let Some(name) = key.as_str().strip_suffix("-flags") else {
+                    continue;
+                };
+                if let Ok(flags) = value.as_str().parse() {
+                    if !is_agent_owned(flags) {
+                        result.push(format!("secrets.{name}"));
+                    }
+                }
+            }
+        }
+    }
+
+    result
+}
+
+/// Decode the public keys of all peers from a wireguard peer list (`aa{sv}`) value
+fn decode_wireguard_peers(value: &Value) -> anyhow::Result<Vec<String>> {
+    let Value::Array(peers) = unwrap_variant(value) else {
+        anyhow::bail!("Value is not an array");
+    };
+    peers
+        .inner()
+        .iter()
+        .map(|peer| {
+            let Value::Dict(peer) = unwrap_variant(peer) else {
+                anyhow::bail!("Peer is not a dictionary");
+            };
+            for (key, value) in peer.iter() {
+                if matches!(key, Value::Str(key) if key.as_str() == "public-key") {
+                    return match unwrap_variant(value) {
+                        Value::Str(public_key) => Ok(public_key.to_string()),
+                        _ => Err(anyhow::anyhow!("Peer public-key is not a string")),
+                    };
+                }
+            }
+            Err(anyhow::anyhow!("Peer has no public-key"))
+        })
+        .collect()
+}
+
+/// Verify that the caller of a management method runs as root
+async fn verify_root_caller(conn: &Connection, header: &Header<'_>) -> zbus::fdo::Result<()> {
+    let denied = || zbus::fdo::Error::AccessDenied("Access Denied".to_owned());
+    let sender = header.sender().ok_or_else(denied)?;
+    let uid = async {
+        DBusProxy::new(conn)
+            .await?
+            .get_connection_unix_user(sender.clone().into())
+            .await
+    };
+    match uid.await {
+        Ok(0) => Ok(()),
+        Ok(uid) => {
+            tracing::warn!(%sender, uid, "Denying access to management method for sender that is not root");
+            Err(denied())
         }
-        Some(sender) => match known_nm_names.iter().any(|i| i.as_str() == sender.deref()) {
-            true => Ok(()),
-            false => {
+        Err(e) => {
+            tracing::warn!(%sender, "Could not query user id of sender: {e}");
+            Err(denied())
+        }
+    }
+}
+
+impl AccessControl {
+    /// Verify that NetworkManager was the one who called
+    ///
+    /// The sender must own one of NetworkManager's bus names and must run as one of the allowed users since bus names
+    /// can be racy across NetworkManager restarts while user ids are not.
+    async fn verify(&self, conn: &Connection, header: &Header<'_>) -> Result<(), MethodError> {
+        tracing::debug!("Verifying that it was NetworkManager that called us");
+        let denied = || SecretAgentError::PermissionDenied.method_err("Access Denied");
+        let Some(sender) = header.sender() else {
+            tracing::debug!("Denying method access for sender without a bus name");
+            return Err(denied());
+        };
+        if !self.is_known_nm_name(sender) {
+            // NetworkManager might have been restarted and acquired a new unique name in the meantime
+            tracing::debug!(
+                %sender,
+                "Sender is not a known NetworkManager name; querying the current name owner again"
+            );
+            match get_nm_names(conn).await {
+                Ok(names) => *self.known_nm_names.lock().unwrap() = names,
+                Err(e) => tracing::warn!("{e:#}"),
+            }
+            if !self.is_known_nm_name(sender) {
                 tracing::debug!("Denying method access for sender that is not NetworkManager");
-                Err(MethodErr::failed("Access Denied"))
+                return Err(denied());
             }
-        },
+        }
+
+        let proxy = DBusProxy::new(conn).await.map_err(|e| {
+            tracing::warn!("Could not connect to the D-Bus bus manager: {e}");
+            denied()
+        })?;
+        let uid = proxy
+            .get_connection_unix_user(sender.clone().into())
+            .await
+            .map_err(|e| {
+                tracing::warn!(%sender, "Could not query user id of sender: {e}");
+                denied()
+            })?;
+        if !self.allowed_uids.contains(&uid) {
+            let pid = proxy
+                .get_connection_unix_process_id(sender.clone().into())
+                .await
+                .ok();
+            tracing::warn!(
+                %sender,
+                uid,
+                ?pid,
+                "Denying method access for sender that does not run as an allowed user"
+            );
+            return Err(denied());
+        }
+        Ok(())
+    }
+
+    /// Whether `name` is one of the bus names that NetworkManager is known to own
+    fn is_known_nm_name(&self, name: &str) -> bool {
+        self.known_nm_names
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|i| i == name)
     }
 }
@@ -0,0 +1,239 @@
+use std::{
+    collections::BTreeSet,
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use clap::Args;
+use nm_secret_agent_core::{encryption::Encryption, mapping::MappingConfig, privileges};
+
+use crate::dbus_server::AGENT_PATH;
+
+/// Location of the systemd unit below the installation root
+const UNIT_PATH: &str = "etc/systemd/system/nm-file-secret-agent.service";
+
+/// Location of the D-Bus policy below the installation root
+const POLICY_PATH: &str = "etc/dbus-1/system.d/nm-file-secret-agent.conf";
+
+/// Directory in which `systemd-ask-password` places the questions of prompts for password agents
+const ASK_PASSWORD_DIR: &str = "/run/systemd/ask-password";
+
+/// Arguments of the `install` subcommand
+#[derive(Args, Debug, Eq, PartialEq, Hash)]
+pub struct InstallArgs {
+    /// Directory below which the unit and the D-Bus policy are installed, e.g. when building a package
+    #[arg(long, value_name = "DIR", default_value = "/")]
+    root: PathBuf,
+
+    /// Print the unit and the D-Bus policy instead of installing them
+    #[arg(long, conflicts_with = "root")]
+    print: bool,
+}
+
+/// How the agent is started by the installed service
+#[derive(Debug)]
+pub struct Service {
+    /// Arguments which are passed to the agent
+    pub args: Vec<String>,
+    /// Whether the agent switches to an unprivileged user via `--user`
+    pub switches_user: bool,
+    /// Capabilities which are kept after switching users
+    pub keep_capabilities: Vec<privileges::Capability>,
+    /// Whether all memory is locked into RAM via `--lock-memory`
+    pub lock_memory: bool,
+    /// Directories besides those of writable backing files into which the agent writes
+    pub writable_dirs: Vec<PathBuf>,
+    /// Whether remote configs are cached in the default cache directory
+    pub default_cache_dir: bool,
+}
+
+/// Install or print a systemd unit which runs the agent as `service` describes and the D-Bus policy it needs
+///
+/// The sandbox of the unit is tailored to `config`, e.g. the directories of writable entries stay writable.
+pub fn install(
+    config: &MappingConfig,
+    service: &Service,
+    args: &InstallArgs,
+) -> anyhow::Result<()> {
+    let exe = std::env::current_exe().context("Could not determine the path of the agent")?;
+    let exe = exe
+        .to_str()
+        .context("The path of the agent is not valid UTF-8")?;
+    let unit = unit(config, service, exe);
+    let policy = policy();
+    if args.print {
+        println!(
+            "# {}\n{unit}\n# {}\n{policy}",
+            Path::new("/").join(UNIT_PATH).display(),
+            Path::new("/").join(POLICY_PATH).display()
+        );
+        return Ok(());
+    }
+    for (path, content) in [(UNIT_PATH, &unit), (POLICY_PATH, &policy)] {
+        let path = args.root.join(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Could not create directory {}", parent.display()))?;
+        }
+        fs::write(&path, content).with_context(|| format!("Could not write {}", path.display()))?;
+        println!("Installed {}", path.display());
+    }
+    println!("Run `systemctl daemon-reload && systemctl enable --now nm-file-secret-agent` to start the agent");
+    Ok(())
+}
+
+/// Render the systemd unit which starts the agent at `exe`
+fn unit(config: &MappingConfig, service: &Service, exe: &str) -> String {
+    let entries = config.entries();
+    let writable = entries.iter().any(|entry| entry.is_writable());
+    let lock_memory = service.lock_memory || entries.iter().any(|entry| entry.is_pinned());
+    // systemd-creds may need the TPM to decrypt credentials
+    let needs_devices = entries
+        .iter()
+        .any(|entry| matches!(entry.encryption(), Some(Encryption::SystemdCreds { .. })));
+
+    let mut capabilities = BTreeSet::from(["CAP_DAC_READ_SEARCH"]);
+    if writable {
+        // saved values replace the backing files with copies that keep their owner and mode
+        capabilities.extend(["CAP_DAC_OVERRIDE", "CAP_CHOWN", "CAP_FOWNER"]);
+    }
+    if service.switches_user {
+        capabilities.extend(["CAP_SETUID", "CAP_SETGID"]);
+        capabilities.extend(
+            service
+                .keep_capabilities
+                .iter()
+                .map(|capability| capability.name()),
+        );
+    }
+    if lock_memory {
+        capabilities.insert("CAP_IPC_LOCK");
+    }
+
+    let mut writable_dirs = service
+        .writable_dirs
+        .iter()
+        .cloned()
+        .collect::<BTreeSet<_>>();
+    writable_dirs.extend(
+        entries
+            .iter()
+            .filter(|entry| entry.is_writable())
+            .flat_map(|entry| entry.files())
+            .filter_map(|file| {
+                file.ancestors()
+                    .skip(1)
+                    .find(|dir| !dir.to_string_lossy().contains('{'))
+            })
+            .map(Path::to_path_buf),
+    );
+    if config.prompt_command().is_some() {
+        writable_dirs.insert(PathBuf::from(ASK_PASSWORD_DIR));
+    }
+
+    let mut unit = String::new();
+    let exec_start = std::iter::once(exe)
+        .chain(service.args.iter().map(String::as_str))
+        .map(quote)
+        .collect::<Vec<_>>()
+        .join(" ");
+    let _ = writeln!(
+        unit,
+        "# Generated by `nm-file-secret-agent install`; adjust it with drop-ins via `systemctl edit nm-file-secret-agent`
+[Unit]
+Description=NetworkManager secret agent that responds with the content of preconfigured files
+Documentation=https://git.lly.sh/ftsell/nm-file-secret-agent
+Requires=NetworkManager.service
+After=NetworkManager.service
+
+[Service]
+Type=notify
+ExecStart={exec_start}
+ExecReload=/bin/kill -HUP $MAINPID
+WatchdogSec=60s
+Restart=on-failure
+RestartSec=5s
+UMask=0077
+CapabilityBoundingSet={}
+NoNewPrivileges=yes
+ProtectSystem=strict
+ProtectHome=read-only
+PrivateTmp=yes
+ProtectKernelTunables=yes
+ProtectKernelModules=yes
+ProtectKernelLogs=yes
+ProtectControlGroups=yes
+ProtectClock=yes
+ProtectHostname=yes
+RestrictNamespaces=yes
+RestrictRealtime=yes
+RestrictSUIDSGID=yes
+LockPersonality=yes
+MemoryDenyWriteExecute=yes
+RestrictAddressFamilies=AF_UNIX AF_INET AF_INET6
+SystemCallArchitectures=native
+SystemCallFilter=@system-service
+SystemCallErrorNumber=EPERM",
+        capabilities.into_iter().collect::<Vec<_>>().join(" ")
+    );
+    if !needs_devices {
+        let _ = writeln!(unit, "PrivateDevices=yes");
+    }
+    if service.default_cache_dir {
+        let _ = writeln!(unit, "CacheDirectory=nm-file-secret-agent");
+    }
+    for dir in &writable_dirs {
+        let _ = writeln!(unit, "ReadWritePaths={}", quote(&dir.to_string_lossy()));
+    }
+    if lock_memory {
+        let _ = writeln!(unit, "LimitMEMLOCK=infinity");
+    }
+    let _ = write!(unit, "\n[Install]\nWantedBy=multi-user.target\n");
+    unit
+}
+
+/// Render the D-Bus policy which allows calling the `sh.lly.NmFileSecretAgent` interface of the agent
+///
+/// NetworkManager's own policy already allows it to call the secret agent interface.
+fn policy() -> String {
+    format!(
+        r#"<?xml version="1.0"?>
+<!DOCTYPE busconfig PUBLIC "-//freedesktop//DTD D-BUS Bus Configuration 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/busconfig.dtd">
+<!-- Generated by `nm-file-secret-agent install` -->
+<busconfig>
+  <!-- management methods; the agent additionally checks that callers are root -->
+  <policy user="root">
+    <allow send_path="{AGENT_PATH}" send_interface="sh.lly.NmFileSecretAgent"/>
+  </policy>
+  <!-- status properties, introspection and pings are available to everyone -->
+  <policy context="default">
+    <allow send_path="{AGENT_PATH}" send_interface="org.freedesktop.DBus.Properties" send_member="Get"/>
+    <allow send_path="{AGENT_PATH}" send_interface="org.freedesktop.DBus.Properties" send_member="GetAll"/>
+    <allow send_path="{AGENT_PATH}" send_interface="org.freedesktop.DBus.Introspectable"/>
+    <allow send_path="{AGENT_PATH}" send_interface="org.freedesktop.DBus.Peer"/>
+  </policy>
+</busconfig>
+"#
+    )
+}
+
+/// Quote `word` for use in a systemd unit
+///
+/// `%` and `$` are escaped so that systemd neither expands specifiers nor environment variables in it.
+fn quote(word: &str) -> String {
+    let escaped = word
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('%', "%%")
+        .replace('$', "$$");
+    match !word.is_empty()
+        && !word
+            .contains(|c: char| c.is_whitespace() || c == '"' || c == '\'' || c == '\\' || c == ';')
+    {
+        true => escaped,
+        false => format!("\"{escaped}\""),
+    }
+}
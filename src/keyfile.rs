@@ -0,0 +1,127 @@
+use std::path::Path;
+
+use anyhow::Context;
+
+use crate::nm_settings;
+
+/// Names under which keyfiles store settings whose canonical name is different
+///
+/// See [reference](https://networkmanager.dev/docs/api/latest/nm-settings-keyfile.html).
+const SETTING_ALIASES: &[(&str, &str)] = &[
+    ("wifi", "802-11-wireless"),
+    ("wifi-security", "802-11-wireless-security"),
+    ("ethernet", "802-3-ethernet"),
+];
+
+/// A connection profile read from a NetworkManager keyfile
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Keyfile {
+    pub id: String,
+    pub uuid: String,
+    pub conn_type: String,
+    /// All secrets stored in the keyfile
+    pub secrets: Vec<KeyfileSecret>,
+}
+
+/// A secret stored in a keyfile
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct KeyfileSecret {
+    pub setting_name: String,
+    /// Key in the form in which it is configured for an entry, e.g. `secrets.password` for VPN secrets
+    pub key: String,
+    /// Name of the section and key under which the secret is stored in the keyfile
+    pub location: (String, String),
+    pub value: String,
+}
+
+impl Keyfile {
+    /// Read the keyfile at `path`
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path).context("Could not read keyfile")?;
+        let mut result = Self::default();
+        let mut section = String::new();
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim_start();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[') {
+                section = name
+                    .strip_suffix(']')
+                    .with_context(|| format!("Line {} contains an unterminated section", i + 1))?
+                    .to_owned();
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .with_context(|| format!("Line {} is neither a section nor a key", i + 1))?;
+            let (key, value) = (key.trim_end(), unescape(value.trim_start()));
+            let location = (section.clone(), key.to_owned());
+            let setting_name = SETTING_ALIASES
+                .iter()
+                .find(|(alias, _)| *alias == section)
+                .map_or(section.as_str(), |(_, name)| name);
+
+            match (setting_name, key) {
+                ("connection", "id") => result.id = value,
+                ("connection", "uuid") => result.uuid = value,
+                ("connection", "type") => result.conn_type = value,
+                ("vpn-secrets", _) => result.secrets.push(KeyfileSecret {
+                    setting_name: "vpn".to_owned(),
+                    key: format!("secrets.{key}"),
+                    location,
+                    value,
+                }),
+                (peer, "preshared-key") if peer.starts_with("wireguard-peer.") => {
+                    let public_key = &peer["wireguard-peer.".len()..];
+                    result.secrets.push(KeyfileSecret {
+                        setting_name: "wireguard".to_owned(),
+                        key: format!("peers.{public_key}.preshared-key"),
+                        location,
+                        value,
+                    })
+                }
+                (setting_name, key) if nm_settings::is_secret_key(setting_name, key) => {
+                    result.secrets.push(KeyfileSecret {
+                        setting_name: setting_name.to_owned(),
+                        key: key.to_owned(),
+                        location,
+                        value,
+                    })
+                }
+                _ => {}
+            }
+        }
+        if result.uuid.is_empty() {
+            anyhow::bail!("Keyfile does not contain a connection uuid");
+        }
+        if let Some(alias) = SETTING_ALIASES
+            .iter()
+            .find(|(alias, _)| *alias == result.conn_type)
+        {
+            result.conn_type = alias.1.to_owned();
+        }
+        Ok(result)
+    }
+}
+
+/// Resolve the escape sequences that GKeyFile uses in values
+fn unescape(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('s') => result.push(' '),
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('r') => result.push('\r'),
+            Some(other) => result.push(other),
+            None => result.push('\\'),
+        }
+    }
+    result
+}
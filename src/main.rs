@@ -1,28 +1,223 @@
-use std::path::PathBuf;
+use std::{
+    os::{fd::AsFd, unix::fs::MetadataExt},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::Duration,
+};
 
 use anyhow::Context;
-use clap::{ArgAction, Parser};
+use clap::{
+    error::ErrorKind, parser::ValueSource, ArgAction, CommandFactory, Parser, Subcommand,
+    ValueEnum, ValueHint,
+};
+use nm_secret_agent_core::{cache, mapping, privileges, reader, redact, remote};
 use tracing::level_filters::LevelFilter;
-use tracing_subscriber::prelude::*;
+use tracing_subscriber::{
+    filter::{Directive, EnvFilter},
+    prelude::*,
+};
 
-#[allow(unused)]
 mod agent_manager;
-#[allow(unused)]
-mod dbus_bus_manager;
+mod commands;
 mod dbus_server;
-mod mapping;
+mod install;
+mod keyfile;
+mod mock_nm;
+mod nm_settings;
+mod notify;
+mod polkit;
+mod systemd;
+
+use dbus_server::NoMatchReply;
+use mapping::ValidationMode;
+
+/// Environment variable listing colon-separated config files which are used if no `--conf` is given
+const CONFIG_ENV: &str = "NM_FILE_SECRET_AGENT_CONFIG";
 
 /// Small NetworkManager secret agent that responds with the content of preconfigured files
 #[derive(Parser, Debug, Eq, PartialEq, Hash)]
 #[command(version, about, long_about = None)]
 struct Cli {
-    /// Path to a config file
+    /// Path or `http://` or `https://` URL of a config file
+    ///
+    /// Can be given multiple times in which case the entries of all files are merged in order.
+    /// Entries of later files replace entries of earlier files that have the same `id`.
+    ///
+    /// If no config file is given, the colon-separated files listed in the `NM_FILE_SECRET_AGENT_CONFIG` environment
+    /// variable are used. If that is not set either and no `--entry` is given, `/etc/nm-file-secret-agent/config.toml`
+    /// and all `*.toml` files in `/etc/nm-file-secret-agent/conf.d` are loaded in that order.
     #[arg(short = 'c', long = "conf")]
-    config: PathBuf,
+    config: Vec<PathBuf>,
+
+    /// Directory with host-specific override files
+    ///
+    /// All `*.toml` files in the subdirectory named after the hostname of this machine are merged in lexical order after
+    /// the other config files, so that their entries replace those of the base config with the same `id`.
+    /// Defaults to `/etc/nm-file-secret-agent/overrides` if the config is loaded from the default locations.
+    #[arg(long = "overrides-dir", value_name = "DIR")]
+    overrides_dir: Option<PathBuf>,
+
+    /// Public key with which the signatures of config files given as `http://` or `https://` URLs are verified
+    ///
+    /// Remote configs are downloaded with `curl` together with a detached minisign signature from `<url>.minisig` or
+    /// a signify signature from `<url>.sig` and are refused if the signature cannot be verified with this key.
+    #[arg(long = "conf-signature-key", value_name = "PATH")]
+    conf_signature_key: Option<PathBuf>,
+
+    /// Directory in which the last verified copy of every remote config is kept
+    ///
+    /// The copy is used when the remote config cannot be fetched or verified, e.g. while booting without network.
+    #[arg(
+        long = "conf-cache-dir",
+        value_name = "DIR",
+        default_value = remote::DEFAULT_CACHE_DIR
+    )]
+    conf_cache_dir: PathBuf,
+
+    /// Profile whose entries are used in addition to the common entries of the config files
+    ///
+    /// Config files can declare entries under `[[profiles.<name>.entry]]` which are only used while their profile is
+    /// selected. They are merged after the common entries of all config files, so that they replace common entries
+    /// with the same `id`.
+    #[arg(
+        long = "profile",
+        value_name = "NAME",
+        env = "NM_FILE_SECRET_AGENT_PROFILE"
+    )]
+    profile: Option<String>,
+
+    /// Entry given directly on the command line as comma-separated key=value pairs
+    ///
+    /// The keys are the same as in config files and `setting`, `uuid`, `type` and `iface` are accepted as short
+    /// forms of the corresponding match settings, e.g.
+    /// `match_uuid=<uuid>,setting=wireguard,key=private-key,file=/run/key`.
+    /// Can be given multiple times. These entries are merged after those of all config files.
+    #[arg(long = "entry", value_name = "SPEC")]
+    entries: Vec<String>,
+
+    /// Only serve entries which have at least one of these comma-separated tags
+    ///
+    /// Can be given multiple times. Entries without tags are dropped as well, so that a single config can be deployed
+    /// to all machines and its entries activated selectively per machine role.
+    #[arg(long = "only-tags", value_name = "TAGS", value_delimiter = ',')]
+    only_tags: Vec<String>,
+
+    /// Drop entries which have any of these comma-separated tags
+    ///
+    /// Can be given multiple times and takes precedence over `--only-tags`.
+    #[arg(long = "exclude-tags", value_name = "TAGS", value_delimiter = ',')]
+    exclude_tags: Vec<String>,
+
+    /// How strictly the config file is validated at startup
+    ///
+    /// `strict` aborts on any problem, `warn` only logs problems and `off` skips validation entirely.
+    #[arg(long = "validate", value_enum, default_value_t = ValidationMode::Strict)]
+    validate: ValidationMode,
+
+    /// How strictly the config is validated when it is reloaded
+    ///
+    /// A reloaded config only replaces the active one once it has been loaded and validated completely. Problems are
+    /// always fatal to the reload by default so that a broken config, e.g. one referencing unreadable files, never
+    /// replaces a working one even if problems are only warned about at startup.
+    #[arg(long = "reload-validate", value_enum, default_value_t = ValidationMode::Strict)]
+    reload_validate: ValidationMode,
+
+    /// Address of the D-Bus daemon to connect to instead of the default system bus
+    ///
+    /// This is needed when the system bus socket is not at its default location, e.g. inside containers.
+    /// The format is that of D-Bus server addresses, e.g. `unix:path=/run/dbus/system_bus_socket`.
+    #[arg(long = "bus-address", env = "NM_FILE_SECRET_AGENT_BUS_ADDRESS")]
+    bus_address: Option<String>,
+
+    /// Connect to the session bus instead of the system bus for testing
+    ///
+    /// The agent does not register with NetworkManager and accepts calls from anyone in this mode so that it can be
+    /// driven by a mock NetworkManager without root privileges.
+    #[arg(long = "session-bus", conflicts_with = "bus_address")]
+    session_bus: bool,
+
+    /// Serve canned requests from a fake NetworkManager on a private bus instead of registering with the real one
+    ///
+    /// The file lists GetSecrets requests as `[[request]]` tables which are sent to the agent once it has registered.
+    /// The agent exits after all of them were answered and fails if any reply was an error other than `NoSecrets`.
+    /// This requires `dbus-daemon` but no NetworkManager.
+    #[arg(
+        long = "mock-nm",
+        value_name = "REQUESTS",
+        conflicts_with_all = ["bus_address", "session_bus"]
+    )]
+    mock_nm: Option<PathBuf>,
+
+    /// Maximum number of seconds to wait for NetworkManager to become available at startup
+    ///
+    /// Registration with NetworkManager is retried with increasing delays until it succeeds so that the agent can be
+    /// started before NetworkManager. Without this option, the agent waits indefinitely.
+    #[arg(long = "wait-for-nm", value_name = "SECONDS")]
+    wait_for_nm: Option<u64>,
+
+    /// Number of seconds between checks that the agent is still registered with NetworkManager
+    ///
+    /// NetworkManager may silently drop agent registrations, e.g. after suspend/resume, in which case the agent
+    /// registers again. A value of 0 disables the check.
+    #[arg(
+        long = "health-check-interval",
+        value_name = "SECONDS",
+        default_value_t = 60
+    )]
+    health_check_interval: u64,
+
+    /// Maximum number of seconds that resolving a single request for secrets may take
+    ///
+    /// When the deadline expires, e.g. because a network filesystem hangs, the request fails immediately instead of
+    /// NetworkManager running into its own much longer timeout. A value of 0 disables the deadline.
+    #[arg(long = "request-timeout", value_name = "SECONDS", default_value_t = 30)]
+    request_timeout: u64,
+
+    /// Register with NetworkManager's VpnHints capability
+    ///
+    /// VPN plugins then pass the names of the secrets they require and an accompanying `x-vpn-message` hint directly to
+    /// the agent which is required to drive challenge/response style VPN plugins.
+    #[arg(long = "vpn-hints")]
+    vpn_hints: bool,
+
+    /// User id from which NetworkManager's calls are accepted
+    ///
+    /// Can be given multiple times. Calls must additionally originate from NetworkManager's bus name.
+    #[arg(long = "allow-uid", value_name = "UID", default_value = "0")]
+    allowed_uids: Vec<u32>,
+
+    /// Maximum number of requests for secrets per connection within a minute
+    ///
+    /// Further requests are rejected with an error until earlier ones are older than a minute, which protects slow or
+    /// remote sources from a connection that keeps failing and reconnecting. A value of 0 disables the limit.
+    #[arg(long = "rate-limit", value_name = "CALLS", default_value_t = 0)]
+    rate_limit: u32,
+
+    /// How requests that no entry provides secrets for are answered
+    ///
+    /// Some NetworkManager versions consider an empty reply a satisfied request and never ask other agents, which
+    /// `no-secrets` avoids by replying with the `NoSecrets` error instead.
+    #[arg(long = "no-match", value_enum, default_value_t = NoMatchReply::Empty)]
+    no_match: NoMatchReply,
+
+    /// Resolve and log every request as usual but reply to NetworkManager as if no secrets were available
+    ///
+    /// This allows validating a config on a production machine before relying on it. Secrets which NetworkManager
+    /// asks to save are not written back and renew commands, fallback commands and prompts are not run in this mode.
+    #[arg(long = "dry-run")]
+    dry_run: bool,
+
+    /// Append every request for secrets as a line of JSON to this file
+    ///
+    /// The recorded requests contain the connection properties that entries are matched against but no secret values,
+    /// so they can be attached to bug reports and fed back through the matcher with the `replay` subcommand.
+    #[arg(long = "record-requests", value_name = "FILE")]
+    record_requests: Option<PathBuf>,
 
     /// Increase program verbosity
     ///
-    /// The default verbosity level is INFO.
+    /// The default verbosity level is INFO. Per-target levels can additionally be configured via the `RUST_LOG`
+    /// environment variable, e.g. `RUST_LOG=nm_secret_agent_core::mapping=trace`.
     #[arg(short = 'v', long = "verbose", action = ArgAction::Count, default_value = "0")]
     pub verbose: u8,
 
@@ -31,14 +226,402 @@ struct Cli {
     /// The default verbosity level is INFO.
     #[arg(short = 'q', long = "quiet", action = ArgAction::Count, default_value = "0")]
     pub quiet: u8,
+
+    /// Where log messages are written to
+    ///
+    /// `auto` logs directly to journald if stderr is connected to the journal, i.e. when running as a systemd
+    /// service, and to stderr otherwise.
+    #[arg(long = "log-target", value_enum, default_value_t = LogTarget::Auto)]
+    log_target: LogTarget,
+
+    /// Lock all memory of the agent into RAM so that secrets are never written to swap
+    ///
+    /// Unlike `pin` in the config, this also covers the buffers used while reading and encoding secrets. The agent
+    /// refuses to start if the memory cannot be locked, e.g. because `RLIMIT_MEMLOCK` is too low for an unprivileged
+    /// user.
+    #[arg(long = "lock-memory")]
+    lock_memory: bool,
+
+    /// Log a hash of the connection id of requests instead of the id itself
+    ///
+    /// Connection ids are often named after places or people. The hash still allows correlating the log messages of
+    /// a single connection.
+    #[arg(long = "hash-connection-ids")]
+    hash_connection_ids: bool,
+
+    /// Switch to this user after connecting to the bus and preloading secrets
+    ///
+    /// Backing files which are not preloaded must then be readable by this user, its groups or one of the capabilities
+    /// given via `--keep-capability`. After a restart of the D-Bus daemon, the agent reconnects as this user.
+    #[arg(long = "user", value_name = "USER")]
+    user: Option<String>,
+
+    /// Capability which is retained after switching to the user given via `--user`
+    ///
+    /// Can be given multiple times. All other capabilities are dropped.
+    #[arg(
+        long = "keep-capability",
+        value_enum,
+        value_name = "CAP",
+        requires = "user"
+    )]
+    keep_capabilities: Vec<privileges::Capability>,
+
+    /// Open all files via a separate helper process so that the agent itself needs no capabilities
+    ///
+    /// The helper is forked at startup, switches to the user given via `--user` while keeping only
+    /// `CAP_DAC_READ_SEARCH` and does nothing but open files for reading and pass their descriptors to the agent.
+    /// It only opens the config files and the files and directories they reference.
+    #[arg(long = "privsep", requires = "user")]
+    privsep: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+/// Destinations of log messages
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Hash, ValueEnum)]
+enum LogTarget {
+    /// journald if stderr is connected to the journal and stderr otherwise
+    #[default]
+    Auto,
+    /// Human-readable lines on stderr
+    Stderr,
+    /// Structured entries in the systemd journal in which all fields of log events are indexed
+    Journald,
+}
+
+/// Operations besides serving the secret agent, which is what happens if no subcommand is given
+#[derive(Subcommand, Debug, Eq, PartialEq, Hash)]
+enum Command {
+    /// Simulate a request for secrets offline and print which entries match and which keys would be returned
+    Resolve(commands::ResolveArgs),
+    /// Print for every entry whether it matches a simulated request or which match settings reject it
+    Explain(commands::ResolveArgs),
+    /// Resolve requests recorded via `--record-requests` offline and print which keys would be returned to each
+    Replay(commands::ReplayArgs),
+    /// Fetch a connection profile from NetworkManager and report which secrets would be served for it
+    VerifyConnection(commands::VerifyConnectionArgs),
+    /// Report entries whose match settings can not match any connection profile of NetworkManager
+    CheckConnections,
+    /// Write the configured secrets directly into the connection profiles of NetworkManager and exit
+    Push(commands::PushArgs),
+    /// Print all entries of the effective configuration with their match criteria, source and file status
+    List(commands::ListArgs),
+    /// Query NetworkManager for its connections and print a config skeleton providing all secrets they require
+    Generate(commands::GenerateArgs),
+    /// Move secrets from another store into individual files and print the config entries which provide them
+    Import(commands::ImportArgs),
+    /// Query the running agent for how often each entry was matched and served
+    Stats(commands::StatsArgs),
+    /// Show a desktop notification whenever the agent fails to serve a request; meant to run in the user session
+    Notify(notify::NotifyArgs),
+    /// Install a hardened systemd unit which runs the agent with the given options and the D-Bus policy it needs
+    Install(install::InstallArgs),
+    /// Print a completion script for the given shell
+    Completions(commands::CompletionsArgs),
+    /// Print the man page generated from the command line interface
+    Man(commands::ManArgs),
 }
 
-fn main() -> anyhow::Result<()> {
+fn main() -> ExitCode {
     let cli = Cli::parse();
     init_logger(&cli);
-    let config = mapping::MappingConfig::from_file(&cli.config)?;
-    config.validate().context("Config validation failed")?;
-    dbus_server::run(config)
+    match start(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            let code = dbus_server::error_code(&e);
+            eprintln!("Error [{code}]: {e:?}");
+            ExitCode::from(code.number())
+        }
+    }
+}
+
+/// Spawn the reader helper if requested and run the async runtime until the agent or subcommand has finished
+fn start(cli: Cli) -> anyhow::Result<()> {
+    remote::configure(remote::RemoteOptions {
+        signature_key: cli.conf_signature_key.clone(),
+        cache_dir: cli.conf_cache_dir.clone(),
+    })?;
+    if cli.privsep && cli.command.is_none() {
+        let run_as = privileges::RunAs {
+            user: cli.user.clone().unwrap_or_default(),
+            keep_capabilities: Vec::new(),
+        };
+        // the helper determines the files it may open from the config files itself so that it does not depend on
+        // what the agent tells it
+        let paths = config_paths(&cli)?;
+        let fixed = paths
+            .iter()
+            .map(|path| reader::AllowedPath::new(path, true))
+            .collect();
+        let (profile, entries, tags) = (cli.profile.clone(), cli.entries.clone(), tag_filter(&cli));
+        let configured = move || {
+            mapping::MappingConfig::read(&paths, profile.as_deref(), &entries, &tags)
+                .map(|config| config.readable_paths())
+        };
+        reader::spawn(&run_as, fixed, configured).context("Could not spawn the reader helper")?;
+    }
+    // capabilities are a property of each thread so everything runs on the thread that switches users when one is
+    // given; threads for blocking work are spawned from it later and inherit its capabilities
+    let mut runtime = match cli.user.is_some() {
+        true => tokio::runtime::Builder::new_current_thread(),
+        false => tokio::runtime::Builder::new_multi_thread(),
+    };
+    runtime
+        .enable_all()
+        .build()
+        .context("Could not start the async runtime")?
+        .block_on(run(cli))
+}
+
+async fn run(cli: Cli) -> anyhow::Result<()> {
+    // installing only passes the option on to the service
+    if cli.lock_memory && !matches!(cli.command, Some(Command::Install(_))) {
+        cache::lock_all_memory().context("Could not lock the memory of the agent into RAM")?;
+        tracing::debug!("Locked all memory into RAM");
+    }
+    match &cli.command {
+        None => {}
+        Some(Command::Resolve(args)) => return commands::resolve(&load_config(&cli)?, args),
+        Some(Command::Explain(args)) => return commands::explain(&load_config(&cli)?, args),
+        Some(Command::Replay(args)) => return commands::replay(&load_config(&cli)?, args),
+        Some(Command::List(args)) => return commands::list(&load_config(&cli)?, args),
+        Some(Command::Generate(args)) => {
+            let conn = dbus_server::connect(cli.bus_address.as_deref(), cli.session_bus).await?;
+            return commands::generate(&conn, args).await;
+        }
+        Some(Command::VerifyConnection(args)) => {
+            let config = load_config(&cli)?;
+            let conn = dbus_server::connect(cli.bus_address.as_deref(), cli.session_bus).await?;
+            return commands::verify_connection(&conn, &config, args).await;
+        }
+        Some(Command::CheckConnections) => {
+            let config = load_config(&cli)?;
+            let conn = dbus_server::connect(cli.bus_address.as_deref(), cli.session_bus).await?;
+            return commands::check_connections(&conn, &config).await;
+        }
+        Some(Command::Push(args)) => {
+            let config = load_config(&cli)?;
+            let conn = dbus_server::connect(cli.bus_address.as_deref(), cli.session_bus).await?;
+            return commands::push(&conn, &config, args).await;
+        }
+        Some(Command::Stats(args)) => {
+            let conn = dbus_server::connect(cli.bus_address.as_deref(), cli.session_bus).await?;
+            return commands::stats(&conn, args).await;
+        }
+        Some(Command::Notify(args)) => {
+            let conn = dbus_server::connect(cli.bus_address.as_deref(), cli.session_bus).await?;
+            return notify::run(&conn, args).await;
+        }
+        Some(Command::Import(args)) => return commands::import(args),
+        Some(Command::Install(args)) => {
+            // backing files do not need to exist yet when the service is installed
+            let config = mapping::MappingConfig::load(
+                &config_paths(&cli)?,
+                cli.profile.as_deref(),
+                &cli.entries,
+                &tag_filter(&cli),
+                ValidationMode::Off,
+            )?;
+            return install::install(&config, &service(&cli)?, args);
+        }
+        Some(Command::Completions(args)) => return commands::completions(Cli::command(), args),
+        Some(Command::Man(args)) => return commands::man(Cli::command(), args),
+    }
+
+    let config_paths = config_paths(&cli)?;
+    let config = mapping::MappingConfig::load(
+        &config_paths,
+        cli.profile.as_deref(),
+        &cli.entries,
+        &tag_filter(&cli),
+        cli.validate,
+    )?;
+    let mock = match &cli.mock_nm {
+        Some(path) => Some((
+            mock_nm::load_requests(path)?,
+            mock_nm::MockNm::start().await?,
+        )),
+        None => None,
+    };
+    let mut allowed_uids = cli.allowed_uids.clone();
+    if mock.is_some() {
+        // the mock runs in this process and thus as the current user
+        // SAFETY: getuid is always successful
+        allowed_uids.push(unsafe { libc::getuid() });
+    }
+    let agent = dbus_server::run(
+        config,
+        dbus_server::AgentOptions {
+            config_paths,
+            profile: cli.profile.clone(),
+            cli_entries: cli.entries.clone(),
+            tags: tag_filter(&cli),
+            validation: cli.reload_validate,
+            vpn_hints: cli.vpn_hints,
+            bus_address: match &mock {
+                Some((_, mock)) => Some(mock.address().to_owned()),
+                None => cli.bus_address.clone(),
+            },
+            session_bus: cli.session_bus,
+            wait_for_nm: cli.wait_for_nm.map(Duration::from_secs),
+            health_check_interval: Some(cli.health_check_interval)
+                .filter(|&interval| interval > 0)
+                .map(Duration::from_secs),
+            request_timeout: Some(cli.request_timeout)
+                .filter(|&timeout| timeout > 0)
+                .map(Duration::from_secs),
+            allowed_uids,
+            dry_run: cli.dry_run,
+            rate_limit: Some(cli.rate_limit).filter(|&limit| limit > 0),
+            no_match: cli.no_match,
+            run_as: cli.user.clone().map(|user| privileges::RunAs {
+                user,
+                keep_capabilities: cli.keep_capabilities.clone(),
+            }),
+            record_requests: cli.record_requests.clone(),
+        },
+    );
+    match &mock {
+        Some((requests, mock)) => tokio::select! {
+            result = agent => result,
+            result = mock.replay(requests) => result,
+        },
+        None => agent.await,
+    }
+}
+
+/// Load the config files and entries given via `--conf` and `--entry` or found at the default locations
+fn load_config(cli: &Cli) -> anyhow::Result<mapping::MappingConfig> {
+    mapping::MappingConfig::load(
+        &config_paths(cli)?,
+        cli.profile.as_deref(),
+        &cli.entries,
+        &tag_filter(cli),
+        cli.validate,
+    )
+}
+
+/// The filter which selects entries by the tags given via `--only-tags` and `--exclude-tags`
+fn tag_filter(cli: &Cli) -> mapping::TagFilter {
+    mapping::TagFilter {
+        only: cli.only_tags.clone(),
+        exclude: cli.exclude_tags.clone(),
+    }
+}
+
+/// Describe how the service installed by the `install` subcommand runs the agent with the options given to `cli`
+///
+/// All options given on the command line or via environment variables are passed on, with relative paths made
+/// absolute since services run in `/`.
+fn service(cli: &Cli) -> anyhow::Result<install::Service> {
+    if cli.session_bus || cli.mock_nm.is_some() {
+        anyhow::bail!("--session-bus and --mock-nm cannot be used by an installed service");
+    }
+    let absolute = |path: &Path| {
+        std::path::absolute(path)
+            .with_context(|| format!("Could not make {} absolute", path.display()))
+    };
+    let command = Cli::command();
+    let matches = command.clone().get_matches();
+    let mut args = Vec::new();
+    for arg in command.get_arguments() {
+        let id = arg.get_id().as_str();
+        let Some(long) = arg.get_long() else {
+            continue;
+        };
+        if !matches!(
+            matches.value_source(id),
+            Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+        ) {
+            continue;
+        }
+        match arg.get_action() {
+            ArgAction::SetTrue => args.push(format!("--{long}")),
+            ArgAction::Count => {
+                args.extend((0..matches.get_count(id)).map(|_| format!("--{long}")))
+            }
+            _ => {
+                for value in matches.get_raw(id).into_iter().flatten() {
+                    let mut value = PathBuf::from(value);
+                    if arg.get_value_hint() == ValueHint::AnyPath && !remote::is_url(&value) {
+                        value = absolute(&value)?;
+                    }
+                    let value = value.into_os_string().into_string().map_err(|value| {
+                        anyhow::anyhow!("Value {value:?} of --{long} is not valid UTF-8")
+                    })?;
+                    args.extend([format!("--{long}"), value]);
+                }
+            }
+        }
+    }
+    if cli.config.is_empty() {
+        if let Some(paths) = std::env::var_os(CONFIG_ENV).filter(|paths| !paths.is_empty()) {
+            for path in std::env::split_paths(&paths) {
+                let path = absolute(&path)?;
+                let path = path.to_str().context("Config path is not valid UTF-8")?;
+                args.extend(["--conf".to_owned(), path.to_owned()]);
+            }
+        }
+    }
+
+    let mut writable_dirs = Vec::new();
+    if let Some(dir) = cli.record_requests.as_deref().and_then(Path::parent) {
+        writable_dirs.push(absolute(dir)?);
+    }
+    let remote = cli.config.iter().any(|path| remote::is_url(path));
+    let default_cache_dir = cli.conf_cache_dir == Path::new(remote::DEFAULT_CACHE_DIR);
+    if remote && !default_cache_dir {
+        writable_dirs.push(absolute(&cli.conf_cache_dir)?);
+    }
+    Ok(install::Service {
+        args,
+        switches_user: cli.user.is_some(),
+        keep_capabilities: cli.keep_capabilities.clone(),
+        lock_memory: cli.lock_memory,
+        writable_dirs,
+        default_cache_dir: remote && default_cache_dir,
+    })
+}
+
+/// Determine the config files to load or exit with a usage error if there are neither files nor entries
+///
+/// Files given via `--conf` take precedence over those listed in the environment which in turn take precedence over
+/// the files at the default locations. The override files of this host are appended to them.
+fn config_paths(cli: &Cli) -> anyhow::Result<Vec<PathBuf>> {
+    let mut overrides_dir = cli.overrides_dir.clone();
+    let mut paths = if !cli.config.is_empty() {
+        cli.config.clone()
+    } else if let Some(paths) = std::env::var_os(CONFIG_ENV).filter(|paths| !paths.is_empty()) {
+        std::env::split_paths(&paths).collect()
+    } else if cli.entries.is_empty() {
+        overrides_dir.get_or_insert_with(|| PathBuf::from(mapping::DEFAULT_OVERRIDES_DIR));
+        mapping::default_config_paths().context("Could not search the default config locations")?
+    } else {
+        Vec::new()
+    };
+    if let Some(dir) = &overrides_dir {
+        let overrides = mapping::override_paths(dir)
+            .with_context(|| format!("Could not search the override files in {}", dir.display()))?;
+        for path in &overrides {
+            tracing::debug!("Loading host-specific overrides from {}", path.display());
+        }
+        paths.extend(overrides);
+    }
+    if paths.is_empty() && cli.entries.is_empty() {
+        Cli::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                format!(
+                    "the following required arguments were not provided:\n  --conf <CONFIG> or --entry <SPEC>\n\nNo config file was found at {} or in {}",
+                    mapping::DEFAULT_CONFIG_FILE,
+                    mapping::DEFAULT_CONFIG_DIR,
+                ),
+            )
+            .exit();
+    }
+    Ok(paths)
 }
 
 fn init_logger(args: &Cli) {
@@ -56,11 +639,78 @@ fn init_logger(args: &Cli) {
         _ => LevelFilter::TRACE,
     };
 
+    // fall back to stderr if journald is not reachable so that messages are not lost
+    let use_journald = match args.log_target {
+        LogTarget::Auto => stderr_is_journal(),
+        LogTarget::Stderr => false,
+        LogTarget::Journald => true,
+    };
+    let (journald, journald_error) = match use_journald {
+        true => match tracing_journald::layer() {
+            Ok(layer) => (Some(layer.with_field_prefix(None)), None),
+            Err(e) => (None, Some(e)),
+        },
+        false => (None, None),
+    };
+    let stderr = journald.is_none().then(|| {
+        tracing_subscriber::fmt::layer()
+            .without_time()
+            .with_writer(std::io::stderr)
+    });
+
     // configure appropriate level filter
-    // tokio is very spammy on higher log levels which is usually not interesting so we filter it out
-    let filter = tracing_subscriber::filter::Targets::new().with_default(log_level);
+    // directives from RUST_LOG are added on top of the level so that e.g. a single module can be traced while the very
+    // spammy dependencies like zbus and tokio stay at the default level
+    let mut filter = EnvFilter::default().add_directive(log_level.into());
+    let mut invalid_directives = Vec::new();
+    for directive in std::env::var(EnvFilter::DEFAULT_ENV)
+        .unwrap_or_default()
+        .split(',')
+        .filter(|directive| !directive.is_empty())
+    {
+        match directive.parse::<Directive>() {
+            Ok(parsed) => filter = filter.add_directive(parsed),
+            Err(e) => invalid_directives.push(format!("{directive}: {e}")),
+        }
+    }
+    // the redaction layer is added last so that it sees every event before the layers which format it
     tracing_subscriber::registry()
-        .with(tracing_subscriber::fmt::layer().without_time())
+        .with(journald)
+        .with(stderr)
         .with(filter)
+        .with(redact::RedactionLayer)
         .init();
+    if args.hash_connection_ids {
+        redact::hash_connection_ids();
+    }
+    for directive in invalid_directives {
+        tracing::warn!(
+            "Ignoring invalid directive in {}: {directive}",
+            EnvFilter::DEFAULT_ENV
+        );
+    }
+    if let Some(e) = journald_error {
+        tracing::warn!("Could not connect to journald; logging to stderr instead: {e}");
+    }
+}
+
+/// Whether stderr is connected to the systemd journal
+///
+/// systemd sets `JOURNAL_STREAM` to the device and inode number of the stream it connects to the journal so that
+/// services can detect whether their output ends up there.
+/// See [reference](https://www.freedesktop.org/software/systemd/man/latest/systemd.exec.html#%24JOURNAL_STREAM).
+fn stderr_is_journal() -> bool {
+    let Some(stream) = std::env::var_os("JOURNAL_STREAM") else {
+        return false;
+    };
+    let Some((dev, ino)) = stream.to_str().and_then(|stream| stream.split_once(':')) else {
+        return false;
+    };
+    let Ok(stderr) = std::io::stderr().as_fd().try_clone_to_owned() else {
+        return false;
+    };
+    match std::fs::File::from(stderr).metadata() {
+        Ok(metadata) => dev.parse() == Ok(metadata.dev()) && ino.parse() == Ok(metadata.ino()),
+        Err(_) => false,
+    }
 }
@@ -0,0 +1,274 @@
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader},
+    path::Path,
+    process::{Child, Command, Stdio},
+    time::Duration,
+};
+
+use anyhow::Context;
+use serde::Deserialize;
+use tokio::sync::watch;
+use zbus::{
+    message::Header,
+    zvariant::{ObjectPath, OwnedObjectPath, OwnedValue, Value},
+    Connection,
+};
+
+use crate::dbus_server::{GetSecretsFlags, AGENT_PATH};
+
+/// How long the agent may take to register with the mock before replaying fails
+const REGISTRATION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A file of canned requests which the mock sends to the agent
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MockRequests {
+    #[serde(default, rename = "request")]
+    requests: Vec<MockRequest>,
+}
+
+/// A GetSecrets request like NetworkManager would send it
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MockRequest {
+    #[serde(default)]
+    id: String,
+    #[serde(default)]
+    uuid: String,
+    #[serde(default, rename = "type")]
+    conn_type: String,
+    #[serde(default)]
+    iface: String,
+    setting: String,
+    #[serde(default)]
+    hints: Vec<String>,
+    ssid: Option<String>,
+    #[serde(default)]
+    request_new: bool,
+    #[serde(default)]
+    user_requested: bool,
+    #[serde(default)]
+    allow_interaction: bool,
+}
+
+impl MockRequest {
+    /// The connection settings that NetworkManager passes along with the request
+    fn connection(&self) -> HashMap<&str, HashMap<&str, Value<'_>>> {
+        let mut connection = HashMap::from([
+            ("id", Value::from(self.id.as_str())),
+            ("uuid", Value::from(self.uuid.as_str())),
+            ("type", Value::from(self.conn_type.as_str())),
+        ]);
+        if !self.iface.is_empty() {
+            connection.insert("interface-name", Value::from(self.iface.as_str()));
+        }
+        let mut settings = HashMap::from([("connection", connection)]);
+        if let Some(ssid) = &self.ssid {
+            settings.insert(
+                "802-11-wireless",
+                HashMap::from([("ssid", Value::from(ssid.as_bytes()))]),
+            );
+        }
+        settings
+    }
+
+    fn flags(&self) -> u32 {
+        let mut flags = 0;
+        if self.request_new {
+            flags |= GetSecretsFlags::RequestNew as u32;
+        }
+        if self.user_requested {
+            flags |= GetSecretsFlags::UserRequested as u32;
+        }
+        if self.allow_interaction {
+            flags |= GetSecretsFlags::AllowInteraction as u32;
+        }
+        flags
+    }
+}
+
+/// Load the canned requests from a TOML file with one `[[request]]` table per request
+pub fn load_requests(path: &Path) -> anyhow::Result<Vec<MockRequest>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Could not read mock requests from {}", path.display()))?;
+    let requests: MockRequests = toml::from_str(&content)
+        .with_context(|| format!("Could not parse mock requests from {}", path.display()))?;
+    Ok(requests.requests)
+}
+
+/// The secret agent manager of the mock which remembers the agent that registered
+struct AgentManager {
+    agent: watch::Sender<Option<String>>,
+}
+
+#[zbus::interface(name = "org.freedesktop.NetworkManager.AgentManager")]
+impl AgentManager {
+    async fn register_with_capabilities(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        identifier: String,
+        capabilities: u32,
+    ) -> zbus::fdo::Result<()> {
+        let sender = header.sender().map(|sender| sender.to_string());
+        tracing::info!(
+            identifier,
+            capabilities,
+            "Mock NetworkManager accepted the registration of {}",
+            sender.as_deref().unwrap_or_default()
+        );
+        self.agent.send_replace(sender);
+        Ok(())
+    }
+}
+
+#[zbus::proxy(interface = "org.freedesktop.NetworkManager.SecretAgent")]
+trait SecretAgent {
+    fn get_secrets(
+        &self,
+        connection: HashMap<&str, HashMap<&str, Value<'_>>>,
+        connection_path: &ObjectPath<'_>,
+        setting_name: &str,
+        hints: &[String],
+        flags: u32,
+    ) -> zbus::Result<HashMap<String, HashMap<String, OwnedValue>>>;
+}
+
+/// A fake NetworkManager on a private D-Bus daemon which lets the agent run on machines without NetworkManager
+///
+/// The daemon is spawned as child process while the `AgentManager` interface is served in-process.
+pub struct MockNm {
+    daemon: Child,
+    address: String,
+    conn: Connection,
+    agent: watch::Receiver<Option<String>>,
+}
+
+impl MockNm {
+    /// Spawn the private D-Bus daemon and claim NetworkManager's name on it
+    pub async fn start() -> anyhow::Result<Self> {
+        let mut daemon = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address"])
+            .arg(format!(
+                "--address=unix:tmpdir={}",
+                std::env::temp_dir().display()
+            ))
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("Could not spawn dbus-daemon for the mock NetworkManager")?;
+        let mut address = String::new();
+        BufReader::new(
+            daemon
+                .stdout
+                .take()
+                .expect("stdout of dbus-daemon is piped"),
+        )
+        .read_line(&mut address)
+        .context("Could not read the address of the mock NetworkManager's bus")?;
+        let address = address.trim().to_owned();
+        tracing::info!("Spawned private D-Bus daemon for the mock NetworkManager at {address}");
+
+        let (agent, registered) = watch::channel(None);
+        let conn = zbus::connection::Builder::address(address.as_str())?
+            .name("org.freedesktop.NetworkManager")?
+            .serve_at(
+                "/org/freedesktop/NetworkManager/AgentManager",
+                AgentManager { agent },
+            )?
+            .build()
+            .await
+            .context("Could not connect the mock NetworkManager to its bus")?;
+        Ok(Self {
+            daemon,
+            address,
+            conn,
+            agent: registered,
+        })
+    }
+
+    /// Address of the private bus to which the agent connects
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Wait for the agent to register, send it every request and log the replies
+    ///
+    /// Fails if any request was answered with an error other than `NoSecrets`.
+    pub async fn replay(&self, requests: &[MockRequest]) -> anyhow::Result<()> {
+        let mut registered = self.agent.clone();
+        let agent =
+            tokio::time::timeout(REGISTRATION_TIMEOUT, registered.wait_for(Option::is_some))
+                .await
+                .context("The agent did not register with the mock NetworkManager in time")??
+                .clone()
+                .expect("the agent has registered");
+        let proxy = SecretAgentProxy::builder(&self.conn)
+            .destination(agent)?
+            .path(AGENT_PATH)?
+            .build()
+            .await?;
+
+        let mut failed = 0;
+        for (i, request) in requests.iter().enumerate() {
+            let path = OwnedObjectPath::try_from(format!(
+                "/org/freedesktop/NetworkManager/Settings/{}",
+                i + 1
+            ))?;
+            let result = proxy
+                .get_secrets(
+                    request.connection(),
+                    &path,
+                    &request.setting,
+                    &request.hints,
+                    request.flags(),
+                )
+                .await;
+            match result {
+                Ok(secrets) => {
+                    let keys = secrets
+                        .iter()
+                        .flat_map(|(setting, values)| {
+                            values.keys().map(move |key| format!("{setting}.{key}"))
+                        })
+                        .collect::<Vec<_>>();
+                    tracing::info!(
+                        "Mock request #{i} for {} of {:?} returned {keys:?}",
+                        request.setting,
+                        request.id
+                    );
+                }
+                Err(zbus::Error::MethodError(name, message, _))
+                    if name.as_str() == "org.freedesktop.NetworkManager.SecretAgent.NoSecrets" =>
+                {
+                    tracing::info!(
+                        "Mock request #{i} for {} of {:?} returned NoSecrets: {}",
+                        request.setting,
+                        request.id,
+                        message.unwrap_or_default()
+                    );
+                }
+                Err(e) => {
+                    failed += 1;
+                    tracing::error!(
+                        "Mock request #{i} for {} of {:?} failed: {e}",
+                        request.setting,
+                        request.id
+                    );
+                }
+            }
+        }
+        if failed > 0 {
+            anyhow::bail!("{failed} of {} mock requests failed", requests.len());
+        }
+        tracing::info!("Replayed all {} mock requests", requests.len());
+        Ok(())
+    }
+}
+
+impl Drop for MockNm {
+    fn drop(&mut self) {
+        let _ = self.daemon.kill();
+        let _ = self.daemon.wait();
+    }
+}
@@ -0,0 +1,424 @@
+use std::collections::{BTreeSet, HashMap};
+
+use anyhow::Context;
+use nm_secret_agent_core::{
+    encoder::{unwrap_variant, PropMap},
+    error::{Error, ErrorCode},
+    mapping::{MappingConfig, MappingEntry},
+};
+use zbus::{
+    zvariant::{OwnedObjectPath, OwnedValue, Value},
+    Connection,
+};
+
+use crate::dbus_server::{self, NestedSettingsMap, SecretFlags};
+
+/// The interface through which NetworkManager exposes its connection profiles
+///
+/// See [reference](https://networkmanager.dev/docs/api/latest/gdbus-org.freedesktop.NetworkManager.Settings.html).
+#[zbus::proxy(
+    interface = "org.freedesktop.NetworkManager.Settings",
+    default_service = "org.freedesktop.NetworkManager",
+    default_path = "/org/freedesktop/NetworkManager/Settings"
+)]
+pub trait Settings {
+    /// Object paths of all connection profiles
+    fn list_connections(&self) -> zbus::Result<Vec<OwnedObjectPath>>;
+
+    /// Emitted when a connection profile was added
+    #[zbus(signal)]
+    fn new_connection(&self, connection: OwnedObjectPath) -> zbus::Result<()>;
+
+    /// Emitted when a connection profile was removed
+    #[zbus(signal)]
+    fn connection_removed(&self, connection: OwnedObjectPath) -> zbus::Result<()>;
+}
+
+/// The interface of a single connection profile
+///
+/// See [reference](https://networkmanager.dev/docs/api/latest/gdbus-org.freedesktop.NetworkManager.Settings.Connection.html).
+#[zbus::proxy(
+    interface = "org.freedesktop.NetworkManager.Settings.Connection",
+    default_service = "org.freedesktop.NetworkManager"
+)]
+pub trait SettingsConnection {
+    /// All settings of the profile without its secrets
+    fn get_settings(&self) -> zbus::Result<NestedSettingsMap>;
+
+    /// The secrets of the setting `setting_name` which NetworkManager stores itself, without asking agents of other
+    /// sessions or the user
+    fn get_secrets(&self, setting_name: &str) -> zbus::Result<NestedSettingsMap>;
+
+    /// Replace all settings of the profile including its secrets
+    fn update2(
+        &self,
+        settings: &NestedSettingsMap,
+        flags: u32,
+        args: HashMap<&str, Value<'_>>,
+    ) -> zbus::Result<HashMap<String, OwnedValue>>;
+}
+
+/// Flags of `Update2` calls
+///
+/// See [reference](https://networkmanager.dev/docs/api/latest/nm-dbus-types.html#NMSettingsUpdate2Flags).
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[repr(u32)]
+pub enum Update2Flags {
+    /// The profile is written to disk
+    ToDisk = 0x1,
+    /// The profile is only changed in memory, leaving a profile on disk unchanged until NetworkManager restarts
+    InMemory = 0x2,
+}
+
+/// Query NetworkManager for the settings of all its connection profiles together with their object paths
+pub async fn list_profiles(
+    conn: &Connection,
+) -> anyhow::Result<Vec<(OwnedObjectPath, NestedSettingsMap)>> {
+    let paths = SettingsProxy::new(conn)
+        .await?
+        .list_connections()
+        .await
+        .context(Error::new(
+            ErrorCode::NmUnreachable,
+            "Could not list connections of NetworkManager",
+        ))?;
+    let mut profiles = Vec::with_capacity(paths.len());
+    for path in paths {
+        let settings = get_profile(conn, &path).await?;
+        profiles.push((path, settings));
+    }
+    Ok(profiles)
+}
+
+/// Query NetworkManager for the settings of the connection profile at `path`
+pub async fn get_profile(
+    conn: &Connection,
+    path: &OwnedObjectPath,
+) -> anyhow::Result<NestedSettingsMap> {
+    SettingsConnectionProxy::builder(conn)
+        .path(path)?
+        .build()
+        .await?
+        .get_settings()
+        .await
+        .with_context(|| format!("Could not query settings of connection {}", path.as_str()))
+}
+
+/// The id of a connection profile, i.e. the name displayed in GUIs
+pub fn profile_id(profile: &NestedSettingsMap) -> &str {
+    profile
+        .get("connection")
+        .and_then(|connection| connection.get("id"))
+        .and_then(|value| value.downcast_ref::<&str>().ok())
+        .unwrap_or_default()
+}
+
+/// The uuid of a connection profile
+pub fn profile_uuid(profile: &NestedSettingsMap) -> &str {
+    profile
+        .get("connection")
+        .and_then(|connection| connection.get("uuid"))
+        .and_then(|value| value.downcast_ref::<&str>().ok())
+        .unwrap_or_default()
+}
+
+/// Indexes of all entries of `mapping` which match no setting of any of the connection `profiles`
+///
+/// Such entries can never provide a value, e.g. because of a typo in their uuid or an outdated connection id.
+/// Neither `match_vpn_message` nor the hook is taken into account since both depend on the request.
+pub fn unmatchable_entries<'a>(
+    mapping: &MappingConfig,
+    profiles: impl IntoIterator<Item = &'a NestedSettingsMap>,
+) -> BTreeSet<usize> {
+    let requests = profiles
+        .into_iter()
+        .flat_map(|profile| {
+            profile.keys().filter_map(|setting_name| {
+                dbus_server::build_request(profile, setting_name, &[], 0).ok()
+            })
+        })
+        .collect::<Vec<_>>();
+    mapping
+        .entries()
+        .iter()
+        .enumerate()
+        .filter(|(_, entry)| !requests.iter().any(|request| entry.can_match(request)))
+        .map(|(index, _)| index)
+        .collect()
+}
+
+/// The match settings of `entry` which relate to connection profiles, e.g. `match_id = "home"`
+pub fn describe_criteria(entry: &MappingEntry) -> String {
+    entry
+        .match_criteria()
+        .into_iter()
+        .filter(|(name, _)| *name != "match_vpn_message")
+        .map(|(name, value)| format!("{name} = {value:?}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Settings which can contain secrets
+///
+/// Other settings also have properties ending in `-flags` (e.g. `connection.mptcp-flags`) which are not secret flags.
+const SECRET_SETTINGS: &[&str] = &[
+    "802-11-wireless-security",
+    "802-1x",
+    "wireguard",
+    "vpn",
+    "gsm",
+    "cdma",
+    "pppoe",
+    "adsl",
+    "macsec",
+];
+
+/// Keys of settings which hold secrets as plain strings
+///
+/// VPN secrets and wireguard peer preshared-keys are stored differently and not part of this list.
+const SECRET_KEYS: &[(&str, &[&str])] = &[
+    (
+        "802-11-wireless-security",
+        &[
+            "psk",
+            "wep-key0",
+            "wep-key1",
+            "wep-key2",
+            "wep-key3",
+            "leap-password",
+        ],
+    ),
+    (
+        "802-1x",
+        &[
+            "password",
+            "private-key-password",
+            "phase2-private-key-password",
+            "pin",
+        ],
+    ),
+    ("wireguard", &["private-key"]),
+    ("gsm", &["password", "pin"]),
+    ("cdma", &["password"]),
+    ("pppoe", &["password"]),
+    ("adsl", &["password"]),
+    ("macsec", &["mka-cak"]),
+];
+
+/// Whether the setting `setting_name` can contain secrets
+pub fn has_secrets(setting_name: &str) -> bool {
+    SECRET_SETTINGS.contains(&setting_name)
+}
+
+/// Merge the encoded `secrets` of a setting into its properties `setting`
+///
+/// VPN secrets are added to the existing `secrets` dictionary and the secrets of wireguard peers to the peers of the
+/// profile with the same public key, while all other properties are replaced.
+pub fn merge_secrets(setting: &mut PropMap, secrets: PropMap) -> anyhow::Result<()> {
+    for (key, value) in secrets {
+        let merged = match (key.as_str(), setting.remove(&key)) {
+            ("secrets", Some(existing)) => {
+                let mut merged = HashMap::<String, String>::try_from(existing)
+                    .context("The VPN secrets of the profile are not a dictionary of strings")?;
+                merged.extend(HashMap::<String, String>::try_from(value)?);
+                OwnedValue::try_from(Value::from(merged))?
+            }
+            ("peers", Some(existing)) => {
+                let mut peers = Vec::<HashMap<String, OwnedValue>>::try_from(existing)
+                    .context("The wireguard peers of the profile are not a list of dictionaries")?;
+                for secrets in Vec::<HashMap<String, OwnedValue>>::try_from(value)? {
+                    let public_key = |peer: &HashMap<String, OwnedValue>| {
+                        peer.get("public-key")
+                            .and_then(|key| key.downcast_ref::<String>().ok())
+                    };
+                    let peer = peers
+                        .iter_mut()
+                        .find(|peer| {
+                            public_key(peer).is_some() && public_key(peer) == public_key(&secrets)
+                        })
+                        .context(
+                            "The profile has no wireguard peer with the public key of a secret",
+                        )?;
+                    peer.extend(secrets);
+                }
+                OwnedValue::try_from(Value::from(peers))?
+            }
+            (_, _) => value,
+        };
+        setting.insert(key, merged);
+    }
+    Ok(())
+}
+
+/// Whether `key` holds a secret string in the setting `setting_name`
+pub fn is_secret_key(setting_name: &str, key: &str) -> bool {
+    SECRET_KEYS
+        .iter()
+        .any(|(name, keys)| *name == setting_name && keys.contains(&key))
+}
+
+/// A secret which a connection profile requires
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RequiredSecret {
+    pub setting_name: String,
+    /// Key in the form in which it is configured for an entry, e.g. `secrets.password` for VPN secrets
+    pub key: String,
+    /// Secret flags that the profile configures for the key
+    pub flags: u32,
+}
+
+impl RequiredSecret {
+    /// Whether NetworkManager expects a secret agent to provide this secret
+    pub fn is_agent_owned(&self) -> bool {
+        self.flags & SecretFlags::AgentOwned as u32 != 0
+    }
+}
+
+/// Determine which secrets the connection profile described by `settings` requires
+///
+/// Since NetworkManager never returns secrets along with the settings, this relies on the configured authentication
+/// methods of well-known settings as well as all `<key>-flags` properties which NetworkManager only sends for
+/// secrets whose flags differ from the default.
+pub fn required_secrets(settings: &NestedSettingsMap) -> Vec<RequiredSecret> {
+    let mut result = Vec::new();
+    let mut settings = settings.iter().collect::<Vec<_>>();
+    settings.sort_by_key(|(setting_name, _)| *setting_name);
+    for (setting_name, setting) in settings {
+        if !SECRET_SETTINGS.contains(&setting_name.as_str()) {
+            continue;
+        }
+        let mut keys = well_known_secrets(setting_name, setting)
+            .into_iter()
+            .map(|key| (key, 0))
+            .collect::<HashMap<_, _>>();
+        for (key, value) in setting.iter() {
+            if let (Some(key), Ok(flags)) = (key.strip_suffix("-flags"), value.downcast_ref()) {
+                keys.insert(key.to_owned(), flags);
+            }
+        }
+        if setting_name == "vpn" {
+            keys.extend(vpn_secret_flags(setting));
+        }
+        if setting_name == "wireguard" {
+            keys.extend(wireguard_peer_secret_flags(setting));
+        }
+
+        let mut keys = keys
+            .into_iter()
+            .filter(|(_, flags)| flags & SecretFlags::NotRequired as u32 == 0)
+            .collect::<Vec<_>>();
+        keys.sort();
+        result.extend(keys.into_iter().map(|(key, flags)| RequiredSecret {
+            setting_name: setting_name.to_owned(),
+            key,
+            flags,
+        }));
+    }
+    result
+}
+
+/// Secrets which a well-known setting requires given its configured authentication method
+fn well_known_secrets(setting_name: &str, setting: &PropMap) -> Vec<String> {
+    let string = |key: &str| {
+        setting
+            .get(key)
+            .and_then(|value| value.downcast_ref::<&str>().ok())
+            .map(str::to_owned)
+    };
+    match setting_name {
+        "802-11-wireless-security" => match string("key-mgmt").as_deref() {
+            Some("wpa-psk" | "sae") => vec!["psk".to_owned()],
+            Some("none") => {
+                let index = setting
+                    .get("wep-tx-keyidx")
+                    .and_then(|value| value.downcast_ref::<u32>().ok())
+                    .unwrap_or(0);
+                vec![format!("wep-key{index}")]
+            }
+            Some("ieee8021x") if string("auth-alg").as_deref() == Some("leap") => {
+                vec!["leap-password".to_owned()]
+            }
+            _ => Vec::new(),
+        },
+        "802-1x" => {
+            let methods = string_list(setting, "eap");
+            let mut keys = Vec::new();
+            if methods.iter().any(|method| method == "tls") {
+                keys.push("private-key-password".to_owned());
+            }
+            if methods.iter().any(|method| method != "tls") {
+                keys.push("password".to_owned());
+            }
+            if string("phase2-autheap").as_deref() == Some("tls") {
+                keys.push("phase2-private-key-password".to_owned());
+            }
+            keys
+        }
+        "wireguard" => vec!["private-key".to_owned()],
+        "gsm" | "cdma" | "pppoe" | "adsl" => vec!["password".to_owned()],
+        _ => Vec::new(),
+    }
+}
+
+/// Secret flags of VPN secrets which plugins store as strings inside the `data` dictionary
+fn vpn_secret_flags(setting: &PropMap) -> Vec<(String, u32)> {
+    let Some(Value::Dict(data)) = setting.get("data").map(|data| unwrap_variant(data)) else {
+        return Vec::new();
+    };
+    data.iter()
+        .filter_map(|(key, value)| {
+            let (Value::Str(key), Value::Str(value)) = (key, unwrap_variant(value)) else {
+                return None;
+            };
+            let name = key.as_str().strip_suffix("-flags")?;
+            Some((format!("secrets.{name}"), value.as_str().parse().ok()?))
+        })
+        .collect()
+}
+
+/// Secret flags of the preshared-keys of all wireguard peers which configure them
+fn wireguard_peer_secret_flags(setting: &PropMap) -> Vec<(String, u32)> {
+    let Some(Value::Array(peers)) = setting.get("peers").map(|peers| unwrap_variant(peers)) else {
+        return Vec::new();
+    };
+    peers
+        .inner()
+        .iter()
+        .filter_map(|peer| {
+            let Value::Dict(peer) = unwrap_variant(peer) else {
+                return None;
+            };
+            let mut public_key = None;
+            let mut flags = None;
+            for (key, value) in peer.iter() {
+                match (key, unwrap_variant(value)) {
+                    (Value::Str(key), Value::Str(value)) if key.as_str() == "public-key" => {
+                        public_key = Some(value.to_string())
+                    }
+                    (Value::Str(key), Value::U32(value))
+                        if key.as_str() == "preshared-key-flags" =>
+                    {
+                        flags = Some(*value)
+                    }
+                    _ => {}
+                }
+            }
+            Some((format!("peers.{}.preshared-key", public_key?), flags?))
+        })
+        .collect()
+}
+
+/// Decode a list of strings (`as`) property of `setting`
+fn string_list(setting: &PropMap, key: &str) -> Vec<String> {
+    match setting.get(key).map(|value| unwrap_variant(value)) {
+        Some(Value::Array(items)) => items
+            .inner()
+            .iter()
+            .filter_map(|item| match unwrap_variant(item) {
+                Value::Str(item) => Some(item.to_string()),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
@@ -0,0 +1,152 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use clap::Args;
+use futures_util::StreamExt;
+use zbus::{fdo::DBusProxy, message::Type, zvariant::Value, Connection, MatchRule, MessageStream};
+
+use crate::dbus_server::AGENT_PATH;
+
+/// Urgency hint value of notifications that must not time out
+const URGENCY_CRITICAL: u8 = 2;
+
+/// The notification daemon of the desktop session
+///
+/// See [reference](https://specifications.freedesktop.org/notification-spec/latest/protocol.html).
+#[zbus::proxy(
+    interface = "org.freedesktop.Notifications",
+    default_service = "org.freedesktop.Notifications",
+    default_path = "/org/freedesktop/Notifications"
+)]
+trait Notifications {
+    /// Show a notification, replacing the one with id `replaces_id` unless it is 0, and return its id
+    #[allow(clippy::too_many_arguments)]
+    fn notify(
+        &self,
+        app_name: &str,
+        replaces_id: u32,
+        app_icon: &str,
+        summary: &str,
+        body: &str,
+        actions: &[&str],
+        hints: HashMap<&str, Value<'_>>,
+        expire_timeout: i32,
+    ) -> zbus::Result<u32>;
+}
+
+/// Arguments of the `notify` subcommand
+#[derive(Args, Debug, Eq, PartialEq, Hash)]
+pub struct NotifyArgs {
+    /// Address of the bus of the notification daemon instead of the session bus
+    #[arg(long, value_name = "ADDRESS")]
+    notifications_bus_address: Option<String>,
+
+    /// Number of seconds during which repeated failures of the same connection and error are not notified again
+    #[arg(long, value_name = "SECONDS", default_value_t = 60)]
+    repeat_after: u64,
+
+    /// User id as which the agent runs; signals sent by other users are ignored
+    #[arg(long, value_name = "UID", default_value_t = 0)]
+    agent_uid: u32,
+}
+
+/// Show a desktop notification for every request for secrets that the agent on `conn` fails to serve
+///
+/// This runs in the user session since the agent itself has no access to its notification daemon. It returns once
+/// the connection to either bus is lost.
+pub async fn run(conn: &Connection, args: &NotifyArgs) -> anyhow::Result<()> {
+    let session = match &args.notifications_bus_address {
+        Some(address) => zbus::connection::Builder::address(address.as_str())?
+            .build()
+            .await
+            .with_context(|| format!("Could not connect to the notification bus at {address}"))?,
+        None => Connection::session()
+            .await
+            .context("Could not connect to the session D-Bus daemon")?,
+    };
+    let notifications = NotificationsProxy::new(&session).await?;
+    let bus = DBusProxy::new(conn).await?;
+    let rule = MatchRule::builder()
+        .msg_type(Type::Signal)
+        .interface("sh.lly.NmFileSecretAgent")?
+        .member("RequestFailed")?
+        .path(AGENT_PATH)?
+        .build();
+    let mut signals = MessageStream::for_match_rule(rule, conn, None)
+        .await
+        .context("Could not subscribe to the RequestFailed signal of the agent")?;
+    tracing::info!("Waiting for requests which the agent fails to serve");
+
+    let repeat_after = Duration::from_secs(args.repeat_after);
+    // the last notification by connection uuid with its id, error code and time
+    let mut shown: HashMap<String, (u32, String, Instant)> = HashMap::new();
+    while let Some(message) = signals.next().await {
+        let message = message.context("Lost the connection to the bus of the agent")?;
+        // any process may emit a signal on the path of the agent
+        let Some(sender) = message.header().sender().cloned() else {
+            continue;
+        };
+        match bus.get_connection_unix_user(sender.clone().into()).await {
+            Ok(uid) if uid == args.agent_uid => {}
+            Ok(uid) => {
+                tracing::warn!(%sender, uid, "Ignoring RequestFailed signal of a sender that is not the agent");
+                continue;
+            }
+            Err(e) => {
+                tracing::warn!(%sender, "Could not query user id of sender: {e}");
+                continue;
+            }
+        }
+        let (connection_id, connection_uuid, setting_name, error_code, error_message) =
+            match message
+                .body()
+                .deserialize::<(String, String, String, String, String)>()
+            {
+                Ok(body) => body,
+                Err(e) => {
+                    tracing::warn!("Ignoring malformed RequestFailed signal: {e}");
+                    continue;
+                }
+            };
+        let previous = shown.get(&connection_uuid);
+        if let Some((_, code, at)) = previous {
+            if *code == error_code && at.elapsed() < repeat_after {
+                tracing::debug!(
+                    "Not notifying again about {error_code} of connection {connection_uuid}"
+                );
+                continue;
+            }
+        }
+
+        let summary = match connection_id.is_empty() {
+            true => "Could not provide network secrets".to_owned(),
+            false => format!("Could not provide secrets for {connection_id}"),
+        };
+        let body = format!("{setting_name}: [{error_code}] {error_message}");
+        let hints = HashMap::from([("urgency", Value::from(URGENCY_CRITICAL))]);
+        let replaces_id = previous.map(|(id, _, _)| *id).unwrap_or_default();
+        match notifications
+            .notify(
+                "nm-file-secret-agent",
+                replaces_id,
+                "network-error",
+                &summary,
+                &body,
+                &[],
+                hints,
+                -1,
+            )
+            .await
+        {
+            Ok(id) => {
+                tracing::info!("Notified about {error_code} of connection {connection_uuid}");
+                shown.insert(connection_uuid, (id, error_code, Instant::now()));
+            }
+            Err(e) => tracing::warn!("Could not show notification: {e}"),
+        }
+    }
+    anyhow::bail!("Lost the connection to the bus of the agent")
+}
@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+
+use anyhow::Context;
+use nm_secret_agent_core::mapping::SecretRequest;
+use zbus::{
+    zvariant::{OwnedObjectPath, Value},
+    Connection,
+};
+
+/// Flag of `CheckAuthorization` allowing polkit to ask the user to authenticate if the action requires it
+const ALLOW_USER_INTERACTION: u32 = 0x1;
+
+/// The interface through which polkit decides whether a subject is authorized for an action
+///
+/// See [reference](https://www.freedesktop.org/software/polkit/docs/latest/eggdbus-interface-org.freedesktop.PolicyKit1.Authority.html).
+#[zbus::proxy(
+    interface = "org.freedesktop.PolicyKit1.Authority",
+    default_service = "org.freedesktop.PolicyKit1",
+    default_path = "/org/freedesktop/PolicyKit1/Authority"
+)]
+trait Authority {
+    /// Whether `subject` is authorized for `action_id`, whether it could be after authenticating and further details
+    fn check_authorization(
+        &self,
+        subject: &(&str, HashMap<&str, Value<'_>>),
+        action_id: &str,
+        details: &HashMap<&str, &str>,
+        flags: u32,
+        cancellation_id: &str,
+    ) -> zbus::Result<(bool, bool, HashMap<String, String>)>;
+}
+
+/// The seat whose active session is assumed to have requested an activation
+///
+/// See [reference](https://www.freedesktop.org/software/systemd/man/latest/org.freedesktop.login1.html).
+#[zbus::proxy(
+    interface = "org.freedesktop.login1.Seat",
+    default_service = "org.freedesktop.login1",
+    default_path = "/org/freedesktop/login1/seat/seat0"
+)]
+trait Seat {
+    /// Id and object path of the session currently in the foreground of the seat
+    #[zbus(property)]
+    fn active_session(&self) -> zbus::Result<(String, OwnedObjectPath)>;
+}
+
+/// Check whether the user who requested `request` is authorized for the polkit action `action`
+///
+/// NetworkManager does not tell agents who requested an activation, so the subject is the active session of the
+/// primary seat. Polkit may ask that session's user to authenticate. Details about the connection are passed along
+/// so that polkit rules can restrict the action to specific connections.
+pub async fn check_authorization(
+    conn: &Connection,
+    action: &str,
+    request: &SecretRequest,
+) -> anyhow::Result<bool> {
+    let (session_id, _) = SeatProxy::new(conn)
+        .await?
+        .active_session()
+        .await
+        .context("Could not determine the active session of seat0")?;
+    if session_id.is_empty() {
+        anyhow::bail!("seat0 has no active session");
+    }
+    let subject = (
+        "unix-session",
+        HashMap::from([("session-id", Value::from(session_id.as_str()))]),
+    );
+    let details = HashMap::from([
+        ("connection_id", request.conn_id.as_str()),
+        ("connection_uuid", request.conn_uuid.as_str()),
+        ("connection_type", request.conn_type.as_str()),
+        ("setting_name", request.setting_name.as_str()),
+    ]);
+    let (authorized, _, _) = AuthorityProxy::new(conn)
+        .await?
+        .check_authorization(&subject, action, &details, ALLOW_USER_INTERACTION, "")
+        .await
+        .context("Could not check the authorization with polkit")?;
+    tracing::debug!(
+        session = session_id,
+        action,
+        authorized,
+        "Checked polkit authorization"
+    );
+    Ok(authorized)
+}
@@ -0,0 +1,66 @@
+use std::{
+    env,
+    os::{
+        linux::net::SocketAddrExt,
+        unix::net::{SocketAddr, UnixDatagram},
+    },
+    time::Duration,
+};
+
+use anyhow::Context;
+
+/// Send `state` (e.g. `READY=1`) to the service manager via the protocol described in `sd_notify(3)`
+///
+/// Problems are only logged since the agent works the same without service manager notifications.
+pub fn notify(state: &str) {
+    if let Err(e) = try_notify(state) {
+        tracing::warn!("Could not notify service manager: {e:#}");
+    }
+}
+
+/// Notify the service manager that the agent is reloading its configuration
+///
+/// `Type=notify-reload` units require the reload to be acknowledged with a monotonic timestamp.
+/// The reload must be concluded with `READY=1` once it is done.
+pub fn notify_reloading() {
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: clock_gettime only writes into the provided timespec
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) };
+    let usec = now.tv_sec as u64 * 1_000_000 + now.tv_nsec as u64 / 1_000;
+    notify(&format!("RELOADING=1\nMONOTONIC_USEC={usec}"));
+}
+
+fn try_notify(state: &str) -> anyhow::Result<()> {
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return Ok(());
+    };
+    let path = path
+        .into_string()
+        .map_err(|_| anyhow::anyhow!("NOTIFY_SOCKET is not valid UTF-8"))?;
+    let addr = match path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name),
+        None => SocketAddr::from_pathname(&path),
+    }
+    .with_context(|| format!("NOTIFY_SOCKET {path} is not a valid socket address"))?;
+
+    let socket = UnixDatagram::unbound().context("Could not create notification socket")?;
+    socket
+        .send_to_addr(state.as_bytes(), &addr)
+        .with_context(|| format!("Could not send notification to {path}"))?;
+    tracing::trace!("Notified service manager with {state:?}");
+    Ok(())
+}
+
+/// The interval in which the service manager expects `WATCHDOG=1` notifications or `None` if the watchdog is disabled
+pub fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(std::process::id()) {
+            return None;
+        }
+    }
+    let usec = env::var("WATCHDOG_USEC").ok()?.parse::<u64>().ok()?;
+    Some(Duration::from_micros(usec)).filter(|interval| !interval.is_zero())
+}
@@ -0,0 +1,1046 @@
+//! Tests which run the agent against a fake NetworkManager on a private D-Bus daemon
+
+mod common;
+
+use std::collections::HashMap;
+
+use common::{string_secret, FakeNm};
+use zbus::zvariant::{OwnedValue, Value};
+
+const CONFIG: &str = r#"
+[[entry]]
+match_id = "home"
+match_setting = "802-11-wireless-security"
+key = "psk"
+file = "psk"
+trim = "newline"
+"#;
+
+/// The `AllowInteraction` flag of GetSecrets requests
+const ALLOW_INTERACTION: u32 = 0x1;
+
+/// Properties of the `connection` setting of a Wi-Fi connection with the given id
+fn wifi(id: &str) -> [(&str, Value<'_>); 3] {
+    [
+        ("id", Value::from(id)),
+        ("uuid", Value::from("1b7cfa2e-4f4b-4b8b-9b5e-3c1d0bdd6a51")),
+        ("type", Value::from("802-11-wireless")),
+    ]
+}
+
+#[tokio::test]
+async fn registers_with_network_manager() {
+    let nm = FakeNm::start().await;
+    nm.write_file("psk", "correct horse battery staple\n");
+    let _agent = nm.spawn_agent(CONFIG, &["--vpn-hints"]);
+    let registration = nm.wait_for_registration().await;
+    assert_eq!(registration.identifier, "nm-file-secret-agent");
+    // the VpnHints capability
+    assert_eq!(registration.capabilities, 1);
+}
+
+#[tokio::test]
+async fn serves_secrets_from_files() {
+    let nm = FakeNm::start().await;
+    nm.write_file("psk", "correct horse battery staple\n");
+    let _agent = nm.spawn_agent(CONFIG, &[]);
+    let secrets = nm
+        .get_secrets(
+            &[("connection", &wifi("home"))],
+            "802-11-wireless-security",
+            &["psk"],
+            0,
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        string_secret(&secrets, "802-11-wireless-security", "psk").as_deref(),
+        Some("correct horse battery staple")
+    );
+}
+
+#[tokio::test]
+async fn normalizes_byte_order_mark_and_crlf_line_endings() {
+    let nm = FakeNm::start().await;
+    nm.write_file("psk", "\u{feff}correct horse battery staple\r\n");
+    let agent = nm.spawn_agent(CONFIG, &[]);
+    for _ in 0..2 {
+        let secrets = nm
+            .get_secrets(
+                &[("connection", &wifi("home"))],
+                "802-11-wireless-security",
+                &["psk"],
+                0,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            string_secret(&secrets, "802-11-wireless-security", "psk").as_deref(),
+            Some("correct horse battery staple")
+        );
+    }
+    agent
+        .wait_for_log("Removed a UTF-8 byte order mark from the start of a secret value")
+        .await;
+    // the file is only warned about once, later requests log the normalization at debug level
+    let log = std::fs::read_to_string(nm.path("agent.log")).unwrap();
+    let warnings = log
+        .lines()
+        .filter(|line| line.contains("WARN") && line.contains("CRLF line endings"))
+        .count();
+    assert_eq!(warnings, 1);
+}
+
+#[tokio::test]
+async fn serves_multiple_keys_from_one_file() {
+    let nm = FakeNm::start().await;
+    nm.write_file(
+        "credentials",
+        "# 802.1x credentials\nidentity = alice\npassword=hunter2\n",
+    );
+    let config = r#"
+[[entry]]
+match_id = "home"
+match_setting = "802-1x"
+keys = ["identity", "password"]
+file = "credentials"
+"#;
+    let _agent = nm.spawn_agent(config, &[]);
+    let secrets = nm
+        .get_secrets(&[("connection", &wifi("home"))], "802-1x", &[], 0)
+        .await
+        .unwrap();
+    assert_eq!(
+        string_secret(&secrets, "802-1x", "identity").as_deref(),
+        Some("alice")
+    );
+    assert_eq!(
+        string_secret(&secrets, "802-1x", "password").as_deref(),
+        Some("hunter2")
+    );
+}
+
+#[tokio::test]
+async fn rejects_entries_with_key_and_keys() {
+    let nm = FakeNm::start().await;
+    nm.write_file("credentials", "password=hunter2\n");
+    let config = r#"
+[[entry]]
+match_id = "home"
+key = "password"
+keys = ["identity", "password"]
+file = "credentials"
+"#;
+    let output = nm.run_command(config, &["list"]).await;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    // the exit code of config-invalid
+    assert_eq!(output.status.code(), Some(3), "{stderr}");
+    assert!(
+        stderr.contains("entry 0 must specify either key or keys"),
+        "{stderr}"
+    );
+}
+
+#[tokio::test]
+async fn rejects_invalid_totp_sources() {
+    let nm = FakeNm::start().await;
+    nm.write_file("totp", "JBSWY3DPEHPK3PXP\n");
+    for (totp, error) in [
+        ("digits = 12", "TOTP codes must have between 1 and 9 digits"),
+        ("period = 0", "TOTP period must not be 0"),
+        ("digit = 8", "unknown field `digit`"),
+    ] {
+        let config = format!(
+            "[[entry]]\nmatch_id = \"office\"\nkey = \"otp\"\ntotp = {{ secret_file = \"totp\", {totp} }}\n"
+        );
+        let output = nm.run_command(&config, &["list"]).await;
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        // the exit code of config-invalid
+        assert_eq!(output.status.code(), Some(3), "{stderr}");
+        assert!(stderr.contains(error), "{stderr}");
+    }
+}
+
+#[tokio::test]
+async fn replies_empty_secrets_without_matching_entry() {
+    let nm = FakeNm::start().await;
+    nm.write_file("psk", "correct horse battery staple\n");
+    let _agent = nm.spawn_agent(CONFIG, &[]);
+    let secrets = nm
+        .get_secrets(
+            &[("connection", &wifi("office"))],
+            "802-11-wireless-security",
+            &[],
+            0,
+        )
+        .await
+        .unwrap();
+    assert!(secrets.values().all(|setting| setting.is_empty()));
+}
+
+#[tokio::test]
+async fn replies_no_secrets_without_matching_entry_if_configured() {
+    let nm = FakeNm::start().await;
+    nm.write_file("psk", "correct horse battery staple\n");
+    let _agent = nm.spawn_agent(CONFIG, &["--no-match", "no-secrets"]);
+    let error = nm
+        .get_secrets(
+            &[("connection", &wifi("office"))],
+            "802-11-wireless-security",
+            &[],
+            0,
+        )
+        .await
+        .unwrap_err();
+    let zbus::Error::MethodError(name, Some(message), _) = error else {
+        panic!("Expected a method error but got {error:?}");
+    };
+    assert_eq!(
+        name.as_str(),
+        "org.freedesktop.NetworkManager.SecretAgent.NoSecrets"
+    );
+    assert!(message.starts_with("[no-secrets] "), "{message}");
+}
+
+#[tokio::test]
+async fn runs_no_commands_in_dry_run() {
+    let nm = FakeNm::start().await;
+    nm.write_file("psk", "correct horse battery staple\n");
+    let config = format!(
+        r#"
+[fallback]
+command = ["sh", "-c", "touch ran && echo {{}}"]
+[prompt]
+command = ["sh", "-c", "touch asked && echo typed-by-user"]
+{CONFIG}"#
+    );
+    let agent = nm.spawn_agent(&config, &["--dry-run"]);
+    let error = nm
+        .get_secrets(
+            &[("connection", &wifi("office"))],
+            "802-11-wireless-security",
+            &[],
+            ALLOW_INTERACTION,
+        )
+        .await
+        .unwrap_err();
+    let zbus::Error::MethodError(name, _, _) = error else {
+        panic!("Expected a method error but got {error:?}");
+    };
+    assert_eq!(
+        name.as_str(),
+        "org.freedesktop.NetworkManager.SecretAgent.NoSecrets"
+    );
+    agent
+        .wait_for_log("Dry run: not asking fallback command")
+        .await;
+    agent
+        .wait_for_log("Dry run: not prompting for [802-11-wireless-security.psk]")
+        .await;
+    assert!(!nm.path("ran").exists());
+    assert!(!nm.path("asked").exists());
+}
+
+#[tokio::test]
+async fn reports_missing_files() {
+    let nm = FakeNm::start().await;
+    let psk = nm.write_file("psk", "correct horse battery staple\n");
+    let _agent = nm.spawn_agent(CONFIG, &[]);
+    // the file is only removed after it passed validation at startup
+    nm.wait_for_registration().await;
+    std::fs::remove_file(psk).unwrap();
+    let error = nm
+        .get_secrets(
+            &[("connection", &wifi("home"))],
+            "802-11-wireless-security",
+            &[],
+            0,
+        )
+        .await
+        .unwrap_err();
+    let zbus::Error::MethodError(name, Some(message), _) = error else {
+        panic!("Expected a method error but got {error:?}");
+    };
+    assert_eq!(
+        name.as_str(),
+        "org.freedesktop.NetworkManager.SecretAgent.Failed"
+    );
+    assert!(message.starts_with("[file-missing] "), "{message}");
+}
+
+#[tokio::test]
+async fn denies_callers_other_than_network_manager() {
+    let nm = FakeNm::start().await;
+    nm.write_file("psk", "correct horse battery staple\n");
+    let _agent = nm.spawn_agent(CONFIG, &[]);
+    let error = nm
+        .get_secrets_as_impostor(&[("connection", &wifi("home"))], "802-11-wireless-security")
+        .await
+        .unwrap_err();
+    let zbus::Error::MethodError(name, _, _) = error else {
+        panic!("Expected a method error but got {error:?}");
+    };
+    assert_eq!(
+        name.as_str(),
+        "org.freedesktop.NetworkManager.SecretAgent.PermissionDenied"
+    );
+}
+
+#[tokio::test]
+async fn records_requests_without_secrets() {
+    let nm = FakeNm::start().await;
+    nm.write_file("psk", "correct horse battery staple\n");
+    let _agent = nm.spawn_agent(CONFIG, &["--record-requests", "requests.jsonl"]);
+    nm.get_secrets(
+        &[("connection", &wifi("home"))],
+        "802-11-wireless-security",
+        &["psk"],
+        0,
+    )
+    .await
+    .unwrap();
+    let recorded = std::fs::read_to_string(nm.path("requests.jsonl")).unwrap();
+    let request: serde_json::Value = serde_json::from_str(recorded.trim()).unwrap();
+    assert_eq!(request["conn_id"], "home");
+    assert_eq!(request["setting_name"], "802-11-wireless-security");
+    assert!(!recorded.contains("correct horse"));
+}
+
+#[tokio::test]
+async fn checks_entries_against_connections() {
+    let nm = FakeNm::start().await;
+    nm.write_file("psk", "correct horse battery staple\n");
+    nm.add_profile(&[
+        ("connection", &wifi("home")),
+        (
+            "802-11-wireless-security",
+            &[("key-mgmt", Value::from("wpa-psk"))],
+        ),
+    ])
+    .await;
+    let config = format!(
+        "{CONFIG}{}",
+        r#"
+[[entry]]
+match_id = "hoem"
+key = "password"
+file = "psk"
+"#
+    );
+
+    let output = nm.run_command(&config, &["check-connections"]).await;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    // the exit code of config-invalid
+    assert_eq!(output.status.code(), Some(3), "{stdout}");
+    assert!(
+        stdout.contains("#1 providing password matches no connection"),
+        "{stdout}"
+    );
+    assert!(!stdout.contains("#0"), "{stdout}");
+
+    nm.add_profile(&[("connection", &wifi("hoem"))]).await;
+    let output = nm.run_command(&config, &["check-connections"]).await;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+}
+
+#[tokio::test]
+async fn logs_when_entries_stop_and_start_matching() {
+    let nm = FakeNm::start().await;
+    nm.write_file("psk", "correct horse battery staple\n");
+    let home = [
+        ("connection", &wifi("home")[..]),
+        (
+            "802-11-wireless-security",
+            &[("key-mgmt", Value::from("wpa-psk"))],
+        ),
+    ];
+    let path = nm.add_profile(&home).await;
+    let agent = nm.spawn_agent(CONFIG, &[]);
+    agent
+        .wait_for_log("All config entries match at least one of the 1 connections")
+        .await;
+
+    nm.remove_profile(&path).await;
+    agent
+        .wait_for_log(r#"Config entry 0 no longer matches any connection since connection "home" was removed"#)
+        .await;
+    nm.add_profile(&home).await;
+    agent
+        .wait_for_log(
+            r#"Config entry 0 matches a connection again since connection "home" was added"#,
+        )
+        .await;
+}
+
+#[tokio::test]
+async fn refuses_expired_entries() {
+    let nm = FakeNm::start().await;
+    nm.write_file("psk", "correct horse battery staple\n");
+    let config = format!("{CONFIG}not_after = \"2000-01-01T00:00:00Z\"\n");
+    let _agent = nm.spawn_agent(&config, &[]);
+    let error = nm
+        .get_secrets(
+            &[("connection", &wifi("home"))],
+            "802-11-wireless-security",
+            &[],
+            0,
+        )
+        .await
+        .unwrap_err();
+    let zbus::Error::MethodError(_, Some(message), _) = error else {
+        panic!("Expected a method error but got {error:?}");
+    };
+    assert!(message.starts_with("[expired] "), "{message}");
+}
+
+#[tokio::test]
+async fn serves_peer_preshared_keys_from_directory() {
+    const PEER: &str = "xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=";
+    const OTHER_PEER: &str = "TrMvSoP4jYQlY6RIzBgbssQqY3vxI2Pi+y71lOWWXX0=";
+    let nm = FakeNm::start().await;
+    std::fs::create_dir(nm.path("peers")).unwrap();
+    // slashes cannot be part of file names so they are written in the URL-safe alphabet
+    nm.write_file(
+        "peers/xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=",
+        "FpCyhws9cxwWoV4xELtfJvjJN+zQVRPISllRWgeopVE=\n",
+    );
+    nm.write_file(
+        "peers/TrMvSoP4jYQlY6RIzBgbssQqY3vxI2Pi-y71lOWWXX0=",
+        "HEx1i8ilTkVcIN1tsmCwdC3D3nw9Tt9rEkgYhSt+6e4=\n",
+    );
+    let config = r#"
+[[entry]]
+match_setting = "wireguard"
+key = "peers.*.preshared-key"
+peers_dir = "peers"
+trim = "newline"
+"#;
+    let _agent = nm.spawn_agent(config, &[]);
+    let peers = Value::from(vec![HashMap::from([("public-key", Value::from(PEER))])]);
+    let secrets = nm
+        .get_secrets(
+            &[
+                (
+                    "connection",
+                    &[
+                        ("id", Value::from("wg0")),
+                        ("uuid", Value::from("5d1b7e8c-2f2c-4c3e-9a7e-8f0e6a1b2c3d")),
+                        ("type", Value::from("wireguard")),
+                    ],
+                ),
+                ("wireguard", &[("peers", peers)]),
+            ],
+            "wireguard",
+            &[],
+            0,
+        )
+        .await
+        .unwrap();
+    let peers: Vec<HashMap<String, OwnedValue>> = secrets["wireguard"]["peers"]
+        .try_clone()
+        .unwrap()
+        .try_into()
+        .unwrap();
+    // the other peer is left out since the connection does not have it
+    assert_eq!(peers.len(), 1, "{OTHER_PEER} must not be returned");
+    assert_eq!(
+        String::try_from(peers[0]["public-key"].try_clone().unwrap()).unwrap(),
+        PEER
+    );
+    assert_eq!(
+        String::try_from(peers[0]["preshared-key"].try_clone().unwrap()).unwrap(),
+        "FpCyhws9cxwWoV4xELtfJvjJN+zQVRPISllRWgeopVE="
+    );
+}
+
+#[tokio::test]
+async fn prompts_for_secrets_if_interaction_is_allowed() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let nm = FakeNm::start().await;
+    nm.write_file("psk", "correct horse battery staple\n");
+    let prompt = nm.write_file(
+        "prompt",
+        "#!/bin/sh\necho \"$NM_KEY of $NM_CONNECTION_ID\" >> asked\necho typed-by-user\n",
+    );
+    std::fs::set_permissions(&prompt, std::fs::Permissions::from_mode(0o700)).unwrap();
+    let config = format!("[prompt]\ncommand = [\"./prompt\"]\n{CONFIG}");
+    let _agent = nm.spawn_agent(&config, &[]);
+
+    let secrets = nm
+        .get_secrets(
+            &[("connection", &wifi("office"))],
+            "802-11-wireless-security",
+            &[],
+            0,
+        )
+        .await
+        .unwrap();
+    assert!(secrets.values().all(|setting| setting.is_empty()));
+
+    // the second request is answered from the cache
+    for _ in 0..2 {
+        let secrets = nm
+            .get_secrets(
+                &[("connection", &wifi("office"))],
+                "802-11-wireless-security",
+                &[],
+                ALLOW_INTERACTION,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            string_secret(&secrets, "802-11-wireless-security", "psk").as_deref(),
+            Some("typed-by-user")
+        );
+    }
+    let asked = std::fs::read_to_string(nm.path("asked")).unwrap();
+    assert_eq!(asked, "psk of office\n");
+}
+
+#[tokio::test]
+async fn cancels_requests_exceeding_the_timeout() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let nm = FakeNm::start().await;
+    nm.write_file("psk", "correct horse battery staple\n");
+    let prompt = nm.write_file("prompt", "#!/bin/sh\nsleep 10\n");
+    std::fs::set_permissions(&prompt, std::fs::Permissions::from_mode(0o700)).unwrap();
+    let config = format!("[prompt]\ncommand = [\"./prompt\"]\n{CONFIG}");
+    let _agent = nm.spawn_agent(&config, &["--request-timeout", "1"]);
+
+    let error = nm
+        .get_secrets(
+            &[("connection", &wifi("office"))],
+            "802-11-wireless-security",
+            &[],
+            ALLOW_INTERACTION,
+        )
+        .await
+        .unwrap_err();
+    let zbus::Error::MethodError(name, Some(message), _) = error else {
+        panic!("Expected a method error but got {error:?}");
+    };
+    assert_eq!(
+        name.as_str(),
+        "org.freedesktop.NetworkManager.SecretAgent.AgentCanceled"
+    );
+    assert!(message.starts_with("[canceled] "), "{message}");
+}
+
+#[tokio::test]
+async fn notifies_about_failed_requests() {
+    let nm = FakeNm::start().await;
+    let psk = nm.write_file("psk", "correct horse battery staple\n");
+    let _agent = nm.spawn_agent(CONFIG, &[]);
+    nm.wait_for_registration().await;
+    let notifier = nm.spawn_notifier(None);
+    notifier
+        .wait_for_log("Waiting for requests which the agent fails to serve")
+        .await;
+    std::fs::remove_file(psk).unwrap();
+    nm.get_secrets(
+        &[("connection", &wifi("home"))],
+        "802-11-wireless-security",
+        &[],
+        0,
+    )
+    .await
+    .unwrap_err();
+
+    let (summary, body) = nm.wait_for_notification().await;
+    assert_eq!(summary, "Could not provide secrets for home");
+    assert!(
+        body.starts_with("802-11-wireless-security: [file-missing] "),
+        "{body}"
+    );
+}
+
+#[tokio::test]
+async fn ignores_failure_signals_of_other_users() {
+    let nm = FakeNm::start().await;
+    let psk = nm.write_file("psk", "correct horse battery staple\n");
+    let _agent = nm.spawn_agent(CONFIG, &[]);
+    nm.wait_for_registration().await;
+    // SAFETY: getuid is always successful
+    let other_uid = unsafe { libc::getuid() } + 1;
+    let notifier = nm.spawn_notifier(Some(other_uid));
+    notifier
+        .wait_for_log("Waiting for requests which the agent fails to serve")
+        .await;
+    std::fs::remove_file(psk).unwrap();
+    nm.get_secrets(
+        &[("connection", &wifi("home"))],
+        "802-11-wireless-security",
+        &[],
+        0,
+    )
+    .await
+    .unwrap_err();
+
+    notifier
+        .wait_for_log("Ignoring RequestFailed signal of a sender that is not the agent")
+        .await;
+}
+
+#[tokio::test]
+async fn saves_and_serves_encrypted_secrets() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let nm = FakeNm::start().await;
+    std::fs::create_dir(nm.path("bin")).unwrap();
+    // stands in for age by encoding content as base64 and records its arguments
+    let age = nm.write_file(
+        "bin/age",
+        "#!/bin/sh\necho \"$@\" >> age-calls\ncase \"$1\" in --decrypt) exec base64 -d ;; *) exec base64 ;; esac\n",
+    );
+    std::fs::set_permissions(&age, std::fs::Permissions::from_mode(0o700)).unwrap();
+    nm.write_file("psk", "Y29ycmVjdCBob3JzZSBiYXR0ZXJ5IHN0YXBsZQo=\n");
+    let config = format!(
+        "{CONFIG}writable = true\nencryption = {{ type = \"age\", identity = \"key.txt\", recipients = [\"age1example\"] }}\n"
+    );
+    let _agent = nm.spawn_agent(&config, &[]);
+
+    let secrets = nm
+        .get_secrets(
+            &[("connection", &wifi("home"))],
+            "802-11-wireless-security",
+            &[],
+            0,
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        string_secret(&secrets, "802-11-wireless-security", "psk").as_deref(),
+        Some("correct horse battery staple")
+    );
+
+    nm.save_secrets(&[
+        ("connection", &wifi("home")),
+        (
+            "802-11-wireless-security",
+            &[("psk", Value::from("Tr0ub4dor&3"))],
+        ),
+    ])
+    .await
+    .unwrap();
+    let saved = std::fs::read_to_string(nm.path("psk")).unwrap();
+    assert_eq!(saved, "VHIwdWI0ZG9yJjM=\n");
+    let calls = std::fs::read_to_string(nm.path("age-calls")).unwrap();
+    assert!(
+        calls.contains("--encrypt --recipient age1example"),
+        "{calls}"
+    );
+    let secrets = nm
+        .get_secrets(
+            &[("connection", &wifi("home"))],
+            "802-11-wireless-security",
+            &[],
+            0,
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        string_secret(&secrets, "802-11-wireless-security", "psk").as_deref(),
+        Some("Tr0ub4dor&3")
+    );
+}
+
+#[tokio::test]
+async fn expands_placeholders_in_file_paths() {
+    let nm = FakeNm::start().await;
+    std::fs::create_dir_all(nm.path("wifi/1b7cfa2e-4f4b-4b8b-9b5e-3c1d0bdd6a51")).unwrap();
+    nm.write_file(
+        "wifi/1b7cfa2e-4f4b-4b8b-9b5e-3c1d0bdd6a51/psk",
+        "correct horse battery staple\n",
+    );
+    let config = r#"
+[[entry]]
+match_setting = "802-11-wireless-security"
+key = "psk"
+file = "wifi/{uuid}/{key}"
+trim = "newline"
+"#;
+    let _agent = nm.spawn_agent(config, &[]);
+    let secrets = nm
+        .get_secrets(
+            &[("connection", &wifi("home"))],
+            "802-11-wireless-security",
+            &[],
+            0,
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        string_secret(&secrets, "802-11-wireless-security", "psk").as_deref(),
+        Some("correct horse battery staple")
+    );
+}
+
+#[tokio::test]
+async fn applies_overrides_of_this_host() {
+    let nm = FakeNm::start().await;
+    let hostname = std::fs::read_to_string("/proc/sys/kernel/hostname").unwrap();
+    std::fs::create_dir_all(nm.path(&format!("overrides/{}", hostname.trim()))).unwrap();
+    nm.write_file(
+        &format!("overrides/{}/wifi.toml", hostname.trim()),
+        "[[entry]]\nid = \"wifi\"\nmatch_id = \"home\"\nkey = \"psk\"\nfile = \"../../host-psk\"\ntrim = \"newline\"\n",
+    );
+    nm.write_file("psk", "correct horse battery staple\n");
+    nm.write_file("host-psk", "Tr0ub4dor&3\n");
+    let overrides = nm.path("overrides");
+    let _agent = nm.spawn_agent(
+        &format!("{CONFIG}id = \"wifi\"\n"),
+        &["--overrides-dir", overrides.to_str().unwrap()],
+    );
+    let secrets = nm
+        .get_secrets(
+            &[("connection", &wifi("home"))],
+            "802-11-wireless-security",
+            &[],
+            0,
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        string_secret(&secrets, "802-11-wireless-security", "psk").as_deref(),
+        Some("Tr0ub4dor&3")
+    );
+}
+
+#[tokio::test]
+async fn expands_host_variables() {
+    let nm = FakeNm::start().await;
+    let hostname = std::fs::read_to_string("/proc/sys/kernel/hostname").unwrap();
+    std::fs::create_dir_all(nm.path(hostname.trim())).unwrap();
+    nm.write_file(
+        &format!("{}/psk", hostname.trim()),
+        "correct horse battery staple\n",
+    );
+    let config = r#"
+[[entry]]
+match_id = "home-{hostname}"
+key = "psk"
+file = "{hostname}/psk"
+trim = "newline"
+"#;
+    let _agent = nm.spawn_agent(config, &[]);
+    let secrets = nm
+        .get_secrets(
+            &[("connection", &wifi(&format!("home-{}", hostname.trim())))],
+            "802-11-wireless-security",
+            &[],
+            0,
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        string_secret(&secrets, "802-11-wireless-security", "psk").as_deref(),
+        Some("correct horse battery staple")
+    );
+}
+
+#[tokio::test]
+async fn loads_signed_remote_configs() {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use std::os::unix::fs::PermissionsExt;
+
+    let nm = FakeNm::start().await;
+    std::fs::create_dir_all(nm.path("bin")).unwrap();
+    std::fs::create_dir_all(nm.path("remote")).unwrap();
+    // stands in for curl by serving the files in the remote directory by the last component of the URL
+    let curl = nm.write_file(
+        "bin/curl",
+        &format!(
+            "#!/bin/sh\nfor url; do :; done\nexec cat \"{}/${{url##*/}}\"\n",
+            nm.path("remote").display()
+        ),
+    );
+    std::fs::set_permissions(&curl, std::fs::Permissions::from_mode(0o700)).unwrap();
+
+    // a signify key pair and signature of the remote config
+    let key_pair = ed25519_compact::KeyPair::from_seed(ed25519_compact::Seed::new([7; 32]));
+    let key_id = [1, 2, 3, 4, 5, 6, 7, 8];
+    let public_key = [b"Ed".as_slice(), &key_id, key_pair.pk.as_slice()].concat();
+    nm.write_file(
+        "config.pub",
+        &format!(
+            "untrusted comment: test key\n{}\n",
+            STANDARD.encode(public_key)
+        ),
+    );
+    let remote_config = format!(
+        "[[entry]]\nid = \"wifi\"\nmatch_id = \"home\"\nkey = \"psk\"\nfile = \"{}\"\ntrim = \"newline\"\n",
+        nm.path("remote-psk").display()
+    );
+    let signature = key_pair.sk.sign(remote_config.as_bytes(), None);
+    let signature = [b"Ed".as_slice(), &key_id, signature.as_slice()].concat();
+    nm.write_file("remote/agent.toml", &remote_config);
+    nm.write_file(
+        "remote/agent.toml.sig",
+        &format!(
+            "untrusted comment: test signature\n{}\n",
+            STANDARD.encode(signature)
+        ),
+    );
+    nm.write_file("psk", "correct horse battery staple\n");
+    nm.write_file("remote-psk", "Tr0ub4dor&3\n");
+
+    let _agent = nm.spawn_agent(
+        &format!("{CONFIG}id = \"wifi\"\n"),
+        &[
+            "--conf",
+            "https://config.example/agent.toml",
+            "--conf-signature-key",
+            "config.pub",
+            "--conf-cache-dir",
+            "cache",
+        ],
+    );
+    let secrets = nm
+        .get_secrets(
+            &[("connection", &wifi("home"))],
+            "802-11-wireless-security",
+            &[],
+            0,
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        string_secret(&secrets, "802-11-wireless-security", "psk").as_deref(),
+        Some("Tr0ub4dor&3")
+    );
+    // the verified copy is cached for offline boots
+    let cached = std::fs::read_dir(nm.path("cache"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(cached.len(), 2, "{cached:?}");
+}
+
+#[tokio::test]
+async fn keeps_previous_config_when_reload_fails() {
+    let nm = FakeNm::start().await;
+    nm.write_file("psk", "correct horse battery staple\n");
+    let agent = nm.spawn_agent(CONFIG, &[]);
+    nm.wait_for_registration().await;
+
+    // the new config parses but references a file that does not exist
+    nm.write_file(
+        "config.toml",
+        &CONFIG.replace("\"psk\"\ntrim", "\"missing\"\ntrim"),
+    );
+    agent.signal(libc::SIGHUP);
+    agent
+        .wait_for_log("Could not reload config; keeping the previous one")
+        .await;
+
+    let secrets = nm
+        .get_secrets(
+            &[("connection", &wifi("home"))],
+            "802-11-wireless-security",
+            &[],
+            0,
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        string_secret(&secrets, "802-11-wireless-security", "psk").as_deref(),
+        Some("correct horse battery staple")
+    );
+}
+
+#[tokio::test]
+async fn fires_event_hooks() {
+    let nm = FakeNm::start().await;
+    nm.write_file("psk", "correct horse battery staple\n");
+    let config = format!(
+        "[[event_hook]]\nevents = [\"secrets-served\"]\ncommand = [\"sh\", \"-c\", \"cat > {}\"]\n{CONFIG}",
+        nm.path("event.json").display()
+    );
+    let _agent = nm.spawn_agent(&config, &[]);
+    nm.get_secrets(
+        &[("connection", &wifi("home"))],
+        "802-11-wireless-security",
+        &[],
+        0,
+    )
+    .await
+    .unwrap();
+
+    let event = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+        loop {
+            if let Ok(content) = std::fs::read_to_string(nm.path("event.json")) {
+                if let Ok(event) = serde_json::from_str::<serde_json::Value>(&content) {
+                    return event;
+                }
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .expect("The event hook did not run in time");
+    assert_eq!(event["event"], "secrets-served");
+    assert_eq!(event["setting_name"], "802-11-wireless-security");
+    assert_eq!(event["keys"], serde_json::json!(["psk"]));
+}
+
+#[tokio::test]
+async fn installs_service_tailored_to_the_config() {
+    let nm = FakeNm::start().await;
+    let config = format!(
+        "[prompt]\n[[entry]]\nmatch_id = \"home\"\nkey = \"psk\"\nfile = \"{}\"\nwritable = true\n",
+        nm.path("secrets/psk").display()
+    );
+    let root = nm.path("root");
+    let output = nm
+        .run_command(
+            &config,
+            &["--vpn-hints", "install", "--root", root.to_str().unwrap()],
+        )
+        .await;
+    assert!(output.status.success(), "{output:?}");
+
+    let unit =
+        std::fs::read_to_string(root.join("etc/systemd/system/nm-file-secret-agent.service"))
+            .unwrap();
+    let exec_start = unit
+        .lines()
+        .find_map(|line| line.strip_prefix("ExecStart="))
+        .unwrap();
+    assert!(exec_start.contains(&format!("--conf {}", nm.path("config.toml").display())));
+    assert!(exec_start.contains("--vpn-hints"));
+    assert!(unit.contains(&format!("ReadWritePaths={}", nm.path("secrets").display())));
+    // systemd-ask-password places the questions of the prompt there
+    assert!(unit.contains("ReadWritePaths=/run/systemd/ask-password"));
+    assert!(unit.contains("CAP_DAC_OVERRIDE"));
+    assert!(!unit.contains("CAP_SETUID"));
+
+    let policy =
+        std::fs::read_to_string(root.join("etc/dbus-1/system.d/nm-file-secret-agent.conf"))
+            .unwrap();
+    assert!(policy.contains("sh.lly.NmFileSecretAgent"));
+}
+
+#[tokio::test]
+async fn serves_only_entries_with_selected_tags() {
+    let nm = FakeNm::start().await;
+    nm.write_file("psk", "correct horse battery staple\n");
+    nm.write_file("other", "wrong\n");
+    let config = r#"
+[[entry]]
+match_id = "home"
+match_setting = "802-11-wireless-security"
+key = "psk"
+file = "psk"
+trim = "newline"
+tags = ["wifi", "site-a"]
+
+[[entry]]
+match_id = "home"
+match_setting = "802-11-wireless-security"
+key = "psk"
+file = "other"
+tags = ["wifi", "site-b"]
+
+[[entry]]
+match_id = "home"
+match_setting = "802-11-wireless-security"
+key = "psk"
+file = "missing"
+"#;
+    let _agent = nm.spawn_agent(config, &["--only-tags", "wifi", "--exclude-tags", "site-b"]);
+    let secrets = nm
+        .get_secrets(
+            &[("connection", &wifi("home"))],
+            "802-11-wireless-security",
+            &["psk"],
+            0,
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        string_secret(&secrets, "802-11-wireless-security", "psk").as_deref(),
+        Some("correct horse battery staple")
+    );
+}
+
+#[tokio::test]
+async fn serves_entries_of_the_selected_profile() {
+    let nm = FakeNm::start().await;
+    nm.write_file("psk", "shared\n");
+    nm.write_file("work-psk", "correct horse battery staple\n");
+    let config = r#"
+[[entry]]
+id = "home"
+match_id = "home"
+match_setting = "802-11-wireless-security"
+key = "psk"
+file = "psk"
+trim = "newline"
+
+[[profiles.work.entry]]
+id = "home"
+match_id = "home"
+match_setting = "802-11-wireless-security"
+key = "psk"
+file = "work-psk"
+trim = "newline"
+
+[[profiles.other.entry]]
+match_id = "home"
+key = "psk"
+file = "missing"
+"#;
+    let _agent = nm.spawn_agent(config, &["--profile", "work"]);
+    let secrets = nm
+        .get_secrets(
+            &[("connection", &wifi("home"))],
+            "802-11-wireless-security",
+            &["psk"],
+            0,
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        string_secret(&secrets, "802-11-wireless-security", "psk").as_deref(),
+        Some("correct horse battery staple")
+    );
+}
+
+#[tokio::test]
+async fn pushes_secrets_into_connection_profiles() {
+    let nm = FakeNm::start().await;
+    nm.write_file("psk", "correct horse battery staple\n");
+    let path = nm
+        .add_profile(&[
+            ("connection", &wifi("home")),
+            (
+                "802-11-wireless-security",
+                &[("key-mgmt", Value::from("wpa-psk"))],
+            ),
+        ])
+        .await;
+    nm.add_profile(&[("connection", &wifi("office"))]).await;
+
+    let output = nm.run_command(CONFIG, &["push", "--in-memory"]).await;
+    assert!(output.status.success(), "{output:?}");
+
+    let updates = nm.take_updates();
+    assert_eq!(updates.len(), 1, "{updates:?}");
+    assert_eq!(updates[0].path, path);
+    // NM_SETTINGS_UPDATE2_FLAG_IN_MEMORY
+    assert_eq!(updates[0].flags, 0x2);
+    assert_eq!(
+        string_secret(&updates[0].settings, "802-11-wireless-security", "psk").as_deref(),
+        Some("correct horse battery staple")
+    );
+    assert_eq!(
+        string_secret(&updates[0].settings, "802-11-wireless-security", "key-mgmt").as_deref(),
+        Some("wpa-psk")
+    );
+}
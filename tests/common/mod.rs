@@ -0,0 +1,616 @@
+//! A fake NetworkManager on a private D-Bus daemon against which the agent binary is run
+//!
+//! The fake owns `org.freedesktop.NetworkManager`, serves the `AgentManager` interface at which agents register and
+//! calls the `SecretAgent` interface of registered agents just like NetworkManager does. Connection profiles added via
+//! [`FakeNm::add_profile`] are served through the `Settings` interface. The same bus also hosts a fake notification
+//! daemon which records the notifications it is asked to show.
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::{BufRead, BufReader},
+    os::unix::fs::PermissionsExt,
+    path::PathBuf,
+    process::{Child, Command, Output, Stdio},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tempfile::TempDir;
+use tokio::{sync::Notify, time};
+use zbus::{
+    message::Header,
+    zvariant::{ObjectPath, OwnedObjectPath, OwnedValue, Value},
+    Connection,
+};
+
+/// How long the agent may take to start and register with the fake NetworkManager
+const REGISTRATION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Settings of a connection as NetworkManager passes them to secret agents
+pub type ConnectionSettings = HashMap<String, HashMap<String, OwnedValue>>;
+
+/// A secret agent that registered with the fake NetworkManager
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Registration {
+    pub sender: String,
+    pub identifier: String,
+    pub capabilities: u32,
+}
+
+/// Errors which the `AgentManager` interface of NetworkManager replies with
+#[derive(Debug, zbus::DBusError)]
+#[zbus(prefix = "org.freedesktop.NetworkManager.AgentManager")]
+enum AgentManagerError {
+    #[zbus(error)]
+    ZBus(zbus::Error),
+    PermissionDenied(String),
+}
+
+struct AgentManager {
+    registrations: Arc<Mutex<Vec<Registration>>>,
+    registered: Arc<Notify>,
+}
+
+#[zbus::interface(name = "org.freedesktop.NetworkManager.AgentManager")]
+impl AgentManager {
+    async fn register_with_capabilities(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        identifier: String,
+        capabilities: u32,
+    ) -> Result<(), AgentManagerError> {
+        let sender = header
+            .sender()
+            .map(|sender| sender.to_string())
+            .unwrap_or_default();
+        let mut registrations = self.registrations.lock().unwrap();
+        // like NetworkManager, refuse to register the same agent twice
+        if registrations
+            .iter()
+            .any(|registration| registration.sender == sender)
+        {
+            return Err(AgentManagerError::PermissionDenied(
+                "An agent with this ID is already registered for this user.".to_owned(),
+            ));
+        }
+        registrations.push(Registration {
+            sender,
+            identifier,
+            capabilities,
+        });
+        self.registered.notify_waiters();
+        Ok(())
+    }
+}
+
+/// The settings of NetworkManager through which its connection profiles are listed
+struct Settings {
+    connections: Arc<Mutex<Vec<OwnedObjectPath>>>,
+}
+
+#[zbus::interface(name = "org.freedesktop.NetworkManager.Settings")]
+impl Settings {
+    fn list_connections(&self) -> Vec<OwnedObjectPath> {
+        self.connections.lock().unwrap().clone()
+    }
+}
+
+/// A notification daemon which records the summary and body of every notification
+struct Notifications {
+    shown: Arc<Mutex<Vec<(String, String)>>>,
+}
+
+#[zbus::interface(name = "org.freedesktop.Notifications")]
+impl Notifications {
+    #[allow(clippy::too_many_arguments)]
+    fn notify(
+        &self,
+        _app_name: String,
+        _replaces_id: u32,
+        _app_icon: String,
+        summary: String,
+        body: String,
+        _actions: Vec<String>,
+        _hints: HashMap<String, OwnedValue>,
+        _expire_timeout: i32,
+    ) -> u32 {
+        let mut shown = self.shown.lock().unwrap();
+        shown.push((summary, body));
+        shown.len() as u32
+    }
+}
+
+/// A single connection profile
+struct SettingsConnection {
+    path: OwnedObjectPath,
+    settings: ConnectionSettings,
+    updates: Arc<Mutex<Vec<Update>>>,
+}
+
+/// An `Update2` call of a connection profile
+#[derive(Debug)]
+pub struct Update {
+    pub path: OwnedObjectPath,
+    pub settings: ConnectionSettings,
+    pub flags: u32,
+}
+
+#[zbus::interface(name = "org.freedesktop.NetworkManager.Settings.Connection")]
+impl SettingsConnection {
+    fn get_settings(&self) -> ConnectionSettings {
+        self.settings
+            .iter()
+            .map(|(setting, properties)| {
+                let properties = properties
+                    .iter()
+                    .map(|(key, value)| (key.clone(), value.try_clone().unwrap()))
+                    .collect();
+                (setting.clone(), properties)
+            })
+            .collect()
+    }
+
+    /// The fake stores no secrets itself
+    fn get_secrets(&self, _setting_name: String) -> ConnectionSettings {
+        ConnectionSettings::new()
+    }
+
+    fn update2(
+        &mut self,
+        settings: ConnectionSettings,
+        flags: u32,
+        _args: HashMap<String, OwnedValue>,
+    ) -> HashMap<String, OwnedValue> {
+        self.settings = settings;
+        self.updates.lock().unwrap().push(Update {
+            path: self.path.clone(),
+            settings: self.get_settings(),
+            flags,
+        });
+        HashMap::new()
+    }
+}
+
+#[zbus::proxy(
+    interface = "org.freedesktop.NetworkManager.SecretAgent",
+    default_path = "/org/freedesktop/NetworkManager/SecretAgent"
+)]
+trait SecretAgent {
+    fn get_secrets(
+        &self,
+        connection: HashMap<&str, HashMap<&str, Value<'_>>>,
+        connection_path: &ObjectPath<'_>,
+        setting_name: &str,
+        hints: &[&str],
+        flags: u32,
+    ) -> zbus::Result<ConnectionSettings>;
+
+    fn save_secrets(
+        &self,
+        connection: HashMap<&str, HashMap<&str, Value<'_>>>,
+        connection_path: &ObjectPath<'_>,
+    ) -> zbus::Result<()>;
+}
+
+/// A private D-Bus daemon on which the fake NetworkManager runs
+pub struct FakeNm {
+    dir: TempDir,
+    address: String,
+    daemon: Child,
+    conn: Connection,
+    registrations: Arc<Mutex<Vec<Registration>>>,
+    registered: Arc<Notify>,
+    connections: Arc<Mutex<Vec<OwnedObjectPath>>>,
+    updates: Arc<Mutex<Vec<Update>>>,
+    notifications: Arc<Mutex<Vec<(String, String)>>>,
+}
+
+impl FakeNm {
+    /// Start a D-Bus daemon and claim NetworkManager's name on it
+    pub async fn start() -> Self {
+        let dir = tempfile::tempdir().expect("Could not create temporary directory");
+        let mut daemon = Command::new("dbus-daemon")
+            .arg("--session")
+            .arg("--nofork")
+            .arg("--print-address")
+            .arg(format!(
+                "--address=unix:path={}",
+                dir.path().join("bus").display()
+            ))
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("Could not start dbus-daemon which is required to run the integration tests");
+        let mut address = String::new();
+        BufReader::new(daemon.stdout.take().unwrap())
+            .read_line(&mut address)
+            .expect("Could not read address of dbus-daemon");
+        let address = address.trim().to_owned();
+
+        let registrations = Arc::default();
+        let registered = Arc::default();
+        let connections = Arc::default();
+        let notifications = Arc::default();
+        let conn = zbus::connection::Builder::address(address.as_str())
+            .unwrap()
+            .name("org.freedesktop.NetworkManager")
+            .unwrap()
+            .name("org.freedesktop.Notifications")
+            .unwrap()
+            .serve_at(
+                "/org/freedesktop/NetworkManager/AgentManager",
+                AgentManager {
+                    registrations: Arc::clone(&registrations),
+                    registered: Arc::clone(&registered),
+                },
+            )
+            .unwrap()
+            .serve_at(
+                "/org/freedesktop/NetworkManager/Settings",
+                Settings {
+                    connections: Arc::clone(&connections),
+                },
+            )
+            .unwrap()
+            .serve_at(
+                "/org/freedesktop/Notifications",
+                Notifications {
+                    shown: Arc::clone(&notifications),
+                },
+            )
+            .unwrap()
+            .build()
+            .await
+            .expect("Could not connect to dbus-daemon");
+        Self {
+            dir,
+            address,
+            daemon,
+            conn,
+            registrations,
+            registered,
+            connections,
+            updates: Arc::default(),
+            notifications,
+        }
+    }
+
+    /// Add a connection profile with the given settings, announce it like NetworkManager and return its path
+    pub async fn add_profile(
+        &self,
+        connection: &[(&str, &[(&str, Value<'_>)])],
+    ) -> OwnedObjectPath {
+        let path = {
+            let mut connections = self.connections.lock().unwrap();
+            let path = OwnedObjectPath::try_from(format!(
+                "/org/freedesktop/NetworkManager/Settings/{}",
+                connections.len() + 1
+            ))
+            .unwrap();
+            connections.push(path.clone());
+            path
+        };
+        let settings = connection
+            .iter()
+            .map(|(setting, properties)| {
+                let properties = properties
+                    .iter()
+                    .map(|(key, value)| ((*key).to_owned(), value.try_to_owned().unwrap()))
+                    .collect();
+                ((*setting).to_owned(), properties)
+            })
+            .collect();
+        self.conn
+            .object_server()
+            .at(
+                &path,
+                SettingsConnection {
+                    path: path.clone(),
+                    settings,
+                    updates: Arc::clone(&self.updates),
+                },
+            )
+            .await
+            .unwrap();
+        self.emit_settings_signal("NewConnection", &path).await;
+        path
+    }
+
+    /// All `Update2` calls of connection profiles so far
+    pub fn take_updates(&self) -> Vec<Update> {
+        std::mem::take(&mut self.updates.lock().unwrap())
+    }
+
+    /// Remove the connection profile at `path` and announce it like NetworkManager
+    pub async fn remove_profile(&self, path: &OwnedObjectPath) {
+        self.connections.lock().unwrap().retain(|p| p != path);
+        self.conn
+            .object_server()
+            .remove::<SettingsConnection, _>(path)
+            .await
+            .unwrap();
+        self.emit_settings_signal("ConnectionRemoved", path).await;
+    }
+
+    async fn emit_settings_signal(&self, name: &str, path: &OwnedObjectPath) {
+        self.conn
+            .emit_signal(
+                None::<()>,
+                "/org/freedesktop/NetworkManager/Settings",
+                "org.freedesktop.NetworkManager.Settings",
+                name,
+                &(path,),
+            )
+            .await
+            .unwrap();
+    }
+
+    /// Address of the private bus
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Path of the file `name` in the temporary directory of this test in which the agent runs
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.dir.path().join(name)
+    }
+
+    /// Write a private file with `content` into the temporary directory of this test and return its path
+    pub fn write_file(&self, name: &str, content: &str) -> PathBuf {
+        let path = self.path(name);
+        fs::write(&path, content).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        path
+    }
+
+    /// Start the agent with `config` as its config file and the given additional arguments
+    pub fn spawn_agent(&self, config: &str, args: &[&str]) -> Agent {
+        let config = self.write_file("config.toml", config);
+        // SAFETY: getuid is always successful
+        let uid = unsafe { libc::getuid() };
+        let uid = uid.to_string();
+        let config = config.to_str().unwrap();
+        // the fake NetworkManager runs as the same user as the tests
+        let mut agent_args = vec!["--conf", config, "--allow-uid", &uid];
+        agent_args.extend_from_slice(args);
+        self.spawn(&agent_args, "agent.log")
+    }
+
+    /// Start the `notify` subcommand which shows notifications via the fake notification daemon
+    ///
+    /// It accepts signals of processes running as `agent_uid`, which defaults to the user running the tests.
+    pub fn spawn_notifier(&self, agent_uid: Option<u32>) -> Agent {
+        // SAFETY: getuid is always successful
+        let uid = agent_uid.unwrap_or_else(|| unsafe { libc::getuid() });
+        let uid = uid.to_string();
+        let notify_args = [
+            "notify",
+            "--notifications-bus-address",
+            &self.address,
+            "--agent-uid",
+            &uid,
+        ];
+        self.spawn(&notify_args, "notify.log")
+    }
+
+    /// Run the agent binary in the background with its log written to the file `log`
+    ///
+    /// Executables in the `bin` directory of the test shadow those of the system.
+    fn spawn(&self, args: &[&str], log: &str) -> Agent {
+        let log = self.dir.path().join(log);
+        let path = std::env::join_paths(std::iter::once(self.path("bin")).chain(
+            std::env::split_paths(&std::env::var_os("PATH").unwrap_or_default()),
+        ))
+        .unwrap();
+        let child = Command::new(env!("CARGO_BIN_EXE_nm-file-secret-agent"))
+            .env("PATH", path)
+            .arg("--bus-address")
+            .arg(&self.address)
+            .arg("-v")
+            .args(args)
+            .current_dir(self.dir.path())
+            .env_remove("NOTIFY_SOCKET")
+            .stdout(Stdio::null())
+            .stderr(fs::File::create(&log).unwrap())
+            .spawn()
+            .expect("Could not start the agent");
+        Agent { child, log }
+    }
+
+    /// Wait until a notification was shown and return its summary and body
+    pub async fn wait_for_notification(&self) -> (String, String) {
+        time::timeout(REGISTRATION_TIMEOUT, async {
+            loop {
+                if let Some(notification) = self.notifications.lock().unwrap().first() {
+                    return notification.clone();
+                }
+                time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("No notification was shown in time")
+    }
+
+    /// Run a subcommand of the agent binary with `config` as its config file and wait for it to exit
+    pub async fn run_command(&self, config: &str, args: &[&str]) -> Output {
+        let config = self.write_file("config.toml", config);
+        let mut command = Command::new(env!("CARGO_BIN_EXE_nm-file-secret-agent"));
+        command
+            .arg("--bus-address")
+            .arg(&self.address)
+            .arg("--conf")
+            .arg(&config)
+            .args(args)
+            .current_dir(self.dir.path());
+        // the fake NetworkManager must keep answering calls while the command runs
+        tokio::task::spawn_blocking(move || command.output())
+            .await
+            .unwrap()
+            .expect("Could not run the agent")
+    }
+
+    /// Wait until an agent has registered and return its registration
+    pub async fn wait_for_registration(&self) -> Registration {
+        time::timeout(REGISTRATION_TIMEOUT, async {
+            loop {
+                let registered = self.registered.notified();
+                if let Some(registration) = self.registrations.lock().unwrap().first() {
+                    return registration.clone();
+                }
+                registered.await;
+            }
+        })
+        .await
+        .expect("The agent did not register with NetworkManager in time")
+    }
+
+    /// Call GetSecrets on the registered agent like NetworkManager does
+    pub async fn get_secrets(
+        &self,
+        connection: &[(&str, &[(&str, Value<'_>)])],
+        setting_name: &str,
+        hints: &[&str],
+        flags: u32,
+    ) -> zbus::Result<ConnectionSettings> {
+        let agent = self.wait_for_registration().await;
+        get_secrets(
+            &self.conn,
+            &agent.sender,
+            connection,
+            setting_name,
+            hints,
+            flags,
+        )
+        .await
+    }
+
+    /// Call SaveSecrets on the registered agent like NetworkManager does after secrets were changed
+    pub async fn save_secrets(
+        &self,
+        connection: &[(&str, &[(&str, Value<'_>)])],
+    ) -> zbus::Result<()> {
+        let agent = self.wait_for_registration().await;
+        SecretAgentProxy::builder(&self.conn)
+            .destination(agent.sender)?
+            .build()
+            .await?
+            .save_secrets(settings_map(connection), &profile_path())
+            .await
+    }
+
+    /// Call GetSecrets on the registered agent from a connection that is not NetworkManager
+    pub async fn get_secrets_as_impostor(
+        &self,
+        connection: &[(&str, &[(&str, Value<'_>)])],
+        setting_name: &str,
+    ) -> zbus::Result<ConnectionSettings> {
+        let agent = self.wait_for_registration().await;
+        let conn = zbus::connection::Builder::address(self.address())?
+            .build()
+            .await?;
+        get_secrets(&conn, &agent.sender, connection, setting_name, &[], 0).await
+    }
+}
+
+impl Drop for FakeNm {
+    fn drop(&mut self) {
+        let _ = self.daemon.kill();
+        let _ = self.daemon.wait();
+    }
+}
+
+async fn get_secrets(
+    conn: &Connection,
+    agent: &str,
+    connection: &[(&str, &[(&str, Value<'_>)])],
+    setting_name: &str,
+    hints: &[&str],
+    flags: u32,
+) -> zbus::Result<ConnectionSettings> {
+    let proxy = SecretAgentProxy::builder(conn)
+        .destination(agent)?
+        .build()
+        .await?;
+    proxy
+        .get_secrets(
+            settings_map(connection),
+            &profile_path(),
+            setting_name,
+            hints,
+            flags,
+        )
+        .await
+}
+
+/// The settings of a connection in the form in which they are passed to secret agents
+fn settings_map<'a>(
+    connection: &[(&'a str, &[(&'a str, Value<'_>)])],
+) -> HashMap<&'a str, HashMap<&'a str, Value<'static>>> {
+    connection
+        .iter()
+        .map(|(setting, properties)| {
+            let properties = properties
+                .iter()
+                .map(|(key, value)| (*key, value.try_to_owned().unwrap().into()))
+                .collect();
+            (*setting, properties)
+        })
+        .collect()
+}
+
+/// The path of the connection profile that requests are made for
+fn profile_path() -> ObjectPath<'static> {
+    ObjectPath::from_static_str_unchecked("/org/freedesktop/NetworkManager/Settings/1")
+}
+
+/// The agent binary running against a [`FakeNm`]
+pub struct Agent {
+    child: Child,
+    log: PathBuf,
+}
+
+impl Agent {
+    /// Send `signal` to the agent, e.g. `libc::SIGHUP` to reload its config
+    pub fn signal(&self, signal: libc::c_int) {
+        // SAFETY: kill has no memory safety requirements
+        assert_eq!(
+            unsafe { libc::kill(self.child.id() as libc::pid_t, signal) },
+            0
+        );
+    }
+
+    /// Wait until the agent has logged a line containing `needle`
+    pub async fn wait_for_log(&self, needle: &str) {
+        time::timeout(REGISTRATION_TIMEOUT, async {
+            while !fs::read_to_string(&self.log)
+                .unwrap_or_default()
+                .contains(needle)
+            {
+                time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .unwrap_or_else(|_| panic!("The agent did not log {needle:?} in time"))
+    }
+}
+
+impl Drop for Agent {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        // the log is the only way to find out why the agent misbehaved
+        if std::thread::panicking() {
+            eprintln!(
+                "Log of the agent:\n{}",
+                fs::read_to_string(&self.log).unwrap_or_default()
+            );
+        }
+    }
+}
+
+/// Extract the string value of `setting.key` from the result of a GetSecrets call
+pub fn string_secret(secrets: &ConnectionSettings, setting: &str, key: &str) -> Option<String> {
+    let value = secrets.get(setting)?.get(key)?;
+    String::try_from(value.try_clone().unwrap()).ok()
+}
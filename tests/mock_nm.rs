@@ -0,0 +1,59 @@
+//! Tests of the `--mock-nm` developer mode
+
+use std::{fs, os::unix::fs::PermissionsExt, path::Path, process::Command};
+
+const CONFIG: &str = r#"
+[[entry]]
+match_id = "home"
+match_setting = "802-11-wireless-security"
+key = "psk"
+file = "psk"
+"#;
+
+const REQUESTS: &str = r#"
+[[request]]
+id = "home"
+type = "802-11-wireless"
+setting = "802-11-wireless-security"
+hints = ["psk"]
+
+[[request]]
+id = "office"
+type = "802-11-wireless"
+setting = "802-11-wireless-security"
+"#;
+
+/// Run the agent in mock mode within `dir` and return whether it exited successfully
+fn run_mock(dir: &Path) -> bool {
+    fs::write(dir.join("config.toml"), CONFIG).unwrap();
+    fs::write(dir.join("requests.toml"), REQUESTS).unwrap();
+    Command::new(env!("CARGO_BIN_EXE_nm-file-secret-agent"))
+        .args([
+            "--conf",
+            "config.toml",
+            "--validate",
+            "off",
+            "--mock-nm",
+            "requests.toml",
+        ])
+        .current_dir(dir)
+        .env_remove("NOTIFY_SOCKET")
+        .status()
+        .expect("Could not run the agent")
+        .success()
+}
+
+#[test]
+fn replays_requests() {
+    let dir = tempfile::tempdir().unwrap();
+    let psk = dir.path().join("psk");
+    fs::write(&psk, "correct horse battery staple").unwrap();
+    fs::set_permissions(&psk, fs::Permissions::from_mode(0o600)).unwrap();
+    assert!(run_mock(dir.path()));
+}
+
+#[test]
+fn fails_if_a_request_fails() {
+    let dir = tempfile::tempdir().unwrap();
+    assert!(!run_mock(dir.path()));
+}